@@ -0,0 +1,141 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{
+    Deserialize, Serialize,
+    de::{Error, Visitor},
+    ser::SerializeSeq,
+};
+
+use crate::{
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
+};
+
+/// Tells the report-to endpoint of a bundle that the application it was delivered to
+/// acknowledged it.
+///
+/// Only whole bundles are delivered to applications, so there is no fragment information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplicationAcknowledgement {
+    pub acknowledged_by: Endpoint,
+    pub acknowledgement_time: DtnTime,
+    pub bundle_source: Endpoint,
+    pub bundle_creation_timestamp: CreationTimestamp,
+}
+
+impl Serialize for ApplicationAcknowledgement {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(4))?;
+        seq.serialize_element(&self.acknowledged_by)?;
+        seq.serialize_element(&self.acknowledgement_time)?;
+        seq.serialize_element(&self.bundle_source)?;
+        seq.serialize_element(&self.bundle_creation_timestamp)?;
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ApplicationAcknowledgement {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ApplicationAcknowledgementVisitor;
+        impl<'de> Visitor<'de> for ApplicationAcknowledgementVisitor {
+            type Value = ApplicationAcknowledgement;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("application acknowledgement")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let length = seq.size_hint().ok_or(Error::custom(
+                    "CBOR Array for ApplicationAcknowledgement must have a size hint",
+                ))?;
+                if length != 4 {
+                    Err(Error::invalid_length(
+                        length,
+                        &"An ApplicationAcknowledgement must have 4 elements",
+                    ))?;
+                }
+                let acknowledged_by = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'acknowledged_by'"))?;
+                let acknowledgement_time = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'acknowledgement_time'"))?;
+                let bundle_source = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'bundle_source'"))?;
+                let bundle_creation_timestamp = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'bundle_creation_timestamp'"))?;
+                Ok(ApplicationAcknowledgement {
+                    acknowledged_by,
+                    acknowledgement_time,
+                    bundle_source,
+                    bundle_creation_timestamp,
+                })
+            }
+        }
+        deserializer.deserialize_seq(ApplicationAcknowledgementVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        administrative_record::{
+            AdministrativeRecord, application_acknowledgement::ApplicationAcknowledgement,
+        },
+        endpoint::Endpoint,
+        time::{CreationTimestamp, DtnTime},
+    };
+
+    #[test]
+    fn application_acknowledgement_roundtrip() -> Result<(), crate::SerializationError> {
+        let acknowledgement = ApplicationAcknowledgement {
+            acknowledged_by: Endpoint::new("dtn://receiver/app").unwrap(),
+            acknowledgement_time: DtnTime {
+                timestamp: 123_457_000,
+            },
+            bundle_source: Endpoint::new("dtn://sender/").unwrap(),
+            bundle_creation_timestamp: CreationTimestamp {
+                creation_time: DtnTime {
+                    timestamp: 123_456_789,
+                },
+                sequence_number: 3,
+            },
+        };
+        let data: Vec<u8> =
+            (&AdministrativeRecord::ApplicationAcknowledgement(acknowledgement.clone()))
+                .try_into()?;
+        let AdministrativeRecord::ApplicationAcknowledgement(parsed) =
+            AdministrativeRecord::try_from(data)?
+        else {
+            panic!("not an application acknowledgement");
+        };
+        assert_eq!(parsed, acknowledgement);
+        Ok(())
+    }
+}
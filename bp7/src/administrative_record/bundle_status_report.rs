@@ -96,7 +96,7 @@ impl<'de> Deserialize<'de> for BundleStatusItem {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize_repr, Deserialize_repr)]
 #[repr(u64)]
 pub enum BundleStatusReason {
     NoAdditionalInformation = 0,
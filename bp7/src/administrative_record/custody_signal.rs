@@ -0,0 +1,173 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{
+    Deserialize, Serialize,
+    de::{Error, Visitor},
+    ser::SerializeSeq,
+};
+
+use crate::{
+    administrative_record::bundle_status_report::BundleStatusReason, endpoint::Endpoint,
+    time::CreationTimestamp,
+};
+
+/// Tells the custodian of a bundle whether the next node took custody of it.
+///
+/// The bundle is identified the same way as in a bundle status report. A refusal carries the
+/// reason the bundle could not be kept.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CustodySignal {
+    pub accepted: bool,
+    pub reason: BundleStatusReason,
+    pub bundle_source: Endpoint,
+    pub bundle_creation_timestamp: CreationTimestamp,
+    pub fragment_offset: Option<u64>,
+    pub fragment_length: Option<u64>,
+}
+
+impl Serialize for CustodySignal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let length = if self.fragment_offset.is_some() && self.fragment_length.is_some() {
+            6
+        } else {
+            4
+        };
+        let mut seq = serializer.serialize_seq(Some(length))?;
+
+        seq.serialize_element(&self.accepted)?;
+        seq.serialize_element(&self.reason)?;
+        seq.serialize_element(&self.bundle_source)?;
+        seq.serialize_element(&self.bundle_creation_timestamp)?;
+        if length == 6 {
+            seq.serialize_element(&self.fragment_offset.unwrap())?;
+            seq.serialize_element(&self.fragment_length.unwrap())?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for CustodySignal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct CustodySignalVisitor;
+        impl<'de> Visitor<'de> for CustodySignalVisitor {
+            type Value = CustodySignal;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("custody signal")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let length = seq.size_hint().ok_or(Error::custom(
+                    "CBOR Array for CustodySignal must have a size hint",
+                ))?;
+                if length != 4 && length != 6 {
+                    Err(Error::invalid_length(
+                        length,
+                        &"A CustodySignal must have 4 or 6 elements",
+                    ))?;
+                }
+                let accepted = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'accepted'"))?;
+                let reason = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'reason'"))?;
+                let bundle_source = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'bundle_source'"))?;
+                let bundle_creation_timestamp = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'bundle_creation_timestamp'"))?;
+                let mut fragment_offset = None;
+                let mut fragment_length = None;
+                if length == 6 {
+                    fragment_offset = Some(
+                        seq.next_element()?
+                            .ok_or(Error::custom("Error for field 'fragment_offset'"))?,
+                    );
+                    fragment_length = Some(
+                        seq.next_element()?
+                            .ok_or(Error::custom("Error for field 'fragment_length'"))?,
+                    );
+                }
+                Ok(CustodySignal {
+                    accepted,
+                    reason,
+                    bundle_source,
+                    bundle_creation_timestamp,
+                    fragment_offset,
+                    fragment_length,
+                })
+            }
+        }
+        deserializer.deserialize_seq(CustodySignalVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        administrative_record::{
+            AdministrativeRecord, bundle_status_report::BundleStatusReason,
+            custody_signal::CustodySignal,
+        },
+        endpoint::Endpoint,
+        time::{CreationTimestamp, DtnTime},
+    };
+
+    #[test]
+    fn custody_signal_roundtrip() -> Result<(), crate::SerializationError> {
+        for (accepted, reason, fragment) in [
+            (true, BundleStatusReason::NoAdditionalInformation, None),
+            (false, BundleStatusReason::HopLimitExceeded, Some((100, 50))),
+        ] {
+            let signal = CustodySignal {
+                accepted,
+                reason,
+                bundle_source: Endpoint::new("dtn://test/abc").unwrap(),
+                bundle_creation_timestamp: CreationTimestamp {
+                    creation_time: DtnTime {
+                        timestamp: 123_456_789,
+                    },
+                    sequence_number: 3,
+                },
+                fragment_offset: fragment.map(|(offset, _)| offset),
+                fragment_length: fragment.map(|(_, length)| length),
+            };
+            let data: Vec<u8> = (&AdministrativeRecord::CustodySignal(signal)).try_into()?;
+            let AdministrativeRecord::CustodySignal(parsed) = AdministrativeRecord::try_from(data)?
+            else {
+                panic!("not a custody signal");
+            };
+            assert_eq!(parsed.accepted, accepted);
+            assert_eq!(parsed.reason, reason);
+            assert_eq!(parsed.fragment_offset, fragment.map(|(offset, _)| offset));
+            assert_eq!(parsed.fragment_length, fragment.map(|(_, length)| length));
+        }
+        Ok(())
+    }
+}
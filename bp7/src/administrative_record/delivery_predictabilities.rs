@@ -0,0 +1,132 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{
+    Deserialize, Serialize,
+    de::{Error, Visitor},
+    ser::SerializeSeq,
+};
+
+use crate::endpoint::Endpoint;
+
+/// The Prophet delivery predictabilities of a node, sent to each newly connected peer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DeliveryPredictabilities {
+    pub predictabilities: Vec<DeliveryPredictability>,
+}
+
+/// How likely the sending node is to deliver bundles to `node`, between 0 and 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeliveryPredictability {
+    pub node: Endpoint,
+    pub predictability: f64,
+}
+
+impl Serialize for DeliveryPredictability {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(2))?;
+        seq.serialize_element(&self.node)?;
+        seq.serialize_element(&self.predictability)?;
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for DeliveryPredictability {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct DeliveryPredictabilityVisitor;
+        impl<'de> Visitor<'de> for DeliveryPredictabilityVisitor {
+            type Value = DeliveryPredictability;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("delivery predictability")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let length = seq.size_hint().ok_or(Error::custom(
+                    "CBOR Array for DeliveryPredictability must have a size hint",
+                ))?;
+                if length != 2 {
+                    Err(Error::invalid_length(
+                        length,
+                        &"A DeliveryPredictability must have 2 elements",
+                    ))?;
+                }
+                let node = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'node'"))?;
+                let predictability: f64 = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'predictability'"))?;
+                if !(0.0..=1.0).contains(&predictability) {
+                    Err(Error::custom("predictability must be between 0 and 1"))?;
+                }
+                Ok(DeliveryPredictability {
+                    node,
+                    predictability,
+                })
+            }
+        }
+        deserializer.deserialize_seq(DeliveryPredictabilityVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        administrative_record::{
+            AdministrativeRecord,
+            delivery_predictabilities::{DeliveryPredictabilities, DeliveryPredictability},
+        },
+        endpoint::Endpoint,
+    };
+
+    #[test]
+    fn delivery_predictabilities_roundtrip() -> Result<(), crate::SerializationError> {
+        let predictabilities = DeliveryPredictabilities {
+            predictabilities: vec![
+                DeliveryPredictability {
+                    node: Endpoint::new("dtn://node2").unwrap(),
+                    predictability: 0.75,
+                },
+                DeliveryPredictability {
+                    node: Endpoint::new("dtn://node3").unwrap(),
+                    predictability: 0.140_625,
+                },
+            ],
+        };
+        let data: Vec<u8> =
+            (&AdministrativeRecord::DeliveryPredictabilities(predictabilities.clone()))
+                .try_into()?;
+        let AdministrativeRecord::DeliveryPredictabilities(parsed) =
+            AdministrativeRecord::try_from(data)?
+        else {
+            panic!("not delivery predictabilities");
+        };
+        assert_eq!(parsed, predictabilities);
+        Ok(())
+    }
+}
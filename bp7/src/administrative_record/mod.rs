@@ -22,19 +22,44 @@ use serde::{
 };
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
-use crate::{SerializationError, administrative_record::bundle_status_report::BundleStatusReport};
+use crate::{
+    SerializationError,
+    administrative_record::{
+        application_acknowledgement::ApplicationAcknowledgement,
+        bundle_status_report::BundleStatusReport, custody_signal::CustodySignal,
+        delivery_predictabilities::DeliveryPredictabilities, summary_vector::SummaryVector,
+    },
+};
 
+pub mod application_acknowledgement;
 pub mod bundle_status_report;
+pub mod custody_signal;
+pub mod delivery_predictabilities;
+pub mod summary_vector;
 
 #[derive(Debug, Serialize_repr, Deserialize_repr, PartialEq, Eq)]
 #[repr(u64)]
 enum AdministrativeRecordType {
     BundleStatusReport = 1,
+    /// There is no custody signal for `BPv7` outside of bundle-in-bundle encapsulation, so this
+    /// one is dtrd specific.
+    CustodySignal = 192,
+    /// `BPv7` only has the flag requesting an application acknowledgement, the record carrying
+    /// it is dtrd specific as well.
+    ApplicationAcknowledgement = 193,
+    /// Exchanged by peers doing opportunistic routing, dtrd specific too.
+    SummaryVector = 194,
+    /// Prophet has its own TLV format, we send the predictabilities as a record instead.
+    DeliveryPredictabilities = 195,
 }
 
 #[derive(Debug)]
 pub enum AdministrativeRecord {
     BundleStatusReport(BundleStatusReport),
+    CustodySignal(CustodySignal),
+    ApplicationAcknowledgement(ApplicationAcknowledgement),
+    SummaryVector(SummaryVector),
+    DeliveryPredictabilities(DeliveryPredictabilities),
 }
 
 impl Serialize for AdministrativeRecord {
@@ -48,6 +73,22 @@ impl Serialize for AdministrativeRecord {
                 seq.serialize_element(&AdministrativeRecordType::BundleStatusReport)?;
                 seq.serialize_element(e)?;
             }
+            AdministrativeRecord::CustodySignal(e) => {
+                seq.serialize_element(&AdministrativeRecordType::CustodySignal)?;
+                seq.serialize_element(e)?;
+            }
+            AdministrativeRecord::ApplicationAcknowledgement(e) => {
+                seq.serialize_element(&AdministrativeRecordType::ApplicationAcknowledgement)?;
+                seq.serialize_element(e)?;
+            }
+            AdministrativeRecord::SummaryVector(e) => {
+                seq.serialize_element(&AdministrativeRecordType::SummaryVector)?;
+                seq.serialize_element(e)?;
+            }
+            AdministrativeRecord::DeliveryPredictabilities(e) => {
+                seq.serialize_element(&AdministrativeRecordType::DeliveryPredictabilities)?;
+                seq.serialize_element(e)?;
+            }
         }
         seq.end()
     }
@@ -83,6 +124,35 @@ impl<'de> Deserialize<'de> for AdministrativeRecord {
                             bundle_status_report,
                         ))
                     }
+                    AdministrativeRecordType::CustodySignal => {
+                        let custody_signal: CustodySignal = seq
+                            .next_element()?
+                            .ok_or(Error::custom("Error for field 'custody_signal'"))?;
+                        Ok(AdministrativeRecord::CustodySignal(custody_signal))
+                    }
+                    AdministrativeRecordType::ApplicationAcknowledgement => {
+                        let application_acknowledgement: ApplicationAcknowledgement =
+                            seq.next_element()?.ok_or(Error::custom(
+                                "Error for field 'application_acknowledgement'",
+                            ))?;
+                        Ok(AdministrativeRecord::ApplicationAcknowledgement(
+                            application_acknowledgement,
+                        ))
+                    }
+                    AdministrativeRecordType::SummaryVector => {
+                        let summary_vector: SummaryVector = seq
+                            .next_element()?
+                            .ok_or(Error::custom("Error for field 'summary_vector'"))?;
+                        Ok(AdministrativeRecord::SummaryVector(summary_vector))
+                    }
+                    AdministrativeRecordType::DeliveryPredictabilities => {
+                        let delivery_predictabilities: DeliveryPredictabilities = seq
+                            .next_element()?
+                            .ok_or(Error::custom("Error for field 'delivery_predictabilities'"))?;
+                        Ok(AdministrativeRecord::DeliveryPredictabilities(
+                            delivery_predictabilities,
+                        ))
+                    }
                 }
             }
         }
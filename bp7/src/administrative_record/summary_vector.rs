@@ -0,0 +1,174 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{
+    Deserialize, Serialize,
+    de::{Error, Visitor},
+    ser::SerializeSeq,
+};
+
+use crate::{endpoint::Endpoint, time::CreationTimestamp};
+
+/// Lists the bundles a node keeps for opportunistic routing, sent to each newly connected peer
+/// so that it only gets the bundles it does not have yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SummaryVector {
+    pub bundles: Vec<SummaryVectorEntry>,
+}
+
+/// A bundle, identified the same way as in a bundle status report, together with the number of
+/// copies the peer gets if it does not have the bundle yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummaryVectorEntry {
+    pub bundle_source: Endpoint,
+    pub bundle_creation_timestamp: CreationTimestamp,
+    pub fragment_offset: Option<u64>,
+    pub fragment_length: Option<u64>,
+    pub copies: u64,
+}
+
+impl Serialize for SummaryVectorEntry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let length = if self.fragment_offset.is_some() && self.fragment_length.is_some() {
+            5
+        } else {
+            3
+        };
+        let mut seq = serializer.serialize_seq(Some(length))?;
+
+        seq.serialize_element(&self.bundle_source)?;
+        seq.serialize_element(&self.bundle_creation_timestamp)?;
+        seq.serialize_element(&self.copies)?;
+        if length == 5 {
+            seq.serialize_element(&self.fragment_offset.unwrap())?;
+            seq.serialize_element(&self.fragment_length.unwrap())?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for SummaryVectorEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct SummaryVectorEntryVisitor;
+        impl<'de> Visitor<'de> for SummaryVectorEntryVisitor {
+            type Value = SummaryVectorEntry;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("summary vector entry")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let length = seq.size_hint().ok_or(Error::custom(
+                    "CBOR Array for SummaryVectorEntry must have a size hint",
+                ))?;
+                if length != 3 && length != 5 {
+                    Err(Error::invalid_length(
+                        length,
+                        &"A SummaryVectorEntry must have 3 or 5 elements",
+                    ))?;
+                }
+                let bundle_source = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'bundle_source'"))?;
+                let bundle_creation_timestamp = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'bundle_creation_timestamp'"))?;
+                let copies = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'copies'"))?;
+                let mut fragment_offset = None;
+                let mut fragment_length = None;
+                if length == 5 {
+                    fragment_offset = Some(
+                        seq.next_element()?
+                            .ok_or(Error::custom("Error for field 'fragment_offset'"))?,
+                    );
+                    fragment_length = Some(
+                        seq.next_element()?
+                            .ok_or(Error::custom("Error for field 'fragment_length'"))?,
+                    );
+                }
+                Ok(SummaryVectorEntry {
+                    bundle_source,
+                    bundle_creation_timestamp,
+                    fragment_offset,
+                    fragment_length,
+                    copies,
+                })
+            }
+        }
+        deserializer.deserialize_seq(SummaryVectorEntryVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        administrative_record::{
+            AdministrativeRecord,
+            summary_vector::{SummaryVector, SummaryVectorEntry},
+        },
+        endpoint::Endpoint,
+        time::{CreationTimestamp, DtnTime},
+    };
+
+    #[test]
+    fn summary_vector_roundtrip() -> Result<(), crate::SerializationError> {
+        let creation_timestamp = CreationTimestamp {
+            creation_time: DtnTime {
+                timestamp: 123_456_789,
+            },
+            sequence_number: 3,
+        };
+        let summary_vector = SummaryVector {
+            bundles: vec![
+                SummaryVectorEntry {
+                    bundle_source: Endpoint::new("dtn://sender/").unwrap(),
+                    bundle_creation_timestamp: creation_timestamp.clone(),
+                    fragment_offset: None,
+                    fragment_length: None,
+                    copies: 4,
+                },
+                SummaryVectorEntry {
+                    bundle_source: Endpoint::new("dtn://other/").unwrap(),
+                    bundle_creation_timestamp: creation_timestamp,
+                    fragment_offset: Some(100),
+                    fragment_length: Some(50),
+                    copies: 0,
+                },
+            ],
+        };
+        let data: Vec<u8> =
+            (&AdministrativeRecord::SummaryVector(summary_vector.clone())).try_into()?;
+        let AdministrativeRecord::SummaryVector(parsed) = AdministrativeRecord::try_from(data)?
+        else {
+            panic!("not a summary vector");
+        };
+        assert_eq!(parsed, summary_vector);
+        Ok(())
+    }
+}
@@ -24,17 +24,21 @@ use crate::Validate;
 use crate::{blockflags::BlockFlags, crc::CRCType};
 
 use self::bundle_age_block::BundleAgeBlock;
+use self::custody_transfer_block::CustodyTransferBlock;
 use self::hop_count_block::HopCountBlock;
 use self::previous_node_block::PreviousNodeBlock;
+use self::quality_of_service_block::QualityOfServiceBlock;
 use self::{payload_block::PayloadBlock, unkown_block::UnkownBlock};
 use num_enum::IntoPrimitive;
 use num_enum::TryFromPrimitive;
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 pub mod bundle_age_block;
+pub mod custody_transfer_block;
 pub mod hop_count_block;
 pub mod payload_block;
 pub mod previous_node_block;
+pub mod quality_of_service_block;
 pub mod unkown_block;
 
 #[derive(
@@ -49,11 +53,13 @@ pub mod unkown_block;
     IntoPrimitive,
 )]
 #[repr(u64)]
-enum BlockType {
+pub(crate) enum BlockType {
     Payload = 1,
     PreviousNode = 6,
     BundleAge = 7,
     HopCount = 10,
+    QualityOfService = 193,
+    CustodyTransfer = 194,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -62,6 +68,8 @@ pub enum Block<'a> {
     PreviousNode(PreviousNodeBlock),
     BundleAge(BundleAgeBlock),
     HopCount(HopCountBlock),
+    QualityOfService(QualityOfServiceBlock),
+    CustodyTransfer(CustodyTransferBlock),
     Unkown(UnkownBlock<'a>),
 }
 
@@ -72,6 +80,8 @@ impl Clone for Block<'_> {
             Self::PreviousNode(b) => Self::PreviousNode(b.clone()),
             Self::BundleAge(b) => Self::BundleAge(b.clone()),
             Self::HopCount(b) => Self::HopCount(b.clone()),
+            Self::QualityOfService(b) => Self::QualityOfService(*b),
+            Self::CustodyTransfer(b) => Self::CustodyTransfer(b.clone()),
             Self::Unkown(b) => Self::Unkown(b.clone()),
         }
     }
@@ -97,6 +107,8 @@ impl Serialize for CanonicalBlock<'_> {
             Block::PreviousNode(_) => BlockType::PreviousNode.into(),
             Block::BundleAge(_) => BlockType::BundleAge.into(),
             Block::HopCount(_) => BlockType::HopCount.into(),
+            Block::QualityOfService(_) => BlockType::QualityOfService.into(),
+            Block::CustodyTransfer(_) => BlockType::CustodyTransfer.into(),
             Block::Unkown(b) => b.block_type,
         };
         seq.serialize_element(&blocktype)?;
@@ -116,17 +128,17 @@ impl Serialize for CanonicalBlock<'_> {
             Block::HopCount(b) => {
                 seq.serialize_element(&b)?;
             }
-            Block::Unkown(b) => {
+            Block::QualityOfService(b) => {
                 seq.serialize_element(&b)?;
             }
-        }
-        if self.crc != CRCType::NoCRC {
-            match self.crc {
-                CRCType::NoCRC => panic!("Attempting to serialize content when we dont have a CRC"),
-                CRCType::CRC16(x) => seq.serialize_element(&x)?,
-                CRCType::CRC32(x) => seq.serialize_element(&x)?,
+            Block::CustodyTransfer(b) => {
+                seq.serialize_element(&b)?;
+            }
+            Block::Unkown(b) => {
+                seq.serialize_element(&b)?;
             }
         }
+        self.crc.serialize_value(&mut seq)?;
         seq.end()
     }
 }
@@ -191,6 +203,18 @@ impl<'de: 'a, 'a> Deserialize<'de> for CanonicalBlock<'a> {
                         let data: Vec<u8> = Vec::from(data_bytes);
                         Block::HopCount(HopCountBlock::try_from(data).map_err(Error::custom)?)
                     }
+                    Ok(BlockType::QualityOfService) => {
+                        let data: Vec<u8> = Vec::from(data_bytes);
+                        Block::QualityOfService(
+                            QualityOfServiceBlock::try_from(data).map_err(Error::custom)?,
+                        )
+                    }
+                    Ok(BlockType::CustodyTransfer) => {
+                        let data: Vec<u8> = Vec::from(data_bytes);
+                        Block::CustodyTransfer(
+                            CustodyTransferBlock::try_from(data).map_err(Error::custom)?,
+                        )
+                    }
                     Err(_) => Block::Unkown(UnkownBlock {
                         block_type: block_type_num,
                         data: data_bytes,
@@ -0,0 +1,136 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+use std::convert::TryFrom;
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{
+    Deserialize, Serialize,
+    de::{Error, Visitor},
+    ser::SerializeSeq,
+};
+use serde_cbor::Serializer;
+
+use crate::Validate;
+
+/// How urgent a bundle is, from least to most urgent.
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Clone,
+    Copy,
+    Default,
+    TryFromPrimitive,
+    IntoPrimitive,
+)]
+#[repr(u8)]
+pub enum PriorityClass {
+    Bulk = 0,
+    #[default]
+    Normal = 1,
+    Expedited = 2,
+}
+
+/// Extension block carrying the priority of a bundle.
+///
+/// There is no standardized block for this (the one of RFC 6258 only exists for `BPv6`), so we use
+/// a block type from the experimental range. Bundles without it are treated as
+/// [`PriorityClass::Normal`].
+/// Ordering compares the class first and the ordinal within the class second.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub struct QualityOfServiceBlock {
+    pub class: PriorityClass,
+    pub ordinal: u8,
+}
+
+impl Serialize for QualityOfServiceBlock {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut vec = Vec::new();
+        let inner_ser = &mut Serializer::new(&mut vec);
+        let mut seq = serde::Serializer::serialize_seq(inner_ser, Some(2))
+            .map_err(serde::ser::Error::custom)?;
+        seq.serialize_element(&u64::from(u8::from(self.class)))
+            .map_err(serde::ser::Error::custom)?;
+        seq.serialize_element(&u64::from(self.ordinal))
+            .map_err(serde::ser::Error::custom)?;
+        seq.end().map_err(serde::ser::Error::custom)?;
+
+        serializer.serialize_bytes(&vec)
+    }
+}
+
+impl<'de> Deserialize<'de> for QualityOfServiceBlock {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct QualityOfServiceBlockVisitor;
+        impl<'de> Visitor<'de> for QualityOfServiceBlockVisitor {
+            type Value = QualityOfServiceBlock;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("Quality of Service Block")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                let size = seq.size_hint().ok_or_else(|| {
+                    Error::custom("Quality of Service Block must know the length of its contents")
+                })?;
+                if size != 2 {
+                    return Err(Error::invalid_length(
+                        size,
+                        &"Quality of Service Block has 2 elements",
+                    ));
+                }
+
+                let class: u8 = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'class'"))?;
+                let class = PriorityClass::try_from(class).map_err(Error::custom)?;
+
+                let ordinal: u8 = seq
+                    .next_element()?
+                    .ok_or(Error::custom("Error for field 'ordinal'"))?;
+                Ok(QualityOfServiceBlock { class, ordinal })
+            }
+        }
+        deserializer.deserialize_seq(QualityOfServiceBlockVisitor)
+    }
+}
+
+impl Validate for QualityOfServiceBlock {
+    fn validate(&self) -> bool {
+        true
+    }
+}
+
+impl TryFrom<Vec<u8>> for QualityOfServiceBlock {
+    type Error = serde_cbor::Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        serde_cbor::from_slice(&value)
+    }
+}
@@ -20,7 +20,7 @@ use std::{
     convert::{TryFrom, TryInto},
     fmt::Write,
     marker::PhantomData,
-    ops::ControlFlow,
+    ops::{ControlFlow, Range},
 };
 
 use serde::{Deserialize, Serialize, de::Error, de::Visitor, ser::SerializeSeq};
@@ -28,8 +28,9 @@ use serde::{Deserialize, Serialize, de::Error, de::Visitor, ser::SerializeSeq};
 use crate::{
     FragmentationError, SerializationError, Validate,
     block::{
-        Block, CanonicalBlock, hop_count_block::HopCountBlock,
-        previous_node_block::PreviousNodeBlock,
+        Block, BlockType, CanonicalBlock, bundle_age_block::BundleAgeBlock,
+        custody_transfer_block::CustodyTransferBlock, hop_count_block::HopCountBlock,
+        previous_node_block::PreviousNodeBlock, quality_of_service_block::QualityOfServiceBlock,
     },
     blockflags::BlockFlags,
     bundleflags::BundleFlags,
@@ -44,6 +45,182 @@ const BUNDLE_SERIALIZATION_OVERHEAD: usize = 2; // 1 byte for the start of the c
 // for block with the highest possibe values for all fields + CRC32 is 41 bytes.
 // we need to account for the payload length value encoding as well. To be safe we go to 128 bytes in total.
 const PAYLOAD_BLOCK_SERIALIZATION_OVERHEAD: usize = 128;
+// a bundle is serialized as an indefinite length cbor array
+const CBOR_INDEFINITE_ARRAY_START: u8 = 0x9f;
+const CBOR_BREAK: u8 = 0xff;
+const CBOR_MAJOR_UNSIGNED: u8 = 0;
+const CBOR_MAJOR_BYTES: u8 = 2;
+const CBOR_MAJOR_ARRAY: u8 = 4;
+/// How deep cbor items may be nested before we consider the data invalid.
+const CBOR_MAX_NESTING: u8 = 32;
+
+/// Reads the head of a cbor data item.
+/// Returns the major type, the argument and the length of the head.
+fn read_cbor_head(data: &[u8]) -> Option<(u8, u64, usize)> {
+    let first = *data.first()?;
+    let (argument, length) = match first & 0x1f {
+        info @ 0..=23 => (u64::from(info), 1),
+        24 => (u64::from(*data.get(1)?), 2),
+        25 => (
+            u64::from(u16::from_be_bytes(data.get(1..3)?.try_into().ok()?)),
+            3,
+        ),
+        26 => (
+            u64::from(u32::from_be_bytes(data.get(1..5)?.try_into().ok()?)),
+            5,
+        ),
+        27 => (u64::from_be_bytes(data.get(1..9)?.try_into().ok()?), 9),
+        _ => return None,
+    };
+    Some((first >> 5, argument, length))
+}
+
+/// Parses the head of a payload block at the start of `data`, up to where its data starts.
+/// Returns the block with empty data, the length of the payload and the length of the head.
+fn read_payload_block_head<'a>(data: &[u8]) -> Option<(CanonicalBlock<'a>, usize, usize)> {
+    let mut pos = 0;
+    let mut next = |major: u8| {
+        let (m, argument, length) = read_cbor_head(&data[pos..])?;
+        pos += length;
+        (m == major).then_some(argument)
+    };
+    let fields = next(CBOR_MAJOR_ARRAY)?;
+    if fields != 5 && fields != 6 {
+        return None;
+    }
+    if next(CBOR_MAJOR_UNSIGNED)? != BlockType::Payload.into() {
+        return None;
+    }
+    let block_number = next(CBOR_MAJOR_UNSIGNED)?;
+    let block_flags = BlockFlags::from_bits_truncate(next(CBOR_MAJOR_UNSIGNED)?);
+    let crc = match next(CBOR_MAJOR_UNSIGNED)? {
+        0 => CRCType::NoCRC,
+        1 => CRCType::CRC16([0; 2]),
+        2 => CRCType::CRC32([0; 4]),
+        _ => return None,
+    };
+    let payload_length = usize::try_from(next(CBOR_MAJOR_BYTES)?).ok()?;
+    Some((
+        CanonicalBlock {
+            block: Block::Payload(PayloadBlock { data: &[] }),
+            block_number,
+            block_flags,
+            crc,
+        },
+        payload_length,
+        pos,
+    ))
+}
+
+/// Parses the start of a payload block whose data is only partially included in `data`.
+/// Returns the block with the included data and the full length of the payload.
+fn read_partial_payload_block(data: &[u8]) -> Option<(CanonicalBlock<'_>, usize)> {
+    let (mut block, payload_length, pos) = read_payload_block_head(data)?;
+    let available = &data[pos..];
+    let received = &available[..min(payload_length, available.len())];
+    if received.is_empty() || received.len() == payload_length {
+        return None;
+    }
+    block.block = Block::Payload(PayloadBlock { data: received });
+    Some((block, payload_length))
+}
+
+/// The length of the cbor data item at the start of `data`.
+fn cbor_item_length(data: &[u8], nesting: u8) -> Option<usize> {
+    let nesting = nesting.checked_sub(1)?;
+    let first = *data.first()?;
+    if first & 0x1f == 31 {
+        // indefinite length, the contents are followed by a break
+        if !matches!(first >> 5, 2..=5) {
+            return None;
+        }
+        let mut pos = 1;
+        while *data.get(pos)? != CBOR_BREAK {
+            pos += cbor_item_length(data.get(pos..)?, nesting)?;
+        }
+        return Some(pos + 1);
+    }
+    let (major, argument, head) = read_cbor_head(data)?;
+    let length = match major {
+        2 | 3 => head.checked_add(usize::try_from(argument).ok()?)?,
+        4 | 5 => {
+            let items = if major == 4 {
+                argument
+            } else {
+                argument.checked_mul(2)?
+            };
+            let mut pos = head;
+            for _ in 0..items {
+                pos += cbor_item_length(data.get(pos..)?, nesting)?;
+            }
+            pos
+        }
+        6 => head + cbor_item_length(data.get(head..)?, nesting)?,
+        _ => head,
+    };
+    (length <= data.len()).then_some(length)
+}
+
+/// The positions of the items of the cbor array at the start of `data`.
+fn cbor_array_items(data: &[u8]) -> Option<Vec<Range<usize>>> {
+    let (count, mut pos) = if *data.first()? == CBOR_INDEFINITE_ARRAY_START {
+        (None, 1)
+    } else {
+        let (major, count, head) = read_cbor_head(data)?;
+        if major != CBOR_MAJOR_ARRAY {
+            return None;
+        }
+        (Some(count), head)
+    };
+    let mut items = Vec::new();
+    loop {
+        match count {
+            Some(count) if items.len() as u64 == count => break,
+            None if *data.get(pos)? == CBOR_BREAK => break,
+            _ => {}
+        }
+        let length = cbor_item_length(data.get(pos..)?, CBOR_MAX_NESTING)?;
+        items.push(pos..pos + length);
+        pos += length;
+    }
+    Some(items)
+}
+
+/// A block of a serialized bundle that has a CRC.
+struct SerializedCRC {
+    block: Range<usize>,
+    /// Where the value is, relative to the start of the block.
+    value: Range<usize>,
+    crc: CRCType,
+}
+
+/// Finds all blocks with a CRC in a serialized bundle.
+fn find_crcs(data: &[u8]) -> Option<Vec<SerializedCRC>> {
+    let mut crcs = Vec::new();
+    for (index, block) in cbor_array_items(data)?.into_iter().enumerate() {
+        let fields = cbor_array_items(&data[block.clone()])?;
+        // the crc type is the third field of the primary block and the fourth of the others
+        let crc_type_field = if index == 0 { 2 } else { 3 };
+        let crc_type = fields.get(crc_type_field)?.start + block.start;
+        let crc = match read_cbor_head(&data[crc_type..])? {
+            (CBOR_MAJOR_UNSIGNED, 0, _) => continue,
+            (CBOR_MAJOR_UNSIGNED, 1, _) => CRCType::CRC16([0; 2]),
+            (CBOR_MAJOR_UNSIGNED, 2, _) => CRCType::CRC32([0; 4]),
+            _ => return None,
+        };
+        let value = fields.last()?.clone();
+        let (major, length, head) = read_cbor_head(&data[block.start + value.start..])?;
+        if major != CBOR_MAJOR_BYTES || length != crc.value().len() as u64 {
+            return None;
+        }
+        crcs.push(SerializedCRC {
+            block,
+            value: value.start + head..value.end,
+            crc,
+        });
+    }
+    Some(crcs)
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Bundle<'a> {
@@ -145,11 +322,38 @@ impl TryFrom<&Bundle<'_>> for Vec<u8> {
     type Error = SerializationError;
 
     fn try_from(value: &Bundle) -> Result<Self, Self::Error> {
-        serde_cbor::to_vec(value).map_err(SerializationError::SerializationError)
+        let mut data = serde_cbor::to_vec(value).map_err(SerializationError::SerializationError)?;
+        if value.primary_block.crc != CRCType::NoCRC
+            || value.blocks.iter().any(|b| b.crc != CRCType::NoCRC)
+        {
+            // the CRCs cover the serialized blocks, so we can only calculate them now
+            for SerializedCRC { block, value, crc } in
+                find_crcs(&data).ok_or(SerializationError::ConversionError)?
+            {
+                let block = &mut data[block];
+                block[value.clone()].fill(0);
+                let crc = crc.compute(block);
+                block[value].copy_from_slice(crc.value());
+            }
+        }
+        Ok(data)
     }
 }
 
 impl<'a> Bundle<'a> {
+    /// Checks the CRCs of all blocks of the serialized bundle in `data`.
+    pub fn verify_crcs(data: &[u8]) -> bool {
+        let Some(crcs) = find_crcs(data) else {
+            return false;
+        };
+        crcs.into_iter().all(|SerializedCRC { block, value, crc }| {
+            let mut block = data[block].to_vec();
+            let expected = block[value.clone()].to_vec();
+            block[value].fill(0);
+            crc.compute(&block).value() == expected
+        })
+    }
+
     pub fn as_hex(&self) -> Result<String, SerializationError> {
         let vec: Vec<u8> = self.try_into()?;
         let mut s = String::with_capacity(2 * vec.len());
@@ -222,6 +426,88 @@ impl<'a> Bundle<'a> {
         true
     }
 
+    /// The age of the bundle in milliseconds from its Bundle Age block.
+    pub fn bundle_age(&self) -> Option<u64> {
+        self.blocks.iter().find_map(|block| match &block.block {
+            Block::BundleAge(b) => Some(b.age),
+            _ => None,
+        })
+    }
+
+    /// Adds the time in milliseconds the bundle spent at this node to its Bundle Age block.
+    /// The block is added if the bundle does not have one yet.
+    pub fn add_bundle_age(&'_ mut self, dwell_time: u64) {
+        for block in &mut self.blocks {
+            if let Block::BundleAge(v) = &mut block.block {
+                v.age = v.age.saturating_add(dwell_time);
+                return;
+            }
+        }
+        let block = CanonicalBlock {
+            block: Block::BundleAge(BundleAgeBlock { age: dwell_time }),
+            block_number: self.next_block_number(),
+            block_flags: BlockFlags::empty(),
+            crc: CRCType::NoCRC,
+        };
+        self.blocks.push(block);
+    }
+
+    /// The priority of the bundle from its Quality of Service block, `Normal` if it has none.
+    pub fn quality_of_service(&self) -> QualityOfServiceBlock {
+        self.blocks
+            .iter()
+            .find_map(|block| match &block.block {
+                Block::QualityOfService(b) => Some(*b),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Sets the priority of the bundle, adding a Quality of Service block if it has none yet.
+    pub fn set_quality_of_service(&'_ mut self, qos: QualityOfServiceBlock) {
+        for block in &mut self.blocks {
+            if let Block::QualityOfService(v) = &mut block.block {
+                *v = qos;
+                return;
+            }
+        }
+        let block = CanonicalBlock {
+            block: Block::QualityOfService(qos),
+            block_number: self.next_block_number(),
+            block_flags: BlockFlags::MUST_REPLICATE_TO_ALL_FRAGMENTS,
+            crc: CRCType::NoCRC,
+        };
+        self.blocks.push(block);
+    }
+
+    /// The node that currently has custody of the bundle, if custody transfer is used for it.
+    pub fn custodian(&self) -> Option<&Endpoint> {
+        self.blocks.iter().find_map(|block| match &block.block {
+            Block::CustodyTransfer(b) => Some(&b.custodian),
+            _ => None,
+        })
+    }
+
+    /// Makes `custodian` the current custodian, adding a Custody Transfer block if there is none
+    /// yet.
+    pub fn set_custodian(&'_ mut self, custodian: &Endpoint) {
+        for block in &mut self.blocks {
+            if let Block::CustodyTransfer(v) = &mut block.block {
+                v.custodian = custodian.clone();
+                return;
+            }
+        }
+        let block = CanonicalBlock {
+            block: Block::CustodyTransfer(CustodyTransferBlock {
+                custodian: custodian.clone(),
+            }),
+            block_number: self.next_block_number(),
+            block_flags: BlockFlags::MUST_REPLICATE_TO_ALL_FRAGMENTS,
+            crc: CRCType::NoCRC,
+        };
+        self.blocks.push(block);
+    }
+
     pub fn fragment(
         self,
         max_size: usize,
@@ -301,8 +587,7 @@ impl<'a> Bundle<'a> {
             .iter()
             .filter(|b| {
                 !matches!(b.block, Block::Payload(_))
-                    && !b
-                        .block_flags
+                    && b.block_flags
                         .contains(BlockFlags::MUST_REPLICATE_TO_ALL_FRAGMENTS)
             })
             .cloned()
@@ -374,6 +659,188 @@ impl<'a> Bundle<'a> {
         ))
     }
 
+    /// Where the data of the payload block is in the serialized bundle starting with `head`.
+    /// `head` only needs to include the blocks before the payload data.
+    pub fn payload_range(head: &[u8]) -> Option<Range<usize>> {
+        if head.first() != Some(&CBOR_INDEFINITE_ARRAY_START) {
+            return None;
+        }
+        let mut pos = 1 + cbor_item_length(head.get(1..)?, CBOR_MAX_NESTING)?;
+        loop {
+            if let Some((_, payload_length, length)) = read_payload_block_head(head.get(pos..)?) {
+                let start = pos + length;
+                return Some(start..start.checked_add(payload_length)?);
+            }
+            pos += cbor_item_length(head.get(pos..)?, CBOR_MAX_NESTING)?;
+        }
+    }
+
+    /// Parses a serialized bundle without reading its payload data. `head` is the part of the
+    /// bundle before the payload data and `tail` the part after it, see
+    /// [`Bundle::payload_range`]. The payload block of the bundle is empty.
+    pub fn without_payload(
+        head: &'a [u8],
+        tail: &'a [u8],
+    ) -> Result<Bundle<'a>, SerializationError> {
+        if head.first() != Some(&CBOR_INDEFINITE_ARRAY_START) {
+            return Err(SerializationError::ConversionError);
+        }
+        let mut pos = 1;
+        let mut deserializer = serde_cbor::Deserializer::from_slice(&head[pos..]);
+        let primary_block = PrimaryBlock::deserialize(&mut deserializer)?;
+        pos += deserializer.byte_offset();
+
+        let mut blocks = Vec::new();
+        loop {
+            if let Some((payload_block, _, length)) = read_payload_block_head(&head[pos..]) {
+                if pos + length != head.len() {
+                    return Err(SerializationError::ConversionError);
+                }
+                blocks.push(payload_block);
+                break;
+            }
+            let mut deserializer = serde_cbor::Deserializer::from_slice(&head[pos..]);
+            blocks.push(CanonicalBlock::deserialize(&mut deserializer)?);
+            pos += deserializer.byte_offset();
+        }
+
+        let mut pos = 0;
+        while tail.get(pos) != Some(&CBOR_BREAK) {
+            if pos >= tail.len() {
+                return Err(SerializationError::ConversionError);
+            }
+            let mut deserializer = serde_cbor::Deserializer::from_slice(&tail[pos..]);
+            blocks.push(CanonicalBlock::deserialize(&mut deserializer)?);
+            pos += deserializer.byte_offset();
+        }
+
+        Ok(Bundle {
+            primary_block,
+            blocks,
+        })
+    }
+
+    /// Builds a fragment from the start of a serialized bundle whose transmission was
+    /// interrupted (reactive fragmentation, see 5.8 of RFC9171). The fragment contains all
+    /// payload bytes included in `data`.
+    pub fn from_received_prefix(data: &'a [u8]) -> Result<Bundle<'a>, FragmentationError> {
+        if data.first() != Some(&CBOR_INDEFINITE_ARRAY_START) {
+            return Err(FragmentationError::BundleInvalid);
+        }
+        let mut pos = 1;
+        let mut deserializer = serde_cbor::Deserializer::from_slice(&data[pos..]);
+        let primary_block = PrimaryBlock::deserialize(&mut deserializer)
+            .map_err(|_| FragmentationError::NotEnoughData)?;
+        pos += deserializer.byte_offset();
+        if primary_block
+            .bundle_processing_flags
+            .contains(BundleFlags::MUST_NOT_FRAGMENT)
+        {
+            return Err(FragmentationError::MustNotFragment);
+        }
+
+        let mut blocks = Vec::new();
+        while pos < data.len() && data[pos] != CBOR_BREAK {
+            let mut deserializer = serde_cbor::Deserializer::from_slice(&data[pos..]);
+            let Ok(block) = CanonicalBlock::deserialize(&mut deserializer) else {
+                break;
+            };
+            pos += deserializer.byte_offset();
+            blocks.push(block);
+        }
+        if blocks.iter().any(|b| matches!(b.block, Block::Payload(_))) {
+            // The payload is complete, so this is no fragment. As blocks might follow the
+            // payload we can not be sure we have them all.
+            return Err(FragmentationError::NotEnoughData);
+        }
+        let (payload_block, payload_length) =
+            read_partial_payload_block(&data[pos..]).ok_or(FragmentationError::NotEnoughData)?;
+        blocks.push(payload_block);
+
+        Ok(Bundle {
+            primary_block: PrimaryBlock {
+                bundle_processing_flags: primary_block.bundle_processing_flags
+                    | BundleFlags::FRAGMENT,
+                fragment_offset: Some(primary_block.fragment_offset.unwrap_or(0)),
+                total_data_length: Some(
+                    primary_block
+                        .total_data_length
+                        .unwrap_or(payload_length as u64),
+                ),
+                ..primary_block
+            },
+            blocks,
+        })
+    }
+
+    /// Returns the fragment with the part of the payload the next node did not receive when
+    /// the transmission was interrupted after `received_payload` bytes of the payload
+    /// (reactive fragmentation, see 5.8 of RFC9171). Returns None if the whole payload has
+    /// been received.
+    pub fn remaining_fragment(
+        self,
+        received_payload: usize,
+    ) -> Result<Option<Bundle<'a>>, FragmentationError> {
+        let payload = self.payload_block().data;
+        if received_payload >= payload.len() {
+            return Ok(None);
+        }
+        if received_payload == 0 {
+            return Ok(Some(self));
+        }
+        if self
+            .primary_block
+            .bundle_processing_flags
+            .contains(BundleFlags::MUST_NOT_FRAGMENT)
+        {
+            return Err(FragmentationError::MustNotFragment);
+        }
+
+        let payload_index = self
+            .blocks
+            .iter()
+            .position(|b| matches!(b.block, Block::Payload(_)))
+            .expect("All Bundles MUST contain a payload block");
+        let blocks = self
+            .blocks
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, block)| match block.block {
+                Block::Payload(_) => Some(CanonicalBlock {
+                    block: Block::Payload(PayloadBlock {
+                        data: &payload[received_payload..],
+                    }),
+                    ..block
+                }),
+                // blocks after the payload have not been received at all
+                _ if i > payload_index
+                    || block
+                        .block_flags
+                        .contains(BlockFlags::MUST_REPLICATE_TO_ALL_FRAGMENTS) =>
+                {
+                    Some(block)
+                }
+                _ => None,
+            })
+            .collect();
+
+        let offset = self.primary_block.fragment_offset.unwrap_or(0);
+        Ok(Some(Bundle {
+            primary_block: PrimaryBlock {
+                bundle_processing_flags: self.primary_block.bundle_processing_flags
+                    | BundleFlags::FRAGMENT,
+                fragment_offset: Some(offset + received_payload as u64),
+                total_data_length: Some(
+                    self.primary_block
+                        .total_data_length
+                        .unwrap_or(payload.len() as u64),
+                ),
+                ..self.primary_block
+            },
+            blocks,
+        }))
+    }
+
     pub fn can_reassemble_bundles(bundles: &mut Vec<Bundle>) -> bool {
         if bundles.is_empty() {
             return false;
@@ -475,7 +942,10 @@ mod tests {
     use crate::{
         FragmentationError,
         block::{
-            Block, CanonicalBlock, hop_count_block::HopCountBlock, payload_block::PayloadBlock,
+            Block, CanonicalBlock,
+            hop_count_block::HopCountBlock,
+            payload_block::PayloadBlock,
+            quality_of_service_block::{PriorityClass, QualityOfServiceBlock},
         },
         blockflags::BlockFlags,
         bundleflags::BundleFlags,
@@ -571,13 +1041,13 @@ mod tests {
         let mut fragments_first = bundle.fragment(750)?.0;
         let fragments: Vec<Bundle> = fragments_first
             .drain(0..fragments_first.len())
-            .flat_map(|f| f.fragment(600).unwrap().0)
+            .flat_map(|f| f.fragment(550).unwrap().0)
             .collect();
 
         let mut current_offset = 0;
         for fragment in &fragments {
             let fragment_length = Vec::<u8>::try_from(fragment)?.len() as u64;
-            assert!(fragment_length <= 600);
+            assert!(fragment_length <= 550);
             let offset = fragment.primary_block.fragment_offset.unwrap();
             let length = fragment.payload_block().data.len() as u64;
             assert_eq!(offset, current_offset);
@@ -622,13 +1092,13 @@ mod tests {
         let mut fragments_first = bundle.fragment(750)?.0;
         let mut fragments: Vec<Bundle> = fragments_first
             .drain(0..fragments_first.len())
-            .flat_map(|f| f.fragment(600).unwrap().0)
+            .flat_map(|f| f.fragment(550).unwrap().0)
             .collect();
 
         let mut current_offset = 0;
         for fragment in &fragments {
             let fragment_length = Vec::<u8>::try_from(fragment)?.len() as u64;
-            assert!(fragment_length <= 600);
+            assert!(fragment_length <= 550);
             let offset = fragment.primary_block.fragment_offset.unwrap();
             let length = fragment.payload_block().data.len() as u64;
             assert_eq!(offset, current_offset);
@@ -683,4 +1153,233 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn reactive_fragmentation() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let mut bundle = get_test_bundle(&testdata);
+        // blocks after the payload are not received before the transmission is interrupted
+        bundle.set_previous_node(&Endpoint::new("dtn://node1").unwrap());
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+
+        let received = Bundle::from_received_prefix(&serialized[..500])?;
+        assert_eq!(received.primary_block.fragment_offset, Some(0));
+        assert_eq!(received.primary_block.total_data_length, Some(1024));
+        assert!(
+            received
+                .blocks
+                .iter()
+                .all(|b| !matches!(b.block, Block::PreviousNode(_)))
+        );
+        let received_payload = received.payload_block().data.len();
+        assert_eq!(received.payload_block().data, &testdata[..received_payload]);
+
+        let remaining = bundle.remaining_fragment(received_payload)?.unwrap();
+        assert_eq!(
+            remaining.primary_block.fragment_offset,
+            Some(received_payload as u64)
+        );
+        assert!(
+            remaining
+                .blocks
+                .iter()
+                .any(|b| matches!(b.block, Block::PreviousNode(_)))
+        );
+        assert!(
+            remaining
+                .blocks
+                .iter()
+                .all(|b| !matches!(b.block, Block::HopCount(_)))
+        );
+
+        // both fragments need to survive serialization
+        let received = Vec::<u8>::try_from(&received)?;
+        let remaining = Vec::<u8>::try_from(&remaining)?;
+        let fragments = vec![
+            Bundle::try_from(received.as_slice())?,
+            Bundle::try_from(remaining.as_slice())?,
+        ];
+        let reassembled = Bundle::reassemble_bundles(fragments).unwrap();
+        let parsed: Bundle<'_> = reassembled.as_slice().try_into().unwrap();
+        assert_eq!(parsed.payload_block().data, get_bundle_data());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reactive_fragmentation_not_enough_data() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let bundle = get_test_bundle(&testdata);
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+
+        for len in [0, 10, 60, serialized.len() - 1] {
+            assert!(matches!(
+                Bundle::from_received_prefix(&serialized[..len]),
+                Err(FragmentationError::BundleInvalid | FragmentationError::NotEnoughData)
+            ));
+        }
+        assert!(bundle.remaining_fragment(1024)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reactive_fragmentation_must_not_fragment() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let mut bundle = get_test_bundle(&testdata);
+        bundle.primary_block.bundle_processing_flags |= BundleFlags::MUST_NOT_FRAGMENT;
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+
+        assert!(matches!(
+            Bundle::from_received_prefix(&serialized[..500]),
+            Err(FragmentationError::MustNotFragment)
+        ));
+        assert!(matches!(
+            bundle.remaining_fragment(100),
+            Err(FragmentationError::MustNotFragment)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_age() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let mut bundle = get_test_bundle(&testdata);
+        assert!(bundle.primary_block.has_clock());
+        assert_eq!(
+            bundle.primary_block.expiration_time(),
+            Some(DtnTime {
+                timestamp: 681_257_389_438
+            })
+        );
+        assert_eq!(bundle.bundle_age(), None);
+
+        bundle.primary_block.creation_timestamp.creation_time = DtnTime { timestamp: 0 };
+        assert!(!bundle.primary_block.has_clock());
+        assert_eq!(bundle.primary_block.expiration_time(), None);
+
+        bundle.add_bundle_age(1500);
+        bundle.add_bundle_age(500);
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+        let bundle = Bundle::try_from(serialized.as_slice())?;
+        assert_eq!(bundle.bundle_age(), Some(2000));
+        assert_eq!(bundle.blocks.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn quality_of_service() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let mut bundle = get_test_bundle(&testdata);
+        assert_eq!(
+            bundle.quality_of_service(),
+            QualityOfServiceBlock::default()
+        );
+        assert_eq!(bundle.quality_of_service().class, PriorityClass::Normal);
+
+        let qos = QualityOfServiceBlock {
+            class: PriorityClass::Expedited,
+            ordinal: 7,
+        };
+        bundle.set_quality_of_service(qos);
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+        let bundle = Bundle::try_from(serialized.as_slice())?;
+        assert_eq!(bundle.quality_of_service(), qos);
+        assert!(qos > QualityOfServiceBlock::default());
+        assert_eq!(bundle.blocks.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn custodian() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let mut bundle = get_test_bundle(&testdata);
+        assert_eq!(bundle.custodian(), None);
+
+        bundle.set_custodian(&Endpoint::new("dtn://node1").unwrap());
+        bundle.set_custodian(&Endpoint::new("dtn://node2").unwrap());
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+        let bundle = Bundle::try_from(serialized.as_slice())?;
+        assert_eq!(
+            bundle.custodian(),
+            Some(&Endpoint::new("dtn://node2").unwrap())
+        );
+        assert_eq!(bundle.blocks.len(), 3);
+
+        // every fragment names the custodian
+        let (fragments, _, _) = bundle.fragment(400)?;
+        assert!(fragments.len() > 1);
+        for fragment in fragments {
+            assert_eq!(
+                fragment.custodian(),
+                Some(&Endpoint::new("dtn://node2").unwrap())
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn without_payload() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let mut bundle = get_test_bundle(&testdata);
+        // the custody transfer block follows the payload
+        bundle.set_custodian(&Endpoint::new("dtn://node1").unwrap());
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+
+        let payload = Bundle::payload_range(&serialized[..100]).unwrap();
+        assert_eq!(&serialized[payload.clone()], testdata.as_slice());
+        assert_eq!(
+            Bundle::payload_range(&serialized[..payload.start]),
+            Some(payload.clone())
+        );
+        assert_eq!(Bundle::payload_range(&serialized[..10]), None);
+
+        let parsed =
+            Bundle::without_payload(&serialized[..payload.start], &serialized[payload.end..])?;
+        assert_eq!(parsed.primary_block, bundle.primary_block);
+        assert_eq!(parsed.blocks.len(), 3);
+        assert!(parsed.payload_block().data.is_empty());
+        assert_eq!(
+            parsed.custodian(),
+            Some(&Endpoint::new("dtn://node1").unwrap())
+        );
+
+        assert!(
+            Bundle::without_payload(&serialized[..payload.start - 1], &serialized[payload.end..])
+                .is_err()
+        );
+        assert!(
+            Bundle::without_payload(&serialized[..payload.start], &serialized[payload.end + 1..])
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn crcs() -> Result<(), FragmentationError> {
+        let testdata = get_bundle_data();
+        let mut bundle = get_test_bundle(&testdata);
+        let serialized = Vec::<u8>::try_from(&bundle)?;
+        assert!(Bundle::verify_crcs(&serialized));
+
+        bundle.primary_block.crc = CRCType::CRC16([0; 2]);
+        bundle.blocks[1].crc = CRCType::CRC32([0; 4]);
+        let mut serialized = Vec::<u8>::try_from(&bundle)?;
+        assert!(Bundle::verify_crcs(&serialized));
+        let deserialized = Bundle::try_from(serialized.as_slice())?;
+        assert_ne!(deserialized.primary_block.crc, CRCType::CRC16([0; 2]));
+        assert_eq!(Vec::<u8>::try_from(&deserialized)?, serialized);
+
+        let last = serialized.len() - 10;
+        serialized[last] ^= 0x01;
+        assert!(!Bundle::verify_crcs(&serialized));
+        assert!(!Bundle::verify_crcs(&serialized[..last]));
+
+        Ok(())
+    }
 }
@@ -20,6 +20,7 @@ use std::convert::TryInto;
 use serde::{
     Deserialize, Serialize,
     de::{Error, Unexpected, Visitor},
+    ser::SerializeSeq,
 };
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -75,7 +76,80 @@ impl<'de> Deserialize<'de> for CRCType {
     }
 }
 
+/// A CRC value, which is serialized as a byte string.
+struct CRCValue<'a>(&'a [u8]);
+
+impl Serialize for CRCValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// CRC-16/X.25, see 4.2.1 of RFC9171.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for byte in data {
+        crc ^= u16::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// CRC-32C (Castagnoli), see 4.2.1 of RFC9171.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
 impl CRCType {
+    /// The CRC value, empty for `NoCRC`.
+    pub fn value(&self) -> &[u8] {
+        match self {
+            CRCType::NoCRC => &[],
+            CRCType::CRC16(x) => x,
+            CRCType::CRC32(x) => x,
+        }
+    }
+
+    /// Computes a CRC of the same type over `data`, in which the CRC value must be zeroed.
+    pub fn compute(&self, data: &[u8]) -> CRCType {
+        match self {
+            CRCType::NoCRC => CRCType::NoCRC,
+            CRCType::CRC16(_) => CRCType::CRC16(crc16(data).to_be_bytes()),
+            CRCType::CRC32(_) => CRCType::CRC32(crc32c(data).to_be_bytes()),
+        }
+    }
+
+    /// Adds the CRC value as the last element of a block, unless there is none.
+    pub fn serialize_value<S>(&self, seq: &mut S) -> Result<(), S::Error>
+    where
+        S: SerializeSeq,
+    {
+        match self {
+            CRCType::NoCRC => Ok(()),
+            CRCType::CRC16(_) | CRCType::CRC32(_) => seq.serialize_element(&CRCValue(self.value())),
+        }
+    }
+
     pub fn deserialize_value<'de, A>(&self, mut seq: A) -> Result<CRCType, A::Error>
     where
         A: serde::de::SeqAccess<'de>,
@@ -116,7 +190,29 @@ impl CRCType {
 
 #[cfg(test)]
 mod tests {
-    use crate::crc::CRCType;
+    use crate::crc::{CRCType, crc16, crc32c};
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc16(b"123456789"), 0x906E);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(
+            CRCType::CRC16([0; 2]).compute(b"123456789"),
+            CRCType::CRC16([0x90, 0x6E])
+        );
+    }
+
+    #[test]
+    fn serialize_value() -> Result<(), serde_cbor::Error> {
+        let mut data = Vec::new();
+        let mut serializer = serde_cbor::Serializer::new(&mut data);
+        let mut seq = serde::Serializer::serialize_seq(&mut serializer, None)?;
+        CRCType::NoCRC.serialize_value(&mut seq)?;
+        CRCType::CRC16([0x55, 0xAA]).serialize_value(&mut seq)?;
+        serde::ser::SerializeSeq::end(seq)?;
+        assert_eq!(data, [0x9f, 0x42, 0x55, 0xAA, 0xff]);
+        Ok(())
+    }
 
     #[test]
     fn serialize_nocrc() -> Result<(), serde_cbor::Error> {
@@ -47,6 +47,8 @@ pub enum FragmentationError {
     CanNotFragmentThatSmall(u64),
     MustNotFragment,
     BundleInvalid,
+    /// Not enough of the bundle has been received to build a fragment from it.
+    NotEnoughData,
 }
 
 impl From<SerializationError> for FragmentationError {
@@ -18,7 +18,11 @@
 use serde::{Deserialize, Serialize, de::Error, de::Visitor, ser::SerializeSeq};
 
 use crate::{
-    Validate, bundleflags::BundleFlags, crc::CRCType, endpoint::Endpoint, time::CreationTimestamp,
+    Validate,
+    bundleflags::BundleFlags,
+    crc::CRCType,
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
 };
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -58,17 +62,11 @@ impl Serialize for PrimaryBlock {
         seq.serialize_element(&self.report_to)?;
         seq.serialize_element(&self.creation_timestamp)?;
         seq.serialize_element(&self.lifetime)?;
-        if self.fragment_offset.is_some() {
-            seq.serialize_element(&self.fragment_offset.unwrap())?;
+        if let Some(fragment_offset) = self.fragment_offset {
+            seq.serialize_element(&fragment_offset)?;
             seq.serialize_element(&self.total_data_length.unwrap())?;
         }
-        if self.crc != CRCType::NoCRC {
-            match self.crc {
-                CRCType::NoCRC => panic!("Attempting to serialize content when we dont have a CRC"),
-                CRCType::CRC16(x) => seq.serialize_element(&x)?,
-                CRCType::CRC32(x) => seq.serialize_element(&x)?,
-            }
-        }
+        self.crc.serialize_value(&mut seq)?;
         seq.end()
     }
 }
@@ -206,4 +204,21 @@ impl PrimaryBlock {
         };
         self_cleaned == other_cleaned
     }
+
+    /// The source node had an accurate clock when creating the bundle. Otherwise the creation
+    /// time is 0 and the age of the bundle is tracked in a Bundle Age block.
+    pub fn has_clock(&self) -> bool {
+        self.creation_timestamp.creation_time.timestamp != 0
+    }
+
+    /// The time the bundle expires at, if the source node had a clock.
+    pub fn expiration_time(&self) -> Option<DtnTime> {
+        self.has_clock().then(|| DtnTime {
+            timestamp: self
+                .creation_timestamp
+                .creation_time
+                .timestamp
+                .saturating_add(self.lifetime),
+        })
+    }
 }
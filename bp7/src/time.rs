@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{
@@ -23,7 +23,7 @@ use serde::{
     de::{Error, Visitor},
     ser::SerializeSeq,
 };
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct CreationTimestamp {
     pub creation_time: DtnTime,
     pub sequence_number: u64,
@@ -76,7 +76,7 @@ impl<'de> Deserialize<'de> for CreationTimestamp {
 
 const DTN_UNIX_DIFFERENCE_MS: i64 = 946_684_800_000; // 946684800 seconds between 1970-01-01 and 2000-01-01
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct DtnTime {
     pub timestamp: u64,
@@ -92,6 +92,13 @@ impl Debug for DtnTime {
     }
 }
 
+impl Display for DtnTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let datetime: DateTime<Utc> = self.into();
+        Display::fmt(&datetime, f)
+    }
+}
+
 impl From<DtnTime> for DateTime<Utc> {
     fn from(dtn: DtnTime) -> Self {
         DateTime::from(&dtn)
@@ -125,6 +132,13 @@ impl DtnTime {
     pub fn now() -> Self {
         Utc::now().into()
     }
+
+    /// Converts seconds since the unix epoch, returning `None` for times before the DTN epoch.
+    pub fn from_unix_seconds(seconds: u64) -> Option<Self> {
+        let millis = seconds.checked_mul(1000)?;
+        let timestamp = millis.checked_sub(DTN_UNIX_DIFFERENCE_MS.unsigned_abs())?;
+        Some(DtnTime { timestamp })
+    }
 }
 
 #[cfg(test)]
@@ -164,6 +178,15 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn dtntime_from_unix_seconds() {
+        assert_eq!(
+            DtnTime::from_unix_seconds(946_684_801),
+            Some(DtnTime { timestamp: 1000 })
+        );
+        assert_eq!(DtnTime::from_unix_seconds(946_684_799), None);
+    }
+
     const DTNTIME_SERIALIZATION: &[u8] = &[0x1A, 0x07, 0x5B, 0xCD, 0x15];
 
     #[test]
@@ -16,9 +16,11 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::io::Write;
+use std::time::Duration;
 
+use bp7::time::DtnTime;
 use clap::{CommandFactory, Parser, Subcommand, ValueEnum, error::ErrorKind};
-use dtrd_client::Client;
+use dtrd_client::{Client, Contact, Priority};
 use futures_util::StreamExt;
 use tabular::{Table, row};
 use tokio::fs;
@@ -47,6 +49,23 @@ enum OutputMode {
     Raw,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum PriorityArg {
+    Bulk,
+    Normal,
+    Expedited,
+}
+
+impl From<PriorityArg> for Priority {
+    fn from(priority: PriorityArg) -> Self {
+        match priority {
+            PriorityArg::Bulk => Priority::Bulk,
+            PriorityArg::Normal => Priority::Normal,
+            PriorityArg::Expedited => Priority::Expedited,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Bundle {
@@ -61,6 +80,11 @@ enum Commands {
         #[clap(subcommand)]
         command: RouteCommands,
     },
+    Contact {
+        #[clap(subcommand)]
+        command: ContactCommands,
+    },
+    Statistics,
 }
 
 #[derive(Subcommand)]
@@ -85,10 +109,29 @@ enum BundleCommands {
         data_file: Option<String>,
         #[clap(long, help = "If bundle should be traced", required = false)]
         debug: bool,
+        #[clap(value_enum,
+            short,
+            long,
+            help = "How urgent the bundle is",
+            default_value_t = PriorityArg::Normal
+        )]
+        priority: PriorityArg,
+        #[clap(
+            long,
+            help = "Wait until the receiving application acknowledges the bundle",
+            required = false
+        )]
+        wait_for_ack: bool,
     },
     Listen {
         #[clap(short, long, help = "The endpoint to listen on")]
         endpoint: String,
+        #[clap(
+            long,
+            help = "Acknowledge the bundles that request it",
+            required = false
+        )]
+        acknowledge: bool,
         #[clap(value_enum,
             short,
             long,
@@ -103,6 +146,10 @@ enum BundleCommands {
         #[clap(short, long, help = "The file to write the bundle to")]
         file: Option<String>,
     },
+    History {
+        #[clap(short, long, help = "Only show the bundles from this node")]
+        source: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -122,10 +169,28 @@ enum NodeCommands {
 enum RouteCommands {
     List,
     Add {
-        #[clap(short, long, help = "The target the route should apply to")]
+        #[clap(
+            short,
+            long,
+            help = "The target the route should apply to: a node, a pattern like dtn://mars-* or default"
+        )]
         target: String,
         #[clap(short, long, help = "The next hop of the traffic")]
         nexthop: String,
+        #[clap(
+            short,
+            long,
+            default_value_t = 0,
+            help = "The cost of the route, lower metrics are preferred"
+        )]
+        metric: u64,
+        #[clap(
+            short,
+            long,
+            default_value_t = 1,
+            help = "The share of bundles among routes with the same metric"
+        )]
+        weight: u64,
     },
     Remove {
         #[clap(short, long, help = "The target the route should apply to")]
@@ -135,6 +200,70 @@ enum RouteCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum ContactCommands {
+    List,
+    Add {
+        #[clap(short, long, help = "The node sending during the contact")]
+        from: String,
+        #[clap(short, long, help = "The node receiving during the contact")]
+        to: String,
+        #[clap(
+            short,
+            long,
+            value_parser = parse_time,
+            help = "When the contact starts, in unix seconds or +<seconds> from now"
+        )]
+        start: DtnTime,
+        #[clap(
+            short,
+            long,
+            value_parser = parse_time,
+            help = "When the contact ends, in unix seconds or +<seconds> from now"
+        )]
+        end: DtnTime,
+        #[clap(short, long, help = "The transmission rate in bytes per second")]
+        rate: u64,
+        #[clap(
+            short,
+            long,
+            default_value_t = 0,
+            help = "The one way light time in milliseconds"
+        )]
+        owlt: u64,
+        #[clap(
+            short,
+            long,
+            help = "Where to connect to the receiving node if we are the sending one"
+        )]
+        url: Option<String>,
+    },
+    Remove {
+        #[clap(short, long, help = "The node sending during the contact")]
+        from: String,
+        #[clap(short, long, help = "The node receiving during the contact")]
+        to: String,
+        #[clap(
+            short,
+            long,
+            value_parser = parse_time,
+            help = "When the contact starts, in unix seconds or +<seconds> from now"
+        )]
+        start: DtnTime,
+    },
+}
+
+fn parse_time(time: &str) -> Result<DtnTime, String> {
+    if let Some(offset) = time.strip_prefix('+') {
+        let offset: u64 = offset.parse().map_err(|e| format!("{e}"))?;
+        return Ok(DtnTime {
+            timestamp: DtnTime::now().timestamp + offset * 1000,
+        });
+    }
+    let seconds = time.parse().map_err(|e| format!("{e}"))?;
+    DtnTime::from_unix_seconds(seconds).ok_or_else(|| "time must be after 2000".into())
+}
+
 #[tokio::main]
 pub async fn main() {
     let cli = Cli::parse();
@@ -159,17 +288,32 @@ pub async fn main() {
                 data,
                 data_file,
                 debug,
+                priority,
+                wait_for_ack,
             } => {
-                command_bundle_submit(&mut client, destination, lifetime, data, data_file, debug)
-                    .await;
+                command_bundle_submit(
+                    &mut client,
+                    destination,
+                    lifetime,
+                    data,
+                    data_file,
+                    debug,
+                    priority,
+                    wait_for_ack,
+                )
+                .await;
             }
             BundleCommands::Listen {
                 endpoint,
+                acknowledge,
                 output_mode,
-            } => command_bundle_listen(&mut client, endpoint, output_mode).await,
+            } => command_bundle_listen(&mut client, endpoint, acknowledge, output_mode).await,
             BundleCommands::Receive { endpoint, file } => {
                 command_bundle_receive(&mut client, endpoint, file).await;
             }
+            BundleCommands::History { source } => {
+                command_bundle_history(&mut client, source).await;
+            }
         },
         Commands::Node { command } => match command {
             NodeCommands::List => command_node_list(&mut client).await,
@@ -178,16 +322,49 @@ pub async fn main() {
         },
         Commands::Route { command } => match command {
             RouteCommands::List => command_route_list(&mut client).await,
-            RouteCommands::Add { target, nexthop } => {
-                command_route_add(&mut client, target, nexthop).await;
+            RouteCommands::Add {
+                target,
+                nexthop,
+                metric,
+                weight,
+            } => {
+                command_route_add(&mut client, target, nexthop, metric, weight).await;
             }
             RouteCommands::Remove { target, nexthop } => {
                 command_route_remove(&mut client, target, nexthop).await;
             }
         },
+        Commands::Contact { command } => match command {
+            ContactCommands::List => command_contact_list(&mut client).await,
+            ContactCommands::Add {
+                from,
+                to,
+                start,
+                end,
+                rate,
+                owlt,
+                url,
+            } => {
+                let contact = Contact {
+                    from,
+                    to,
+                    start_time: start.timestamp,
+                    end_time: end.timestamp,
+                    rate_bytes_per_second: rate,
+                    owlt_millis: owlt,
+                    url: url.unwrap_or_default(),
+                };
+                command_contact_add(&mut client, contact).await;
+            }
+            ContactCommands::Remove { from, to, start } => {
+                command_contact_remove(&mut client, from, to, start).await;
+            }
+        },
+        Commands::Statistics => command_statistics(&mut client).await,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn command_bundle_submit(
     client: &mut Client,
     destination: String,
@@ -195,6 +372,8 @@ async fn command_bundle_submit(
     data: Option<String>,
     data_file: Option<String>,
     debug: bool,
+    priority: PriorityArg,
+    wait_for_ack: bool,
 ) {
     if data.is_none() == data_file.is_none() {
         let mut cmd = Cli::command();
@@ -219,47 +398,77 @@ async fn command_bundle_submit(
             })
             .unwrap()
     };
-    match client
-        .submit_bundle(&destination, lifetime, &payload, debug)
+    if !wait_for_ack {
+        match client
+            .submit_bundle_with_priority(&destination, lifetime, &payload, debug, priority.into())
+            .await
+        {
+            Ok(()) => {
+                println!("Bundle submitted successfully");
+            }
+            Err(e) => {
+                println!("Error submitting bundle: {e:?}");
+            }
+        }
+        return;
+    }
+    let bundle_id = match client
+        .submit_bundle_with_acknowledgement(
+            &destination,
+            lifetime,
+            &payload,
+            debug,
+            priority.into(),
+        )
         .await
     {
-        Ok(()) => {
-            println!("Bundle submitted successfully");
+        Ok(bundle_id) => {
+            println!("Bundle submitted successfully, waiting for the acknowledgement");
+            bundle_id
         }
         Err(e) => {
             println!("Error submitting bundle: {e:?}");
+            return;
+        }
+    };
+    match client.await_acknowledgement(bundle_id).await {
+        Ok(acknowledgement) => {
+            println!("Bundle acknowledged by {}", acknowledgement.acknowledged_by);
+        }
+        Err(e) => {
+            println!("Error waiting for the acknowledgement: {e:?}");
         }
     }
 }
 
-async fn command_bundle_listen(client: &mut Client, endpoint: String, output_mode: OutputMode) {
-    match client.listen_bundles(&endpoint).await {
+async fn command_bundle_listen(
+    client: &mut Client,
+    endpoint: String,
+    acknowledge: bool,
+    output_mode: OutputMode,
+) {
+    match client.listen_received_bundles(&endpoint).await {
         Ok(mut stream) => {
             println!("Now listening for bundles. Press CTRL+C to abort");
-            while let Some(data) = stream.next().await {
-                match data {
-                    Ok(data) => match output_mode {
-                        OutputMode::Parse => {
-                            match bp7::administrative_record::AdministrativeRecord::try_from(&data)
-                            {
-                                Ok(ar) => {
-                                    println!("Successfully parsed administrative record: {ar:?}");
-                                }
-                                Err(_) => {
-                                    println!(
-                                        "Is no administrative record. This is the output as string.\n<<<BEGIN\n{}\n<<<END",
-                                        String::from_utf8_lossy(&data)
-                                    );
-                                }
-                            }
-                        }
-                        OutputMode::Hex => println!("Received bundle: {data:?}"),
-                        OutputMode::Raw => {
-                            let mut stdout = std::io::stdout();
-                            stdout.write_all(&data).unwrap();
-                            stdout.flush().unwrap();
+            while let Some(bundle) = stream.next().await {
+                let bundle = bundle.map(|bundle| {
+                    (
+                        bundle.payload,
+                        bundle
+                            .bundle_id
+                            .filter(|_| bundle.acknowledgement_requested),
+                    )
+                });
+                match bundle {
+                    Ok((data, acknowledgement_requested)) => {
+                        command_bundle_listen_output(&data, output_mode);
+                        if acknowledge
+                            && let Some(bundle_id) = acknowledgement_requested
+                            && let Err(e) = client.acknowledge_bundle(&endpoint, bundle_id).await
+                        {
+                            println!("Error acknowledging bundle: {e:?}");
                         }
-                    },
+                    }
                     Err(e) => {
                         println!("Error receiving bundle: {e:?}");
                         break;
@@ -274,6 +483,30 @@ async fn command_bundle_listen(client: &mut Client, endpoint: String, output_mod
     }
 }
 
+fn command_bundle_listen_output(data: &Vec<u8>, output_mode: OutputMode) {
+    match output_mode {
+        OutputMode::Parse => {
+            match bp7::administrative_record::AdministrativeRecord::try_from(data) {
+                Ok(ar) => {
+                    println!("Successfully parsed administrative record: {ar:?}");
+                }
+                Err(_) => {
+                    println!(
+                        "Is no administrative record. This is the output as string.\n<<<BEGIN\n{}\n<<<END",
+                        String::from_utf8_lossy(data)
+                    );
+                }
+            }
+        }
+        OutputMode::Hex => println!("Received bundle: {data:?}"),
+        OutputMode::Raw => {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(data).unwrap();
+            stdout.flush().unwrap();
+        }
+    }
+}
+
 async fn command_bundle_receive(client: &mut Client, endpoint: String, file: Option<String>) {
     match client.receive_bundle(&endpoint).await {
         Ok(data) => {
@@ -291,17 +524,76 @@ async fn command_bundle_receive(client: &mut Client, endpoint: String, file: Opt
     }
 }
 
+async fn command_bundle_history(client: &mut Client, source: Option<String>) {
+    match client.list_bundle_history(source.unwrap_or_default()).await {
+        Ok(data) => {
+            let mut table = Table::new("{:<}  {:<}  {:<}  {:<}  {:<}  {:<}");
+            table.add_row(row!(
+                "Bundle",
+                "Status",
+                "Time",
+                "Reported by",
+                "Reason",
+                "Fragment"
+            ));
+            for bundle in data {
+                let id = format!(
+                    "{}:{}:{}",
+                    bundle.source, bundle.creation_time, bundle.sequence_number
+                );
+                for event in &bundle.events {
+                    let time = bp7::time::DtnTime {
+                        timestamp: event.time,
+                    };
+                    let fragment = event.fragment.as_ref().map_or(String::new(), |fragment| {
+                        format!("{}+{}", fragment.offset, fragment.length)
+                    });
+                    table.add_row(row!(
+                        &id,
+                        event.status().as_str_name(),
+                        time,
+                        &event.reporting_node,
+                        &event.reason,
+                        fragment
+                    ));
+                }
+            }
+            print!("{table}");
+        }
+        Err(e) => {
+            println!("Error receiving bundle history: {e:?}");
+        }
+    }
+}
+
 async fn command_node_list(client: &mut Client) {
     match client.list_nodes().await {
         Ok(data) => {
-            let mut table = Table::new("{:<}  {:<}  {:<}  {:<}");
-            table.add_row(row!("URL", "Status", "Endpoint", "Temporary"));
+            let mut table = Table::new("{:<}  {:<}  {:<}  {:<}  {:<}  {:<}");
+            table.add_row(row!(
+                "URL",
+                "Status",
+                "Endpoint",
+                "Temporary",
+                "Uptime",
+                "RTT"
+            ));
             for node in data {
+                let (uptime, rtt) = match &node.link_stats {
+                    Some(s) if s.rtt_micros > 0 => (
+                        format!("{}s", s.uptime_seconds),
+                        format!("{:?}", Duration::from_micros(s.rtt_micros)),
+                    ),
+                    Some(s) => (format!("{}s", s.uptime_seconds), String::new()),
+                    None => (String::new(), String::new()),
+                };
                 table.add_row(row!(
                     node.url,
                     node.status,
                     node.endpoint,
-                    if node.temporary { "temporary" } else { "" }
+                    if node.temporary { "temporary" } else { "" },
+                    uptime,
+                    rtt
                 ));
             }
             print!("{table}");
@@ -333,14 +625,17 @@ async fn command_node_remove(client: &mut Client, url: String) {
 async fn command_route_list(client: &mut Client) {
     match client.list_routes().await {
         Ok(data) => {
-            let mut table = Table::new("{:<}  {:<}  {:<}  {:<}  {:<}  {:<}");
+            let mut table = Table::new("{:<}  {:<}  {:<}  {:<}  {:<}  {:<}  {:<}  {:<}  {:<}");
             table.add_row(row!(
                 "Target",
                 "Nexthop",
                 "Status",
                 "Prefrered",
                 "Available",
-                "Bundle size limit"
+                "Bundle size limit",
+                "Metric",
+                "Weight",
+                "Reason"
             ));
             for route in data {
                 table.add_row(row!(
@@ -349,7 +644,10 @@ async fn command_route_list(client: &mut Client) {
                     route.r#type().as_str_name(),
                     route.preferred,
                     route.available,
-                    route.max_bundle_size
+                    route.max_bundle_size,
+                    route.route.as_ref().unwrap().metric,
+                    route.route.as_ref().unwrap().weight,
+                    &route.reason
                 ));
             }
             print!("{table}");
@@ -360,8 +658,17 @@ async fn command_route_list(client: &mut Client) {
     }
 }
 
-async fn command_route_add(client: &mut Client, target: String, nexthop: String) {
-    match client.add_route(target, nexthop).await {
+async fn command_route_add(
+    client: &mut Client,
+    target: String,
+    nexthop: String,
+    metric: u64,
+    weight: u64,
+) {
+    match client
+        .add_route_with_metric(target, nexthop, metric, weight)
+        .await
+    {
         Ok(()) => {}
         Err(e) => {
             println!("Error adding route: {e:?}");
@@ -377,3 +684,76 @@ async fn command_route_remove(client: &mut Client, target: String, nexthop: Stri
         }
     }
 }
+
+async fn command_contact_list(client: &mut Client) {
+    match client.list_contacts().await {
+        Ok(data) => {
+            let mut table = Table::new("{:<}  {:<}  {:<}  {:<}  {:>}  {:>}  {:>}  {:<}");
+            table.add_row(row!(
+                "From", "To", "Start", "End", "Rate", "OWLT", "Booked", "URL"
+            ));
+            for status in data {
+                let contact = status.contact.unwrap_or_default();
+                table.add_row(row!(
+                    &contact.from,
+                    &contact.to,
+                    DtnTime {
+                        timestamp: contact.start_time
+                    },
+                    DtnTime {
+                        timestamp: contact.end_time
+                    },
+                    format!("{}B/s", contact.rate_bytes_per_second),
+                    format!("{}ms", contact.owlt_millis),
+                    format!("{}B", status.booked_bytes),
+                    &contact.url
+                ));
+            }
+            print!("{table}");
+        }
+        Err(e) => {
+            println!("Error receiving contact list: {e:?}");
+        }
+    }
+}
+
+async fn command_contact_add(client: &mut Client, contact: Contact) {
+    match client.add_contact(contact).await {
+        Ok(()) => {}
+        Err(e) => {
+            println!("Error adding contact: {e:?}");
+        }
+    }
+}
+
+async fn command_contact_remove(client: &mut Client, from: String, to: String, start: DtnTime) {
+    match client.remove_contact(from, to, start.timestamp).await {
+        Ok(()) => {}
+        Err(e) => {
+            println!("Error removing contact: {e:?}");
+        }
+    }
+}
+
+async fn command_statistics(client: &mut Client) {
+    match client.get_statistics().await {
+        Ok(statistics) => {
+            println!("Duplicate bundles: {}", statistics.duplicate_bundles);
+            let mut queued_bundles: Vec<_> = statistics.queued_bundles.into_iter().collect();
+            queued_bundles.sort();
+            for (class, queued) in queued_bundles {
+                println!("Queued {class} bundles: {queued}");
+            }
+            println!("Bundles in custody: {}", statistics.bundles_in_custody);
+            println!(
+                "Custody accepted: {}, refused: {}, retransmissions: {}",
+                statistics.custody_accepted,
+                statistics.custody_refused,
+                statistics.custody_retransmissions
+            );
+        }
+        Err(e) => {
+            println!("Error receiving statistics: {e:?}");
+        }
+    }
+}
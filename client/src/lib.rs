@@ -19,11 +19,20 @@ use std::str::FromStr;
 use std::time::Duration;
 
 use crate::error::Error;
+pub use adminservice::BundleHistory;
+pub use adminservice::Contact;
+use adminservice::ContactStatus;
 use adminservice::Node;
 use adminservice::Route;
 use adminservice::RouteStatus;
+use adminservice::Statistics;
 use adminservice::admin_service_client::AdminServiceClient;
+pub use adminservice::bundle_status_event::Status as BundleStatus;
+pub use bundleservice::AwaitAcknowledgementResponse as Acknowledgement;
+pub use bundleservice::BundleId;
+pub use bundleservice::ListenBundleResponse as ReceivedBundle;
 use bundleservice::bundle_service_client::BundleServiceClient;
+pub use bundleservice::submit_bundle_request::Priority;
 use futures_util::Stream;
 use futures_util::StreamExt;
 use maybe_async::maybe_async;
@@ -79,17 +88,88 @@ impl Client {
         lifetime: u64,
         data: &[u8],
         debug: bool,
+    ) -> Result<(), Error> {
+        self.submit_bundle_with_priority(target, lifetime, data, debug, Priority::Normal)
+            .await
+    }
+
+    #[maybe_async]
+    pub async fn submit_bundle_with_priority(
+        &mut self,
+        target: &str,
+        lifetime: u64,
+        data: &[u8],
+        debug: bool,
+        priority: Priority,
     ) -> Result<(), Error> {
         let req = bundleservice::SubmitBundleRequest {
             destination: target.to_string(),
             lifetime,
             payload: data.to_vec(),
             debug,
+            priority: priority.into(),
+            request_acknowledgement: false,
         };
         self.bundle_client.submit_bundle(req).await?;
         Ok(())
     }
 
+    /// Submits a bundle the receiving application should acknowledge. The returned id can be
+    /// passed to [`Client::await_acknowledgement`].
+    #[maybe_async]
+    pub async fn submit_bundle_with_acknowledgement(
+        &mut self,
+        target: &str,
+        lifetime: u64,
+        data: &[u8],
+        debug: bool,
+        priority: Priority,
+    ) -> Result<BundleId, Error> {
+        let req = bundleservice::SubmitBundleRequest {
+            destination: target.to_string(),
+            lifetime,
+            payload: data.to_vec(),
+            debug,
+            priority: priority.into(),
+            request_acknowledgement: true,
+        };
+        let resp = self.bundle_client.submit_bundle(req).await?.into_inner();
+        Ok(resp.bundle_id.unwrap_or_default())
+    }
+
+    /// Waits until the bundle is acknowledged by the application it was delivered to. Fails if
+    /// the bundle expires first, or after the request timeout of 30 seconds.
+    #[maybe_async]
+    pub async fn await_acknowledgement(
+        &mut self,
+        bundle_id: BundleId,
+    ) -> Result<Acknowledgement, Error> {
+        let req = bundleservice::AwaitAcknowledgementRequest {
+            bundle_id: Some(bundle_id),
+        };
+        let resp = self
+            .bundle_client
+            .await_acknowledgement(req)
+            .await?
+            .into_inner();
+        Ok(resp)
+    }
+
+    /// Acknowledges a bundle received on `endpoint` that requested an acknowledgement.
+    #[maybe_async]
+    pub async fn acknowledge_bundle(
+        &mut self,
+        endpoint: &str,
+        bundle_id: BundleId,
+    ) -> Result<(), Error> {
+        let req = bundleservice::AcknowledgeBundleRequest {
+            endpoint: endpoint.to_string(),
+            bundle_id: Some(bundle_id),
+        };
+        self.bundle_client.acknowledge_bundle(req).await?;
+        Ok(())
+    }
+
     #[maybe_async]
     pub async fn listen_bundles(
         &mut self,
@@ -110,6 +190,25 @@ impl Client {
         Ok(stream)
     }
 
+    /// Like [`Client::listen_bundles`], but with the source and id of each bundle and whether
+    /// it should be acknowledged.
+    #[maybe_async]
+    pub async fn listen_received_bundles(
+        &mut self,
+        endpoint: &str,
+    ) -> Result<impl Stream<Item = Result<ReceivedBundle, Error>> + use<>, Error> {
+        let req = bundleservice::ListenBundleRequest {
+            endpoint: endpoint.to_string(),
+        };
+        let stream = self
+            .bundle_client
+            .listen_bundles(req)
+            .await?
+            .into_inner()
+            .map(|r| r.map_err(Error::GrpcError));
+        Ok(stream)
+    }
+
     #[maybe_async]
     pub async fn receive_bundle(&mut self, endpoint: &str) -> Result<Vec<u8>, Error> {
         let req = bundleservice::ListenBundleRequest {
@@ -158,8 +257,24 @@ impl Client {
 
     #[maybe_async]
     pub async fn add_route(&mut self, target: String, next_hop: String) -> Result<(), Error> {
+        self.add_route_with_metric(target, next_hop, 0, 1).await
+    }
+
+    #[maybe_async]
+    pub async fn add_route_with_metric(
+        &mut self,
+        target: String,
+        next_hop: String,
+        metric: u64,
+        weight: u64,
+    ) -> Result<(), Error> {
         let req = adminservice::AddRouteRequest {
-            route: Some(Route { target, next_hop }),
+            route: Some(Route {
+                target,
+                next_hop,
+                metric,
+                weight,
+            }),
         };
         self.admin_client.add_route(req).await?.into_inner();
         Ok(())
@@ -168,9 +283,70 @@ impl Client {
     #[maybe_async]
     pub async fn remove_route(&mut self, target: String, next_hop: String) -> Result<(), Error> {
         let req = adminservice::RemoveRouteRequest {
-            route: Some(Route { target, next_hop }),
+            route: Some(Route {
+                target,
+                next_hop,
+                ..Default::default()
+            }),
         };
         self.admin_client.remove_route(req).await?.into_inner();
         Ok(())
     }
+
+    #[maybe_async]
+    pub async fn list_contacts(&mut self) -> Result<Vec<ContactStatus>, Error> {
+        let req = adminservice::ListContactsRequest {};
+        let resp = self.admin_client.list_contacts(req).await?.into_inner();
+        Ok(resp.contacts)
+    }
+
+    #[maybe_async]
+    pub async fn add_contact(&mut self, contact: Contact) -> Result<(), Error> {
+        let req = adminservice::AddContactRequest {
+            contact: Some(contact),
+        };
+        self.admin_client.add_contact(req).await?.into_inner();
+        Ok(())
+    }
+
+    /// Removes the contact from `from` to `to` starting at `start_time` in milliseconds since
+    /// 2000-01-01.
+    #[maybe_async]
+    pub async fn remove_contact(
+        &mut self,
+        from: String,
+        to: String,
+        start_time: u64,
+    ) -> Result<(), Error> {
+        let req = adminservice::RemoveContactRequest {
+            from,
+            to,
+            start_time,
+        };
+        self.admin_client.remove_contact(req).await?.into_inner();
+        Ok(())
+    }
+
+    #[maybe_async]
+    pub async fn get_statistics(&mut self) -> Result<Statistics, Error> {
+        let req = adminservice::GetStatisticsRequest {};
+        let resp = self.admin_client.get_statistics(req).await?.into_inner();
+        Ok(resp.statistics.unwrap_or_default())
+    }
+
+    /// Lists what the received status reports say about the bundles from `source`, or about
+    /// all bundles if it is empty.
+    #[maybe_async]
+    pub async fn list_bundle_history(
+        &mut self,
+        source: String,
+    ) -> Result<Vec<BundleHistory>, Error> {
+        let req = adminservice::ListBundleHistoryRequest { source };
+        let resp = self
+            .admin_client
+            .list_bundle_history(req)
+            .await?
+            .into_inner();
+        Ok(resp.bundles)
+    }
 }
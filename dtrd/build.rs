@@ -24,6 +24,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .filter_map(|p| p.map(|path| path.path()).ok())
         .collect();
     println!("{proto_files:?}");
+    println!("cargo:rerun-if-changed={}", proto_path.display());
     tonic_prost_build::configure()
         .build_client(false)
         .compile_protos(&proto_files, &[proto_path])?;
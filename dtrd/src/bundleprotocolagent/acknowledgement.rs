@@ -0,0 +1,93 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use bp7::{
+    bundleflags::BundleFlags,
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
+};
+
+use crate::bundlestorageagent::StoredBundleRef;
+
+#[derive(Debug)]
+struct Requested {
+    destination: Endpoint,
+    report_to: Endpoint,
+    expires_at: DtnTime,
+}
+
+/// The delivered bundles that asked for an application acknowledgement, by their source and
+/// creation timestamp.
+///
+/// They are only kept in memory, so applications can not acknowledge bundles delivered before a
+/// restart.
+#[derive(Debug, Default)]
+pub struct AcknowledgementRequests {
+    requests: HashMap<(Endpoint, CreationTimestamp), Requested>,
+}
+
+impl AcknowledgementRequests {
+    /// Remembers the bundle if it asked for an application acknowledgement.
+    pub fn insert(&mut self, bundle: &StoredBundleRef) {
+        let pb = bundle.get_primary_block();
+        if !pb
+            .bundle_processing_flags
+            .contains(BundleFlags::APPLICATION_ACKNOWLEGEMENT_REQUESTED)
+            || pb
+                .bundle_processing_flags
+                .contains(BundleFlags::ADMINISTRATIVE_RECORD)
+        {
+            return;
+        }
+        let now = DtnTime::now();
+        self.requests.retain(|_, request| request.expires_at > now);
+        self.requests.insert(
+            (pb.source_node.clone(), pb.creation_timestamp.clone()),
+            Requested {
+                destination: pb.destination_endpoint.clone(),
+                report_to: pb.report_to.clone(),
+                expires_at: bundle.get_expires_at(),
+            },
+        );
+    }
+
+    /// Returns where to send the acknowledgement of the bundle delivered to `endpoint`, and the
+    /// lifetime it has left.
+    pub fn take(
+        &mut self,
+        endpoint: &Endpoint,
+        source: &Endpoint,
+        creation_timestamp: &CreationTimestamp,
+    ) -> Result<(Endpoint, u64), String> {
+        let key = (source.clone(), creation_timestamp.clone());
+        let now = DtnTime::now();
+        match self.requests.get(&key) {
+            Some(request) if request.destination == *endpoint && request.expires_at > now => {
+                let request = self.requests.remove(&key).unwrap();
+                Ok((
+                    request.report_to,
+                    request.expires_at.timestamp - now.timestamp,
+                ))
+            }
+            _ => Err(format!(
+                "No bundle from {source} delivered to {endpoint} waits for an acknowledgement"
+            )),
+        }
+    }
+}
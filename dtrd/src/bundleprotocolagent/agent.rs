@@ -15,33 +15,57 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    mem,
-};
+use std::collections::HashMap;
 
+use super::{
+    acknowledgement::AcknowledgementRequests,
+    custody::{Custody, custodian_of, is_permanent_refusal},
+    messages::{
+        AcknowledgeBundle, GetBundleHistory, GetCustodyStatistics, GetQueueStatistics,
+        QueueStatistics,
+    },
+    priority::PriorityPolicy,
+    replication::{Replication, ReplicationMode},
+    scheduler::{BundleQueue, CLASSES, Queued, Scheduler},
+    spreading::Spreading,
+    status_history::StatusHistory,
+};
 use crate::{
     bundlestorageagent::{
         State, StoredBundleRef,
-        messages::{EventBundleUpdated, FragmentBundle, StoreNewBundle, UpdateBundle},
+        messages::{
+            EventBundleEvicted, EventBundleExpired, EventBundleFragmentationFailed,
+            EventBundleUpdated, EventDuplicateBundleReceived, FragmentBundle,
+            FragmentBundleRemainder, RecordFailedAttempt, StoreNewBundle, UpdateBundle,
+        },
     },
     clientagent::messages::{
-        ClientDeliverBundle, EventBundleDelivered, EventBundleDeliveryFailed, EventClientConnected,
-        EventClientDisconnected,
+        ClientDeliverBundle, EventBundleAcknowledged, EventBundleDelivered,
+        EventBundleDeliveryFailed, EventClientConnected, EventClientDisconnected,
     },
     common::settings::Settings,
     converganceagent::messages::{
-        AgentForwardBundle, EventBundleForwarded, EventBundleForwardingFailed, EventPeerConnected,
-        EventPeerDisconnected,
+        AgentForwardBundle, EventBundleForwarded, EventBundleForwardingFailed,
+        EventBundleForwardingInterrupted, EventPeerConnected, EventPeerDisconnected,
+    },
+    routingagent::{
+        messages::{
+            EventContactPlanChanged, EventRoutingTableUpdate, FindContactRoute,
+            LearnPredictabilities, NexthopInfo, RecordEncounter, ReleaseContactBooking,
+        },
+        route_target::{self, RouteTarget},
     },
-    routingagent::messages::{EventRoutingTableUpdate, NexthopInfo},
 };
 use bp7::{
     administrative_record::{
         AdministrativeRecord,
+        application_acknowledgement::ApplicationAcknowledgement,
         bundle_status_report::{
             BundleStatusInformation, BundleStatusItem, BundleStatusReason, BundleStatusReport,
         },
+        custody_signal::CustodySignal,
+        delivery_predictabilities::{DeliveryPredictabilities, DeliveryPredictability},
+        summary_vector::SummaryVector,
     },
     block::{Block, CanonicalBlock, payload_block::PayloadBlock},
     blockflags::BlockFlags,
@@ -57,17 +81,28 @@ use log::{debug, warn};
 use actix::prelude::*;
 
 const HOP_LIMIT_DEFAULT: u8 = 16;
+/// Summary vectors are only meant for the peer we are connected to.
+const SUMMARY_VECTOR_LIFETIME: u64 = 60_000;
 
 #[derive(Default)]
 pub struct Daemon {
     endpoint: Option<Endpoint>,
-    bundles_pending_local_delivery: HashMap<Endpoint, Vec<StoredBundleRef>>,
-    bundles_pending_forwarding: HashMap<Endpoint, Vec<StoredBundleRef>>,
-    local_bundles: HashMap<Endpoint, VecDeque<StoredBundleRef>>,
-    remote_bundles: HashMap<Endpoint, VecDeque<StoredBundleRef>>,
+    bundles_pending_local_delivery: HashMap<Endpoint, Vec<Queued>>,
+    bundles_pending_forwarding: HashMap<Endpoint, Vec<Queued>>,
+    local_bundles: HashMap<Endpoint, BundleQueue>,
+    remote_bundles: HashMap<Endpoint, BundleQueue>,
     local_connections: HashMap<Endpoint, Recipient<ClientDeliverBundle>>,
     remote_connections: HashMap<Endpoint, Recipient<AgentForwardBundle>>,
-    remote_routes: HashMap<Endpoint, NexthopInfo>,
+    remote_routes: HashMap<RouteTarget, Vec<NexthopInfo>>,
+    spreading: Spreading,
+    policy: PriorityPolicy,
+    scheduler: Scheduler,
+    custody: Custody,
+    acknowledgement_requests: AcknowledgementRequests,
+    status_history: StatusHistory,
+    replication: Replication,
+    /// End of the contact the contact plan routed a bundle over, by bundle identity.
+    contact_routes: HashMap<String, DtnTime>,
 }
 
 impl Actor for Daemon {
@@ -75,6 +110,11 @@ impl Actor for Daemon {
     fn started(&mut self, _ctx: &mut Context<Self>) {
         let settings = Settings::from_env();
         self.endpoint = Some(Endpoint::new(&settings.my_node_id).unwrap());
+        self.policy = PriorityPolicy::from_settings(&settings);
+        self.scheduler = Scheduler::from_settings(&settings);
+        self.custody = Custody::from_settings(&settings);
+        self.status_history = StatusHistory::from_settings(&settings);
+        self.replication = Replication::from_settings(&settings);
     }
 }
 impl actix::Supervised for Daemon {}
@@ -104,23 +144,64 @@ impl Handler<EventBundleUpdated> for Daemon {
                 });
             }
             State::Valid => {
-                let (new_state, new_data) =
-                    if self.endpoint.as_ref().unwrap().matches_node(&destination) {
-                        if bundle.get_primary_block().fragment_offset.is_some() {
-                            (State::DefragmentationPending, None)
-                        } else {
-                            (State::DeliveryQueued, None)
-                        }
+                if bundle.get_bundle_data().is_none() {
+                    // evicted from the storage in the meantime
+                    return;
+                }
+                let local = self.endpoint.as_ref().unwrap().matches_node(&destination);
+                if local
+                    && let Some(record) = administrative_record_of(&bundle)
+                    && self.handle_administrative_record(
+                        record,
+                        &bundle.get_primary_block().source_node,
+                        ctx,
+                    )
+                {
+                    crate::bundlestorageagent::agent::Daemon::from_registry().do_send(
+                        UpdateBundle {
+                            bundleref: bundle,
+                            new_state: State::Delivered,
+                            new_data: None,
+                        },
+                    );
+                    return;
+                }
+                let previous_custodian = self.previous_custodian(&bundle, local);
+                if bundle.is_expired() {
+                    if let Some(custodian) = &previous_custodian {
+                        self.send_custody_signal(
+                            &bundle,
+                            custodian,
+                            Some(BundleStatusReason::LifetimeExpired),
+                        );
+                    }
+                    self.delete_expired_bundle(bundle);
+                    return;
+                }
+                let (new_state, new_data) = if local {
+                    if bundle.get_primary_block().fragment_offset.is_some() {
+                        (State::DefragmentationPending, None)
                     } else {
-                        match self.forward_bundle(&bundle) {
-                            Ok(new_data) => (State::ForwardingQueued, Some(new_data)),
-                            Err(e) => {
-                                warn!("forwarding bundle failed: {e:?}");
-                                self.send_status_report_deleted(&bundle, e);
-                                (State::Invalid, None)
+                        (State::DeliveryQueued, None)
+                    }
+                } else {
+                    match self.forward_bundle(&bundle) {
+                        Ok(new_data) => (State::ForwardingQueued, Some(new_data)),
+                        Err(e) => {
+                            warn!("forwarding bundle failed: {e:?}");
+                            if let Some(custodian) = &previous_custodian {
+                                self.send_custody_signal(&bundle, custodian, Some(e));
                             }
+                            self.send_status_report_deleted(&bundle, e);
+                            (State::Invalid, None)
                         }
-                    };
+                    }
+                };
+                if !matches!(new_state, State::Invalid)
+                    && let Some(custodian) = &previous_custodian
+                {
+                    self.send_custody_signal(&bundle, custodian, None);
+                }
                 crate::bundlestorageagent::agent::Daemon::from_registry().do_send(UpdateBundle {
                     bundleref: bundle,
                     new_state,
@@ -128,18 +209,17 @@ impl Handler<EventBundleUpdated> for Daemon {
                 });
             }
             State::DeliveryQueued => {
+                let priority = self.policy.priority_of(&bundle);
                 self.local_bundles
                     .entry(destination.clone())
                     .or_default()
-                    .push_back(bundle);
+                    .push(bundle, priority);
                 self.deliver_local_bundles(&destination, ctx);
             }
-            State::ForwardingQueued => {
-                self.remote_bundles
-                    .entry(destination.get_node_endpoint())
-                    .or_default()
-                    .push_back(bundle);
-                self.deliver_remote_bundles(&destination, ctx);
+            State::ForwardingQueued => self.queue_remote_bundle(bundle, ctx),
+            State::CustodyPending => {
+                // loaded after a restart, we still wait for the next custodian
+                self.hold_custody(bundle, ctx);
             }
             State::DefragmentationPending
             | State::Delivered
@@ -152,15 +232,66 @@ impl Handler<EventBundleUpdated> for Daemon {
     }
 }
 
+impl Handler<EventBundleExpired> for Daemon {
+    type Result = ();
+
+    fn handle(&mut self, msg: EventBundleExpired, _ctx: &mut Self::Context) -> Self::Result {
+        let EventBundleExpired { bundle } = msg;
+        self.forget_bundle(&bundle);
+        self.delete_expired_bundle(bundle);
+    }
+}
+
+impl Handler<EventBundleEvicted> for Daemon {
+    type Result = ();
+
+    fn handle(&mut self, msg: EventBundleEvicted, _ctx: &mut Self::Context) -> Self::Result {
+        let EventBundleEvicted { bundle, custodian } = msg;
+        debug!(
+            "Bundle {} was deleted as the storage is full",
+            bundle.get_id()
+        );
+        self.forget_bundle(&bundle);
+        if let Some(custodian) = custodian
+            && self.takes_custody_from(&bundle, &custodian)
+        {
+            self.send_custody_signal(
+                &bundle,
+                &custodian,
+                Some(BundleStatusReason::DepletedStorage),
+            );
+        }
+        self.send_status_report_deleted(&bundle, BundleStatusReason::DepletedStorage);
+    }
+}
+
+impl Handler<EventBundleFragmentationFailed> for Daemon {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: EventBundleFragmentationFailed,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let EventBundleFragmentationFailed { bundle } = msg;
+        warn!(
+            "Fragmenting bundle {} failed. Requeueing it whole",
+            bundle.get_id()
+        );
+        self.queue_remote_bundle(bundle, ctx);
+    }
+}
+
 impl Handler<EventBundleDelivered> for Daemon {
     type Result = ();
 
     fn handle(&mut self, msg: EventBundleDelivered, ctx: &mut Self::Context) -> Self::Result {
         let EventBundleDelivered { endpoint, bundle } = msg;
         if let Some(pending) = self.bundles_pending_local_delivery.get_mut(&endpoint) {
-            pending.retain(|e| e != bundle);
+            pending.retain(|q| q.bundle != bundle);
         }
         self.send_status_report_delivered(&bundle);
+        self.acknowledgement_requests.insert(&bundle);
 
         crate::bundlestorageagent::agent::Daemon::from_registry().do_send(UpdateBundle {
             bundleref: bundle,
@@ -181,13 +312,15 @@ impl Handler<EventBundleDeliveryFailed> for Daemon {
             "Delivering local bundle to endpoint {} failed. Requeueing",
             &endpoint
         );
-        if let Some(pending) = self.bundles_pending_local_delivery.get_mut(&endpoint) {
-            pending.retain(|e| e != bundle);
-        }
+        let queued = take_pending(&mut self.bundles_pending_local_delivery, &endpoint, &bundle);
+        crate::bundlestorageagent::agent::Daemon::from_registry().do_send(RecordFailedAttempt {
+            bundleref: bundle.clone(),
+        });
+        let priority = self.policy.priority_of(&bundle);
         self.local_bundles
             .entry(endpoint)
             .or_default()
-            .push_front(bundle);
+            .requeue(queued.unwrap_or_else(|| Queued::unqueued(bundle)), priority);
     }
 }
 
@@ -226,6 +359,12 @@ impl Handler<EventPeerConnected> for Daemon {
         assert!(destination.get_node_endpoint() == destination);
 
         self.remote_connections.insert(destination.clone(), sender);
+        if self.replication.enabled() {
+            self.send_summary_vector(&destination, None);
+        }
+        if self.replication.mode == ReplicationMode::Prophet {
+            self.send_delivery_predictabilities(&destination, ctx);
+        }
 
         self.deliver_remote_bundles(&destination, ctx);
     }
@@ -238,6 +377,7 @@ impl Handler<EventPeerDisconnected> for Daemon {
         let EventPeerDisconnected { destination } = msg;
         assert!(destination.get_node_endpoint() == destination);
         self.remote_connections.remove(&destination);
+        self.replication.peer_disconnected(&destination);
     }
 }
 
@@ -248,13 +388,34 @@ impl Handler<EventBundleForwarded> for Daemon {
         let EventBundleForwarded { endpoint, bundle } = msg;
         let endpoint = endpoint.get_node_endpoint();
         if let Some(pending) = self.bundles_pending_forwarding.get_mut(&endpoint) {
-            pending.retain(|e| e != bundle);
+            pending.retain(|q| q.bundle != bundle);
+        }
+        if self.replication.copied(&bundle) {
+            debug!("Copied bundle {}", bundle.get_id());
+            // the other peers waited for this copy to be done
+            for peer in self.replication.peers() {
+                self.replicate_to(&peer, ctx);
+            }
+            self.deliver_remote_bundles(&endpoint, ctx);
+            return;
         }
+        self.replication.forget(&bundle);
+        // the contact capacity was used, the booking stays
+        self.contact_routes.remove(&bundle.get_identity());
         self.send_status_report_forwarded(&bundle);
 
+        let new_state = if self.custody.enabled
+            && custodian_of(&bundle)
+                .is_some_and(|custodian| self.endpoint.as_ref().unwrap().matches_node(&custodian))
+        {
+            self.hold_custody(bundle.clone(), ctx);
+            State::CustodyPending
+        } else {
+            State::Forwarded
+        };
         crate::bundlestorageagent::agent::Daemon::from_registry().do_send(UpdateBundle {
             bundleref: bundle,
-            new_state: State::Forwarded,
+            new_state,
             new_data: None,
         });
 
@@ -268,7 +429,7 @@ impl Handler<EventBundleForwardingFailed> for Daemon {
     fn handle(
         &mut self,
         msg: EventBundleForwardingFailed,
-        _ctx: &mut Self::Context,
+        ctx: &mut Self::Context,
     ) -> Self::Result {
         let EventBundleForwardingFailed { endpoint, bundle } = msg;
         let endpoint = endpoint.get_node_endpoint();
@@ -276,13 +437,60 @@ impl Handler<EventBundleForwardingFailed> for Daemon {
             "Forwarding bundle to endpoint {} failed. Requeueing",
             &endpoint
         );
-        if let Some(pending) = self.bundles_pending_forwarding.get_mut(&endpoint) {
-            pending.retain(|e| e != bundle);
+        let queued = take_pending(&mut self.bundles_pending_forwarding, &endpoint, &bundle);
+        if self.replication.copy_failed(&bundle) {
+            // we still have the bundle, it gets copied again on the next summary vector
+            return;
+        }
+        crate::bundlestorageagent::agent::Daemon::from_registry().do_send(RecordFailedAttempt {
+            bundleref: bundle.clone(),
+        });
+        if self.contact_routes.contains_key(&bundle.get_identity()) {
+            // the new route replaces the booking on the contact it failed on
+            self.route_by_contact_plan(bundle, ctx);
+            return;
         }
+        let priority = self.policy.priority_of(&bundle);
         self.remote_bundles
             .entry(endpoint)
             .or_default()
-            .push_front(bundle);
+            .requeue(queued.unwrap_or_else(|| Queued::unqueued(bundle)), priority);
+    }
+}
+
+impl Handler<EventBundleForwardingInterrupted> for Daemon {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: EventBundleForwardingInterrupted,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let EventBundleForwardingInterrupted {
+            endpoint,
+            bundle,
+            received_payload,
+        } = msg;
+        let endpoint = endpoint.get_node_endpoint();
+        debug!(
+            "Forwarding bundle {} to endpoint {endpoint} was interrupted after {received_payload} bytes of payload",
+            bundle.get_id()
+        );
+        if let Some(pending) = self.bundles_pending_forwarding.get_mut(&endpoint) {
+            pending.retain(|q| q.bundle != bundle);
+        }
+        if self.replication.copy_failed(&bundle) {
+            // our copy is still complete, there is no remainder to fragment
+            return;
+        }
+        self.release_contact_booking(&bundle);
+        // the remaining fragment is queued for forwarding once it is stored
+        crate::bundlestorageagent::agent::Daemon::from_registry().do_send(
+            FragmentBundleRemainder {
+                bundleref: bundle,
+                received_payload,
+            },
+        );
     }
 }
 
@@ -291,172 +499,503 @@ impl Handler<EventRoutingTableUpdate> for Daemon {
 
     fn handle(&mut self, msg: EventRoutingTableUpdate, ctx: &mut Self::Context) -> Self::Result {
         debug!("Updating routing table");
-        let old_routes = mem::replace(&mut self.remote_routes, msg.routes);
-        let old_route_targets: HashSet<Endpoint> = old_routes.keys().cloned().collect();
-        let new_route_targets: HashSet<Endpoint> = self.remote_routes.keys().cloned().collect();
-        let added_routes: HashSet<&Endpoint> =
-            new_route_targets.difference(&old_route_targets).collect();
-        let updated_routes: HashSet<&Endpoint> =
-            new_route_targets.intersection(&old_route_targets).collect();
-        for added_route in added_routes {
-            debug!("New route available for {added_route}");
-            self.deliver_remote_bundles(added_route, ctx);
-        }
-        for updated_route in updated_routes {
-            debug!("Updated route available for {updated_route}");
-            self.deliver_remote_bundles(updated_route, ctx);
+        self.remote_routes = msg.routes;
+        self.spreading.reset();
+        // bundles spread to a next hop that is no longer among the routes of their destination
+        // fail over to the remaining ones
+        let mut rerouted = Vec::new();
+        for (target, queue) in &mut self.remote_bundles {
+            rerouted.extend(queue.take_matching(|bundle| {
+                let destination = &bundle.get_primary_block().destination_endpoint;
+                !destination.matches_node(target)
+                    && route_target::lookup(&self.remote_routes, destination)
+                        .is_some_and(|next_hops| !next_hops.iter().any(|n| &n.next_hop == target))
+            }));
+        }
+        for bundle in rerouted {
+            debug!("Rerouting bundle {}", bundle.get_id());
+            self.queue_remote_bundle(bundle, ctx);
+        }
+        // a single pattern or default route can cover any of the queued destinations
+        let routed: Vec<Endpoint> = self
+            .remote_bundles
+            .keys()
+            .filter(|target| route_target::lookup(&self.remote_routes, target).is_some())
+            .cloned()
+            .collect();
+        for target in routed {
+            debug!("Route available for {target}");
+            self.deliver_remote_bundles(&target, ctx);
+        }
+    }
+}
+
+impl Handler<EventContactPlanChanged> for Daemon {
+    type Result = ();
+
+    fn handle(&mut self, _msg: EventContactPlanChanged, ctx: &mut Self::Context) -> Self::Result {
+        // Bundles queued for a next hop out of the contact plan stay there until its contact
+        // ended, the ones waiting for any route to their destination get routed again.
+        let now = DtnTime::now();
+        let mut unrouted = Vec::new();
+        for (target, queue) in &mut self.remote_bundles {
+            unrouted.extend(queue.take_matching(|bundle| {
+                self.contact_routes
+                    .get(&bundle.get_identity())
+                    .is_some_and(|end| *end <= now)
+            }));
+            if route_target::lookup(&self.remote_routes, target).is_none() {
+                unrouted.extend(queue.take_matching(|bundle| {
+                    bundle
+                        .get_primary_block()
+                        .destination_endpoint
+                        .matches_node(target)
+                }));
+            }
+        }
+        for bundle in unrouted {
+            self.route_by_contact_plan(bundle, ctx);
         }
     }
 }
 
+impl Handler<EventDuplicateBundleReceived> for Daemon {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: EventDuplicateBundleReceived,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let EventDuplicateBundleReceived { bundle, custodian } = msg;
+        if self.takes_custody_from(&bundle, &custodian) {
+            // We took custody the first time, but the custodian did not get our signal.
+            self.send_custody_signal(&bundle, &custodian, None);
+        }
+    }
+}
+
+impl Handler<AcknowledgeBundle> for Daemon {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: AcknowledgeBundle, _ctx: &mut Self::Context) -> Self::Result {
+        let AcknowledgeBundle {
+            endpoint,
+            bundle_source,
+            bundle_creation_timestamp,
+        } = msg;
+        let (report_to, lifetime) = self.acknowledgement_requests.take(
+            &endpoint,
+            &bundle_source,
+            &bundle_creation_timestamp,
+        )?;
+        let acknowledgement =
+            AdministrativeRecord::ApplicationAcknowledgement(ApplicationAcknowledgement {
+                acknowledged_by: endpoint,
+                acknowledgement_time: DtnTime::now(),
+                bundle_source,
+                bundle_creation_timestamp,
+            });
+        self.send_administrative_record(&acknowledgement, &report_to, lifetime);
+        Ok(())
+    }
+}
+
+impl Handler<GetBundleHistory> for Daemon {
+    type Result = MessageResult<GetBundleHistory>;
+
+    fn handle(&mut self, msg: GetBundleHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        let GetBundleHistory { source } = msg;
+        MessageResult(self.status_history.list(source.as_ref()))
+    }
+}
+
+impl Handler<GetCustodyStatistics> for Daemon {
+    type Result = MessageResult<GetCustodyStatistics>;
+
+    fn handle(&mut self, _msg: GetCustodyStatistics, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.custody.statistics())
+    }
+}
+
+impl Handler<GetQueueStatistics> for Daemon {
+    type Result = MessageResult<GetQueueStatistics>;
+
+    fn handle(&mut self, _msg: GetQueueStatistics, _ctx: &mut Context<Self>) -> Self::Result {
+        let queued_bundles = CLASSES
+            .into_iter()
+            .map(|class| {
+                let queued = self
+                    .local_bundles
+                    .values()
+                    .chain(self.remote_bundles.values())
+                    .map(|queue| queue.len(class))
+                    .sum();
+                (class, queued)
+            })
+            .collect();
+        MessageResult(QueueStatistics { queued_bundles })
+    }
+}
+
 impl Daemon {
     fn deliver_local_bundles(&mut self, destination: &Endpoint, ctx: &mut Context<Self>) {
+        if let Some(queue) = self.local_bundles.get_mut(destination) {
+            for bundle in queue.take_expired() {
+                self.delete_expired_bundle(bundle);
+            }
+        }
         let Some(sender) = self.local_connections.get(destination) else {
             return;
         };
+        let Some(queue) = self.local_bundles.get_mut(destination) else {
+            return;
+        };
 
-        if let Some(queue) = self.local_bundles.get_mut(destination) {
-            while let Some(bundle) = queue.pop_front() {
-                debug!(
-                    "locally delivering bundle {:?}",
-                    &bundle.get_primary_block()
-                );
-                assert!(
-                    bundle.get_primary_block().fragment_offset.is_none(),
-                    "Bundle is a fragment. It should have been reassembled before calling this"
-                );
+        let mut queues = [(destination, queue)];
+        while let Some((_, class, queued)) = self.scheduler.next(destination, &mut queues) {
+            let bundle = queued.bundle.clone();
+            debug!(
+                "locally delivering bundle {:?}",
+                &bundle.get_primary_block()
+            );
+            assert!(
+                bundle.get_primary_block().fragment_offset.is_none(),
+                "Bundle is a fragment. It should have been reassembled before calling this"
+            );
 
-                match sender.try_send(ClientDeliverBundle {
-                    bundle: bundle.clone(),
-                    responder: ctx.address().recipient(),
-                }) {
-                    Ok(()) => self
-                        .bundles_pending_local_delivery
-                        .entry(destination.clone())
-                        .or_default()
-                        .push(bundle),
-                    Err(e) => match e {
-                        SendError::Full(cdb) => {
-                            let ClientDeliverBundle { bundle, .. } = cdb;
-                            queue.push_back(bundle);
-                            return;
+            match sender.try_send(ClientDeliverBundle {
+                bundle: bundle.clone(),
+                responder: ctx.address().recipient(),
+            }) {
+                Ok(()) => self
+                    .bundles_pending_local_delivery
+                    .entry(destination.clone())
+                    .or_default()
+                    .push(queued),
+                Err(e) => match e {
+                    SendError::Full(_) => {
+                        queues[0].1.push_front(class, queued);
+                        return;
+                    }
+                    SendError::Closed(_) => {
+                        warn!(
+                            "Client for endpoint {destination} disconnected while sending bundles. Queueing..."
+                        );
+                        queues[0].1.push_front(class, queued);
+                        self.local_connections.remove(destination);
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Queues a bundle without a route to its destination for the next hop the contact plan
+    /// picks. Without a contact plan route it waits for a route to its destination.
+    fn route_by_contact_plan(&mut self, bundle: StoredBundleRef, ctx: &mut Context<Self>) {
+        let destination = bundle
+            .get_primary_block()
+            .destination_endpoint
+            .get_node_endpoint();
+        let request = FindContactRoute {
+            bundle: bundle.get_identity(),
+            destination: destination.clone(),
+            size: bundle.get_bundle_size(),
+            expires_at: bundle.get_expires_at(),
+        };
+        ctx.spawn(
+            crate::routingagent::agent::Daemon::from_registry()
+                .send(request)
+                .into_actor(self)
+                .map(move |res, act, ctx| {
+                    let queue = match res {
+                        Ok(Some(route)) => {
+                            act.contact_routes
+                                .insert(bundle.get_identity(), route.contact_end);
+                            route.next_hop
                         }
-                        SendError::Closed(_) => {
-                            warn!(
-                                "Client for endpoint {destination} disconnected while sending bundles. Queueing..."
-                            );
-                            queue.push_back(bundle);
-                            self.local_connections.remove(destination);
-                            return;
+                        Ok(None) => {
+                            act.contact_routes.remove(&bundle.get_identity());
+                            act.hold_for_replication(&bundle, ctx);
+                            destination
                         }
-                    },
+                        Err(e) => {
+                            warn!("Could not query the contact plan: {e:?}");
+                            act.hold_for_replication(&bundle, ctx);
+                            destination
+                        }
+                    };
+                    let priority = act.policy.priority_of(&bundle);
+                    act.remote_bundles
+                        .entry(queue.clone())
+                        .or_default()
+                        .push(bundle, priority);
+                    act.deliver_remote_bundles(&queue, ctx);
+                }),
+        );
+    }
+
+    /// Frees the capacity the contact plan booked for the bundle, if it was routed by it.
+    fn release_contact_booking(&mut self, bundle: &StoredBundleRef) {
+        let identity = bundle.get_identity();
+        if self.contact_routes.remove(&identity).is_some() {
+            crate::routingagent::agent::Daemon::from_registry()
+                .do_send(ReleaseContactBooking { bundle: identity });
+        }
+    }
+
+    /// Keeps a bundle without any route for copying it to the peers we meet, if opportunistic
+    /// routing is used. It also stays queued for its destination, in case a route shows up.
+    fn hold_for_replication(&mut self, bundle: &StoredBundleRef, ctx: &mut Context<Self>) {
+        if !self.replication.enabled() {
+            return;
+        }
+        let created_here = bundle
+            .get_primary_block()
+            .source_node
+            .matches_node(self.endpoint.as_ref().unwrap());
+        self.replication.hold(bundle.clone(), created_here);
+        for peer in self.replication.peers() {
+            self.send_summary_vector(&peer, Some(bundle));
+            self.replicate_to(&peer, ctx);
+        }
+    }
+
+    fn send_summary_vector(&mut self, peer: &Endpoint, bundle: Option<&StoredBundleRef>) {
+        let summary_vector = self.replication.summary_vector(peer, bundle);
+        self.send_administrative_record(
+            &AdministrativeRecord::SummaryVector(summary_vector),
+            peer,
+            SUMMARY_VECTOR_LIFETIME,
+        );
+    }
+
+    /// Records the encounter with the peer and sends it our updated delivery predictabilities.
+    fn send_delivery_predictabilities(&mut self, peer: &Endpoint, ctx: &mut Context<Self>) {
+        let peer = peer.clone();
+        ctx.spawn(
+            crate::routingagent::agent::Daemon::from_registry()
+                .send(RecordEncounter { peer: peer.clone() })
+                .into_actor(self)
+                .map(move |res, act, _ctx| match res {
+                    Ok(predictabilities) => {
+                        let record = AdministrativeRecord::DeliveryPredictabilities(
+                            DeliveryPredictabilities {
+                                predictabilities: predictabilities
+                                    .into_iter()
+                                    .map(|(node, predictability)| DeliveryPredictability {
+                                        node,
+                                        predictability,
+                                    })
+                                    .collect(),
+                            },
+                        );
+                        act.send_administrative_record(&record, &peer, SUMMARY_VECTOR_LIFETIME);
+                    }
+                    Err(e) => warn!("Could not record the encounter with {peer}: {e:?}"),
+                }),
+        );
+    }
+
+    /// Sends the peer copies of the bundles it does not have yet.
+    fn replicate_to(&mut self, peer: &Endpoint, ctx: &mut Context<Self>) {
+        let Some(sender) = self.remote_connections.get(peer).cloned() else {
+            return;
+        };
+        for bundle in self.replication.take_for(peer) {
+            debug!("Copying bundle {} to {peer}", bundle.get_id());
+            match sender.try_send(AgentForwardBundle {
+                bundle: bundle.clone(),
+                responder: ctx.address().recipient(),
+            }) {
+                Ok(()) => self
+                    .bundles_pending_forwarding
+                    .entry(
+                        bundle
+                            .get_primary_block()
+                            .destination_endpoint
+                            .get_node_endpoint(),
+                    )
+                    .or_default()
+                    .push(Queued::unqueued(bundle)),
+                Err(e) => {
+                    debug!("Can not copy bundle to {peer} right now: {e:?}");
+                    self.replication.copy_failed(&bundle);
                 }
             }
         }
     }
 
-    fn deliver_remote_bundles(&mut self, destination: &Endpoint, ctx: &mut Context<Self>) {
-        let destination = destination.get_node_endpoint();
-        let Some(route) = self.remote_routes.get(&destination) else {
+    /// Queues a bundle for forwarding. Bundles to a destination with several equal cost next
+    /// hops are queued for the next hop they are spread to, bundles without a route are routed
+    /// using the contact plan.
+    fn queue_remote_bundle(&mut self, bundle: StoredBundleRef, ctx: &mut Context<Self>) {
+        let destination = bundle
+            .get_primary_block()
+            .destination_endpoint
+            .get_node_endpoint();
+        let Some(next_hops) = route_target::lookup(&self.remote_routes, &destination) else {
+            self.route_by_contact_plan(bundle, ctx);
             return;
         };
-        let Some(sender) = self.remote_connections.get(&route.next_hop) else {
+        let queue = if next_hops.len() > 1 {
+            self.spreading.pick(&destination, next_hops).clone()
+        } else {
+            destination
+        };
+        self.release_contact_booking(&bundle);
+        let priority = self.policy.priority_of(&bundle);
+        self.remote_bundles
+            .entry(queue.clone())
+            .or_default()
+            .push(bundle, priority);
+        self.deliver_remote_bundles(&queue, ctx);
+    }
+
+    /// Forwards the queued bundles of `destination` and of all other destinations sharing one of
+    /// its next hops, in the order the scheduler picks.
+    fn deliver_remote_bundles(&mut self, destination: &Endpoint, ctx: &mut Context<Self>) {
+        let destination = destination.get_node_endpoint();
+        if let Some(queue) = self.remote_bundles.get_mut(&destination) {
+            for bundle in queue.take_expired() {
+                self.delete_expired_bundle(bundle);
+            }
+        }
+        let Some(next_hops) = route_target::lookup(&self.remote_routes, &destination) else {
             return;
         };
-        let Some(nexthopinfo) = self.remote_routes.get(&route.next_hop) else {
+        let next_hops: Vec<Endpoint> = next_hops.iter().map(|n| n.next_hop.clone()).collect();
+        for next_hop in next_hops {
+            self.deliver_via(&destination, &next_hop, ctx);
+        }
+    }
+
+    fn deliver_via(
+        &mut self,
+        destination: &Endpoint,
+        next_hop: &Endpoint,
+        ctx: &mut Context<Self>,
+    ) {
+        let Some(sender) = self.remote_connections.get(next_hop).cloned() else {
             return;
         };
-        if route.next_hop != nexthopinfo.next_hop {
+        let Some(nexthopinfo) = route_target::lookup(&self.remote_routes, next_hop)
+            .and_then(|next_hops| next_hops.iter().find(|n| &n.next_hop == next_hop))
+        else {
             warn!(
-                "Route {destination} points to nexthop {} that is not directly connected",
-                route.next_hop
+                "Route {destination} points to nexthop {next_hop} that is not directly connected"
             );
             return;
-        }
-        // This gets the smaller max_bundle_size for both of them, ignoring any Nones
-        let max_bundle_size = match route.max_size {
-            Some(ms) => Some(match nexthopinfo.max_size {
-                Some(s_ms) => ms.min(s_ms),
-                None => ms,
-            }),
-            None => nexthopinfo.max_size,
         };
+        // This gets the smaller max_bundle_size for both of them, ignoring any Nones
+        let max_bundle_sizes: HashMap<Endpoint, Option<u64>> = self
+            .remote_bundles
+            .keys()
+            .filter_map(|target| {
+                route_target::lookup(&self.remote_routes, target)
+                    .and_then(|next_hops| next_hops.iter().find(|n| &n.next_hop == next_hop))
+                    .map(|route| (target, route))
+            })
+            .map(|(target, route)| {
+                let max_bundle_size = match route.max_size {
+                    Some(ms) => Some(match nexthopinfo.max_size {
+                        Some(s_ms) => ms.min(s_ms),
+                        None => ms,
+                    }),
+                    None => nexthopinfo.max_size,
+                };
+                (target.clone(), max_bundle_size)
+            })
+            .collect();
 
-        if let Some(queue) = self.remote_bundles.get_mut(&destination) {
-            let mut visited: HashSet<String> = HashSet::new();
-            while let Some(bundle) = queue.pop_front() {
-                if visited.contains(&bundle.get_id()) {
-                    queue.push_front(bundle);
-                    break;
-                }
-                debug!(
-                    "forwarding bundle {} to {:?}",
-                    &bundle.get_id(),
-                    destination
-                );
+        let mut expired = Vec::new();
+        for (target, queue) in &mut self.remote_bundles {
+            if max_bundle_sizes.contains_key(target) {
+                expired.extend(queue.take_expired());
+            }
+        }
+        for bundle in expired {
+            self.delete_expired_bundle(bundle);
+        }
 
-                match max_bundle_size {
-                    Some(mbs) if bundle.get_bundle_size() > mbs => {
-                        if bundle
-                            .get_primary_block()
-                            .bundle_processing_flags
-                            .contains(BundleFlags::MUST_NOT_FRAGMENT)
-                            || bundle.get_bundle_min_size().is_some()
-                                && bundle.get_bundle_min_size().unwrap() > mbs
-                        {
-                            debug!(
-                                "Bundle can not be fragmented as we can not get it that small. Queueing it"
-                            );
-                            visited.insert(bundle.get_id());
-                            queue.push_back(bundle);
-                        } else {
-                            debug!(
-                                "Bundle {} is too large, need to fragment it to {mbs}",
-                                bundle.get_id()
-                            );
-                            crate::bundlestorageagent::agent::Daemon::from_registry().do_send(
-                                FragmentBundle {
-                                    bundleref: bundle,
-                                    target_size: mbs,
-                                },
-                            );
-                        }
-                        continue;
+        let mut queues: Vec<(&Endpoint, &mut BundleQueue)> = self
+            .remote_bundles
+            .iter_mut()
+            .filter(|(target, _)| max_bundle_sizes.contains_key(*target))
+            .collect();
+        // bundles that can not be sent right now, put back into their queues at the end
+        let mut held_back = Vec::new();
+        let mut disconnected = false;
+        while let Some((position, class, queued)) = self.scheduler.next(next_hop, &mut queues) {
+            let target = queues[position].0;
+            let bundle = queued.bundle.clone();
+            debug!("forwarding bundle {} to {:?}", &bundle.get_id(), target);
+
+            match max_bundle_sizes[target] {
+                Some(mbs) if bundle.get_bundle_size() > mbs => {
+                    if bundle
+                        .get_primary_block()
+                        .bundle_processing_flags
+                        .contains(BundleFlags::MUST_NOT_FRAGMENT)
+                        || bundle.get_bundle_min_size().is_some()
+                            && bundle.get_bundle_min_size().unwrap() > mbs
+                    {
+                        debug!(
+                            "Bundle can not be fragmented as we can not get it that small. Queueing it"
+                        );
+                        held_back.push((position, class, queued));
+                    } else {
+                        debug!(
+                            "Bundle {} is too large, need to fragment it to {mbs}",
+                            bundle.get_id()
+                        );
+                        crate::bundlestorageagent::agent::Daemon::from_registry().do_send(
+                            FragmentBundle {
+                                bundleref: bundle,
+                                target_size: mbs,
+                            },
+                        );
                     }
-                    Some(_) | None => {}
+                    continue;
                 }
+                Some(_) | None => {}
+            }
 
-                match sender.try_send(AgentForwardBundle {
-                    bundle: bundle.clone(),
-                    responder: ctx.address().recipient(),
-                }) {
-                    Ok(()) => self
-                        .bundles_pending_forwarding
-                        .entry(destination.clone())
-                        .or_default()
-                        .push(bundle),
-                    Err(e) => match e {
-                        SendError::Full(afb) => {
+            match sender.try_send(AgentForwardBundle {
+                bundle: bundle.clone(),
+                responder: ctx.address().recipient(),
+            }) {
+                Ok(()) => self
+                    .bundles_pending_forwarding
+                    .entry(target.clone())
+                    .or_default()
+                    .push(queued),
+                Err(e) => {
+                    match e {
+                        SendError::Full(_) => {
                             debug!(
-                                "Can not continue forwarding to {}. Waiting for some space in the queue",
-                                route.next_hop
+                                "Can not continue forwarding to {next_hop}. Waiting for some space in the queue"
                             );
-                            let AgentForwardBundle { bundle, .. } = afb;
-                            queue.push_back(bundle);
-                            return;
                         }
                         SendError::Closed(_) => {
                             warn!(
-                                "Peer for endpoint {destination} disconnected while forwarding bundles. Queueing..."
+                                "Peer for endpoint {next_hop} disconnected while forwarding bundles. Queueing..."
                             );
-                            queue.push_back(bundle);
-                            self.remote_connections.remove(&destination);
-                            return;
+                            disconnected = true;
                         }
-                    },
+                    }
+                    held_back.push((position, class, queued));
+                    break;
                 }
             }
         }
+        for (position, class, queued) in held_back.into_iter().rev() {
+            queues[position].1.push_front(class, queued);
+        }
+        if disconnected {
+            self.remote_connections.remove(next_hop);
+        }
     }
 
     fn send_status_report(
@@ -497,25 +1036,32 @@ impl Daemon {
             bundle_source: pb.source_node.clone(),
             bundle_creation_timestamp: pb.creation_timestamp.clone(),
             fragment_offset: pb.fragment_offset,
-            fragment_length: pb
-                .fragment_offset
-                .and(Some(bundle.get_bundle_payload_size())),
+            fragment_length: bundle.get_fragment_length(),
         });
-        match TryInto::<Vec<u8>>::try_into(ar) {
+        self.send_administrative_record(&ar, &pb.report_to, pb.lifetime);
+    }
+
+    fn send_administrative_record(
+        &mut self,
+        record: &AdministrativeRecord,
+        destination: &Endpoint,
+        lifetime: u64,
+    ) {
+        match TryInto::<Vec<u8>>::try_into(record) {
             Ok(data) => {
                 let bundle_data = Bundle {
                     primary_block: PrimaryBlock {
                         version: 7,
                         bundle_processing_flags: BundleFlags::ADMINISTRATIVE_RECORD,
                         crc: CRCType::NoCRC,
-                        destination_endpoint: pb.report_to.clone(),
+                        destination_endpoint: destination.clone(),
                         source_node: self.endpoint.as_ref().unwrap().clone(),
                         report_to: self.endpoint.as_ref().unwrap().clone(),
                         creation_timestamp: CreationTimestamp {
                             creation_time: DtnTime::now(),
                             sequence_number: 0, // uniqueness guaranteed in BSA
                         },
-                        lifetime: pb.lifetime,
+                        lifetime,
                         fragment_offset: None,
                         total_data_length: None,
                     },
@@ -530,12 +1076,12 @@ impl Daemon {
                 }
                 .try_into()
                 .unwrap();
-                debug!("Dispatching administrative record bundle {pb:?}");
+                debug!("Dispatching administrative record bundle {record:?} to {destination}");
                 crate::bundlestorageagent::agent::Daemon::from_registry()
                     .do_send(StoreNewBundle { bundle_data });
             }
             Err(e) => {
-                warn!("Error serializing bundle status report: {e:?}");
+                warn!("Error serializing administrative record: {e:?}");
             }
         }
     }
@@ -605,7 +1151,225 @@ impl Daemon {
         self.send_status_report(bundle, reason, false, false, false, true);
     }
 
-    // TODO: support Bundle Age
+    /// Removes the bundle from all queues, e.g. because it does not exist anymore.
+    fn forget_bundle(&mut self, bundle: &StoredBundleRef) {
+        self.custody.forget(bundle);
+        self.replication.forget(bundle);
+        self.release_contact_booking(bundle);
+        for queue in self
+            .local_bundles
+            .values_mut()
+            .chain(self.remote_bundles.values_mut())
+        {
+            queue.retain(|e| e != bundle);
+        }
+        for pending in self
+            .bundles_pending_local_delivery
+            .values_mut()
+            .chain(self.bundles_pending_forwarding.values_mut())
+        {
+            pending.retain(|q| q.bundle != *bundle);
+        }
+    }
+
+    /// The custodian we take custody of the bundle from, if we take it. We always do for bundles
+    /// we deliver, and for those we forward if custody transfer is enabled.
+    fn previous_custodian(&self, bundle: &StoredBundleRef, local: bool) -> Option<Endpoint> {
+        if !local && !self.custody.enabled {
+            return None;
+        }
+        custodian_of(bundle)
+            .filter(|custodian| !self.endpoint.as_ref().unwrap().matches_node(custodian))
+    }
+
+    /// Whether we would take custody of the received bundle from the custodian named in it. Like
+    /// [`Daemon::previous_custodian`] for bundles that are not stored.
+    fn takes_custody_from(&self, bundle: &StoredBundleRef, custodian: &Endpoint) -> bool {
+        let local = self
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .matches_node(&bundle.get_primary_block().destination_endpoint);
+        (local || self.custody.enabled) && !self.endpoint.as_ref().unwrap().matches_node(custodian)
+    }
+
+    /// Tells the custodian that we took custody of the bundle, or why we could not.
+    fn send_custody_signal(
+        &mut self,
+        bundle: &StoredBundleRef,
+        custodian: &Endpoint,
+        refusal: Option<BundleStatusReason>,
+    ) {
+        let pb = bundle.get_primary_block();
+        let signal = AdministrativeRecord::CustodySignal(CustodySignal {
+            accepted: refusal.is_none(),
+            reason: refusal.unwrap_or(BundleStatusReason::NoAdditionalInformation),
+            bundle_source: pb.source_node.clone(),
+            bundle_creation_timestamp: pb.creation_timestamp.clone(),
+            fragment_offset: pb.fragment_offset,
+            fragment_length: bundle.get_fragment_length(),
+        });
+        self.send_administrative_record(&signal, &custodian.get_node_endpoint(), pb.lifetime);
+    }
+
+    /// Handles an administrative record sent to us. Returns whether we consumed it, otherwise it
+    /// is delivered to whoever listens on our node endpoint.
+    fn handle_administrative_record(
+        &mut self,
+        record: AdministrativeRecord,
+        from: &Endpoint,
+        ctx: &mut Context<Self>,
+    ) -> bool {
+        match record {
+            AdministrativeRecord::BundleStatusReport(report) => {
+                self.status_history.record(&report, from);
+                false
+            }
+            AdministrativeRecord::CustodySignal(signal) => {
+                self.handle_custody_signal(&signal, from, ctx);
+                true
+            }
+            AdministrativeRecord::ApplicationAcknowledgement(acknowledgement) => {
+                debug!(
+                    "{} acknowledged bundle {:?}",
+                    acknowledgement.acknowledged_by, acknowledgement.bundle_creation_timestamp
+                );
+                crate::clientagent::agent::Daemon::from_registry()
+                    .do_send(EventBundleAcknowledged { acknowledgement });
+                true
+            }
+            AdministrativeRecord::SummaryVector(summary_vector) => {
+                self.handle_summary_vector(summary_vector, from, ctx);
+                true
+            }
+            AdministrativeRecord::DeliveryPredictabilities(predictabilities) => {
+                self.handle_delivery_predictabilities(predictabilities, from, ctx);
+                true
+            }
+        }
+    }
+
+    fn handle_delivery_predictabilities(
+        &mut self,
+        predictabilities: DeliveryPredictabilities,
+        from: &Endpoint,
+        ctx: &mut Context<Self>,
+    ) {
+        if self.replication.mode != ReplicationMode::Prophet {
+            return;
+        }
+        let peer = from.get_node_endpoint();
+        let request = LearnPredictabilities {
+            peer: peer.clone(),
+            predictabilities: predictabilities
+                .predictabilities
+                .into_iter()
+                .map(|p| (p.node.get_node_endpoint(), p.predictability))
+                .collect(),
+        };
+        ctx.spawn(
+            crate::routingagent::agent::Daemon::from_registry()
+                .send(request)
+                .into_actor(self)
+                .map(move |res, act, ctx| match res {
+                    Ok(better) => {
+                        act.replication.received_predictabilities(&peer, better);
+                        act.replicate_to(&peer, ctx);
+                    }
+                    Err(e) => warn!("Could not learn the predictabilities of {peer}: {e:?}"),
+                }),
+        );
+    }
+
+    fn handle_summary_vector(
+        &mut self,
+        summary_vector: SummaryVector,
+        from: &Endpoint,
+        ctx: &mut Context<Self>,
+    ) {
+        if !self.replication.enabled() {
+            return;
+        }
+        let peer = from.get_node_endpoint();
+        debug!(
+            "{peer} has {} bundles for opportunistic routing",
+            summary_vector.bundles.len()
+        );
+        self.replication
+            .received_summary_vector(&peer, summary_vector);
+        self.replicate_to(&peer, ctx);
+    }
+
+    fn handle_custody_signal(
+        &mut self,
+        signal: &CustodySignal,
+        from: &Endpoint,
+        ctx: &mut Context<Self>,
+    ) {
+        let Some(bundle) = self.custody.release(signal) else {
+            debug!("Ignoring custody signal from {from} for a bundle we do not have custody of");
+            return;
+        };
+        let new_state = if signal.accepted {
+            debug!("{from} accepted custody of bundle {}", bundle.get_id());
+            State::Forwarded
+        } else if is_permanent_refusal(signal.reason) {
+            // Sending the bundle again would not help
+            warn!(
+                "{from} refused custody of bundle {}: {:?}. Deleting it",
+                bundle.get_id(),
+                signal.reason
+            );
+            self.send_status_report_deleted(&bundle, signal.reason);
+            State::Invalid
+        } else {
+            // We keep custody and forward the bundle again once the retransmission timeout
+            // is over, maybe on another route
+            warn!(
+                "{from} refused custody of bundle {}: {:?}. Retrying later",
+                bundle.get_id(),
+                signal.reason
+            );
+            self.hold_custody(bundle, ctx);
+            return;
+        };
+        crate::bundlestorageagent::agent::Daemon::from_registry().do_send(UpdateBundle {
+            bundleref: bundle,
+            new_state,
+            new_data: None,
+        });
+    }
+
+    /// Keeps the bundle until the next custodian accepts it, forwarding it again if that takes
+    /// too long.
+    fn hold_custody(&mut self, bundle: StoredBundleRef, ctx: &mut Context<Self>) {
+        let (id, attempt) = self.custody.hold(bundle);
+        ctx.run_later(self.custody.retransmission_timeout, move |act, _ctx| {
+            if let Some(bundle) = act.custody.take_timed_out(&id, attempt) {
+                debug!(
+                    "Custody of bundle {} was not accepted in time, forwarding it again",
+                    bundle.get_id()
+                );
+                crate::bundlestorageagent::agent::Daemon::from_registry().do_send(UpdateBundle {
+                    bundleref: bundle,
+                    new_state: State::ForwardingQueued,
+                    new_data: None,
+                });
+            }
+        });
+    }
+
+    fn delete_expired_bundle(&mut self, bundle: StoredBundleRef) {
+        debug!("Bundle {} expired, deleting it", bundle.get_id());
+        self.release_contact_booking(&bundle);
+        self.send_status_report_deleted(&bundle, BundleStatusReason::LifetimeExpired);
+        crate::bundlestorageagent::agent::Daemon::from_registry().do_send(UpdateBundle {
+            bundleref: bundle,
+            new_state: State::Invalid,
+            new_data: None,
+        });
+    }
+
     fn forward_bundle(&self, sbr: &StoredBundleRef) -> Result<Vec<u8>, BundleStatusReason> {
         let data = sbr.get_bundle_data().unwrap();
         let mut bundle: Bundle = data
@@ -620,9 +1384,49 @@ impl Daemon {
         {
             bundle.set_previous_node(self.endpoint.as_ref().unwrap());
         }
+        if self.custody.enabled
+            && !bundle
+                .primary_block
+                .bundle_processing_flags
+                .contains(BundleFlags::ADMINISTRATIVE_RECORD)
+        {
+            bundle.set_custodian(self.endpoint.as_ref().unwrap());
+        }
         if !bundle.inc_hop_count(HOP_LIMIT_DEFAULT) {
             return Err(BundleStatusReason::HopLimitExceeded);
         }
+        if !bundle.primary_block.has_clock() {
+            // the next node only knows how long the bundle lives from its age
+            let dwell_time = DtnTime::now()
+                .timestamp
+                .saturating_sub(sbr.get_received_at().timestamp);
+            bundle.add_bundle_age(dwell_time);
+        }
         Ok(bundle.try_into().expect("No way to fail"))
     }
 }
+
+/// The administrative record carried by the bundle, if it is one.
+fn administrative_record_of(bundle: &StoredBundleRef) -> Option<AdministrativeRecord> {
+    if !bundle
+        .get_primary_block()
+        .bundle_processing_flags
+        .contains(BundleFlags::ADMINISTRATIVE_RECORD)
+    {
+        return None;
+    }
+    let data = bundle.get_bundle_data()?;
+    let bundle: Bundle = data.as_slice().try_into().ok()?;
+    AdministrativeRecord::try_from(bundle.payload_block().data.to_vec()).ok()
+}
+
+/// Takes the bundle out of the bundles that are being sent to `endpoint`.
+fn take_pending(
+    pending: &mut HashMap<Endpoint, Vec<Queued>>,
+    endpoint: &Endpoint,
+    bundle: &StoredBundleRef,
+) -> Option<Queued> {
+    let sending = pending.get_mut(endpoint)?;
+    let position = sending.iter().position(|q| q.bundle == *bundle)?;
+    Some(sending.remove(position))
+}
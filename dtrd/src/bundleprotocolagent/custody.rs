@@ -0,0 +1,128 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashMap, time::Duration};
+
+use bp7::{
+    administrative_record::{
+        bundle_status_report::BundleStatusReason, custody_signal::CustodySignal,
+    },
+    bundle::Bundle,
+    endpoint::Endpoint,
+};
+
+use super::messages::CustodyStatistics;
+use crate::{
+    bundlestorageagent::{StoredBundleRef, bundle_identity},
+    common::settings::Settings,
+};
+
+fn signal_custody_id(signal: &CustodySignal) -> String {
+    bundle_identity(
+        &signal.bundle_source,
+        &signal.bundle_creation_timestamp,
+        signal.fragment_offset,
+        signal.fragment_length,
+    )
+}
+
+/// Whether the next node refused custody for a reason that does not change when we forward the
+/// bundle again. Otherwise it might accept it later, or another node might.
+pub fn is_permanent_refusal(reason: BundleStatusReason) -> bool {
+    matches!(
+        reason,
+        BundleStatusReason::LifetimeExpired
+            | BundleStatusReason::HopLimitExceeded
+            | BundleStatusReason::DestinationEndpointIDUnavailable
+            | BundleStatusReason::BlockUnintelligible
+            | BundleStatusReason::BlockUnsupported
+    )
+}
+
+/// The node that currently has custody of the bundle, if custody transfer is used for it.
+pub fn custodian_of(bundle: &StoredBundleRef) -> Option<Endpoint> {
+    let data = bundle.get_bundle_data()?;
+    let bundle: Bundle = data.as_slice().try_into().ok()?;
+    bundle.custodian().cloned()
+}
+
+/// The bundles we have custody of and forwarded, waiting for the next custodian to accept them.
+#[derive(Debug, Default)]
+pub struct Custody {
+    /// Whether we take custody of bundles we forward.
+    pub enabled: bool,
+    /// How long we wait for a custody signal until we forward the bundle again.
+    pub retransmission_timeout: Duration,
+    /// The bundles by their custody id, with the forwarding attempt we wait for.
+    held: HashMap<String, (StoredBundleRef, u64)>,
+    attempts: u64,
+    accepted: u64,
+    refused: u64,
+    retransmissions: u64,
+}
+
+impl Custody {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Custody {
+            enabled: settings.custody_transfer,
+            retransmission_timeout: Duration::from_secs(settings.custody_retransmission_timeout),
+            ..Custody::default()
+        }
+    }
+
+    /// Keeps the bundle until the next custodian accepts it. Returns the attempt to pass to
+    /// [`Custody::take_timed_out`] once the retransmission timeout is over.
+    pub fn hold(&mut self, bundle: StoredBundleRef) -> (String, u64) {
+        self.attempts += 1;
+        let id = bundle.get_identity();
+        self.held.insert(id.clone(), (bundle, self.attempts));
+        (id, self.attempts)
+    }
+
+    /// Returns the bundle if we still wait for a custody signal of this attempt.
+    pub fn take_timed_out(&mut self, id: &str, attempt: u64) -> Option<StoredBundleRef> {
+        if self.held.get(id).is_none_or(|(_, a)| *a != attempt) {
+            return None;
+        }
+        self.retransmissions += 1;
+        self.held.remove(id).map(|(bundle, _)| bundle)
+    }
+
+    /// Returns the bundle the signal is about, if we have custody of it.
+    pub fn release(&mut self, signal: &CustodySignal) -> Option<StoredBundleRef> {
+        let (bundle, _) = self.held.remove(&signal_custody_id(signal))?;
+        if signal.accepted {
+            self.accepted += 1;
+        } else {
+            self.refused += 1;
+        }
+        Some(bundle)
+    }
+
+    pub fn forget(&mut self, bundle: &StoredBundleRef) {
+        self.held.remove(&bundle.get_identity());
+    }
+
+    pub fn statistics(&self) -> CustodyStatistics {
+        CustodyStatistics {
+            bundles_in_custody: self.held.len() as u64,
+            custody_accepted: self.accepted,
+            custody_refused: self.refused,
+            custody_retransmissions: self.retransmissions,
+        }
+    }
+}
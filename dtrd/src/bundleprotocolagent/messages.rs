@@ -0,0 +1,98 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use bp7::{
+    administrative_record::bundle_status_report::BundleStatusReason,
+    block::quality_of_service_block::PriorityClass,
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
+};
+
+use actix::prelude::*;
+
+#[derive(Debug, Clone, Default)]
+pub struct QueueStatistics {
+    /// Bundles waiting for delivery or forwarding, per priority class.
+    pub queued_bundles: HashMap<PriorityClass, usize>,
+}
+
+#[derive(Message)]
+#[rtype(result = "QueueStatistics")]
+pub struct GetQueueStatistics {}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CustodyStatistics {
+    /// Bundles we forwarded and keep until the next custodian accepts them.
+    pub bundles_in_custody: u64,
+    /// Custody signals that released us from custody.
+    pub custody_accepted: u64,
+    /// Custody signals that refused to take custody.
+    pub custody_refused: u64,
+    /// Bundles forwarded again as no custody signal arrived in time.
+    pub custody_retransmissions: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "CustodyStatistics")]
+pub struct GetCustodyStatistics {}
+
+/// An application acknowledges a bundle that was delivered to `endpoint`.
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct AcknowledgeBundle {
+    pub endpoint: Endpoint,
+    pub bundle_source: Endpoint,
+    pub bundle_creation_timestamp: CreationTimestamp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleStatus {
+    Received,
+    Forwarded,
+    Delivered,
+    Deleted,
+}
+
+/// A status some node reported for a bundle.
+#[derive(Debug, Clone)]
+pub struct StatusEvent {
+    pub status: BundleStatus,
+    /// When the status applied, or when we got the report if the node did not say.
+    pub time: DtnTime,
+    pub reporting_node: Endpoint,
+    pub reason: BundleStatusReason,
+    /// Set if the report is about a fragment of the bundle.
+    pub fragment_offset: Option<u64>,
+    pub fragment_length: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BundleHistory {
+    pub source: Endpoint,
+    pub creation_timestamp: CreationTimestamp,
+    /// Oldest first.
+    pub events: Vec<StatusEvent>,
+}
+
+/// Lists the history of the bundles from `source`, or of all bundles if it is not set.
+#[derive(Message)]
+#[rtype(result = "Vec<BundleHistory>")]
+pub struct GetBundleHistory {
+    pub source: Option<Endpoint>,
+}
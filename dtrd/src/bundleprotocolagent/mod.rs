@@ -15,4 +15,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod acknowledgement;
 pub mod agent;
+pub mod custody;
+pub mod messages;
+pub mod priority;
+pub mod replication;
+pub mod scheduler;
+pub mod spreading;
+pub mod status_history;
@@ -0,0 +1,122 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use bp7::{
+    block::quality_of_service_block::{PriorityClass, QualityOfServiceBlock},
+    endpoint::Endpoint,
+    primaryblock::PrimaryBlock,
+};
+
+use crate::{bundlestorageagent::StoredBundleRef, common::settings::Settings};
+
+pub fn class_from_setting(class: &str) -> Option<PriorityClass> {
+    match class {
+        "bulk" => Some(PriorityClass::Bulk),
+        "normal" => Some(PriorityClass::Normal),
+        "expedited" => Some(PriorityClass::Expedited),
+        _ => None,
+    }
+}
+
+pub fn class_name(class: PriorityClass) -> &'static str {
+    match class {
+        PriorityClass::Bulk => "bulk",
+        PriorityClass::Normal => "normal",
+        PriorityClass::Expedited => "expedited",
+    }
+}
+
+#[derive(Debug)]
+enum RuleField {
+    Source,
+    Destination,
+}
+
+/// Gives all bundles from or to an endpoint the same priority class.
+#[derive(Debug)]
+struct Rule {
+    field: RuleField,
+    /// A node ID matches all endpoints of the node.
+    endpoint: Endpoint,
+    class: PriorityClass,
+}
+
+impl Rule {
+    /// Parses rules like `destination:dtn://node2/commands=expedited`.
+    fn from_setting(rule: &str) -> Option<Self> {
+        let (field, rule) = rule.split_once(':')?;
+        let (endpoint, class) = rule.rsplit_once('=')?;
+        let field = match field {
+            "source" => RuleField::Source,
+            "destination" => RuleField::Destination,
+            _ => return None,
+        };
+        Some(Rule {
+            field,
+            endpoint: Endpoint::new(endpoint)?,
+            class: class_from_setting(class)?,
+        })
+    }
+
+    fn matches(&self, pb: &PrimaryBlock) -> bool {
+        let endpoint = match self.field {
+            RuleField::Source => &pb.source_node,
+            RuleField::Destination => &pb.destination_endpoint,
+        };
+        if self.endpoint == self.endpoint.get_node_endpoint() {
+            self.endpoint.matches_node(endpoint)
+        } else {
+            self.endpoint == *endpoint
+        }
+    }
+}
+
+/// Decides how urgent a bundle is. The first matching rule configured by the operator wins over
+/// the quality of service block of the bundle.
+#[derive(Debug, Default)]
+pub struct PriorityPolicy {
+    rules: Vec<Rule>,
+}
+
+impl PriorityPolicy {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let rules = settings
+            .bundle_priority_policy
+            .iter()
+            .flat_map(|policy| policy.split(','))
+            .map(|rule| {
+                Rule::from_setting(rule.trim())
+                    .unwrap_or_else(|| panic!("Invalid bundle priority rule {rule}"))
+            })
+            .collect();
+        PriorityPolicy { rules }
+    }
+
+    pub fn priority_of(&self, bundle: &StoredBundleRef) -> QualityOfServiceBlock {
+        match self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(bundle.get_primary_block()))
+        {
+            Some(rule) => QualityOfServiceBlock {
+                class: rule.class,
+                ordinal: 0,
+            },
+            None => bundle.get_priority(),
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use bp7::{
+    administrative_record::summary_vector::{SummaryVector, SummaryVectorEntry},
+    endpoint::Endpoint,
+};
+
+use crate::{
+    bundlestorageagent::{StoredBundleRef, bundle_identity},
+    common::settings::Settings,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplicationMode {
+    /// Bundles without a route wait until there is one.
+    #[default]
+    None,
+    /// Bundles without a route are copied to every peer that does not have them yet.
+    Epidemic,
+    /// Binary spray and wait: a node with more than one copy of a bundle hands half of them to
+    /// a peer without the bundle. With a single copy left it waits until it meets the
+    /// destination.
+    SprayAndWait,
+    /// Prophet: bundles are copied to peers that are more likely to meet their destination
+    /// than we are.
+    Prophet,
+}
+
+impl ReplicationMode {
+    fn from_setting(mode: &str) -> Self {
+        match mode {
+            "none" => ReplicationMode::None,
+            "epidemic" => ReplicationMode::Epidemic,
+            "spray-and-wait" => ReplicationMode::SprayAndWait,
+            "prophet" => ReplicationMode::Prophet,
+            mode => panic!("Unknown opportunistic routing {mode}"),
+        }
+    }
+}
+
+fn entry_replica_id(entry: &SummaryVectorEntry) -> String {
+    bundle_identity(
+        &entry.bundle_source,
+        &entry.bundle_creation_timestamp,
+        entry.fragment_offset,
+        entry.fragment_length,
+    )
+}
+
+#[derive(Debug)]
+struct Replica {
+    bundle: StoredBundleRef,
+    /// The copies we have for spray and wait, including our own.
+    copies: u64,
+    /// Copies promised to peers in our summary vector, until they got the bundle.
+    offered: HashMap<Endpoint, u64>,
+    /// Peers that already have the bundle.
+    peers: HashSet<Endpoint>,
+    /// The peer we are sending a copy to. The convergence layer only tells us the bundle when
+    /// the transfer is done, so there is one copy in flight at a time.
+    sending: Option<Endpoint>,
+}
+
+/// The bundles without a route that we keep for copying them to the peers we meet. Unlike
+/// forwarded bundles they stay stored until they expire or get forwarded to their destination.
+#[derive(Debug, Default)]
+pub struct Replication {
+    pub mode: ReplicationMode,
+    /// The copies of a bundle created by this node for spray and wait.
+    copies: u64,
+    replicas: HashMap<String, Replica>,
+    /// The bundles the connected peers told us they have.
+    summaries: HashMap<Endpoint, HashSet<String>>,
+    /// Copies peers offered us of bundles we do not have yet.
+    offers: HashMap<String, (Endpoint, u64)>,
+    /// For Prophet, the nodes each connected peer is more likely to deliver to than we are.
+    better_peers: HashMap<Endpoint, HashSet<Endpoint>>,
+}
+
+impl Replication {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Replication {
+            mode: ReplicationMode::from_setting(&settings.opportunistic_routing),
+            copies: settings.spray_and_wait_copies.max(1),
+            ..Replication::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode != ReplicationMode::None
+    }
+
+    /// Keeps the bundle to copy it to the peers we meet. Bundles we created get all copies,
+    /// others the ones offered with them, or only their own.
+    pub fn hold(&mut self, bundle: StoredBundleRef, created_here: bool) {
+        let id = bundle.get_identity();
+        if self.replicas.contains_key(&id) {
+            return;
+        }
+        let offered = self.offers.remove(&id).map(|(_, copies)| copies);
+        let copies = if created_here {
+            self.copies
+        } else {
+            offered.unwrap_or(1)
+        };
+        self.replicas.insert(
+            id,
+            Replica {
+                bundle,
+                copies,
+                offered: HashMap::new(),
+                peers: HashSet::new(),
+                sending: None,
+            },
+        );
+    }
+
+    /// The connected peers that sent us their summary vector.
+    pub fn peers(&self) -> Vec<Endpoint> {
+        self.summaries.keys().cloned().collect()
+    }
+
+    /// The summary vector for `peer`, listing all bundles we keep or only `bundle`. For spray
+    /// and wait half of our copies are set aside for the peer, they get returned if it turns out
+    /// to have the bundle already.
+    pub fn summary_vector(
+        &mut self,
+        peer: &Endpoint,
+        bundle: Option<&StoredBundleRef>,
+    ) -> SummaryVector {
+        let only = bundle.map(StoredBundleRef::get_identity);
+        let spray = self.mode == ReplicationMode::SprayAndWait;
+        let bundles = self
+            .replicas
+            .iter_mut()
+            .filter(|(id, _)| only.as_ref().is_none_or(|only| only == *id))
+            .map(|(_, replica)| {
+                if spray && replica.copies > 1 && !replica.offered.contains_key(peer) {
+                    let handed_over = replica.copies / 2;
+                    replica.copies -= handed_over;
+                    replica.offered.insert(peer.clone(), handed_over);
+                }
+                let pb = replica.bundle.get_primary_block();
+                SummaryVectorEntry {
+                    bundle_source: pb.source_node.clone(),
+                    bundle_creation_timestamp: pb.creation_timestamp.clone(),
+                    fragment_offset: pb.fragment_offset,
+                    fragment_length: replica.bundle.get_fragment_length(),
+                    copies: replica.offered.get(peer).copied().unwrap_or_default(),
+                }
+            })
+            .collect();
+        SummaryVector { bundles }
+    }
+
+    /// Remembers what the peer has, and the copies it offers us.
+    pub fn received_summary_vector(&mut self, peer: &Endpoint, summary_vector: SummaryVector) {
+        let summary = self.summaries.entry(peer.clone()).or_default();
+        for entry in summary_vector.bundles {
+            let id = entry_replica_id(&entry);
+            if let Some(replica) = self.replicas.get_mut(&id) {
+                replica.peers.insert(peer.clone());
+                if let Some(copies) = replica.offered.remove(peer) {
+                    replica.copies += copies;
+                }
+            } else if entry.copies > 0 {
+                self.offers.insert(id.clone(), (peer.clone(), entry.copies));
+            }
+            summary.insert(id);
+        }
+    }
+
+    /// Remembers the nodes the peer is more likely to deliver to than we are.
+    pub fn received_predictabilities(&mut self, peer: &Endpoint, better: HashSet<Endpoint>) {
+        self.better_peers.insert(peer.clone(), better);
+    }
+
+    /// Takes the bundles to copy to the peer, which are the ones it does not have. For spray
+    /// and wait only the ones we offered copies of, for Prophet only the ones the peer is more
+    /// likely to deliver.
+    pub fn take_for(&mut self, peer: &Endpoint) -> Vec<StoredBundleRef> {
+        let Some(summary) = self.summaries.get(peer) else {
+            return Vec::new();
+        };
+        let mode = self.mode;
+        let better = self.better_peers.get(peer);
+        self.replicas
+            .iter_mut()
+            .filter(|(id, replica)| {
+                let destination = &replica.bundle.get_primary_block().destination_endpoint;
+                let wanted = match mode {
+                    ReplicationMode::None => false,
+                    ReplicationMode::Epidemic => true,
+                    ReplicationMode::SprayAndWait => replica.offered.contains_key(peer),
+                    ReplicationMode::Prophet => better
+                        .is_some_and(|better| better.contains(&destination.get_node_endpoint())),
+                };
+                wanted
+                    && !summary.contains(*id)
+                    && !replica.peers.contains(peer)
+                    && replica.sending.is_none()
+                    // the destination gets the bundle forwarded instead
+                    && !destination.matches_node(peer)
+            })
+            .map(|(_, replica)| {
+                replica.sending = Some(peer.clone());
+                replica.bundle.clone()
+            })
+            .collect()
+    }
+
+    /// Records that the peer we sent a copy of the bundle to got it. Returns false if we were
+    /// not copying the bundle, so it was forwarded.
+    pub fn copied(&mut self, bundle: &StoredBundleRef) -> bool {
+        let Some(replica) = self.replicas.get_mut(&bundle.get_identity()) else {
+            return false;
+        };
+        let Some(peer) = replica.sending.take() else {
+            return false;
+        };
+        replica.offered.remove(&peer);
+        replica.peers.insert(peer);
+        true
+    }
+
+    /// Records that copying the bundle failed, so we keep the copies offered to the peer.
+    /// Returns false if we were not copying the bundle.
+    pub fn copy_failed(&mut self, bundle: &StoredBundleRef) -> bool {
+        let Some(replica) = self.replicas.get_mut(&bundle.get_identity()) else {
+            return false;
+        };
+        let Some(peer) = replica.sending.take() else {
+            return false;
+        };
+        if let Some(copies) = replica.offered.remove(&peer) {
+            replica.copies += copies;
+        }
+        true
+    }
+
+    /// Forgets what we know about the peer, we exchange summary vectors again when it
+    /// reconnects.
+    pub fn peer_disconnected(&mut self, peer: &Endpoint) {
+        self.summaries.remove(peer);
+        self.better_peers.remove(peer);
+        self.offers.retain(|_, (from, _)| from != peer);
+        for replica in self.replicas.values_mut() {
+            if replica.sending.as_ref() != Some(peer)
+                && let Some(copies) = replica.offered.remove(peer)
+            {
+                replica.copies += copies;
+            }
+        }
+    }
+
+    pub fn forget(&mut self, bundle: &StoredBundleRef) {
+        self.replicas.remove(&bundle.get_identity());
+    }
+}
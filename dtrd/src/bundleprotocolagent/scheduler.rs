@@ -0,0 +1,276 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    time::{Duration, Instant},
+};
+
+use bp7::{
+    block::quality_of_service_block::{PriorityClass, QualityOfServiceBlock},
+    endpoint::Endpoint,
+};
+
+use crate::{bundlestorageagent::StoredBundleRef, common::settings::Settings};
+
+/// All priority classes, from the least to the most urgent.
+pub const CLASSES: [PriorityClass; 3] = [
+    PriorityClass::Bulk,
+    PriorityClass::Normal,
+    PriorityClass::Expedited,
+];
+
+/// How many bundles of each class are sent per round in weighted fair scheduling.
+const WEIGHTS: [u32; CLASSES.len()] = [1, 2, 4];
+
+fn index(class: PriorityClass) -> usize {
+    usize::from(u8::from(class))
+}
+
+/// How the next bundle is chosen among the priority classes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulingMode {
+    /// Bundles are only sent if no more urgent ones are waiting.
+    Strict,
+    /// Each class gets a share of the link according to its weight, so less urgent bundles still
+    /// make progress while more urgent ones are waiting.
+    #[default]
+    WeightedFair,
+}
+
+impl SchedulingMode {
+    fn from_setting(mode: &str) -> Self {
+        match mode {
+            "strict" => SchedulingMode::Strict,
+            "weighted-fair" => SchedulingMode::WeightedFair,
+            mode => panic!("Unknown bundle scheduling {mode}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Queued {
+    pub bundle: StoredBundleRef,
+    ordinal: u8,
+    /// When the bundle was first queued. Kept while it is sent, so a bundle that failed to send
+    /// does not start waiting again.
+    since: Instant,
+}
+
+impl Queued {
+    fn new(bundle: StoredBundleRef, priority: QualityOfServiceBlock) -> Self {
+        Queued {
+            bundle,
+            ordinal: priority.ordinal,
+            since: Instant::now(),
+        }
+    }
+
+    /// A bundle that is sent without being queued first.
+    pub fn unqueued(bundle: StoredBundleRef) -> Self {
+        Queued::new(bundle, QualityOfServiceBlock::default())
+    }
+}
+
+/// Bundles waiting to be sent to one destination, with a queue per priority class. Within a
+/// class bundles with a higher ordinal go first, otherwise they are sent in the order they got
+/// queued.
+#[derive(Debug, Default)]
+pub struct BundleQueue {
+    classes: [VecDeque<Queued>; CLASSES.len()],
+}
+
+impl BundleQueue {
+    pub fn push(&mut self, bundle: StoredBundleRef, priority: QualityOfServiceBlock) {
+        let queue = &mut self.classes[index(priority.class)];
+        let position = queue
+            .iter()
+            .rposition(|q| q.ordinal >= priority.ordinal)
+            .map_or(0, |p| p + 1);
+        queue.insert(position, Queued::new(bundle, priority));
+    }
+
+    /// Queues the bundle again in front of all others of the same priority, e.g. after sending
+    /// it failed. It keeps waiting since it was first queued.
+    pub fn requeue(&mut self, mut queued: Queued, priority: QualityOfServiceBlock) {
+        queued.ordinal = priority.ordinal;
+        self.push_front(priority.class, queued);
+    }
+
+    /// Puts back a bundle taken by [`Scheduler::next`].
+    pub fn push_front(&mut self, class: PriorityClass, queued: Queued) {
+        let queue = &mut self.classes[index(class)];
+        let position = queue
+            .iter()
+            .position(|q| q.ordinal <= queued.ordinal)
+            .unwrap_or(queue.len());
+        queue.insert(position, queued);
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&StoredBundleRef) -> bool) {
+        for queue in &mut self.classes {
+            queue.retain(|q| f(&q.bundle));
+        }
+    }
+
+    /// Removes the expired bundles.
+    pub fn take_expired(&mut self) -> Vec<StoredBundleRef> {
+        self.take_matching(StoredBundleRef::is_expired)
+    }
+
+    /// Removes the bundles matching `f`.
+    pub fn take_matching(
+        &mut self,
+        mut f: impl FnMut(&StoredBundleRef) -> bool,
+    ) -> Vec<StoredBundleRef> {
+        let mut taken = Vec::new();
+        for queue in &mut self.classes {
+            let (gone, kept) = mem::take(queue).into_iter().partition(|q| f(&q.bundle));
+            *queue = kept;
+            taken.extend(gone.into_iter().map(|q: Queued| q.bundle));
+        }
+        taken
+    }
+
+    pub fn len(&self, class: PriorityClass) -> usize {
+        self.classes[index(class)].len()
+    }
+}
+
+/// What the scheduler remembers about a link.
+#[derive(Debug, Default)]
+struct LinkState {
+    /// Bundles sent per class in the current weighted fair round.
+    sent: [u32; CLASSES.len()],
+    /// The destination served last per class, so destinations sharing the link take turns.
+    last_destination: [Option<Endpoint>; CLASSES.len()],
+}
+
+/// Decides in which order queued bundles are sent over a link, i.e. to a next hop or a local
+/// client.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    mode: SchedulingMode,
+    /// Bundles waiting for longer are sent first, regardless of their priority.
+    starvation_timeout: Option<Duration>,
+    links: HashMap<Endpoint, LinkState>,
+}
+
+impl Scheduler {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Scheduler {
+            mode: SchedulingMode::from_setting(&settings.bundle_scheduling),
+            starvation_timeout: (settings.bundle_starvation_timeout > 0)
+                .then(|| Duration::from_secs(settings.bundle_starvation_timeout)),
+            links: HashMap::new(),
+        }
+    }
+
+    /// Takes the bundle to send next over `link` out of the queues of all destinations reached
+    /// through it. Returns the position of the queue it was taken from.
+    pub fn next(
+        &mut self,
+        link: &Endpoint,
+        queues: &mut [(&Endpoint, &mut BundleQueue)],
+    ) -> Option<(usize, PriorityClass, Queued)> {
+        if let Some((position, class, entry)) = self.starving(queues) {
+            let queued = queues[position].1.classes[index(class)]
+                .remove(entry)
+                .expect("the starving bundle is queued");
+            return Some((position, class, queued));
+        }
+        let (position, class) = self.pick(link, queues)?;
+        let queued = queues[position].1.classes[index(class)]
+            .pop_front()
+            .expect("only queues with bundles are picked");
+        Some((position, class, queued))
+    }
+
+    /// The bundle that waits the longest, if that is longer than allowed. Returns the position
+    /// of its queue, its class and where it is in the queue of that class.
+    fn starving(
+        &self,
+        queues: &[(&Endpoint, &mut BundleQueue)],
+    ) -> Option<(usize, PriorityClass, usize)> {
+        let timeout = self.starvation_timeout?;
+        let (since, position, class, entry) = queues
+            .iter()
+            .enumerate()
+            .flat_map(|(position, (_, queue))| {
+                CLASSES.into_iter().filter_map(move |class| {
+                    let (entry, oldest) = queue.classes[index(class)]
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, q)| q.since)?;
+                    Some((oldest.since, position, class, entry))
+                })
+            })
+            .min_by_key(|(since, _, _, _)| *since)?;
+        (since.elapsed() >= timeout).then_some((position, class, entry))
+    }
+
+    fn pick(
+        &mut self,
+        link: &Endpoint,
+        queues: &[(&Endpoint, &mut BundleQueue)],
+    ) -> Option<(usize, PriorityClass)> {
+        let waiting: Vec<PriorityClass> = CLASSES
+            .into_iter()
+            .rev()
+            .filter(|class| queues.iter().any(|(_, queue)| queue.len(*class) > 0))
+            .collect();
+        let state = self.links.entry(link.clone()).or_default();
+        let class = match self.mode {
+            SchedulingMode::Strict => *waiting.first()?,
+            SchedulingMode::WeightedFair => {
+                let with_credit = |state: &LinkState| {
+                    waiting
+                        .iter()
+                        .copied()
+                        .find(|class| state.sent[index(*class)] < WEIGHTS[index(*class)])
+                };
+                let class = if let Some(class) = with_credit(state) {
+                    class
+                } else {
+                    // every class with bundles used its share, start the next round
+                    state.sent = [0; CLASSES.len()];
+                    with_credit(state)?
+                };
+                state.sent[index(class)] += 1;
+                class
+            }
+        };
+
+        // take turns between the destinations, in the order of their endpoints
+        let mut candidates: Vec<(usize, &Endpoint)> = queues
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, queue))| queue.len(class) > 0)
+            .map(|(position, (destination, _))| (position, *destination))
+            .collect();
+        candidates.sort_by_key(|(_, destination)| *destination);
+        let last = &state.last_destination[index(class)];
+        let (position, destination) = candidates
+            .iter()
+            .find(|(_, destination)| last.as_ref().is_some_and(|last| *destination > last))
+            .or(candidates.first())
+            .copied()?;
+        state.last_destination[index(class)] = Some(destination.clone());
+        Some((position, class))
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use bp7::endpoint::Endpoint;
+
+use crate::routingagent::messages::NexthopInfo;
+
+/// Spreads the bundles of each destination over its equal cost next hops according to their
+/// weights, using smooth weighted round robin so the next hops take turns.
+#[derive(Debug, Default)]
+pub struct Spreading {
+    /// How far each next hop of a destination is ahead of its share.
+    current: HashMap<Endpoint, HashMap<Endpoint, i128>>,
+}
+
+impl Spreading {
+    /// Picks the next hop for the next bundle to `destination`. `next_hops` must not be empty.
+    pub fn pick<'a>(
+        &mut self,
+        destination: &Endpoint,
+        next_hops: &'a [NexthopInfo],
+    ) -> &'a Endpoint {
+        let current = self.current.entry(destination.clone()).or_default();
+        current.retain(|next_hop, _| next_hops.iter().any(|n| &n.next_hop == next_hop));
+        let total: i128 = next_hops.iter().map(|n| i128::from(n.weight)).sum();
+        let mut picked = &next_hops[0];
+        let mut picked_current = i128::MIN;
+        for next_hop in next_hops {
+            let value = current.entry(next_hop.next_hop.clone()).or_default();
+            *value += i128::from(next_hop.weight);
+            if *value > picked_current {
+                picked = next_hop;
+                picked_current = *value;
+            }
+        }
+        *current.get_mut(&picked.next_hop).unwrap() -= total;
+        &picked.next_hop
+    }
+
+    /// Starts over, e.g. because the routes changed.
+    pub fn reset(&mut self) {
+        self.current.clear();
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, VecDeque};
+
+use bp7::{
+    administrative_record::bundle_status_report::{BundleStatusItem, BundleStatusReport},
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
+};
+
+use super::messages::{BundleHistory, BundleStatus, StatusEvent};
+use crate::common::settings::Settings;
+
+type BundleKey = (Endpoint, CreationTimestamp);
+
+/// What the status reports we received say about the bundles they are about. Only the
+/// `capacity` bundles we heard about most recently are kept.
+#[derive(Debug, Default)]
+pub struct StatusHistory {
+    capacity: usize,
+    bundles: HashMap<BundleKey, Vec<StatusEvent>>,
+    /// The bundles in the order we first heard about them.
+    order: VecDeque<BundleKey>,
+}
+
+impl StatusHistory {
+    pub fn from_settings(settings: &Settings) -> Self {
+        StatusHistory {
+            capacity: settings.status_report_history_size,
+            ..StatusHistory::default()
+        }
+    }
+
+    pub fn record(&mut self, report: &BundleStatusReport, reporting_node: &Endpoint) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = DtnTime::now();
+        let info = &report.status_information;
+        let key = (
+            report.bundle_source.clone(),
+            report.bundle_creation_timestamp.clone(),
+        );
+        if !self.bundles.contains_key(&key) {
+            if self.bundles.len() >= self.capacity
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.bundles.remove(&oldest);
+            }
+            self.order.push_back(key.clone());
+        }
+        let events = self.bundles.entry(key).or_default();
+        for (status, item) in [
+            (BundleStatus::Received, &info.received_bundle),
+            (BundleStatus::Forwarded, &info.forwarded_bundle),
+            (BundleStatus::Delivered, &info.delivered_bundle),
+            (BundleStatus::Deleted, &info.deleted_bundle),
+        ] {
+            let BundleStatusItem {
+                is_asserted: true,
+                timestamp,
+            } = item
+            else {
+                continue;
+            };
+            events.push(StatusEvent {
+                status,
+                time: timestamp.unwrap_or(now),
+                reporting_node: reporting_node.clone(),
+                reason: report.reason,
+                fragment_offset: report.fragment_offset,
+                fragment_length: report.fragment_length,
+            });
+        }
+        // reports do not necessarily arrive in the order they were sent
+        events.sort_by_key(|event| event.time);
+    }
+
+    /// The history of all bundles we know about, or only of those from `source` if given.
+    pub fn list(&self, source: Option<&Endpoint>) -> Vec<BundleHistory> {
+        self.order
+            .iter()
+            .filter(|(bundle_source, _)| source.is_none_or(|s| s.matches_node(bundle_source)))
+            .map(|key| BundleHistory {
+                source: key.0.clone(),
+                creation_timestamp: key.1.clone(),
+                events: self.bundles[key].clone(),
+            })
+            .collect()
+    }
+}
@@ -15,16 +15,30 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{path::PathBuf, sync::Arc};
+use std::{sync::Arc, time::Duration};
 
-use bp7::{bundle::Bundle, endpoint::Endpoint, time::DtnTime};
+use bp7::{
+    bundle::Bundle,
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
+};
 use log::{debug, info, warn};
-use tokio::{fs, io::AsyncWriteExt};
+use tcpcl::{receive_limit::ReceiveLimit, transfer::TransferFile};
+use tokio::{fs, sync::watch};
 
 use crate::{
     bundlestorageagent::{
         State, StoredBundleRef,
-        messages::{EventBundleUpdated, UpdateBundle},
+        index::BundleIndex,
+        messages::{
+            EventBundleEvicted, EventBundleExpired, EventBundleFragmentationFailed,
+            EventBundleUpdated, EventDuplicateBundleReceived, GetReceiveLimit,
+            GetStorageStatistics, RecordFailedAttempt, StorageFull, StorageStatistics,
+            UpdateBundle,
+        },
+        quota::Quota,
+        seen::SeenBundles,
+        store::{self, BundleStore},
     },
     common::settings::Settings,
 };
@@ -32,17 +46,30 @@ use crate::{
 use super::{
     StoredBundle,
     messages::{
-        FragmentBundle, GetBundleForDestination, GetBundleForNode, StoreNewBundle,
-        StoreReceivedBundle,
+        FragmentBundle, FragmentBundleRemainder, GetBundleForDestination, GetBundleForNode,
+        ReceivedBundle, StoreNewBundle, StoreReceivedBundle,
     },
 };
 use actix::prelude::*;
 
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How long changes to the seen bundles are collected before they are written.
+const SEEN_BUNDLES_WRITE_DELAY: Duration = Duration::from_secs(1);
+
 #[derive(Default)]
 pub struct Daemon {
-    bundles: Vec<StoredBundle>,
+    bundles: BundleIndex,
     endpoint: Option<Endpoint>,
-    storage_path: PathBuf,
+    store: Option<Box<dyn BundleStore>>,
+    quota: Quota,
+    receive_limit: watch::Sender<u64>,
+    /// Shared with the convergence layers, which reserve space in it for the bundles they
+    /// receive.
+    shared_receive_limit: Option<ReceiveLimit>,
+    seen: SeenBundles,
+    /// The seen bundles changed and will be written soon.
+    seen_write_pending: bool,
+    duplicate_bundles: u64,
     last_created_dtn_time: Option<DtnTime>,
     last_sequence_number: u64,
 }
@@ -52,73 +79,56 @@ impl Actor for Daemon {
     fn started(&mut self, ctx: &mut Context<Self>) {
         let settings = Settings::from_env();
         self.endpoint = Some(Endpoint::new(&settings.my_node_id).unwrap());
-        self.storage_path = settings.bundle_storage_path.into();
-
-        let storage_path = self.storage_path.clone();
-        let fut = async move {
-            info!("Loading existing bundles");
-            let meta = fs::metadata(&storage_path).await;
-            assert!(
-                meta.is_ok(),
-                "Bundle storage path must point to an existing directory"
-            );
-            if let Ok(m) = meta
-                && !m.is_dir()
-            {
-                panic!("Bundle storage path must point to a valid directory");
-            }
-
-            let mut existing_bundles = Vec::new();
-
-            let mut readdir = fs::read_dir(&storage_path)
-                .await
-                .expect("Failed to read existing bundles");
-
-            while let Some(entry) = readdir
-                .next_entry()
-                .await
-                .expect("Failed to read dir entry")
-            {
-                debug!(
-                    "Loading existing bundle from {}",
-                    entry.path().to_string_lossy()
-                );
-                let meta = entry.metadata().await.expect("Failed to read metadata");
-                if !meta.is_file() {
-                    warn!(
-                        "Skip loading existing bundle {} as it is not a file",
-                        entry.path().to_string_lossy()
-                    );
-                    continue;
+        self.store = Some(store::from_settings(&settings));
+        self.quota = Quota::from_settings(&settings);
+        self.seen = SeenBundles::new(settings.bundle_duplicate_cache_size);
+        self.shared_receive_limit = Some(ReceiveLimit::new(self.receive_limit.subscribe()));
+
+        ctx.run_interval(EXPIRY_CHECK_INTERVAL, Self::expire_bundles);
+
+        self.store()
+            .load_seen_bundles()
+            .into_actor(self)
+            .then(|data, act, _ctx| {
+                match data {
+                    Ok(Some(data)) => act.seen.load(&data),
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to read the seen bundles: {e}"),
                 }
+                fut::ready(())
+            })
+            .wait(ctx);
 
-                let content = fs::read(entry.path()).await.expect("Failed to read bundle");
-                let mut sb = StoredBundle::from(content);
-                if sb.get_filename()
-                    != entry
-                        .path()
-                        .file_name()
-                        .expect("Can not happen")
-                        .to_string_lossy()
-                {
-                    panic!("No idea how we ended up here, someone wrote something wrong");
+        info!("Loading existing bundles");
+        self.store()
+            .load()
+            .into_actor(self)
+            .then(|bundles, act, ctx| {
+                let (bundles, report) = bundles.expect("Failed to load existing bundles");
+                for (name, corruption) in &report.quarantined {
+                    warn!("Moved stored bundle {name} to quarantine: {corruption}");
                 }
-                info!("Loaded bundle {}", sb.get_id());
-                sb.state = State::Valid;
-                existing_bundles.push(sb);
-            }
-
-            existing_bundles
-        };
-        fut.into_actor(self)
-            .then(|bundles, act, _ctx| {
-                act.bundles = bundles;
-                for bundle in &act.bundles {
-                    crate::bundleprotocolagent::agent::Daemon::from_registry().do_send(
-                        EventBundleUpdated {
-                            bundle: bundle.get_ref(),
-                        },
-                    );
+                info!("Bundle storage check done: {report}");
+                let mut defragmentation_pending = Vec::new();
+                for bundle in bundles {
+                    if matches!(bundle.state, State::DefragmentationPending) {
+                        defragmentation_pending.push(bundle.get_ref());
+                    } else {
+                        crate::bundleprotocolagent::agent::Daemon::from_registry().do_send(
+                            EventBundleUpdated {
+                                bundle: bundle.get_ref(),
+                            },
+                        );
+                    }
+                    act.seen.insert(&bundle);
+                    act.bundles.insert(bundle);
+                }
+                act.update_receive_limit();
+                for sbr in defragmentation_pending {
+                    // Reassembling an earlier fragment might already have consumed this one.
+                    if act.bundles.contains(&sbr.get_id()) {
+                        act.try_defragment_bundle(ctx, &sbr);
+                    }
                 }
                 fut::ready(())
             })
@@ -143,15 +153,103 @@ impl Handler<StoreReceivedBundle> for Daemon {
     type Result = ();
 
     fn handle(&mut self, msg: StoreReceivedBundle, ctx: &mut Context<Self>) -> Self::Result {
-        let StoreReceivedBundle { bundle_data } = msg;
-        self.store_bundle(ctx, bundle_data, None, State::Received, false);
+        let StoreReceivedBundle {
+            bundle,
+            reservation,
+        } = msg;
+        let (sb, custodian, received_file) = match bundle {
+            ReceivedBundle::Memory(bundle_data) => {
+                let sb: StoredBundle = bundle_data.into();
+                let custodian = sb.get_bundle().custodian().cloned();
+                (sb, custodian, None)
+            }
+            ReceivedBundle::File {
+                file,
+                head,
+                tail,
+                size,
+                payload_size,
+            } => match Bundle::without_payload(&head, &tail) {
+                Ok(bundle) => (
+                    StoredBundle::without_data(&bundle, size, payload_size),
+                    bundle.custodian().cloned(),
+                    Some(file),
+                ),
+                Err(e) => {
+                    warn!("Dropping received bundle as it could not be parsed: {e:?}");
+                    return;
+                }
+            },
+        };
+        if self.seen.contains(&sb) {
+            info!(
+                "Dropping received bundle {} as we got it before",
+                sb.get_id()
+            );
+            self.duplicate_bundles += 1;
+            if let Some(custodian) = custodian {
+                crate::bundleprotocolagent::agent::Daemon::from_registry().do_send(
+                    EventDuplicateBundleReceived {
+                        bundle: sb.get_ref(),
+                        custodian,
+                    },
+                );
+            }
+            return;
+        }
+        // the space reserved for this bundle is what it takes once stored
+        let reserved = self.reserved_bytes().saturating_sub(reservation.bytes());
+        if self.make_room(ctx, &sb, reserved).is_err() {
+            info!(
+                "Dropping received bundle {} as there is no space left to store it",
+                sb.get_id()
+            );
+            crate::bundleprotocolagent::agent::Daemon::from_registry().do_send(
+                EventBundleEvicted {
+                    bundle: sb.get_ref(),
+                    custodian,
+                },
+            );
+            return;
+        }
+        let Some(file) = received_file else {
+            self.store_bundle(ctx, sb, None, State::Received, false, None);
+            return;
+        };
+        // Only bundles we store are read, the storage holds the data of all of them.
+        let id = sb.get_id();
+        let path = file.path().to_path_buf();
+        fs::read(path)
+            .into_actor(self)
+            .map(move |res, act, ctx| {
+                // released once the bundle is stored and counts towards the receive limit
+                let _reservation = reservation;
+                let bundle_data = match res {
+                    Ok(data) if Bundle::try_from(data.as_slice()).is_ok() => data,
+                    Ok(_) => {
+                        warn!("Dropping received bundle {id} as it could not be parsed");
+                        return;
+                    }
+                    Err(e) => {
+                        warn!("Dropping received bundle {id} as it could not be read: {e}");
+                        return;
+                    }
+                };
+                let sb: StoredBundle = bundle_data.into();
+                if sb.get_id() != id {
+                    warn!("Dropping received bundle {id} as its file changed");
+                    return;
+                }
+                act.store_bundle(ctx, sb, None, State::Received, false, Some(file));
+            })
+            .wait(ctx);
     }
 }
 
 impl Handler<StoreNewBundle> for Daemon {
-    type Result = Result<(), ()>;
+    type Result = AtomicResponse<Self, Result<CreationTimestamp, StorageFull>>;
 
-    fn handle(&mut self, msg: StoreNewBundle, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: StoreNewBundle, ctx: &mut Self::Context) -> Self::Result {
         let StoreNewBundle { bundle_data } = msg;
         let mut bundle: Bundle = bundle_data.as_slice().try_into().unwrap();
 
@@ -179,19 +277,33 @@ impl Handler<StoreNewBundle> for Daemon {
             self.last_sequence_number = 0;
             0
         };
-        debug!("Decided sequence number {sequence_number:?} for new bundle",);
+        debug!("Decided sequence number {sequence_number:?} for new bundle");
         bundle.primary_block.creation_timestamp.sequence_number = sequence_number;
+        let creation_timestamp = bundle.primary_block.creation_timestamp.clone();
 
         debug!("Storing new Bundle {:?} for later", bundle.primary_block);
         let bundle_data: Vec<u8> = bundle.try_into().unwrap();
-        let sb: StoredBundle = bundle_data.into();
+        let mut sb: StoredBundle = bundle_data.into();
+        if let Err(e) = self.make_room(ctx, &sb, self.reserved_bytes()) {
+            info!(
+                "Refusing new bundle {} as there is no space left to store it",
+                sb.get_id()
+            );
+            return AtomicResponse::new(Box::pin(fut::ready(Err(e))));
+        }
+        let write = self.write_bundle_to_file(&mut sb);
+        sb.persisted = true;
         let sb_ref = sb.get_ref();
 
-        self.bundles.push(sb);
+        self.seen.insert(&sb);
+        self.schedule_seen_bundles_write(ctx);
+        self.bundles.insert(sb);
+        self.update_receive_limit();
         crate::bundleprotocolagent::agent::Daemon::from_registry()
             .do_send(EventBundleUpdated { bundle: sb_ref });
 
-        Ok(())
+        // The client only gets an answer once the bundle is written, so it survives a restart.
+        AtomicResponse::new(Box::pin(write.map(|(), _act, _ctx| Ok(creation_timestamp))))
     }
 }
 
@@ -203,58 +315,103 @@ impl Handler<FragmentBundle> for Daemon {
             bundleref,
             target_size,
         } = msg;
-        let mut sb = if let Some(idx) = self.bundles.iter().position(|b| b == bundleref) {
-            self.bundles.remove(idx)
-        } else {
+        let Some(mut sb) = self.bundles.remove(&bundleref.get_id()) else {
             warn!("Trying to fragment bundle, but could not find it. Not fragmenting it.");
             return;
         };
 
-        match sb.get_bundle().fragment(target_size as usize) {
-            Ok((bundles, first_min_size, min_size)) => {
-                let mut iterator = bundles.into_iter();
-                self.store_bundle(
-                    ctx,
-                    iterator
-                        .next()
-                        .expect("There must always be at least one")
-                        .try_into()
-                        .unwrap(),
-                    Some(first_min_size),
-                    State::Valid,
-                    true,
-                );
-
-                for bundle in iterator {
+        let fragments = sb.get_bundle().fragment(target_size as usize).and_then(
+            |(bundles, first_min_size, min_size)| {
+                bundles
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, bundle)| {
+                        let min_size = if i == 0 { first_min_size } else { min_size };
+                        Ok((Vec::<u8>::try_from(bundle)?, min_size))
+                    })
+                    .collect::<Result<Vec<_>, bp7::FragmentationError>>()
+            },
+        );
+        match fragments {
+            Ok(fragments) => {
+                for (bundle_data, min_size) in fragments {
                     self.store_bundle(
                         ctx,
-                        bundle.try_into().unwrap(),
+                        bundle_data.into(),
                         Some(min_size),
                         State::Valid,
                         true,
+                        None,
                     );
                 }
 
                 self.delete_bundle_file(ctx, &sb);
             }
-            Err(e) => match e {
-                bp7::FragmentationError::SerializationError(e) => {
-                    panic!("Error fragmenting bundle: {e:?}")
-                }
-                bp7::FragmentationError::CanNotFragmentThatSmall(min_size) => {
-                    sb.min_size = Some(min_size);
-                    let sbr = sb.get_ref();
-                    self.bundles.push(sb);
-                    crate::bundleprotocolagent::agent::Daemon::from_registry()
-                        .do_send(EventBundleUpdated { bundle: sbr });
-                }
-                bp7::FragmentationError::MustNotFragment => {
-                    panic!("Attempted to fragment a bundle that must not be fragmented")
-                }
-                bp7::FragmentationError::BundleInvalid => {
-                    panic!("Attempted to fragment a invalid bundle")
-                }
-            },
+            Err(bp7::FragmentationError::CanNotFragmentThatSmall(min_size)) => {
+                sb.min_size = Some(min_size);
+                self.persist_state(ctx, &mut sb);
+                let sbr = sb.get_ref();
+                self.bundles.insert(sb);
+                crate::bundleprotocolagent::agent::Daemon::from_registry()
+                    .do_send(EventBundleUpdated { bundle: sbr });
+            }
+            Err(e) => {
+                warn!(
+                    "Can not fragment bundle {}: {e:?}. Keeping it whole",
+                    sb.get_id()
+                );
+                // so it is only forwarded to peers that take it whole instead of failing again
+                sb.min_size = Some(sb.size);
+                self.persist_state(ctx, &mut sb);
+                let sbr = sb.get_ref();
+                self.bundles.insert(sb);
+                crate::bundleprotocolagent::agent::Daemon::from_registry()
+                    .do_send(EventBundleFragmentationFailed { bundle: sbr });
+            }
+        }
+    }
+}
+
+impl Handler<FragmentBundleRemainder> for Daemon {
+    type Result = ();
+
+    fn handle(&mut self, msg: FragmentBundleRemainder, ctx: &mut Self::Context) -> Self::Result {
+        let FragmentBundleRemainder {
+            bundleref,
+            received_payload,
+        } = msg;
+        let Some(sb) = self.bundles.remove(&bundleref.get_id()) else {
+            warn!("Trying to fragment bundle, but could not find it. Not fragmenting it.");
+            return;
+        };
+
+        let remainder = usize::try_from(received_payload)
+            .map_err(|_| bp7::FragmentationError::BundleInvalid)
+            .and_then(|received| sb.get_bundle().remaining_fragment(received))
+            .and_then(|remainder| Ok(remainder.map(Vec::<u8>::try_from).transpose()?));
+        if let Ok(Some(bundle_data)) = remainder {
+            debug!(
+                "Bundle {} was partially forwarded, only forwarding the remaining fragment",
+                sb.get_id()
+            );
+            self.store_bundle(
+                ctx,
+                bundle_data.into(),
+                None,
+                State::ForwardingQueued,
+                true,
+                None,
+            );
+            self.delete_bundle_file(ctx, &sb);
+        } else {
+            debug!(
+                "Can not fragment bundle {} after {received_payload} bytes of payload. Forwarding it again completely",
+                sb.get_id()
+            );
+            let sbr = sb.get_ref();
+            self.bundles.insert(sb);
+            crate::bundleprotocolagent::agent::Daemon::from_registry()
+                .do_send(EventBundleUpdated { bundle: sbr });
         }
     }
 }
@@ -268,15 +425,7 @@ impl Handler<UpdateBundle> for Daemon {
             new_state,
             new_data,
         } = msg;
-        if let Some(idx) = self.bundles.iter().position(|b| b == bundleref) {
-            let mut bundle = self.bundles.remove(idx);
-
-            if matches!(new_state, State::Valid) && !matches!(bundle.state, State::Valid) {
-                // Since the bundle is now valid for the first time we should store it.
-                // We need to exclude existing valid bundles, otherwise we redo this on startup.
-                self.write_bundle_to_file(ctx, &bundle);
-            }
-
+        if let Some(mut bundle) = self.bundles.remove(&bundleref.get_id()) {
             debug!("Bundle {} is in new state {new_state:?}", bundle.get_id());
             match new_state {
                 State::Valid | State::DeliveryQueued | State::ForwardingQueued => {
@@ -285,11 +434,11 @@ impl Handler<UpdateBundle> for Daemon {
                     bundle.state = new_state;
                     if let Some(data) = new_data {
                         bundle.bundle_data = Arc::new(data);
-                        // TODO: we need to write the file again, but we then also need to save the
-                        // state.
+                        bundle.modified = true;
                     }
+                    self.persist_state(ctx, &mut bundle);
                     let sbr = bundle.get_ref();
-                    self.bundles.push(bundle);
+                    self.bundles.insert(bundle);
                     crate::bundleprotocolagent::agent::Daemon::from_registry()
                         .do_send(EventBundleUpdated { bundle: sbr });
                 }
@@ -297,16 +446,47 @@ impl Handler<UpdateBundle> for Daemon {
                     // We try to defragment the bundle now, if we can we remove
                     // all fragments and send the bundle back as valid.
                     // If we fail we just wait until we receive the other fragments.
+                    bundle.state = new_state;
+                    self.persist_state(ctx, &mut bundle);
                     let sbr = bundle.get_ref();
-                    self.bundles.push(bundle);
+                    self.bundles.insert(bundle);
                     self.try_defragment_bundle(ctx, &sbr);
                 }
+                State::CustodyPending => {
+                    // The BPA keeps track of the bundles it has custody of, it only needs to hear
+                    // about them again when they are loaded after a restart.
+                    bundle.state = new_state;
+                    self.persist_state(ctx, &mut bundle);
+                    self.bundles.insert(bundle);
+                }
                 State::Delivered | State::Forwarded | State::Invalid => {
-                    // We are done, delete the file
-                    self.delete_bundle_file(ctx, &bundle);
+                    // We are done, delete the file. Bundles expiring before they got valid
+                    // were never written.
+                    if bundle.persisted {
+                        self.delete_bundle_file(ctx, &bundle);
+                    }
                 }
                 State::Received => unreachable!(),
             }
+            self.update_receive_limit();
+        }
+    }
+}
+
+impl Handler<RecordFailedAttempt> for Daemon {
+    type Result = ();
+
+    fn handle(&mut self, msg: RecordFailedAttempt, ctx: &mut Context<Self>) {
+        let RecordFailedAttempt { bundleref } = msg;
+        if let Some(mut bundle) = self.bundles.remove(&bundleref.get_id()) {
+            bundle.failed_attempts += 1;
+            debug!(
+                "Bundle {} failed to be sent {} times",
+                bundle.get_id(),
+                bundle.failed_attempts
+            );
+            self.persist_state(ctx, &mut bundle);
+            self.bundles.insert(bundle);
         }
     }
 }
@@ -316,17 +496,12 @@ impl Handler<GetBundleForDestination> for Daemon {
 
     fn handle(&mut self, msg: GetBundleForDestination, _ctx: &mut Context<Self>) -> Self::Result {
         let GetBundleForDestination { destination } = msg;
-        let mut ret = Vec::new();
-        for i in 0..self.bundles.len() {
-            if self.bundles[i]
-                .get_bundle()
-                .primary_block
-                .destination_endpoint
-                == destination
-            {
-                ret.push(self.bundles[i].get_ref());
-            }
-        }
+        let ret: Vec<_> = self
+            .bundles
+            .for_node(&destination)
+            .filter(|b| b.primary_block.destination_endpoint == destination)
+            .map(StoredBundle::get_ref)
+            .collect();
         debug!(
             "Returning {} bundles for destination {}",
             ret.len(),
@@ -341,17 +516,11 @@ impl Handler<GetBundleForNode> for Daemon {
 
     fn handle(&mut self, msg: GetBundleForNode, _ctx: &mut Context<Self>) -> Self::Result {
         let GetBundleForNode { destination } = msg;
-        let mut ret = Vec::new();
-        for i in 0..self.bundles.len() {
-            if self.bundles[i]
-                .get_bundle()
-                .primary_block
-                .destination_endpoint
-                .matches_node(&destination)
-            {
-                ret.push(self.bundles[i].get_ref());
-            }
-        }
+        let ret: Vec<_> = self
+            .bundles
+            .for_node(&destination)
+            .map(StoredBundle::get_ref)
+            .collect();
         debug!(
             "Returning {} bundles for destination {}",
             ret.len(),
@@ -361,16 +530,38 @@ impl Handler<GetBundleForNode> for Daemon {
     }
 }
 
+impl Handler<GetStorageStatistics> for Daemon {
+    type Result = MessageResult<GetStorageStatistics>;
+
+    fn handle(&mut self, _msg: GetStorageStatistics, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(StorageStatistics {
+            duplicate_bundles: self.duplicate_bundles,
+        })
+    }
+}
+
+impl Handler<GetReceiveLimit> for Daemon {
+    type Result = MessageResult<GetReceiveLimit>;
+
+    fn handle(&mut self, _msg: GetReceiveLimit, _ctx: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.shared_receive_limit
+                .clone()
+                .expect("The receive limit is set once the actor started"),
+        )
+    }
+}
+
 impl Daemon {
     fn store_bundle(
         &mut self,
         ctx: &mut Context<Self>,
-        bundle_data: Vec<u8>,
+        mut sb: StoredBundle,
         min_size: Option<u64>,
         state: State,
         persist: bool,
+        received_file: Option<TransferFile>,
     ) -> &StoredBundle {
-        let mut sb: StoredBundle = bundle_data.into();
         sb.min_size = min_size;
         sb.state = state;
 
@@ -379,44 +570,162 @@ impl Daemon {
             sb.get_id()
         );
 
-        if persist {
-            self.write_bundle_to_file(ctx, &sb);
+        if let Some(file) = received_file {
+            self.adopt_bundle_file(ctx, &sb, file);
+            sb.persisted = true;
+        } else if persist {
+            self.write_bundle_to_file(&mut sb).wait(ctx);
+            sb.persisted = true;
         }
 
+        self.seen.insert(&sb);
+        self.schedule_seen_bundles_write(ctx);
+
+        let id = sb.get_id();
         let sbr = sb.get_ref();
-        self.bundles.push(sb);
+        self.bundles.insert(sb);
+        self.update_receive_limit();
         crate::bundleprotocolagent::agent::Daemon::from_registry()
             .do_send(EventBundleUpdated { bundle: sbr });
-        self.bundles.last().expect("we just pushed something")
+        self.bundles.get(&id).expect("we just inserted it")
     }
 
-    fn write_bundle_to_file(&self, ctx: &mut Context<Self>, bundle: &StoredBundle) {
-        let mut path = self.storage_path.clone();
-        path.push(bundle.get_filename());
-        debug!("Storing bundle to {}", path.to_string_lossy());
-        let data = bundle.bundle_data.clone();
-        let fut = async move {
-            let mut file = fs::File::create(path).await?;
-            file.write_all(&data).await?;
-            file.sync_all().await?;
-            Ok(())
-        };
-        fut.into_actor(self)
-            .then(|res: std::io::Result<()>, _act, _ctx| {
+    /// Deletes bundles as allowed by the storage policy until `bundle` fits into the storage,
+    /// next to the `reserved` bytes of bundles that are still being received.
+    fn make_room(
+        &mut self,
+        ctx: &mut Context<Self>,
+        bundle: &StoredBundle,
+        reserved: u64,
+    ) -> Result<(), StorageFull> {
+        for id in self
+            .quota
+            .bundles_to_evict(&self.bundles, bundle, reserved)?
+        {
+            let evicted = self
+                .bundles
+                .remove(&id)
+                .expect("Only stored bundles are evicted");
+            info!(
+                "Evicting bundle {id} to make room for bundle {}",
+                bundle.get_id()
+            );
+            if evicted.persisted {
+                self.delete_bundle_file(ctx, &evicted);
+            }
+            crate::bundleprotocolagent::agent::Daemon::from_registry().do_send(
+                EventBundleEvicted {
+                    bundle: evicted.get_ref(),
+                    custodian: None,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// The bytes the convergence layers reserved for bundles they receive.
+    fn reserved_bytes(&self) -> u64 {
+        self.shared_receive_limit
+            .as_ref()
+            .map_or(0, ReceiveLimit::reserved)
+    }
+
+    fn update_receive_limit(&self) {
+        self.receive_limit
+            .send_replace(self.quota.receive_limit(&self.bundles));
+    }
+
+    /// Stores the current processing state of the bundle. Received bundles that are not stored
+    /// yet are written to disk once they are valid.
+    fn persist_state(&self, ctx: &mut Context<Self>, bundle: &mut StoredBundle) {
+        if bundle.persisted {
+            self.write_metadata_to_file(ctx, bundle);
+        } else if !matches!(bundle.state, State::Received) {
+            self.write_bundle_to_file(bundle).wait(ctx);
+            bundle.persisted = true;
+        }
+    }
+
+    /// Writes the seen bundles after a short delay, together with all changes until then.
+    fn schedule_seen_bundles_write(&mut self, ctx: &mut Context<Self>) {
+        if self.seen_write_pending {
+            return;
+        }
+        self.seen_write_pending = true;
+        ctx.run_later(SEEN_BUNDLES_WRITE_DELAY, |act, ctx| {
+            act.seen_write_pending = false;
+            act.store()
+                .write_seen_bundles(act.seen.serialize())
+                .into_actor(act)
+                .then(|res, _act, _ctx| {
+                    if let Err(e) = res {
+                        warn!("Failed to write the seen bundles: {e}");
+                    }
+                    fut::ready(())
+                })
+                .wait(ctx);
+        });
+    }
+
+    fn store(&self) -> &dyn BundleStore {
+        self.store
+            .as_deref()
+            .expect("The store is set once the actor started")
+    }
+
+    /// Writes the bundle together with its metadata once the returned future runs.
+    fn write_bundle_to_file(
+        &self,
+        bundle: &mut StoredBundle,
+    ) -> impl ActorFuture<Self, Output = ()> + use<> {
+        bundle.modified = false;
+        bundle.checksum = crc32fast::hash(&bundle.bundle_data);
+        self.store()
+            .write_bundle(bundle)
+            .into_actor(self)
+            .then(|res, _act, _ctx| {
                 if let Err(e) = res {
                     warn!("Failed to write: {e}");
                 }
                 fut::ready(())
             })
+    }
+
+    fn write_metadata_to_file(&self, ctx: &mut Context<Self>, bundle: &StoredBundle) {
+        self.store()
+            .write_metadata(bundle)
+            .into_actor(self)
+            .then(|res, _act, _ctx| {
+                if let Err(e) = res {
+                    warn!("Failed to write metadata: {e}");
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn adopt_bundle_file(
+        &self,
+        ctx: &mut Context<Self>,
+        bundle: &StoredBundle,
+        file: TransferFile,
+    ) {
+        self.store()
+            .adopt_file(bundle, file)
+            .into_actor(self)
+            .then(|res, _act, _ctx| {
+                if let Err(e) = res {
+                    warn!("Failed to adopt received bundle file: {e}");
+                }
+                fut::ready(())
+            })
             .wait(ctx);
     }
 
     fn delete_bundle_file(&self, ctx: &mut Context<Self>, bundle: &StoredBundle) {
-        let mut path = self.storage_path.clone();
-        path.push(bundle.get_filename());
-        debug!("Deleting bundle from {}", path.to_string_lossy());
-        let fut = async move { fs::remove_file(path).await };
-        fut.into_actor(self)
+        self.store()
+            .delete(bundle)
+            .into_actor(self)
             .then(|res, _act, _ctx| {
                 if let Err(e) = res {
                     warn!("Failed to delete bundle: {e}");
@@ -426,35 +735,49 @@ impl Daemon {
             .spawn(ctx);
     }
 
+    /// Passes all expired bundles to the BPA, which deletes them.
+    fn expire_bundles(&mut self, ctx: &mut Context<Self>) {
+        if self.seen.expire(DtnTime::now()) {
+            self.schedule_seen_bundles_write(ctx);
+        }
+        for bundle in self.bundles.take_expired(DtnTime::now()) {
+            debug!("Bundle {} expired", bundle.get_id());
+            crate::bundleprotocolagent::agent::Daemon::from_registry()
+                .do_send(EventBundleExpired { bundle });
+        }
+    }
+
     fn try_defragment_bundle(&mut self, ctx: &mut Context<Self>, bundle: &StoredBundleRef) {
         let requested_primary_block = bundle.get_primary_block().clone();
 
-        let mut i = 0;
-        let mut fragments: Vec<StoredBundle> = Vec::new();
-        while i < self.bundles.len() {
-            if self.bundles[i]
-                .get_bundle()
-                .primary_block
-                .equals_ignoring_fragment_info(&requested_primary_block)
-            {
-                fragments.push(self.bundles.remove(i));
-            } else {
-                i += 1;
-            }
-        }
+        let fragment_ids: Vec<_> = self
+            .bundles
+            .for_node(&requested_primary_block.destination_endpoint)
+            .filter(|b| {
+                b.primary_block
+                    .equals_ignoring_fragment_info(&requested_primary_block)
+            })
+            .map(StoredBundle::get_id)
+            .collect();
+        let fragments: Vec<StoredBundle> = fragment_ids
+            .iter()
+            .filter_map(|id| self.bundles.remove(id))
+            .collect();
 
         assert!(!fragments.is_empty());
         let fragments_ref = fragments.iter().map(|b| b.get_bundle()).collect();
         if let Ok(bundledata) = Bundle::reassemble_bundles(fragments_ref) {
-            let sb = self.store_bundle(ctx, bundledata, None, State::Valid, true);
+            let sb = self.store_bundle(ctx, bundledata.into(), None, State::Valid, true, None);
             debug!("Bundle {} sucessfully reassembled", sb.get_id());
 
-            // Delete the old fragments.
-            for fragment in fragments {
-                self.delete_bundle_file(ctx, &fragment);
+            // Delete the old fragments. Ones that were just received are not written yet.
+            for fragment in fragments.iter().filter(|f| f.persisted) {
+                self.delete_bundle_file(ctx, fragment);
             }
         } else {
-            self.bundles.append(&mut fragments);
+            for fragment in fragments {
+                self.bundles.insert(fragment);
+            }
         }
     }
 }
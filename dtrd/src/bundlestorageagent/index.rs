@@ -0,0 +1,123 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bp7::{endpoint::Endpoint, time::DtnTime};
+
+use super::{StoredBundle, StoredBundleRef};
+
+/// The bundles the storage agent holds, indexed so that lookups do not need to go through all
+/// of them.
+#[derive(Default)]
+pub struct BundleIndex {
+    bundles: HashMap<String, StoredBundle>,
+    /// Bundle IDs by the node of their destination.
+    by_node: HashMap<Endpoint, HashSet<String>>,
+    /// Bundle IDs ordered by when the bundles expire. Bundles already reported as expired are
+    /// not part of it.
+    by_expiry: BTreeSet<(DtnTime, String)>,
+    /// The size of the data of all bundles.
+    total_bytes: u64,
+}
+
+impl BundleIndex {
+    /// Adds the bundle, replacing one with the same ID.
+    pub fn insert(&mut self, bundle: StoredBundle) {
+        let id = bundle.get_id();
+        self.remove(&id);
+        self.by_node
+            .entry(node_of(&bundle))
+            .or_default()
+            .insert(id.clone());
+        if !bundle.expiring {
+            self.by_expiry.insert((bundle.expires_at, id.clone()));
+        }
+        self.total_bytes += bundle.bundle_data.len() as u64;
+        self.bundles.insert(id, bundle);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<StoredBundle> {
+        let bundle = self.bundles.remove(id)?;
+        let node = node_of(&bundle);
+        if let Some(ids) = self.by_node.get_mut(&node) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.by_node.remove(&node);
+            }
+        }
+        self.by_expiry.remove(&(bundle.expires_at, id.to_string()));
+        self.total_bytes -= bundle.bundle_data.len() as u64;
+        Some(bundle)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&StoredBundle> {
+        self.bundles.get(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.bundles.contains_key(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.bundles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bundles.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StoredBundle> {
+        self.bundles.values()
+    }
+
+    /// All bundles with a destination on the given node.
+    pub fn for_node(&self, node: &Endpoint) -> impl Iterator<Item = &StoredBundle> {
+        self.by_node
+            .get(&node.get_node_endpoint())
+            .into_iter()
+            .flatten()
+            .map(|id| &self.bundles[id])
+    }
+
+    /// Marks all bundles that expire at or before `time` as expiring and returns them. Every
+    /// bundle is only returned once.
+    pub fn take_expired(&mut self, time: DtnTime) -> Vec<StoredBundleRef> {
+        let mut expired = Vec::new();
+        while let Some((expires_at, id)) = self.by_expiry.pop_first() {
+            if expires_at > time {
+                self.by_expiry.insert((expires_at, id));
+                break;
+            }
+            let bundle = self.bundles.get_mut(&id).unwrap();
+            bundle.expiring = true;
+            expired.push(bundle.get_ref());
+        }
+        expired
+    }
+}
+
+fn node_of(bundle: &StoredBundle) -> Endpoint {
+    bundle
+        .primary_block
+        .destination_endpoint
+        .get_node_endpoint()
+}
@@ -15,7 +15,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use bp7::endpoint::Endpoint;
+use bp7::{endpoint::Endpoint, time::CreationTimestamp};
+use tcpcl::{
+    receive_limit::{ReceiveLimit, Reservation},
+    transfer::TransferFile,
+};
 
 use crate::bundlestorageagent::{State, StoredBundleRef};
 
@@ -27,14 +31,69 @@ pub struct EventBundleUpdated {
     pub bundle: StoredBundleRef,
 }
 
+/// The lifetime of the bundle is over. It must be deleted.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EventBundleExpired {
+    pub bundle: StoredBundleRef,
+}
+
+/// The bundle was deleted to stay within the storage limits, or could not be stored at all.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EventBundleEvicted {
+    pub bundle: StoredBundleRef,
+    /// The custodian named in a received bundle that could not be stored. It keeps custody.
+    pub custodian: Option<Endpoint>,
+}
+
+/// The bundle could not be fragmented. It is kept whole and has to be queued again.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EventBundleFragmentationFailed {
+    pub bundle: StoredBundleRef,
+}
+
+/// A bundle was received again and dropped. The custodian named in it did not learn that we
+/// took custody of it the first time.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EventDuplicateBundleReceived {
+    pub bundle: StoredBundleRef,
+    pub custodian: Endpoint,
+}
+
+/// Storing the bundle would exceed the storage limits.
+#[derive(Debug)]
+pub struct StorageFull;
+
+/// A bundle received from a peer.
+pub enum ReceivedBundle {
+    Memory(Vec<u8>),
+    /// Received into a file, which is adopted as the stored copy of the bundle. Only the parts
+    /// of the bundle before and after its payload data have been read, see
+    /// [`bp7::bundle::Bundle::without_payload`].
+    File {
+        file: TransferFile,
+        head: Vec<u8>,
+        tail: Vec<u8>,
+        size: u64,
+        payload_size: u64,
+    },
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct StoreReceivedBundle {
-    pub bundle_data: Vec<u8>,
+    pub bundle: ReceivedBundle,
+    /// The space the convergence layer reserved for the bundle while receiving it. Released
+    /// once the bundle is stored or dropped.
+    pub reservation: Reservation,
 }
 
+/// Answers with the creation timestamp the bundle got, its sequence number is only decided here.
 #[derive(Message)]
-#[rtype(result = "Result<(), ()>")]
+#[rtype(result = "Result<CreationTimestamp, StorageFull>")]
 pub struct StoreNewBundle {
     pub bundle_data: Vec<u8>,
 }
@@ -47,6 +106,13 @@ pub struct UpdateBundle {
     pub new_data: Option<Vec<u8>>,
 }
 
+/// Forwarding or delivering the bundle failed, it is queued again.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RecordFailedAttempt {
+    pub bundleref: StoredBundleRef,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct FragmentBundle {
@@ -54,6 +120,15 @@ pub struct FragmentBundle {
     pub target_size: u64,
 }
 
+/// Replaces the bundle with a fragment of the payload after `received_payload`, after the
+/// next node received the start of it (reactive fragmentation).
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct FragmentBundleRemainder {
+    pub bundleref: StoredBundleRef,
+    pub received_payload: u64,
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<Vec<StoredBundleRef>, String>")]
 pub struct GetBundleForDestination {
@@ -65,3 +140,19 @@ pub struct GetBundleForDestination {
 pub struct GetBundleForNode {
     pub destination: Endpoint,
 }
+
+#[derive(Debug, Clone, Copy)]
+pub struct StorageStatistics {
+    /// Received bundles that were dropped as we got them before.
+    pub duplicate_bundles: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "StorageStatistics")]
+pub struct GetStorageStatistics {}
+
+/// Gets the size of the largest bundle that can currently be stored. It is updated whenever
+/// bundles are stored or deleted. Bundles that are received reserve their space in it.
+#[derive(Message)]
+#[rtype(result = "ReceiveLimit")]
+pub struct GetReceiveLimit {}
@@ -15,14 +15,30 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::{Arc, Weak};
+use std::{
+    fmt, io,
+    sync::{Arc, Weak},
+};
 
-use bp7::{bundle::Bundle, primaryblock::PrimaryBlock};
+use bp7::{
+    SerializationError,
+    block::quality_of_service_block::QualityOfServiceBlock,
+    bundle::Bundle,
+    endpoint::Endpoint,
+    primaryblock::PrimaryBlock,
+    time::{CreationTimestamp, DtnTime},
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
 
 pub mod agent;
+pub mod index;
 pub mod messages;
+pub mod quota;
+pub mod seen;
+pub mod store;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum State {
     /// Received from a remote node, or from a local connection.
     /// Bundle is stored, but not processed in any way.
@@ -43,6 +59,10 @@ pub enum State {
     /// been made.
     ForwardingQueued,
     /// Bundle has been forwarded and "forwarded" status report has
+    /// been sent, but we have custody of it. It is kept until the
+    /// next custodian accepts custody, and forwarded again otherwise.
+    CustodyPending,
+    /// Bundle has been forwarded and "forwarded" status report has
     /// been sent. Bundle may now be deleted.
     Forwarded,
     /// Bundle is for some reason invalid and will not be further processed.
@@ -58,16 +78,140 @@ pub struct StoredBundle {
     payload_size: u64,
     min_size: Option<u64>,
     primary_block: PrimaryBlock,
+    priority: QualityOfServiceBlock,
+    persisted: bool,
+    /// The bundle data was changed since it has been written to the store.
+    modified: bool,
+    /// Checksum of the bundle data as last written to the store.
+    checksum: u32,
+    /// How often forwarding or delivering the bundle failed.
+    failed_attempts: u32,
+    /// When we got the bundle. Bundles stored without metadata count as received when loaded.
+    received_at: DtnTime,
+    expires_at: DtnTime,
+    /// The BPA was told that the bundle expired and is going to delete it.
+    expiring: bool,
 }
 
-fn id_from_pb(pb: &PrimaryBlock, size: u64) -> String {
+/// Everything about a [`StoredBundle`] that is not part of the bundle data. It is stored with the
+/// bundle so that processing resumes where it stopped after a restart.
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleMetadata {
+    state: State,
+    /// The bundle as changed during processing, if it differs from the stored bundle data.
+    #[serde(with = "bundle_bytes")]
+    bundle_data: Option<Vec<u8>>,
+    min_size: Option<u64>,
+    failed_attempts: u32,
+    received_at: DtnTime,
+    expires_at: DtnTime,
+    /// The size the bundle had when we got it, which is part of its ID.
+    size: Option<u64>,
+    /// Checksum of the stored bundle data.
+    checksum: Option<u32>,
+}
+
+/// Why a stored bundle could not be loaded.
+#[derive(Debug)]
+pub enum Corruption {
+    Unreadable(io::Error),
+    NotABundle(SerializationError),
+    CrcMismatch,
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// The bundle is stored under a different ID than its own.
+    WrongName(String),
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Corruption::Unreadable(e) => write!(f, "unreadable: {e}"),
+            Corruption::NotABundle(e) => write!(f, "not a bundle: {e:?}"),
+            Corruption::CrcMismatch => write!(f, "block CRC mismatch"),
+            Corruption::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch, stored {expected:08x} but got {actual:08x}"
+            ),
+            Corruption::WrongName(id) => write!(f, "contains bundle {id} instead"),
+        }
+    }
+}
+
+/// Checks that `data` is a bundle with correct CRCs.
+fn check_bundle_data(data: &[u8]) -> Result<(), Corruption> {
+    Bundle::try_from(data).map_err(Corruption::NotABundle)?;
+    if Bundle::verify_crcs(data) {
+        Ok(())
+    } else {
+        Err(Corruption::CrcMismatch)
+    }
+}
+
+/// Stores the bundle as a cbor byte string instead of an array of numbers.
+mod bundle_bytes {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use serde_cbor::Value;
+
+    // serde passes a reference to the field
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: Serializer>(
+        data: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match data {
+            Some(data) => serializer.serialize_bytes(data),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        match Option::<Value>::deserialize(deserializer)? {
+            Some(Value::Bytes(data)) => Ok(Some(data)),
+            Some(_) => Err(D::Error::custom("bundle data must be a byte string")),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Identifies a bundle regardless of the blocks changed on the way, e.g. the hop count. Status
+/// reports, custody signals and summary vectors refer to bundles like this, with fragments told
+/// apart by their offset and payload length.
+pub fn bundle_identity(
+    source: &Endpoint,
+    creation_timestamp: &CreationTimestamp,
+    fragment_offset: Option<u64>,
+    fragment_length: Option<u64>,
+) -> String {
     format!(
         "{}:{}:{}:{}:{}",
-        pb.source_node,
-        pb.creation_timestamp.creation_time.timestamp,
-        pb.creation_timestamp.sequence_number,
-        pb.fragment_offset.unwrap_or_default(),
-        size,
+        source,
+        creation_timestamp.creation_time.timestamp,
+        creation_timestamp.sequence_number,
+        fragment_offset.unwrap_or_default(),
+        fragment_length.unwrap_or_default(),
+    )
+}
+
+fn id_from_pb(pb: &PrimaryBlock, size: u64) -> String {
+    bundle_identity(
+        &pb.source_node,
+        &pb.creation_timestamp,
+        pb.fragment_offset,
+        Some(size),
+    )
+}
+
+fn identity_from_pb(pb: &PrimaryBlock, payload_size: u64) -> String {
+    bundle_identity(
+        &pb.source_node,
+        &pb.creation_timestamp,
+        pb.fragment_offset,
+        pb.fragment_offset.and(Some(payload_size)),
     )
 }
 
@@ -76,6 +220,11 @@ impl StoredBundle {
         id_from_pb(&self.primary_block, self.size)
     }
 
+    /// See [`bundle_identity`].
+    pub fn get_identity(&self) -> String {
+        identity_from_pb(&self.primary_block, self.payload_size)
+    }
+
     pub fn get_filename(&self) -> String {
         id_from_pb(&self.primary_block, self.size).replace('/', "_")
     }
@@ -104,6 +253,108 @@ impl StoredBundle {
         &self.primary_block
     }
 
+    pub fn is_expired(&self) -> bool {
+        DtnTime::now() >= self.expires_at
+    }
+
+    /// Creates the bundle from its blocks without its data, e.g. to decide whether to store a
+    /// bundle that has not been read yet. `size` is the size of the data and `payload_size`
+    /// the size of its payload.
+    pub fn without_data(bundle: &Bundle, size: u64, payload_size: u64) -> Self {
+        let primary_block = bundle.primary_block.clone();
+        let received_at = DtnTime::now();
+        // without a clock at the source only the age of the bundle tells us how long it lives
+        let expires_at = primary_block.expiration_time().unwrap_or_else(|| DtnTime {
+            timestamp: received_at.timestamp.saturating_add(
+                primary_block
+                    .lifetime
+                    .saturating_sub(bundle.bundle_age().unwrap_or_default()),
+            ),
+        });
+        Self {
+            bundle_data: Arc::new(Vec::new()),
+            state: State::Received,
+            size,
+            payload_size,
+            min_size: None,
+            primary_block,
+            priority: bundle.quality_of_service(),
+            persisted: false,
+            modified: false,
+            checksum: 0,
+            failed_attempts: 0,
+            received_at,
+            expires_at,
+            expiring: false,
+        }
+    }
+
+    /// Creates the bundle as read back from a [`store::BundleStore`], after checking that it is
+    /// still intact.
+    fn from_stored(bundle_data: Vec<u8>, metadata: Option<&[u8]>) -> Result<Self, Corruption> {
+        check_bundle_data(&bundle_data)?;
+        let mut sb = StoredBundle::from(bundle_data);
+        sb.persisted = true;
+        match metadata.map(serde_cbor::from_slice::<BundleMetadata>) {
+            Some(Ok(metadata)) => {
+                if let Some(expected) = metadata.checksum
+                    && expected != sb.checksum
+                {
+                    return Err(Corruption::ChecksumMismatch {
+                        expected,
+                        actual: sb.checksum,
+                    });
+                }
+                if let Some(data) = &metadata.bundle_data {
+                    check_bundle_data(data)?;
+                }
+                sb.apply_metadata(metadata);
+            }
+            Some(Err(e)) => {
+                warn!(
+                    "Failed to parse metadata of bundle {}, processing it again: {e}",
+                    sb.get_id()
+                );
+                sb.state = State::Valid;
+            }
+            // Stored without metadata, so we only know it was valid.
+            None => sb.state = State::Valid,
+        }
+        Ok(sb)
+    }
+
+    fn serialize_metadata(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&self.get_metadata()).expect("Metadata can always be serialized")
+    }
+
+    fn get_metadata(&self) -> BundleMetadata {
+        BundleMetadata {
+            state: self.state,
+            bundle_data: self.modified.then(|| self.bundle_data.to_vec()),
+            min_size: self.min_size,
+            failed_attempts: self.failed_attempts,
+            received_at: self.received_at,
+            expires_at: self.expires_at,
+            size: Some(self.size),
+            checksum: Some(self.checksum),
+        }
+    }
+
+    fn apply_metadata(&mut self, metadata: BundleMetadata) {
+        self.state = metadata.state;
+        if let Some(data) = metadata.bundle_data {
+            self.bundle_data = Arc::new(data);
+            self.modified = true;
+        }
+        self.min_size = metadata.min_size;
+        self.failed_attempts = metadata.failed_attempts;
+        self.received_at = metadata.received_at;
+        self.expires_at = metadata.expires_at;
+        if let Some(size) = metadata.size {
+            self.size = size;
+        }
+    }
+
     fn get_ref(&self) -> StoredBundleRef {
         StoredBundleRef {
             bundle_data: Arc::downgrade(&self.bundle_data),
@@ -112,6 +363,9 @@ impl StoredBundle {
             payload_size: self.payload_size,
             min_size: self.min_size,
             primary_block: self.primary_block.clone(),
+            priority: self.priority,
+            received_at: self.received_at,
+            expires_at: self.expires_at,
         }
     }
 }
@@ -130,18 +384,17 @@ impl PartialEq<StoredBundle> for &StoredBundle {
 
 impl From<Vec<u8>> for StoredBundle {
     fn from(bundle_data: Vec<u8>) -> Self {
-        let bundle: Bundle = bundle_data.as_slice().try_into().unwrap();
-        let primary_block = bundle.primary_block.clone();
-        let size = bundle_data.len() as u64;
-        let payload_size = bundle.payload_block().data.len() as u64;
-        Self {
-            bundle_data: Arc::new(bundle_data),
-            state: State::Received,
-            size,
-            payload_size,
-            min_size: None,
-            primary_block,
-        }
+        let mut sb = {
+            let bundle: Bundle = bundle_data.as_slice().try_into().unwrap();
+            StoredBundle::without_data(
+                &bundle,
+                bundle_data.len() as u64,
+                bundle.payload_block().data.len() as u64,
+            )
+        };
+        sb.checksum = crc32fast::hash(&bundle_data);
+        sb.bundle_data = Arc::new(bundle_data);
+        sb
     }
 }
 
@@ -153,6 +406,9 @@ pub struct StoredBundleRef {
     payload_size: u64,
     min_size: Option<u64>,
     primary_block: PrimaryBlock,
+    priority: QualityOfServiceBlock,
+    received_at: DtnTime,
+    expires_at: DtnTime,
 }
 
 impl StoredBundleRef {
@@ -160,6 +416,18 @@ impl StoredBundleRef {
         id_from_pb(&self.primary_block, self.size)
     }
 
+    /// See [`bundle_identity`].
+    pub fn get_identity(&self) -> String {
+        identity_from_pb(&self.primary_block, self.payload_size)
+    }
+
+    /// The payload length if the bundle is a fragment, which is part of its identity.
+    pub fn get_fragment_length(&self) -> Option<u64> {
+        self.primary_block
+            .fragment_offset
+            .and(Some(self.payload_size))
+    }
+
     pub fn get_bundle_data(&self) -> Option<Arc<Vec<u8>>> {
         self.bundle_data.upgrade()
     }
@@ -183,6 +451,23 @@ impl StoredBundleRef {
     pub fn get_primary_block(&self) -> &PrimaryBlock {
         &self.primary_block
     }
+
+    /// The priority as set by the quality of service block of the bundle.
+    pub fn get_priority(&self) -> QualityOfServiceBlock {
+        self.priority
+    }
+
+    pub fn get_received_at(&self) -> DtnTime {
+        self.received_at
+    }
+
+    pub fn get_expires_at(&self) -> DtnTime {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self) -> bool {
+        DtnTime::now() >= self.expires_at
+    }
 }
 
 impl PartialEq for StoredBundleRef {
@@ -0,0 +1,136 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+use std::iter;
+
+use bp7::{block::quality_of_service_block::QualityOfServiceBlock, time::DtnTime};
+
+use crate::common::settings::Settings;
+
+use super::{StoredBundle, index::BundleIndex, messages::StorageFull};
+
+/// What happens to a bundle that does not fit into the storage anymore.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FullPolicy {
+    /// The new bundle is not stored.
+    #[default]
+    Refuse,
+    /// Bundles that expire the soonest are deleted to make room.
+    EvictShortestLifetime,
+    /// Bundles with the lowest priority are deleted to make room. Among those the ones that
+    /// expire the soonest go first.
+    EvictLowestPriority,
+    /// Bundles we got the earliest are deleted to make room.
+    EvictOldest,
+}
+
+impl FullPolicy {
+    fn from_setting(policy: &str) -> Self {
+        match policy {
+            "refuse" => FullPolicy::Refuse,
+            "evict-shortest-lifetime" => FullPolicy::EvictShortestLifetime,
+            "evict-lowest-priority" => FullPolicy::EvictLowestPriority,
+            "evict-oldest" => FullPolicy::EvictOldest,
+            policy => panic!("Unknown bundle storage full policy {policy}"),
+        }
+    }
+}
+
+/// Limits on how much the storage agent may hold.
+#[derive(Debug, Default)]
+pub struct Quota {
+    max_bytes: Option<u64>,
+    max_bundles: Option<u64>,
+    policy: FullPolicy,
+}
+
+impl Quota {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Quota {
+            max_bytes: settings.bundle_storage_max_bytes,
+            max_bundles: settings.bundle_storage_max_bundles,
+            policy: FullPolicy::from_setting(&settings.bundle_storage_full_policy),
+        }
+    }
+
+    fn fits(&self, bytes: u64, bundles: u64) -> bool {
+        self.max_bytes.is_none_or(|max| bytes <= max)
+            && self.max_bundles.is_none_or(|max| bundles <= max)
+    }
+
+    /// The size of the largest bundle we could currently store.
+    pub fn receive_limit(&self, bundles: &BundleIndex) -> u64 {
+        let max_bytes = self.max_bytes.unwrap_or(u64::MAX);
+        if self.policy != FullPolicy::Refuse {
+            return max_bytes;
+        }
+        if !self.fits(0, bundles.len() as u64 + 1) {
+            return 0;
+        }
+        max_bytes.saturating_sub(bundles.total_bytes())
+    }
+
+    /// The IDs of the bundles that need to be deleted so that `new` can be stored. When bundles
+    /// are refused, the `reserved` bytes of bundles that are still being received are kept free
+    /// for them.
+    pub fn bundles_to_evict(
+        &self,
+        bundles: &BundleIndex,
+        new: &StoredBundle,
+        reserved: u64,
+    ) -> Result<Vec<String>, StorageFull> {
+        let mut bytes = bundles.total_bytes() + new.size;
+        let mut count = bundles.len() as u64 + 1;
+        if self.policy == FullPolicy::Refuse {
+            return if self.fits(bytes.saturating_add(reserved), count) {
+                Ok(Vec::new())
+            } else {
+                Err(StorageFull)
+            };
+        }
+        if self.fits(bytes, count) {
+            return Ok(Vec::new());
+        }
+
+        let mut candidates: Vec<&StoredBundle> = bundles.iter().chain(iter::once(new)).collect();
+        candidates.sort_by_key(|bundle| self.eviction_order(bundle));
+        let mut evicted = Vec::new();
+        for bundle in candidates {
+            if self.fits(bytes, count) {
+                break;
+            }
+            if std::ptr::eq(bundle, new) {
+                // everything else is more important than the new bundle
+                return Err(StorageFull);
+            }
+            bytes -= bundle.bundle_data.len() as u64;
+            count -= 1;
+            evicted.push(bundle.get_id());
+        }
+        Ok(evicted)
+    }
+
+    /// Bundles that sort first are evicted first.
+    fn eviction_order(&self, bundle: &StoredBundle) -> (QualityOfServiceBlock, DtnTime) {
+        match self.policy {
+            FullPolicy::Refuse | FullPolicy::EvictShortestLifetime => {
+                (QualityOfServiceBlock::default(), bundle.expires_at)
+            }
+            FullPolicy::EvictLowestPriority => (bundle.priority, bundle.expires_at),
+            FullPolicy::EvictOldest => (QualityOfServiceBlock::default(), bundle.received_at),
+        }
+    }
+}
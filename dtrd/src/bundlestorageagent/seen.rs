@@ -0,0 +1,93 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap};
+
+use bp7::time::DtnTime;
+use log::warn;
+
+use super::StoredBundle;
+
+/// The bundles we got recently, so that we notice when one of them arrives again. Bundles are
+/// remembered until they expire, or until there are too many, then the ones expiring first are
+/// forgotten.
+#[derive(Default)]
+pub struct SeenBundles {
+    expires_at: HashMap<String, DtnTime>,
+    by_expiry: BTreeSet<(DtnTime, String)>,
+    max_entries: usize,
+}
+
+impl SeenBundles {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            ..Default::default()
+        }
+    }
+
+    pub fn contains(&self, bundle: &StoredBundle) -> bool {
+        self.expires_at.contains_key(&bundle.get_identity())
+    }
+
+    pub fn insert(&mut self, bundle: &StoredBundle) {
+        self.insert_key(bundle.get_identity(), bundle.expires_at);
+    }
+
+    fn insert_key(&mut self, key: String, expires_at: DtnTime) {
+        if let Some(previous) = self.expires_at.insert(key.clone(), expires_at) {
+            self.by_expiry.remove(&(previous, key.clone()));
+        }
+        self.by_expiry.insert((expires_at, key));
+        while self.expires_at.len() > self.max_entries {
+            let (_, key) = self
+                .by_expiry
+                .pop_first()
+                .expect("by_expiry has all entries");
+            self.expires_at.remove(&key);
+        }
+    }
+
+    /// Forgets the bundles that expire at or before `time`. Returns if there were any.
+    pub fn expire(&mut self, time: DtnTime) -> bool {
+        let mut expired = false;
+        while let Some((expires_at, _)) = self.by_expiry.first()
+            && *expires_at <= time
+        {
+            let (_, key) = self.by_expiry.pop_first().expect("we just looked at it");
+            self.expires_at.remove(&key);
+            expired = true;
+        }
+        expired
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        serde_cbor::to_vec(&self.by_expiry).expect("Seen bundles can always be serialized")
+    }
+
+    /// Adds the bundles stored by [`Self::serialize`] before.
+    pub fn load(&mut self, data: &[u8]) {
+        match serde_cbor::from_slice::<Vec<(DtnTime, String)>>(data) {
+            Ok(entries) => {
+                for (expires_at, key) in entries {
+                    self.insert_key(key, expires_at);
+                }
+            }
+            Err(e) => warn!("Failed to parse the seen bundles, starting without them: {e}"),
+        }
+    }
+}
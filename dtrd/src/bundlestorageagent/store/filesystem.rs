@@ -0,0 +1,261 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures_util::future::BoxFuture;
+use log::{debug, info, warn};
+use tcpcl::transfer::TransferFile;
+use tokio::{fs, io::AsyncWriteExt};
+
+use super::{BundleStore, Corruption, IntegrityReport, StoredBundle};
+
+/// Appended to the bundle filename for the file holding the metadata.
+const METADATA_SUFFIX: &str = ".meta";
+/// Appended to the filename while a file is written. They are renamed once complete.
+const TMP_SUFFIX: &str = ".tmp";
+/// Directory in the storage path that damaged bundles are moved to.
+const QUARANTINE_DIR: &str = "quarantine";
+/// File in the storage path holding the IDs of the bundles we got recently.
+const SEEN_BUNDLES_FILE: &str = "seen_bundles";
+
+/// Stores each bundle in its own file in the storage path, with its metadata in a second file
+/// next to it. The metadata is written first, a bundle only counts as stored once its file is
+/// there as well.
+pub struct FilesystemStore {
+    path: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn bundle_path(&self, bundle: &StoredBundle) -> PathBuf {
+        self.path.join(bundle.get_filename())
+    }
+
+    fn metadata_path(&self, bundle: &StoredBundle) -> PathBuf {
+        self.path
+            .join(format!("{}{METADATA_SUFFIX}", bundle.get_filename()))
+    }
+}
+
+impl BundleStore for FilesystemStore {
+    fn load(&self) -> BoxFuture<'static, io::Result<(Vec<StoredBundle>, IntegrityReport)>> {
+        let storage_path = self.path.clone();
+        Box::pin(async move {
+            let meta = fs::metadata(&storage_path).await;
+            assert!(
+                meta.is_ok(),
+                "Bundle storage path must point to an existing directory"
+            );
+            if let Ok(m) = meta
+                && !m.is_dir()
+            {
+                panic!("Bundle storage path must point to a valid directory");
+            }
+
+            let mut existing_bundles = Vec::new();
+            let mut report = IntegrityReport::default();
+
+            let mut readdir = fs::read_dir(&storage_path).await?;
+            while let Some(entry) = readdir.next_entry().await? {
+                let filename = entry.file_name().to_string_lossy().into_owned();
+                if filename == QUARANTINE_DIR || filename == SEEN_BUNDLES_FILE {
+                    continue;
+                }
+                if filename.ends_with(TMP_SUFFIX) {
+                    // An interrupted write, the previous version of the file is still there.
+                    debug!("Removing incomplete file {filename}");
+                    remove_leftover(&entry.path(), &mut report).await;
+                    continue;
+                }
+                if let Some(bundle_filename) = filename.strip_suffix(METADATA_SUFFIX) {
+                    // Metadata is loaded with its bundle. Without one it is left over from
+                    // writing or deleting the bundle.
+                    if fs::metadata(storage_path.join(bundle_filename))
+                        .await
+                        .is_err()
+                    {
+                        debug!("Removing metadata {filename} of deleted bundle");
+                        remove_leftover(&entry.path(), &mut report).await;
+                    }
+                    continue;
+                }
+
+                debug!(
+                    "Loading existing bundle from {}",
+                    entry.path().to_string_lossy()
+                );
+                if let Ok(meta) = entry.metadata().await
+                    && !meta.is_file()
+                {
+                    warn!(
+                        "Skip loading existing bundle {} as it is not a file",
+                        entry.path().to_string_lossy()
+                    );
+                    continue;
+                }
+
+                match load_bundle(&entry.path(), &filename).await {
+                    Ok(Some(sb)) => {
+                        info!("Loaded bundle {} in state {:?}", sb.get_id(), sb.state);
+                        existing_bundles.push(sb);
+                    }
+                    Ok(None) => {
+                        debug!("Removing bundle {filename} that was stored without metadata");
+                        remove_leftover(&entry.path(), &mut report).await;
+                    }
+                    Err(corruption) => {
+                        quarantine(&storage_path, &filename).await;
+                        report.quarantined.push((filename, corruption));
+                    }
+                }
+            }
+
+            report.loaded = existing_bundles.len();
+            Ok((existing_bundles, report))
+        })
+    }
+
+    fn write_bundle(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        let path = self.bundle_path(bundle);
+        let metadata_path = self.metadata_path(bundle);
+        debug!("Storing bundle to {}", path.to_string_lossy());
+        let data = bundle.bundle_data.clone();
+        let metadata = bundle.serialize_metadata();
+        Box::pin(async move {
+            write_file_atomically(&metadata_path, &metadata).await?;
+            write_file_atomically(&path, &data).await
+        })
+    }
+
+    fn write_metadata(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        let path = self.metadata_path(bundle);
+        let metadata = bundle.serialize_metadata();
+        Box::pin(async move { write_file_atomically(&path, &metadata).await })
+    }
+
+    fn adopt_file(
+        &self,
+        bundle: &StoredBundle,
+        file: TransferFile,
+    ) -> BoxFuture<'static, io::Result<()>> {
+        let path = self.bundle_path(bundle);
+        let metadata_path = self.metadata_path(bundle);
+        debug!(
+            "Moving received bundle from {} to {}",
+            file.path().to_string_lossy(),
+            path.to_string_lossy()
+        );
+        let metadata = bundle.serialize_metadata();
+        Box::pin(async move {
+            write_file_atomically(&metadata_path, &metadata).await?;
+            file.persist(&path).await
+        })
+    }
+
+    fn delete(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        let path = self.bundle_path(bundle);
+        let metadata_path = self.metadata_path(bundle);
+        debug!("Deleting bundle from {}", path.to_string_lossy());
+        // Removing the bundle first means it is no longer stored, metadata without a bundle is
+        // cleaned up on startup.
+        Box::pin(async move {
+            fs::remove_file(path).await?;
+            match fs::remove_file(metadata_path).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                res => res,
+            }
+        })
+    }
+
+    fn load_seen_bundles(&self) -> BoxFuture<'static, io::Result<Option<Vec<u8>>>> {
+        let path = self.path.join(SEEN_BUNDLES_FILE);
+        Box::pin(async move {
+            match fs::read(path).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn write_seen_bundles(&self, data: Vec<u8>) -> BoxFuture<'static, io::Result<()>> {
+        let path = self.path.join(SEEN_BUNDLES_FILE);
+        Box::pin(async move { write_file_atomically(&path, &data).await })
+    }
+}
+
+/// Reads the bundle stored in `filename` together with its metadata. Bundles without metadata
+/// were never completely stored and are not returned.
+async fn load_bundle(path: &Path, filename: &str) -> Result<Option<StoredBundle>, Corruption> {
+    let content = fs::read(path).await.map_err(Corruption::Unreadable)?;
+    let metadata = match fs::read(path.with_file_name(format!("{filename}{METADATA_SUFFIX}"))).await
+    {
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(Corruption::Unreadable(e)),
+    };
+    let sb = StoredBundle::from_stored(content, metadata.as_deref())?;
+    if sb.get_filename() != filename {
+        return Err(Corruption::WrongName(sb.get_id()));
+    }
+    Ok(metadata.is_some().then_some(sb))
+}
+
+/// Moves the bundle and its metadata into the quarantine directory, where they are kept for
+/// inspection but no longer loaded.
+async fn quarantine(storage_path: &Path, filename: &str) {
+    let quarantine_path = storage_path.join(QUARANTINE_DIR);
+    if let Err(e) = fs::create_dir_all(&quarantine_path).await {
+        warn!("Failed to create quarantine directory: {e}");
+        return;
+    }
+    for name in [filename.to_string(), format!("{filename}{METADATA_SUFFIX}")] {
+        match fs::rename(storage_path.join(&name), quarantine_path.join(&name)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!("Failed to move {name} to quarantine: {e}");
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn remove_leftover(path: &Path, report: &mut IntegrityReport) {
+    match fs::remove_file(path).await {
+        Ok(()) => report.cleaned_up += 1,
+        // Already moved to quarantine with its bundle
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove {}: {e}", path.to_string_lossy()),
+    }
+}
+
+/// Writes to a temporary file first, so that the file either has the old or the new content
+/// after a crash.
+async fn write_file_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(TMP_SUFFIX);
+    let mut file = fs::File::create(&tmp_path).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp_path, path).await
+}
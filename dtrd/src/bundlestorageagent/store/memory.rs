@@ -0,0 +1,52 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io;
+
+use futures_util::future::{self, BoxFuture};
+
+use super::{BundleStore, IntegrityReport, StoredBundle};
+
+/// Persists nothing. The storage agent already holds all bundles in memory, so they are only
+/// lost on a restart. Intended for tests.
+pub struct MemoryStore;
+
+impl BundleStore for MemoryStore {
+    fn load(&self) -> BoxFuture<'static, io::Result<(Vec<StoredBundle>, IntegrityReport)>> {
+        Box::pin(future::ready(Ok((Vec::new(), IntegrityReport::default()))))
+    }
+
+    fn write_bundle(&self, _bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn write_metadata(&self, _bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn delete(&self, _bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn load_seen_bundles(&self) -> BoxFuture<'static, io::Result<Option<Vec<u8>>>> {
+        Box::pin(future::ready(Ok(None)))
+    }
+
+    fn write_seen_bundles(&self, _data: Vec<u8>) -> BoxFuture<'static, io::Result<()>> {
+        Box::pin(future::ready(Ok(())))
+    }
+}
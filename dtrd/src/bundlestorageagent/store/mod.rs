@@ -0,0 +1,100 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{fmt, io};
+
+use futures_util::future::BoxFuture;
+use tcpcl::transfer::TransferFile;
+
+use crate::common::settings::Settings;
+
+use super::{Corruption, StoredBundle};
+
+pub mod filesystem;
+pub mod memory;
+#[cfg(feature = "rocksdb")]
+pub mod rocksdb;
+
+/// What [`BundleStore::load`] found in the store.
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    pub loaded: usize,
+    /// Bundles that were moved to quarantine instead of being loaded, by their name in the
+    /// store.
+    pub quarantined: Vec<(String, Corruption)>,
+    /// Leftovers of interrupted writes and deletes that were removed.
+    pub cleaned_up: usize,
+}
+
+impl fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bundles loaded, {} quarantined, {} leftovers cleaned up",
+            self.loaded,
+            self.quarantined.len(),
+            self.cleaned_up
+        )
+    }
+}
+
+/// Where the storage agent persists bundles so they survive a restart.
+pub trait BundleStore {
+    /// Reads all bundles stored before, with their metadata applied. Bundles that are damaged
+    /// are moved out of the way into a quarantine.
+    fn load(&self) -> BoxFuture<'static, io::Result<(Vec<StoredBundle>, IntegrityReport)>>;
+
+    /// Stores the bundle data together with its metadata.
+    fn write_bundle(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>>;
+
+    /// Stores the metadata of a bundle that has been written before.
+    fn write_metadata(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>>;
+
+    /// Stores a bundle that has been received into a file. By default the file is dropped and
+    /// the bundle is written like any other.
+    fn adopt_file(
+        &self,
+        bundle: &StoredBundle,
+        file: TransferFile,
+    ) -> BoxFuture<'static, io::Result<()>> {
+        drop(file);
+        self.write_bundle(bundle)
+    }
+
+    /// Removes the bundle together with its metadata.
+    fn delete(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>>;
+
+    /// Reads what was last passed to [`BundleStore::write_seen_bundles`], if anything.
+    fn load_seen_bundles(&self) -> BoxFuture<'static, io::Result<Option<Vec<u8>>>>;
+
+    /// Stores the serialized [`super::seen::SeenBundles`], replacing what was stored before.
+    fn write_seen_bundles(&self, data: Vec<u8>) -> BoxFuture<'static, io::Result<()>>;
+}
+
+/// Creates the store selected by `BUNDLE_STORAGE_BACKEND`.
+pub fn from_settings(settings: &Settings) -> Box<dyn BundleStore> {
+    let path = settings.bundle_storage_path.clone().into();
+    match settings.bundle_storage_backend.as_str() {
+        "filesystem" => Box::new(filesystem::FilesystemStore::new(path)),
+        #[cfg(feature = "rocksdb")]
+        "rocksdb" => Box::new(
+            rocksdb::RocksDbStore::open(&path).expect("Failed to open the bundle database"),
+        ),
+        "memory" => Box::new(memory::MemoryStore),
+        backend => panic!("Unknown bundle storage backend {backend}"),
+    }
+}
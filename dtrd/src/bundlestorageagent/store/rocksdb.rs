@@ -0,0 +1,161 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{io, path::Path, sync::Arc};
+
+use futures_util::future::BoxFuture;
+use log::{debug, info};
+use rocksdb::{DB, Direction, IteratorMode, WriteBatch, WriteOptions};
+
+use super::{BundleStore, Corruption, IntegrityReport, StoredBundle};
+
+/// Keys of bundle data are this followed by the bundle ID.
+const BUNDLE_PREFIX: &[u8] = b"bundle/";
+/// Keys of bundle metadata are this followed by the bundle ID.
+const METADATA_PREFIX: &[u8] = b"meta/";
+/// Damaged bundles and their metadata are moved to their key prefixed with this.
+const QUARANTINE_PREFIX: &[u8] = b"quarantine/";
+/// Key of the IDs of the bundles we got recently.
+const SEEN_BUNDLES_KEY: &[u8] = b"seen_bundles";
+
+/// Stores bundles and their metadata in a `RocksDB` database at the storage path.
+pub struct RocksDbStore {
+    db: Arc<DB>,
+}
+
+impl RocksDbStore {
+    pub fn open(path: &Path) -> Result<Self, rocksdb::Error> {
+        Ok(Self {
+            db: Arc::new(DB::open_default(path)?),
+        })
+    }
+
+    /// Writes the batch in the background. Bundle and metadata changes are always written in
+    /// one batch, so they can not get out of sync.
+    fn write(&self, batch: WriteBatch) -> BoxFuture<'static, io::Result<()>> {
+        let db = self.db.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let mut options = WriteOptions::default();
+                options.set_sync(true);
+                db.write_opt(batch, &options).map_err(io::Error::other)
+            })
+            .await?
+        })
+    }
+}
+
+fn key(prefix: &[u8], bundle: &StoredBundle) -> Vec<u8> {
+    [prefix, bundle.get_id().as_bytes()].concat()
+}
+
+impl BundleStore for RocksDbStore {
+    fn load(&self) -> BoxFuture<'static, io::Result<(Vec<StoredBundle>, IntegrityReport)>> {
+        let db = self.db.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                let mut existing_bundles = Vec::new();
+                let mut report = IntegrityReport::default();
+                let mut quarantine = WriteBatch::default();
+                let entries = db.iterator(IteratorMode::From(BUNDLE_PREFIX, Direction::Forward));
+                for entry in entries {
+                    let (key, content) = entry.map_err(io::Error::other)?;
+                    let Some(id) = key.strip_prefix(BUNDLE_PREFIX) else {
+                        break;
+                    };
+                    debug!(
+                        "Loading existing bundle {} from the database",
+                        String::from_utf8_lossy(id)
+                    );
+                    let metadata_key = [METADATA_PREFIX, id].concat();
+                    let metadata = db.get(&metadata_key).map_err(io::Error::other)?;
+                    let loaded = StoredBundle::from_stored(content.to_vec(), metadata.as_deref())
+                        .and_then(|sb| {
+                            if sb.get_id().as_bytes() == id {
+                                Ok(sb)
+                            } else {
+                                Err(Corruption::WrongName(sb.get_id()))
+                            }
+                        });
+                    match loaded {
+                        Ok(sb) => {
+                            info!("Loaded bundle {} in state {:?}", sb.get_id(), sb.state);
+                            existing_bundles.push(sb);
+                        }
+                        Err(corruption) => {
+                            quarantine.delete(&key);
+                            quarantine.put([QUARANTINE_PREFIX, &key].concat(), &content);
+                            if let Some(metadata) = metadata {
+                                quarantine.delete(&metadata_key);
+                                quarantine
+                                    .put([QUARANTINE_PREFIX, &metadata_key].concat(), metadata);
+                            }
+                            report
+                                .quarantined
+                                .push((String::from_utf8_lossy(id).into_owned(), corruption));
+                        }
+                    }
+                }
+                if !quarantine.is_empty() {
+                    let mut options = WriteOptions::default();
+                    options.set_sync(true);
+                    db.write_opt(quarantine, &options)
+                        .map_err(io::Error::other)?;
+                }
+                report.loaded = existing_bundles.len();
+                Ok((existing_bundles, report))
+            })
+            .await?
+        })
+    }
+
+    fn write_bundle(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        debug!("Storing bundle {} to the database", bundle.get_id());
+        let mut batch = WriteBatch::default();
+        batch.put(key(BUNDLE_PREFIX, bundle), bundle.bundle_data.as_slice());
+        batch.put(key(METADATA_PREFIX, bundle), bundle.serialize_metadata());
+        self.write(batch)
+    }
+
+    fn write_metadata(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        let mut batch = WriteBatch::default();
+        batch.put(key(METADATA_PREFIX, bundle), bundle.serialize_metadata());
+        self.write(batch)
+    }
+
+    fn delete(&self, bundle: &StoredBundle) -> BoxFuture<'static, io::Result<()>> {
+        debug!("Deleting bundle {} from the database", bundle.get_id());
+        let mut batch = WriteBatch::default();
+        batch.delete(key(BUNDLE_PREFIX, bundle));
+        batch.delete(key(METADATA_PREFIX, bundle));
+        self.write(batch)
+    }
+
+    fn load_seen_bundles(&self) -> BoxFuture<'static, io::Result<Option<Vec<u8>>>> {
+        let db = self.db.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || db.get(SEEN_BUNDLES_KEY).map_err(io::Error::other))
+                .await?
+        })
+    }
+
+    fn write_seen_bundles(&self, data: Vec<u8>) -> BoxFuture<'static, io::Result<()>> {
+        let mut batch = WriteBatch::default();
+        batch.put(SEEN_BUNDLES_KEY, data);
+        self.write(batch)
+    }
+}
@@ -15,10 +15,15 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use bp7::{
-    block::{Block, CanonicalBlock, payload_block::PayloadBlock},
+    administrative_record::application_acknowledgement::ApplicationAcknowledgement,
+    block::{
+        Block, CanonicalBlock,
+        payload_block::PayloadBlock,
+        quality_of_service_block::{PriorityClass, QualityOfServiceBlock},
+    },
     blockflags::BlockFlags,
     bundle::Bundle,
     bundleflags::BundleFlags,
@@ -28,26 +33,45 @@ use bp7::{
     time::{CreationTimestamp, DtnTime},
 };
 use log::{debug, info};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
 use crate::{
-    bundlestorageagent::messages::StoreNewBundle,
+    bundleprotocolagent::messages::{
+        AcknowledgeBundle, BundleHistory, GetBundleHistory, GetCustodyStatistics,
+        GetQueueStatistics,
+    },
+    bundlestorageagent::messages::{GetStorageStatistics, StorageFull, StoreNewBundle},
     common::{messages::Shutdown, settings::Settings},
     nodeagent::messages::{AddNode, ListNodes, Node, RemoveNode},
-    routingagent::messages::{AddRoute, ListRoutes, RemoveRoute, RouteStatus, RouteType},
+    routingagent::messages::{
+        AddContact, AddRoute, ContactStatus, ListContacts, ListRoutes, RemoveContact, RemoveRoute,
+        RouteStatus, RouteType,
+    },
 };
 
 use super::messages::{
-    ClientAddNode, ClientAddRoute, ClientDeliverBundle, ClientListNodes, ClientListRoutes,
-    ClientListenConnect, ClientListenDisconnect, ClientRemoveNode, ClientRemoveRoute,
-    ClientSendBundle, EventBundleDeliveryFailed, EventClientConnected, EventClientDisconnected,
+    AcknowledgementError, ClientAcknowledgeBundle, ClientAddContact, ClientAddNode, ClientAddRoute,
+    ClientAwaitAcknowledgement, ClientDeliverBundle, ClientGetStatistics, ClientListBundleHistory,
+    ClientListContacts, ClientListNodes, ClientListRoutes, ClientListenConnect,
+    ClientListenDisconnect, ClientRemoveContact, ClientRemoveNode, ClientRemoveRoute,
+    ClientSendBundle, EventBundleAcknowledged, EventBundleDeliveryFailed, EventClientConnected,
+    EventClientDisconnected, Statistics,
 };
 use actix::prelude::*;
 
+/// A bundle we sent that asked for an application acknowledgement.
+#[derive(Default)]
+struct PendingAcknowledgement {
+    acknowledgement: Option<ApplicationAcknowledgement>,
+    waiters: Vec<oneshot::Sender<ApplicationAcknowledgement>>,
+}
+
 #[derive(Default)]
 pub struct Daemon {
     connected_clients: HashMap<Endpoint, Addr<ListenBundleResponseActor>>,
     endpoint: Option<Endpoint>,
+    /// By the creation timestamp of the bundle, until the bundle expires.
+    acknowledgements: HashMap<CreationTimestamp, PendingAcknowledgement>,
 }
 
 impl Actor for Daemon {
@@ -74,7 +98,7 @@ impl Handler<Shutdown> for Daemon {
 }
 
 impl Handler<ClientSendBundle> for Daemon {
-    type Result = ResponseFuture<Result<(), ()>>;
+    type Result = ResponseActFuture<Self, Result<CreationTimestamp, StorageFull>>;
 
     fn handle(&mut self, msg: ClientSendBundle, _ctx: &mut Context<Self>) -> Self::Result {
         let ClientSendBundle {
@@ -82,9 +106,11 @@ impl Handler<ClientSendBundle> for Daemon {
             payload,
             lifetime,
             debug,
+            priority,
+            request_acknowledgement,
         } = msg;
 
-        let bundle_processing_flags = if debug {
+        let mut bundle_processing_flags = if debug {
             BundleFlags::BUNDLE_RECEIPTION_STATUS_REQUESTED
                 | BundleFlags::BUNDLE_FORWARDING_STATUS_REQUEST
                 | BundleFlags::BUNDLE_DELIVERY_STATUS_REQUESTED
@@ -92,8 +118,11 @@ impl Handler<ClientSendBundle> for Daemon {
         } else {
             BundleFlags::BUNDLE_DELETION_STATUS_REQUESTED
         };
+        if request_acknowledgement {
+            bundle_processing_flags |= BundleFlags::APPLICATION_ACKNOWLEGEMENT_REQUESTED;
+        }
 
-        let bundle = Bundle {
+        let mut bundle = Bundle {
             primary_block: PrimaryBlock {
                 version: 7,
                 bundle_processing_flags,
@@ -105,7 +134,8 @@ impl Handler<ClientSendBundle> for Daemon {
                     creation_time: DtnTime::now(),
                     sequence_number: 0,
                 },
-                lifetime,
+                // clients give the lifetime in seconds
+                lifetime: lifetime.saturating_mul(1000),
                 fragment_offset: None,
                 total_data_length: None,
             },
@@ -118,11 +148,114 @@ impl Handler<ClientSendBundle> for Daemon {
                 crc: CRCType::NoCRC,
             }],
         };
+        if priority != PriorityClass::Normal {
+            bundle.set_quality_of_service(QualityOfServiceBlock {
+                class: priority,
+                ordinal: 0,
+            });
+        }
         debug!("Storing new bundle {:?}", &bundle.primary_block);
         let bundle_data = bundle.try_into().unwrap();
-        Box::pin(async move {
-            crate::bundlestorageagent::agent::Daemon::from_registry()
-                .send(StoreNewBundle { bundle_data })
+        Box::pin(
+            async move {
+                crate::bundlestorageagent::agent::Daemon::from_registry()
+                    .send(StoreNewBundle { bundle_data })
+                    .await
+                    .unwrap()
+            }
+            .into_actor(self)
+            .map(move |result, act, ctx| {
+                if request_acknowledgement && let Ok(creation_timestamp) = &result {
+                    act.acknowledgements.insert(
+                        creation_timestamp.clone(),
+                        PendingAcknowledgement::default(),
+                    );
+                    let creation_timestamp = creation_timestamp.clone();
+                    ctx.run_later(Duration::from_secs(lifetime), move |act, _ctx| {
+                        // drops all waiters, they learn that the bundle expired
+                        act.acknowledgements.remove(&creation_timestamp);
+                    });
+                }
+                result
+            }),
+        )
+    }
+}
+
+impl Handler<EventBundleAcknowledged> for Daemon {
+    type Result = ();
+
+    fn handle(&mut self, msg: EventBundleAcknowledged, _ctx: &mut Context<Self>) -> Self::Result {
+        let EventBundleAcknowledged { acknowledgement } = msg;
+        let pending = self
+            .acknowledgements
+            .get_mut(&acknowledgement.bundle_creation_timestamp)
+            .filter(|pending| pending.acknowledgement.is_none());
+        let Some(pending) = pending else {
+            debug!(
+                "Ignoring acknowledgement of bundle {:?} that we do not wait for",
+                acknowledgement.bundle_creation_timestamp
+            );
+            return;
+        };
+        for waiter in pending.waiters.drain(..) {
+            let _ = waiter.send(acknowledgement.clone());
+        }
+        pending.acknowledgement = Some(acknowledgement);
+    }
+}
+
+impl Handler<ClientAwaitAcknowledgement> for Daemon {
+    type Result = ResponseFuture<Result<ApplicationAcknowledgement, AcknowledgementError>>;
+
+    fn handle(
+        &mut self,
+        msg: ClientAwaitAcknowledgement,
+        _ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        let ClientAwaitAcknowledgement {
+            bundle_source,
+            bundle_creation_timestamp,
+        } = msg;
+        let pending = self
+            .acknowledgements
+            .get_mut(&bundle_creation_timestamp)
+            .filter(|_| self.endpoint.as_ref().unwrap().matches_node(&bundle_source));
+        let Some(pending) = pending else {
+            return Box::pin(async { Err(AcknowledgementError::UnknownBundle) });
+        };
+        if let Some(acknowledgement) = pending.acknowledgement.clone() {
+            return Box::pin(async { Ok(acknowledgement) });
+        }
+        let (sender, receiver) = oneshot::channel();
+        pending.waiters.push(sender);
+        Box::pin(async { receiver.await.map_err(|_| AcknowledgementError::Expired) })
+    }
+}
+
+impl Handler<ClientAcknowledgeBundle> for Daemon {
+    type Result = ResponseFuture<Result<(), String>>;
+
+    fn handle(&mut self, msg: ClientAcknowledgeBundle, _ctx: &mut Context<Self>) -> Self::Result {
+        let ClientAcknowledgeBundle {
+            endpoint,
+            bundle_source,
+            bundle_creation_timestamp,
+        } = msg;
+
+        if !self.endpoint.as_ref().unwrap().matches_node(&endpoint) {
+            return Box::pin(async {
+                Err("Acknowledging endpoint does not match local node".to_string())
+            });
+        }
+
+        Box::pin(async {
+            crate::bundleprotocolagent::agent::Daemon::from_registry()
+                .send(AcknowledgeBundle {
+                    endpoint,
+                    bundle_source,
+                    bundle_creation_timestamp,
+                })
                 .await
                 .unwrap()
         })
@@ -206,6 +339,46 @@ impl Handler<ClientRemoveNode> for Daemon {
     }
 }
 
+impl Handler<ClientGetStatistics> for Daemon {
+    type Result = ResponseFuture<Statistics>;
+
+    fn handle(&mut self, _msg: ClientGetStatistics, _ctx: &mut Context<Self>) -> Self::Result {
+        Box::pin(async {
+            let storage = crate::bundlestorageagent::agent::Daemon::from_registry()
+                .send(GetStorageStatistics {})
+                .await
+                .unwrap();
+            let queues = crate::bundleprotocolagent::agent::Daemon::from_registry()
+                .send(GetQueueStatistics {})
+                .await
+                .unwrap();
+            let custody = crate::bundleprotocolagent::agent::Daemon::from_registry()
+                .send(GetCustodyStatistics {})
+                .await
+                .unwrap();
+            Statistics {
+                storage,
+                queues,
+                custody,
+            }
+        })
+    }
+}
+
+impl Handler<ClientListBundleHistory> for Daemon {
+    type Result = ResponseFuture<Vec<BundleHistory>>;
+
+    fn handle(&mut self, msg: ClientListBundleHistory, _ctx: &mut Context<Self>) -> Self::Result {
+        let ClientListBundleHistory { source } = msg;
+        Box::pin(async {
+            crate::bundleprotocolagent::agent::Daemon::from_registry()
+                .send(GetBundleHistory { source })
+                .await
+                .unwrap()
+        })
+    }
+}
+
 impl Handler<ClientListRoutes> for Daemon {
     type Result = ResponseFuture<Vec<RouteStatus>>;
 
@@ -223,16 +396,64 @@ impl Handler<ClientAddRoute> for Daemon {
     type Result = ();
 
     fn handle(&mut self, msg: ClientAddRoute, _ctx: &mut Context<Self>) -> Self::Result {
-        let ClientAddRoute { target, next_hop } = msg;
+        let ClientAddRoute {
+            target,
+            next_hop,
+            metric,
+            weight,
+        } = msg;
         crate::routingagent::agent::Daemon::from_registry().do_send(AddRoute {
             target,
             next_hop,
             route_type: RouteType::Static,
             max_bundle_size: None,
+            metric,
+            weight,
         });
     }
 }
 
+impl Handler<ClientListContacts> for Daemon {
+    type Result = ResponseFuture<Vec<ContactStatus>>;
+
+    fn handle(&mut self, _msg: ClientListContacts, _ctx: &mut Context<Self>) -> Self::Result {
+        Box::pin(async {
+            crate::routingagent::agent::Daemon::from_registry()
+                .send(ListContacts {})
+                .await
+                .unwrap()
+        })
+    }
+}
+
+impl Handler<ClientAddContact> for Daemon {
+    type Result = ResponseFuture<Result<(), String>>;
+
+    fn handle(&mut self, msg: ClientAddContact, _ctx: &mut Context<Self>) -> Self::Result {
+        let ClientAddContact { contact } = msg;
+        Box::pin(async {
+            crate::routingagent::agent::Daemon::from_registry()
+                .send(AddContact { contact })
+                .await
+                .unwrap()
+        })
+    }
+}
+
+impl Handler<ClientRemoveContact> for Daemon {
+    type Result = ResponseFuture<bool>;
+
+    fn handle(&mut self, msg: ClientRemoveContact, _ctx: &mut Context<Self>) -> Self::Result {
+        let ClientRemoveContact { from, to, start } = msg;
+        Box::pin(async move {
+            crate::routingagent::agent::Daemon::from_registry()
+                .send(RemoveContact { from, to, start })
+                .await
+                .unwrap()
+        })
+    }
+}
+
 impl Handler<ClientRemoveRoute> for Daemon {
     type Result = ();
 
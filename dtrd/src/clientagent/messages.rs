@@ -15,11 +15,21 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::bundleprotocolagent::messages::{BundleHistory, CustodyStatistics, QueueStatistics};
 use crate::bundlestorageagent::StoredBundleRef;
+use crate::bundlestorageagent::messages::{StorageFull, StorageStatistics};
 use crate::nodeagent::messages::Node;
-use crate::routingagent::messages::RouteStatus;
+use crate::routingagent::{
+    messages::{Contact, ContactStatus, RouteStatus},
+    route_target::RouteTarget,
+};
 use actix::prelude::*;
-use bp7::endpoint::Endpoint;
+use bp7::{
+    administrative_record::application_acknowledgement::ApplicationAcknowledgement,
+    block::quality_of_service_block::PriorityClass,
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
+};
 use tokio::sync::mpsc;
 use url::Url;
 
@@ -70,13 +80,47 @@ pub struct ClientListenDisconnect {
     pub destination: Endpoint,
 }
 
+/// Answers with the creation timestamp that identifies the new bundle.
 #[derive(Message)]
-#[rtype(result = "Result<(), ()>")]
+#[rtype(result = "Result<CreationTimestamp, StorageFull>")]
 pub struct ClientSendBundle {
     pub destination: Endpoint,
     pub payload: Vec<u8>,
     pub lifetime: u64,
     pub debug: bool,
+    pub priority: PriorityClass,
+    pub request_acknowledgement: bool,
+}
+
+/// The application at the destination acknowledged one of our bundles.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EventBundleAcknowledged {
+    pub acknowledgement: ApplicationAcknowledgement,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct ClientAcknowledgeBundle {
+    pub endpoint: Endpoint,
+    pub bundle_source: Endpoint,
+    pub bundle_creation_timestamp: CreationTimestamp,
+}
+
+#[derive(Debug)]
+pub enum AcknowledgementError {
+    /// We did not send a bundle requesting an acknowledgement with this id.
+    UnknownBundle,
+    /// The bundle expired before it was acknowledged.
+    Expired,
+}
+
+/// Waits until a bundle we sent is acknowledged.
+#[derive(Message)]
+#[rtype(result = "Result<ApplicationAcknowledgement, AcknowledgementError>")]
+pub struct ClientAwaitAcknowledgement {
+    pub bundle_source: Endpoint,
+    pub bundle_creation_timestamp: CreationTimestamp,
 }
 #[derive(Message)]
 #[rtype(result = "Vec<Node>")]
@@ -98,16 +142,53 @@ pub struct ClientRemoveNode {
 #[rtype(result = "Vec<RouteStatus>")]
 pub struct ClientListRoutes {}
 
+#[derive(Debug, Clone)]
+pub struct Statistics {
+    pub storage: StorageStatistics,
+    pub queues: QueueStatistics,
+    pub custody: CustodyStatistics,
+}
+
+#[derive(Message)]
+#[rtype(result = "Statistics")]
+pub struct ClientGetStatistics {}
+
+#[derive(Message)]
+#[rtype(result = "Vec<BundleHistory>")]
+pub struct ClientListBundleHistory {
+    pub source: Option<Endpoint>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ClientAddRoute {
-    pub target: Endpoint,
+    pub target: RouteTarget,
     pub next_hop: Endpoint,
+    pub metric: u64,
+    pub weight: u64,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct ClientRemoveRoute {
-    pub target: Endpoint,
+    pub target: RouteTarget,
     pub next_hop: Endpoint,
 }
+
+#[derive(Message)]
+#[rtype(result = "Vec<ContactStatus>")]
+pub struct ClientListContacts {}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct ClientAddContact {
+    pub contact: Contact,
+}
+
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct ClientRemoveContact {
+    pub from: Endpoint,
+    pub to: Endpoint,
+    pub start: DtnTime,
+}
@@ -28,18 +28,31 @@ use tonic::{Response, Status, transport::Server};
 use url::Url;
 
 use crate::{
+    bundleprotocolagent::{messages::BundleStatus, priority::class_name},
+    bundlestorageagent::messages::StorageFull,
     clientagent::{
         self,
         messages::{
-            ClientAddNode, ClientAddRoute, ClientDeliverBundle, ClientListNodes, ClientListRoutes,
-            ClientListenConnect, ClientListenDisconnect, ClientRemoveNode, ClientRemoveRoute,
-            ClientSendBundle, EventBundleDelivered,
+            AcknowledgementError, ClientAcknowledgeBundle, ClientAddContact, ClientAddNode,
+            ClientAddRoute, ClientAwaitAcknowledgement, ClientDeliverBundle, ClientGetStatistics,
+            ClientListBundleHistory, ClientListContacts, ClientListNodes, ClientListRoutes,
+            ClientListenConnect, ClientListenDisconnect, ClientRemoveContact, ClientRemoveNode,
+            ClientRemoveRoute, ClientSendBundle, EventBundleDelivered,
         },
     },
     common::settings::Settings,
-    routingagent::messages::RouteType,
+    routingagent::{
+        messages::{Contact, RouteType},
+        route_target::RouteTarget,
+    },
+};
+use bp7::{
+    block::quality_of_service_block::PriorityClass,
+    bundle::Bundle,
+    bundleflags::BundleFlags,
+    endpoint::Endpoint,
+    time::{CreationTimestamp, DtnTime},
 };
-use bp7::{bundle::Bundle, endpoint::Endpoint};
 
 #[allow(clippy::all, clippy::pedantic, clippy::restriction, clippy::nursery)]
 mod bundleservice {
@@ -53,6 +66,34 @@ mod adminservice {
 
 const MESSAGE_SIZE_LIMIT: usize = 10 * 1024 * 1024 * 1024;
 
+fn to_bundle_id(
+    source: &Endpoint,
+    creation_timestamp: &CreationTimestamp,
+) -> bundleservice::BundleId {
+    bundleservice::BundleId {
+        source: source.to_string(),
+        creation_time: creation_timestamp.creation_time.timestamp,
+        sequence_number: creation_timestamp.sequence_number,
+    }
+}
+
+fn from_bundle_id(
+    bundle_id: Option<bundleservice::BundleId>,
+) -> Result<(Endpoint, CreationTimestamp), Status> {
+    let bundle_id = bundle_id.ok_or_else(|| Status::invalid_argument("bundle_id must be set"))?;
+    let source = Endpoint::new(&bundle_id.source)
+        .ok_or_else(|| Status::invalid_argument("bundle source invalid"))?;
+    Ok((
+        source,
+        CreationTimestamp {
+            creation_time: DtnTime {
+                timestamp: bundle_id.creation_time,
+            },
+            sequence_number: bundle_id.sequence_number,
+        },
+    ))
+}
+
 pub struct ListenBundleResponseTransformer {
     client_agent: Addr<clientagent::agent::Daemon>,
     destination: Endpoint,
@@ -71,9 +112,14 @@ impl Stream for ListenBundleResponseTransformer {
                 if let Some(bundle_data) = cdb.bundle.get_bundle_data() {
                     let bundle: Bundle = bundle_data.as_slice().try_into().unwrap();
                     let payload = bundle.payload_block().data.to_vec();
+                    let pb = cdb.bundle.get_primary_block();
                     let lbr = bundleservice::ListenBundleResponse {
-                        source: cdb.bundle.get_primary_block().source_node.to_string(),
+                        source: pb.source_node.to_string(),
                         payload,
+                        acknowledgement_requested: pb
+                            .bundle_processing_flags
+                            .contains(BundleFlags::APPLICATION_ACKNOWLEGEMENT_REQUESTED),
+                        bundle_id: Some(to_bundle_id(&pb.source_node, &pb.creation_timestamp)),
                     };
                     cdb.responder.do_send(EventBundleDelivered {
                         endpoint: cdb.bundle.get_primary_block().destination_endpoint.clone(),
@@ -100,6 +146,7 @@ impl Drop for ListenBundleResponseTransformer {
 
 pub struct MyBundleService {
     client_agent: Addr<clientagent::agent::Daemon>,
+    node_id: Endpoint,
 }
 
 #[tonic::async_trait]
@@ -111,6 +158,13 @@ impl BundleService for MyBundleService {
         let req = request.into_inner();
         let destination = Endpoint::new(&req.destination)
             .ok_or_else(|| tonic::Status::invalid_argument("destination invalid"))?;
+        let priority = match bundleservice::submit_bundle_request::Priority::try_from(req.priority)
+            .map_err(|_| tonic::Status::invalid_argument("priority invalid"))?
+        {
+            bundleservice::submit_bundle_request::Priority::Normal => PriorityClass::Normal,
+            bundleservice::submit_bundle_request::Priority::Bulk => PriorityClass::Bulk,
+            bundleservice::submit_bundle_request::Priority::Expedited => PriorityClass::Expedited,
+        };
 
         let send_result = self
             .client_agent
@@ -119,17 +173,20 @@ impl BundleService for MyBundleService {
                 payload: req.payload,
                 lifetime: req.lifetime,
                 debug: req.debug,
+                priority,
+                request_acknowledgement: req.request_acknowledgement,
             })
             .await
             .map_err(|e| tonic::Status::unknown(e.to_string()))?;
 
         match send_result {
-            Ok(()) => Ok(Response::new(bundleservice::SubmitBundleRespone {
+            Ok(creation_timestamp) => Ok(Response::new(bundleservice::SubmitBundleRespone {
                 success: true,
                 message: String::new(),
+                bundle_id: Some(to_bundle_id(&self.node_id, &creation_timestamp)),
             })),
-            Err(()) => Err(tonic::Status::internal(
-                "something prevented the bundle from being accepted",
+            Err(StorageFull) => Err(tonic::Status::resource_exhausted(
+                "there is no space left to store the bundle",
             )),
         }
     }
@@ -166,6 +223,57 @@ impl BundleService for MyBundleService {
             Err(msg) => Err(tonic::Status::invalid_argument(msg)),
         }
     }
+
+    async fn acknowledge_bundle(
+        &self,
+        request: tonic::Request<bundleservice::AcknowledgeBundleRequest>,
+    ) -> Result<tonic::Response<bundleservice::AcknowledgeBundleResponse>, tonic::Status> {
+        let req = request.into_inner();
+        let endpoint = Endpoint::new(&req.endpoint)
+            .ok_or_else(|| tonic::Status::invalid_argument("endpoint invalid"))?;
+        let (bundle_source, bundle_creation_timestamp) = from_bundle_id(req.bundle_id)?;
+
+        self.client_agent
+            .send(ClientAcknowledgeBundle {
+                endpoint,
+                bundle_source,
+                bundle_creation_timestamp,
+            })
+            .await
+            .map_err(|e| tonic::Status::unknown(e.to_string()))?
+            .map_err(tonic::Status::failed_precondition)?;
+        Ok(Response::new(bundleservice::AcknowledgeBundleResponse {}))
+    }
+
+    async fn await_acknowledgement(
+        &self,
+        request: tonic::Request<bundleservice::AwaitAcknowledgementRequest>,
+    ) -> Result<tonic::Response<bundleservice::AwaitAcknowledgementResponse>, tonic::Status> {
+        let req = request.into_inner();
+        let (bundle_source, bundle_creation_timestamp) = from_bundle_id(req.bundle_id)?;
+
+        let result = self
+            .client_agent
+            .send(ClientAwaitAcknowledgement {
+                bundle_source,
+                bundle_creation_timestamp,
+            })
+            .await
+            .map_err(|e| tonic::Status::unknown(e.to_string()))?;
+
+        match result {
+            Ok(acknowledgement) => Ok(Response::new(bundleservice::AwaitAcknowledgementResponse {
+                acknowledged_by: acknowledgement.acknowledged_by.to_string(),
+                acknowledgement_time: acknowledgement.acknowledgement_time.timestamp,
+            })),
+            Err(AcknowledgementError::UnknownBundle) => Err(tonic::Status::not_found(
+                "no bundle requesting an acknowledgement with this id",
+            )),
+            Err(AcknowledgementError::Expired) => Err(tonic::Status::deadline_exceeded(
+                "the bundle expired without being acknowledged",
+            )),
+        }
+    }
 }
 
 pub struct MyAdminService {
@@ -195,6 +303,25 @@ impl AdminService for MyAdminService {
                     .map(std::string::ToString::to_string)
                     .unwrap_or_default(),
                 temporary: node.temporary,
+                link_stats: node.link_stats.as_ref().map(|s| {
+                    let stats = s.borrow();
+                    adminservice::LinkStats {
+                        state: format!("{:?}", stats.state),
+                        uptime_seconds: stats.uptime().unwrap_or_default().as_secs(),
+                        bytes_sent: stats.bytes_sent,
+                        bytes_received: stats.bytes_received,
+                        segments_sent: stats.segments_sent,
+                        segments_received: stats.segments_received,
+                        transfers_sent: stats.transfers_sent,
+                        transfers_received: stats.transfers_received,
+                        transfers_refused: stats.transfers_refused,
+                        keepalives_sent: stats.keepalives_sent,
+                        keepalives_received: stats.keepalives_received,
+                        rtt_micros: stats
+                            .rtt
+                            .map_or(0, |rtt| rtt.as_micros().try_into().unwrap_or(u64::MAX)),
+                    }
+                }),
             })
             .collect();
         return Ok(Response::new(adminservice::ListNodesResponse { nodes }));
@@ -247,11 +374,14 @@ impl AdminService for MyAdminService {
                     route: Some(adminservice::Route {
                         target: route.target.to_string(),
                         next_hop: route.next_hop.to_string(),
+                        metric: route.metric,
+                        weight: route.weight,
                     }),
                     r#type: route_type,
                     preferred: route.preferred,
                     available: route.available,
                     max_bundle_size: route.max_bundle_size.unwrap_or(0),
+                    reason: route.reason.to_string(),
                 }
             })
             .collect();
@@ -268,13 +398,18 @@ impl AdminService for MyAdminService {
             .route
             .ok_or_else(|| tonic::Status::invalid_argument("Route must be set"))?;
 
-        let target = Endpoint::new(&route.target)
+        let target = RouteTarget::parse(&route.target)
             .ok_or_else(|| tonic::Status::invalid_argument("target invalid"))?;
         let next_hop = Endpoint::new(&route.next_hop)
             .ok_or_else(|| tonic::Status::invalid_argument("next_hop invalid"))?;
 
         self.client_agent
-            .send(ClientAddRoute { target, next_hop })
+            .send(ClientAddRoute {
+                target,
+                next_hop,
+                metric: route.metric,
+                weight: route.weight,
+            })
             .await
             .map_err(|e| tonic::Status::unknown(e.to_string()))?;
         Ok(Response::new(adminservice::AddRouteResponse {}))
@@ -290,7 +425,7 @@ impl AdminService for MyAdminService {
             .route
             .ok_or_else(|| tonic::Status::invalid_argument("Route must be set"))?;
 
-        let target = Endpoint::new(&route.target)
+        let target = RouteTarget::parse(&route.target)
             .ok_or_else(|| tonic::Status::invalid_argument("target invalid"))?;
         let next_hop = Endpoint::new(&route.next_hop)
             .ok_or_else(|| tonic::Status::invalid_argument("next_hop invalid"))?;
@@ -301,6 +436,186 @@ impl AdminService for MyAdminService {
             .map_err(|e| tonic::Status::unknown(e.to_string()))?;
         Ok(Response::new(adminservice::RemoveRouteResponse {}))
     }
+
+    async fn list_contacts(
+        &self,
+        _: tonic::Request<adminservice::ListContactsRequest>,
+    ) -> Result<tonic::Response<adminservice::ListContactsResponse>, tonic::Status> {
+        let contact_list = self
+            .client_agent
+            .send(ClientListContacts {})
+            .await
+            .map_err(|e| tonic::Status::unknown(e.to_string()))?;
+
+        let contacts = contact_list
+            .into_iter()
+            .map(|status| {
+                let contact = status.contact;
+                adminservice::ContactStatus {
+                    contact: Some(adminservice::Contact {
+                        from: contact.from.to_string(),
+                        to: contact.to.to_string(),
+                        start_time: contact.start.timestamp,
+                        end_time: contact.end.timestamp,
+                        rate_bytes_per_second: contact.rate,
+                        owlt_millis: contact.owlt,
+                        url: contact.url.map(|u| u.to_string()).unwrap_or_default(),
+                    }),
+                    booked_bytes: status.booked,
+                }
+            })
+            .collect();
+        Ok(Response::new(adminservice::ListContactsResponse {
+            contacts,
+        }))
+    }
+
+    async fn add_contact(
+        &self,
+        request: tonic::Request<adminservice::AddContactRequest>,
+    ) -> Result<tonic::Response<adminservice::AddContactResponse>, tonic::Status> {
+        let req = request.into_inner();
+
+        let contact = req
+            .contact
+            .ok_or_else(|| tonic::Status::invalid_argument("Contact must be set"))?;
+
+        let url = if contact.url.is_empty() {
+            None
+        } else {
+            Some(Url::parse(&contact.url).map_err(|e| Status::invalid_argument(e.to_string()))?)
+        };
+        let contact = Contact {
+            from: Endpoint::new(&contact.from)
+                .ok_or_else(|| tonic::Status::invalid_argument("from invalid"))?,
+            to: Endpoint::new(&contact.to)
+                .ok_or_else(|| tonic::Status::invalid_argument("to invalid"))?,
+            start: DtnTime {
+                timestamp: contact.start_time,
+            },
+            end: DtnTime {
+                timestamp: contact.end_time,
+            },
+            rate: contact.rate_bytes_per_second,
+            owlt: contact.owlt_millis,
+            url,
+        };
+
+        self.client_agent
+            .send(ClientAddContact { contact })
+            .await
+            .map_err(|e| tonic::Status::unknown(e.to_string()))?
+            .map_err(tonic::Status::invalid_argument)?;
+        Ok(Response::new(adminservice::AddContactResponse {}))
+    }
+
+    async fn remove_contact(
+        &self,
+        request: tonic::Request<adminservice::RemoveContactRequest>,
+    ) -> Result<tonic::Response<adminservice::RemoveContactResponse>, tonic::Status> {
+        let req = request.into_inner();
+
+        let from = Endpoint::new(&req.from)
+            .ok_or_else(|| tonic::Status::invalid_argument("from invalid"))?;
+        let to =
+            Endpoint::new(&req.to).ok_or_else(|| tonic::Status::invalid_argument("to invalid"))?;
+        let start = DtnTime {
+            timestamp: req.start_time,
+        };
+
+        let removed = self
+            .client_agent
+            .send(ClientRemoveContact { from, to, start })
+            .await
+            .map_err(|e| tonic::Status::unknown(e.to_string()))?;
+        if !removed {
+            return Err(tonic::Status::not_found("No such contact"));
+        }
+        Ok(Response::new(adminservice::RemoveContactResponse {}))
+    }
+
+    async fn get_statistics(
+        &self,
+        _: tonic::Request<adminservice::GetStatisticsRequest>,
+    ) -> Result<tonic::Response<adminservice::GetStatisticsResponse>, tonic::Status> {
+        let statistics = self
+            .client_agent
+            .send(ClientGetStatistics {})
+            .await
+            .map_err(|e| tonic::Status::unknown(e.to_string()))?;
+        Ok(Response::new(adminservice::GetStatisticsResponse {
+            statistics: Some(adminservice::Statistics {
+                duplicate_bundles: statistics.storage.duplicate_bundles,
+                queued_bundles: statistics
+                    .queues
+                    .queued_bundles
+                    .into_iter()
+                    .map(|(class, queued)| (class_name(class).to_string(), queued as u64))
+                    .collect(),
+                bundles_in_custody: statistics.custody.bundles_in_custody,
+                custody_accepted: statistics.custody.custody_accepted,
+                custody_refused: statistics.custody.custody_refused,
+                custody_retransmissions: statistics.custody.custody_retransmissions,
+            }),
+        }))
+    }
+
+    async fn list_bundle_history(
+        &self,
+        request: tonic::Request<adminservice::ListBundleHistoryRequest>,
+    ) -> Result<tonic::Response<adminservice::ListBundleHistoryResponse>, tonic::Status> {
+        let req = request.into_inner();
+        let source = if req.source.is_empty() {
+            None
+        } else {
+            Some(
+                Endpoint::new(&req.source)
+                    .ok_or_else(|| tonic::Status::invalid_argument("source invalid"))?,
+            )
+        };
+
+        let history = self
+            .client_agent
+            .send(ClientListBundleHistory { source })
+            .await
+            .map_err(|e| tonic::Status::unknown(e.to_string()))?;
+
+        let bundles = history
+            .into_iter()
+            .map(|bundle| adminservice::BundleHistory {
+                source: bundle.source.to_string(),
+                creation_time: bundle.creation_timestamp.creation_time.timestamp,
+                sequence_number: bundle.creation_timestamp.sequence_number,
+                events: bundle
+                    .events
+                    .into_iter()
+                    .map(|event| {
+                        let status = match event.status {
+                            BundleStatus::Received => 0,
+                            BundleStatus::Forwarded => 1,
+                            BundleStatus::Delivered => 2,
+                            BundleStatus::Deleted => 3,
+                        };
+                        adminservice::BundleStatusEvent {
+                            status,
+                            time: event.time.timestamp,
+                            reporting_node: event.reporting_node.to_string(),
+                            reason: format!("{:?}", event.reason),
+                            fragment: event.fragment_offset.zip(event.fragment_length).map(
+                                |(offset, length)| adminservice::bundle_status_event::Fragment {
+                                    offset,
+                                    length,
+                                },
+                            ),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(Response::new(adminservice::ListBundleHistoryResponse {
+            bundles,
+        }))
+    }
 }
 
 pub async fn main(
@@ -312,6 +627,7 @@ pub async fn main(
     let addr = settings.grpc_clientapi_address.parse().unwrap();
     let bundle_service = MyBundleService {
         client_agent: client_agent.clone(),
+        node_id: Endpoint::new(&settings.my_node_id).unwrap(),
     };
     let admin_service = MyAdminService {
         client_agent: client_agent.clone(),
@@ -21,11 +21,33 @@ use std::env;
 pub struct Settings {
     pub my_node_id: String,
     pub tcpcl_listen_address: String,
+    pub tcpcl_unix_socket_path: Option<String>,
     pub grpc_clientapi_address: String,
     pub bundle_storage_path: String,
+    pub bundle_storage_backend: String,
+    pub bundle_storage_max_bytes: Option<u64>,
+    pub bundle_storage_max_bundles: Option<u64>,
+    pub bundle_storage_full_policy: String,
+    pub bundle_duplicate_cache_size: usize,
+    pub bundle_priority_policy: Option<String>,
+    pub bundle_scheduling: String,
+    pub bundle_starvation_timeout: u64,
+    pub custody_transfer: bool,
+    pub custody_retransmission_timeout: u64,
+    pub status_report_history_size: usize,
+    pub contact_plan_path: Option<String>,
+    pub opportunistic_routing: String,
+    pub spray_and_wait_copies: u64,
     pub tcpcl_certificate_path: Option<String>,
     pub tcpcl_key_path: Option<String>,
     pub tcpcl_trusted_certs_path: Option<String>,
+    pub tcpcl_incoming_path: Option<String>,
+    pub tcpcl_transfer_mru: Option<u64>,
+    pub tcpcl_shutdown_deadline: u64,
+    pub tcpcl_connect_timeout: u64,
+    pub tcpcl_send_window: Option<u64>,
+    pub tcpcl_rate_limit: Option<u64>,
+    pub tcpcl_rate_burst: Option<u64>,
     pub tokio_tracing_port: Option<String>,
 }
 
@@ -34,11 +56,33 @@ impl Default for Settings {
         Self {
             my_node_id: "dtn://defaultnodeid".into(),
             tcpcl_listen_address: "[::1]:4556".into(),
+            tcpcl_unix_socket_path: None,
             grpc_clientapi_address: "[::1]:50051".into(),
             bundle_storage_path: "/tmp".into(),
+            bundle_storage_backend: "filesystem".into(),
+            bundle_storage_max_bytes: None,
+            bundle_storage_max_bundles: None,
+            bundle_storage_full_policy: "refuse".into(),
+            bundle_duplicate_cache_size: 10000,
+            bundle_priority_policy: None,
+            bundle_scheduling: "weighted-fair".into(),
+            bundle_starvation_timeout: 60,
+            custody_transfer: false,
+            custody_retransmission_timeout: 60,
+            status_report_history_size: 1000,
+            contact_plan_path: None,
+            opportunistic_routing: "none".into(),
+            spray_and_wait_copies: 8,
             tcpcl_certificate_path: None,
             tcpcl_key_path: None,
             tcpcl_trusted_certs_path: None,
+            tcpcl_incoming_path: None,
+            tcpcl_transfer_mru: None,
+            tcpcl_shutdown_deadline: 10,
+            tcpcl_connect_timeout: 10,
+            tcpcl_send_window: None,
+            tcpcl_rate_limit: None,
+            tcpcl_rate_burst: None,
             tokio_tracing_port: None,
         }
     }
@@ -53,12 +97,77 @@ impl Settings {
         if let Ok(setting) = env::var("TCPCL_LISTEN_ADDRESS") {
             settings.tcpcl_listen_address = setting;
         }
+        if let Ok(setting) = env::var("TCPCL_UNIX_SOCKET_PATH") {
+            settings.tcpcl_unix_socket_path = Some(setting);
+        }
         if let Ok(setting) = env::var("GRPC_CLIENTAPI_ADDRESS") {
             settings.grpc_clientapi_address = setting;
         }
         if let Ok(setting) = env::var("BUNDLE_STORAGE_PATH") {
             settings.bundle_storage_path = setting;
         }
+        if let Ok(setting) = env::var("BUNDLE_STORAGE_BACKEND") {
+            settings.bundle_storage_backend = setting;
+        }
+        if let Ok(setting) = env::var("BUNDLE_STORAGE_MAX_BYTES") {
+            settings.bundle_storage_max_bytes = Some(
+                setting
+                    .parse()
+                    .expect("BUNDLE_STORAGE_MAX_BYTES must be a number"),
+            );
+        }
+        if let Ok(setting) = env::var("BUNDLE_STORAGE_MAX_BUNDLES") {
+            settings.bundle_storage_max_bundles = Some(
+                setting
+                    .parse()
+                    .expect("BUNDLE_STORAGE_MAX_BUNDLES must be a number"),
+            );
+        }
+        if let Ok(setting) = env::var("BUNDLE_STORAGE_FULL_POLICY") {
+            settings.bundle_storage_full_policy = setting;
+        }
+        if let Ok(setting) = env::var("BUNDLE_DUPLICATE_CACHE_SIZE") {
+            settings.bundle_duplicate_cache_size = setting
+                .parse()
+                .expect("BUNDLE_DUPLICATE_CACHE_SIZE must be a number");
+        }
+        if let Ok(setting) = env::var("BUNDLE_PRIORITY_POLICY") {
+            settings.bundle_priority_policy = Some(setting);
+        }
+        if let Ok(setting) = env::var("BUNDLE_SCHEDULING") {
+            settings.bundle_scheduling = setting;
+        }
+        if let Ok(setting) = env::var("BUNDLE_STARVATION_TIMEOUT") {
+            settings.bundle_starvation_timeout = setting
+                .parse()
+                .expect("BUNDLE_STARVATION_TIMEOUT must be a number");
+        }
+        if let Ok(setting) = env::var("CUSTODY_TRANSFER") {
+            settings.custody_transfer = setting
+                .parse()
+                .expect("CUSTODY_TRANSFER must be true or false");
+        }
+        if let Ok(setting) = env::var("CUSTODY_RETRANSMISSION_TIMEOUT") {
+            settings.custody_retransmission_timeout = setting
+                .parse()
+                .expect("CUSTODY_RETRANSMISSION_TIMEOUT must be a number");
+        }
+        if let Ok(setting) = env::var("STATUS_REPORT_HISTORY_SIZE") {
+            settings.status_report_history_size = setting
+                .parse()
+                .expect("STATUS_REPORT_HISTORY_SIZE must be a number");
+        }
+        if let Ok(setting) = env::var("CONTACT_PLAN_PATH") {
+            settings.contact_plan_path = Some(setting);
+        }
+        if let Ok(setting) = env::var("OPPORTUNISTIC_ROUTING") {
+            settings.opportunistic_routing = setting;
+        }
+        if let Ok(setting) = env::var("SPRAY_AND_WAIT_COPIES") {
+            settings.spray_and_wait_copies = setting
+                .parse()
+                .expect("SPRAY_AND_WAIT_COPIES must be a number");
+        }
         if let Ok(setting) = env::var("TCPCL_CERTIFICATE_PATH") {
             settings.tcpcl_certificate_path = Some(setting);
         }
@@ -68,6 +177,38 @@ impl Settings {
         if let Ok(setting) = env::var("TCPCL_TRUSTED_CERTS_PATH") {
             settings.tcpcl_trusted_certs_path = Some(setting);
         }
+        if let Ok(setting) = env::var("TCPCL_INCOMING_PATH") {
+            settings.tcpcl_incoming_path = Some(setting);
+        }
+        if let Ok(setting) = env::var("TCPCL_TRANSFER_MRU") {
+            settings.tcpcl_transfer_mru = Some(
+                setting
+                    .parse()
+                    .expect("TCPCL_TRANSFER_MRU must be a number"),
+            );
+        }
+        if let Ok(setting) = env::var("TCPCL_SHUTDOWN_DEADLINE") {
+            settings.tcpcl_shutdown_deadline = setting
+                .parse()
+                .expect("TCPCL_SHUTDOWN_DEADLINE must be a number");
+        }
+        if let Ok(setting) = env::var("TCPCL_CONNECT_TIMEOUT") {
+            settings.tcpcl_connect_timeout = setting
+                .parse()
+                .expect("TCPCL_CONNECT_TIMEOUT must be a number");
+        }
+        if let Ok(setting) = env::var("TCPCL_SEND_WINDOW") {
+            settings.tcpcl_send_window =
+                Some(setting.parse().expect("TCPCL_SEND_WINDOW must be a number"));
+        }
+        if let Ok(setting) = env::var("TCPCL_RATE_LIMIT") {
+            settings.tcpcl_rate_limit =
+                Some(setting.parse().expect("TCPCL_RATE_LIMIT must be a number"));
+        }
+        if let Ok(setting) = env::var("TCPCL_RATE_BURST") {
+            settings.tcpcl_rate_burst =
+                Some(setting.parse().expect("TCPCL_RATE_BURST must be a number"));
+        }
         if let Ok(setting) = env::var("TOKIO_TRACING_PORT") {
             settings.tokio_tracing_port = Some(setting);
         }
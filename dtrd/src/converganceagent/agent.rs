@@ -19,6 +19,7 @@ use std::collections::HashMap;
 
 use bp7::endpoint::Endpoint;
 use log::{error, info};
+use tcpcl::session::UNIX_SCHEME;
 
 use crate::{
     converganceagent::messages::{EventPeerConnected, EventPeerDisconnected},
@@ -58,7 +59,7 @@ impl Handler<AgentConnectNode> for Daemon {
     fn handle(&mut self, msg: AgentConnectNode, _ctx: &mut Context<Self>) -> Self::Result {
         let AgentConnectNode { url } = msg;
         match url.scheme() {
-            "tcpcl" => {
+            "tcpcl" | "tcpclv3" | UNIX_SCHEME => {
                 crate::tcpclconverganceagent::server_agent::TCPCLServer::from_registry()
                     .do_send(ConnectRemote { url });
             }
@@ -76,7 +77,7 @@ impl Handler<AgentDisconnectNode> for Daemon {
     fn handle(&mut self, msg: AgentDisconnectNode, _ctx: &mut Context<Self>) -> Self::Result {
         let AgentDisconnectNode { url } = msg;
         match url.scheme() {
-            "tcpcl" => {
+            "tcpcl" | "tcpclv3" | UNIX_SCHEME => {
                 crate::tcpclconverganceagent::server_agent::TCPCLServer::from_registry()
                     .do_send(DisconnectRemote { url });
             }
@@ -97,6 +98,7 @@ impl Handler<CLRegisterNode> for Daemon {
             node,
             max_bundle_size,
             sender,
+            link_stats,
         } = msg;
         info!("registering node {node}");
         self.connected_nodes.insert(node.clone(), sender.clone());
@@ -104,6 +106,7 @@ impl Handler<CLRegisterNode> for Daemon {
             url,
             endpoint: node.clone(),
             max_bundle_size,
+            link_stats,
         });
         crate::bundleprotocolagent::agent::Daemon::from_registry().do_send(EventPeerConnected {
             destination: node,
@@ -17,6 +17,8 @@
 
 use actix::prelude::*;
 use bp7::endpoint::Endpoint;
+use tcpcl::stats::SessionStats;
+use tokio::sync::watch;
 use url::Url;
 
 use crate::bundlestorageagent::StoredBundleRef;
@@ -42,6 +44,16 @@ pub struct EventBundleForwardingFailed {
     pub bundle: StoredBundleRef,
 }
 
+/// The session to the peer was interrupted after it received the first `received_payload`
+/// bytes of the payload. Only the rest needs to be forwarded again.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EventBundleForwardingInterrupted {
+    pub endpoint: Endpoint,
+    pub bundle: StoredBundleRef,
+    pub received_payload: u64,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct EventPeerConnected {
@@ -72,6 +84,7 @@ pub struct CLRegisterNode {
     pub node: Endpoint,
     pub max_bundle_size: u64,
     pub sender: Recipient<AgentForwardBundle>,
+    pub link_stats: Option<watch::Receiver<SessionStats>>,
 }
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -30,13 +30,17 @@ mod tcpclconverganceagent;
 
 use crate::common::{messages::Shutdown, settings::Settings};
 
-use actix::{Actor, System};
+use actix::{Actor, System, SystemService};
 
 #[actix_rt::main]
 async fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     info!("Starting up");
     let settings: Settings = Settings::from_env();
+    if std::env::args().any(|arg| arg == "--check-storage") {
+        check_storage(&settings).await;
+        return;
+    }
     info!("Starting with settings: {settings:?}");
     if let Some(tokio_tracing_port) = settings.tokio_tracing_port.clone() {
         info!("Initializing tokio tracing on port {tokio_tracing_port}");
@@ -48,10 +52,15 @@ async fn main() {
     let (notify_shutdown, _) = broadcast::channel::<()>(1);
     let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel::<()>(1);
 
-    let clientagent_addr = clientagent::agent::Daemon::default().start();
+    // The registered instance, as the bundle protocol agent hands it acknowledgements
+    let clientagent_addr = clientagent::agent::Daemon::from_registry();
+
+    // Loads the contact plan and schedules its contacts, before the loaded bundles get routed.
+    routingagent::agent::Daemon::from_registry();
 
-    // Just to trigger bundle loading on startup
-    bundlestorageagent::agent::Daemon::default().start();
+    // Just to trigger bundle loading on startup. This must be the registered instance, otherwise
+    // the bundles are loaded twice.
+    bundlestorageagent::agent::Daemon::from_registry();
 
     let api_agent_task_shutdown_notifier = notify_shutdown.subscribe();
     let api_agent_task_shutdown_complete_tx_task = shutdown_complete_tx.clone();
@@ -125,3 +134,30 @@ async fn main() {
 
     info!("All done, see you");
 }
+
+/// Checks the stored bundles, moves damaged ones to quarantine and cleans up leftovers, without
+/// starting the daemon. Exits with 2 if anything was quarantined and with 1 if the check failed.
+async fn check_storage(settings: &Settings) {
+    info!(
+        "Checking bundle storage at {}",
+        settings.bundle_storage_path
+    );
+    match bundlestorageagent::store::from_settings(settings)
+        .load()
+        .await
+    {
+        Ok((_, report)) => {
+            for (name, corruption) in &report.quarantined {
+                println!("quarantined {name}: {corruption}");
+            }
+            println!("{report}");
+            if !report.quarantined.is_empty() {
+                std::process::exit(2);
+            }
+        }
+        Err(e) => {
+            error!("Failed to check bundle storage: {e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -17,7 +17,10 @@
 
 use crate::{
     converganceagent::messages::{AgentConnectNode, AgentDisconnectNode},
-    routingagent::messages::{AddRoute, RemoveRoute, RouteType},
+    routingagent::{
+        messages::{AddRoute, RemoveRoute, RouteType},
+        route_target::RouteTarget,
+    },
 };
 use actix::prelude::*;
 use log::{info, warn};
@@ -37,7 +40,7 @@ impl Actor for Daemon {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(Duration::from_secs(60), |_, ctx| {
+        ctx.run_interval(Duration::from_mins(1), |_, ctx| {
             ctx.notify(TryConnect {});
         });
     }
@@ -56,7 +59,7 @@ impl Handler<ListNodes> for Daemon {
 }
 
 impl Handler<AddNode> for Daemon {
-    type Result = ();
+    type Result = bool;
 
     fn handle(&mut self, msg: AddNode, _ctx: &mut Context<Self>) -> Self::Result {
         let AddNode { url } = msg;
@@ -65,13 +68,15 @@ impl Handler<AddNode> for Daemon {
             connection_status: NodeConnectionStatus::Disconnected,
             remote_endpoint: None,
             temporary: false,
+            link_stats: None,
         };
-        if !self.nodes.contains(&node) {
-            node.connection_status = NodeConnectionStatus::Connecting;
-            self.nodes.push(node);
-            crate::converganceagent::agent::Daemon::from_registry()
-                .do_send(AgentConnectNode { url });
+        if self.nodes.contains(&node) {
+            return false;
         }
+        node.connection_status = NodeConnectionStatus::Connecting;
+        self.nodes.push(node);
+        crate::converganceagent::agent::Daemon::from_registry().do_send(AgentConnectNode { url });
+        true
     }
 }
 
@@ -85,6 +90,7 @@ impl Handler<RemoveNode> for Daemon {
             connection_status: NodeConnectionStatus::Disconnected,
             remote_endpoint: None,
             temporary: false,
+            link_stats: None,
         };
         if let Some(pos) = self.nodes.iter().position(|x| x == &node) {
             let node = &mut self.nodes[pos];
@@ -105,12 +111,14 @@ impl Handler<NotifyNodeConnected> for Daemon {
             url,
             endpoint,
             max_bundle_size,
+            link_stats,
         } = msg;
         match self.nodes.iter().position(|n| n.url == url) {
             Some(pos) => {
                 let node = &mut self.nodes[pos];
                 node.connection_status = NodeConnectionStatus::Connected;
                 node.remote_endpoint = Some(endpoint.clone());
+                node.link_stats = link_stats;
             }
             None => {
                 self.nodes.push(Node {
@@ -118,14 +126,17 @@ impl Handler<NotifyNodeConnected> for Daemon {
                     connection_status: NodeConnectionStatus::Connected,
                     remote_endpoint: Some(endpoint.clone()),
                     temporary: true,
+                    link_stats,
                 });
             }
         }
         crate::routingagent::agent::Daemon::from_registry().do_send(AddRoute {
-            target: endpoint.clone(),
+            target: RouteTarget::Node(endpoint.clone()),
             route_type: RouteType::Connected,
             next_hop: endpoint,
             max_bundle_size: Some(max_bundle_size),
+            metric: 0,
+            weight: 1,
         });
     }
 }
@@ -141,7 +152,7 @@ impl Handler<NotifyNodeDisconnected> for Daemon {
 
                 if node.remote_endpoint.is_some() {
                     crate::routingagent::agent::Daemon::from_registry().do_send(RemoveRoute {
-                        target: node.remote_endpoint.clone().unwrap(),
+                        target: RouteTarget::Node(node.remote_endpoint.clone().unwrap()),
                         route_type: RouteType::Connected,
                         next_hop: node.remote_endpoint.clone().unwrap(),
                     });
@@ -152,6 +163,7 @@ impl Handler<NotifyNodeDisconnected> for Daemon {
                 } else {
                     node.connection_status = NodeConnectionStatus::Disconnected;
                     node.remote_endpoint = None;
+                    node.link_stats = None;
                 }
             }
             None => {
@@ -19,6 +19,8 @@ use std::fmt::Display;
 
 use actix::prelude::*;
 use bp7::endpoint::Endpoint;
+use tcpcl::stats::SessionStats;
+use tokio::sync::watch;
 use url::Url;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -39,12 +41,13 @@ impl Display for NodeConnectionStatus {
         })
     }
 }
-#[derive(Debug, Clone, Eq)]
+#[derive(Debug, Clone)]
 pub struct Node {
     pub url: Url,
     pub connection_status: NodeConnectionStatus,
     pub remote_endpoint: Option<Endpoint>,
     pub temporary: bool,
+    pub link_stats: Option<watch::Receiver<SessionStats>>,
 }
 
 impl PartialEq for Node {
@@ -53,12 +56,15 @@ impl PartialEq for Node {
     }
 }
 
+impl Eq for Node {}
+
 #[derive(Message)]
 #[rtype(result = "Vec<Node>")]
 pub struct ListNodes {}
 
+/// Returns `false` if the node was already known.
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "bool")]
 pub struct AddNode {
     pub url: Url,
 }
@@ -75,6 +81,7 @@ pub struct NotifyNodeConnected {
     pub url: Url,
     pub endpoint: Endpoint,
     pub max_bundle_size: u64,
+    pub link_stats: Option<watch::Receiver<SessionStats>>,
 }
 
 #[derive(Message)]
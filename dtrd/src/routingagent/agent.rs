@@ -18,21 +18,41 @@
 use actix::prelude::*;
 use std::{
     collections::{HashMap, HashSet},
+    fs,
     hash::Hash,
+    time::Duration,
 };
 
-use bp7::endpoint::Endpoint;
-use log::{debug, warn};
+use bp7::{endpoint::Endpoint, time::DtnTime};
+use log::{debug, error, info, warn};
+use url::Url;
 
-use crate::routingagent::messages::EventRoutingTableUpdate;
+use crate::{
+    common::settings::Settings,
+    nodeagent::messages::{AddNode, RemoveNode},
+    routingagent::messages::EventRoutingTableUpdate,
+};
 
-use super::messages::{AddRoute, ListRoutes, NexthopInfo, RemoveRoute, RouteStatus, RouteType};
+use super::{
+    cgr::ContactGraph,
+    contact_plan,
+    messages::{
+        AddContact, AddRoute, ContactRoute, ContactStatus, EventContactPlanChanged,
+        FindContactRoute, LearnPredictabilities, ListContacts, ListRoutes, NexthopInfo,
+        RecordEncounter, ReleaseContactBooking, RemoveContact, RemoveRoute, RouteReason,
+        RouteStatus, RouteType,
+    },
+    prophet::DeliveryPredictabilities,
+    route_target::RouteTarget,
+};
 
 #[derive(Debug, Eq)]
 struct RouteEntry {
     route_type: RouteType,
     next_hop: Endpoint,
     max_bundle_size: Option<u64>,
+    metric: u64,
+    weight: u64,
 }
 
 impl Hash for RouteEntry {
@@ -50,12 +70,42 @@ impl PartialEq for RouteEntry {
 
 #[derive(Default)]
 pub struct Daemon {
-    routes: HashMap<Endpoint, HashSet<RouteEntry>>,
-    last_routing_table: Option<HashMap<Endpoint, NexthopInfo>>,
+    endpoint: Option<Endpoint>,
+    routes: HashMap<RouteTarget, HashSet<RouteEntry>>,
+    last_routing_table: Option<HashMap<RouteTarget, Vec<NexthopInfo>>>,
+    contact_graph: ContactGraph,
+    contact_timers: Vec<SpawnHandle>,
+    /// Nodes connected because a contact started, only these are removed when it ends.
+    contact_nodes: HashSet<Url>,
+    predictabilities: DeliveryPredictabilities,
 }
 
 impl Actor for Daemon {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let settings = Settings::from_env();
+        self.endpoint = Some(
+            Endpoint::new(&settings.my_node_id)
+                .unwrap()
+                .get_node_endpoint(),
+        );
+        if let Some(path) = settings.contact_plan_path {
+            let plan = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|plan| contact_plan::parse(&plan, DtnTime::now()));
+            match plan {
+                Ok(contacts) => {
+                    info!("Loaded {} contacts from {path}", contacts.len());
+                    for contact in contacts {
+                        self.contact_graph.add(contact);
+                    }
+                    self.contact_plan_changed(ctx);
+                }
+                Err(e) => error!("Could not load contact plan {path}: {e}"),
+            }
+        }
+    }
 }
 
 impl actix::Supervised for Daemon {}
@@ -71,12 +121,23 @@ impl Handler<AddRoute> for Daemon {
             route_type,
             next_hop,
             max_bundle_size,
+            metric,
+            weight,
         } = msg;
-        if self.routes.entry(target).or_default().insert(RouteEntry {
+        let entry = RouteEntry {
             route_type,
             next_hop,
             max_bundle_size,
-        }) {
+            metric,
+            weight: weight.max(1),
+        };
+        let routes = self.routes.entry(target).or_default();
+        let changed = routes.get(&entry).is_none_or(|old| {
+            (old.metric, old.weight, old.max_bundle_size)
+                != (entry.metric, entry.weight, entry.max_bundle_size)
+        });
+        if changed {
+            routes.replace(entry);
             self.send_route_update();
         }
     }
@@ -95,10 +156,13 @@ impl Handler<RemoveRoute> for Daemon {
         let entry_to_remove = RouteEntry {
             route_type,
             next_hop: next_hop.clone(),
-            max_bundle_size: None, // irrelevant as this is not part of Eq
+            // irrelevant as these are not part of Eq
+            max_bundle_size: None,
+            metric: 0,
+            weight: 0,
         };
         if endpoint_routes.remove(&entry_to_remove) {
-            if target == next_hop {
+            if target == RouteTarget::Node(next_hop.clone()) {
                 debug!("Removed direct route for {target} from routing table");
             } else {
                 debug!("Removed route for {target} via {next_hop} from routing table");
@@ -118,25 +182,200 @@ impl Handler<ListRoutes> for Daemon {
     }
 }
 
+impl Handler<AddContact> for Daemon {
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: AddContact, ctx: &mut Context<Self>) -> Self::Result {
+        let AddContact { mut contact } = msg;
+        contact_plan::validate(&contact)?;
+        contact.from = contact.from.get_node_endpoint();
+        contact.to = contact.to.get_node_endpoint();
+        self.contact_graph.add(contact);
+        self.contact_plan_changed(ctx);
+        Ok(())
+    }
+}
+
+impl Handler<RemoveContact> for Daemon {
+    type Result = bool;
+
+    fn handle(&mut self, msg: RemoveContact, ctx: &mut Context<Self>) -> Self::Result {
+        let RemoveContact { from, to, start } = msg;
+        let removed =
+            self.contact_graph
+                .remove(&from.get_node_endpoint(), &to.get_node_endpoint(), start);
+        if removed {
+            self.contact_plan_changed(ctx);
+        }
+        removed
+    }
+}
+
+impl Handler<ListContacts> for Daemon {
+    type Result = Vec<ContactStatus>;
+
+    fn handle(&mut self, _msg: ListContacts, _ctx: &mut Context<Self>) -> Self::Result {
+        self.contact_graph.prune(DtnTime::now());
+        self.contact_graph.list()
+    }
+}
+
+impl Handler<RecordEncounter> for Daemon {
+    type Result = MessageResult<RecordEncounter>;
+
+    fn handle(&mut self, msg: RecordEncounter, _ctx: &mut Context<Self>) -> Self::Result {
+        let RecordEncounter { peer } = msg;
+        self.predictabilities.encounter(&peer.get_node_endpoint());
+        MessageResult(self.predictabilities.all())
+    }
+}
+
+impl Handler<LearnPredictabilities> for Daemon {
+    type Result = MessageResult<LearnPredictabilities>;
+
+    fn handle(&mut self, msg: LearnPredictabilities, _ctx: &mut Context<Self>) -> Self::Result {
+        let LearnPredictabilities {
+            peer,
+            predictabilities,
+        } = msg;
+        let peer = peer.get_node_endpoint();
+        let better = self.predictabilities.update_transitive(
+            self.endpoint.as_ref().unwrap(),
+            &peer,
+            &predictabilities,
+        );
+        debug!(
+            "{peer} is more likely to deliver to {} nodes, our predictability for it is {}",
+            better.len(),
+            self.predictabilities.predictability(&peer)
+        );
+        MessageResult(better)
+    }
+}
+
+impl Handler<FindContactRoute> for Daemon {
+    type Result = Option<ContactRoute>;
+
+    fn handle(&mut self, msg: FindContactRoute, _ctx: &mut Context<Self>) -> Self::Result {
+        let FindContactRoute {
+            bundle,
+            destination,
+            size,
+            expires_at,
+        } = msg;
+        let now = DtnTime::now();
+        self.contact_graph.prune(now);
+        let route = self.contact_graph.find_route(
+            &bundle,
+            self.endpoint.as_ref().unwrap(),
+            &destination.get_node_endpoint(),
+            size,
+            now,
+            expires_at,
+        );
+        match &route {
+            Some(r) => debug!(
+                "Contact plan routes bundle to {destination} via {} arriving at {}",
+                r.next_hop, r.arrival
+            ),
+            None => debug!("Contact plan has no route to {destination}"),
+        }
+        route
+    }
+}
+
+impl Handler<ReleaseContactBooking> for Daemon {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReleaseContactBooking, _ctx: &mut Context<Self>) -> Self::Result {
+        let ReleaseContactBooking { bundle } = msg;
+        self.contact_graph.release(&bundle);
+    }
+}
+
 impl Daemon {
-    fn send_route_update(&self) {
-        let routes: HashMap<Endpoint, NexthopInfo> = self
-            .get_routes()
-            .into_iter()
-            .filter_map(|rs| {
-                if rs.preferred {
-                    Some((
-                        rs.target,
-                        NexthopInfo {
-                            next_hop: rs.next_hop,
-                            max_size: rs.max_bundle_size,
-                        },
-                    ))
-                } else {
-                    None
+    /// Schedules connecting to the peers of the contacts starting at this node and tells the
+    /// bundle protocol agent to route its waiting bundles again, now and whenever one of these
+    /// contacts ends.
+    fn contact_plan_changed(&mut self, ctx: &mut Context<Self>) {
+        for timer in self.contact_timers.drain(..) {
+            ctx.cancel_future(timer);
+        }
+        let now = DtnTime::now();
+        self.contact_graph.prune(now);
+        let endpoint = self.endpoint.as_ref().unwrap();
+        let local_contacts: Vec<_> = self
+            .contact_graph
+            .contacts()
+            .filter(|c| &c.from == endpoint)
+            .cloned()
+            .collect();
+        for contact in &local_contacts {
+            // bundles still queued for the next hop of an ended contact need a new route
+            self.contact_timers.push(ctx.run_later(
+                Duration::from_millis(contact.end.timestamp - now.timestamp),
+                |_, _| {
+                    crate::bundleprotocolagent::agent::Daemon::from_registry()
+                        .do_send(EventContactPlanChanged {});
+                },
+            ));
+            let Some(url) = contact.url.clone() else {
+                continue;
+            };
+            if contact.start <= now {
+                self.add_contact_node(ctx, url.clone());
+            } else {
+                let url = url.clone();
+                self.contact_timers.push(ctx.run_later(
+                    Duration::from_millis(contact.start.timestamp - now.timestamp),
+                    move |act, ctx| act.add_contact_node(ctx, url),
+                ));
+            }
+            let continued = local_contacts.iter().any(|c| {
+                c.url.as_ref() == Some(&url) && c.start <= contact.end && c.end > contact.end
+            });
+            if !continued {
+                self.contact_timers.push(ctx.run_later(
+                    Duration::from_millis(contact.end.timestamp - now.timestamp),
+                    move |act, _| {
+                        if act.contact_nodes.remove(&url) {
+                            crate::nodeagent::agent::Daemon::from_registry()
+                                .do_send(RemoveNode { url });
+                        }
+                    },
+                ));
+            }
+        }
+        crate::bundleprotocolagent::agent::Daemon::from_registry()
+            .do_send(EventContactPlanChanged {});
+    }
+
+    /// Connects to the peer of a contact, unless it is already known to the node agent, e.g. as
+    /// static peer.
+    fn add_contact_node(&self, ctx: &mut Context<Self>, url: Url) {
+        crate::nodeagent::agent::Daemon::from_registry()
+            .send(AddNode { url: url.clone() })
+            .into_actor(self)
+            .map(|added, act, _ctx| {
+                if added.unwrap_or(false) {
+                    act.contact_nodes.insert(url);
                 }
             })
-            .collect();
+            .spawn(ctx);
+    }
+
+    fn send_route_update(&self) {
+        let mut routes: HashMap<RouteTarget, Vec<NexthopInfo>> = HashMap::new();
+        for rs in self.get_routes().into_iter().filter(|rs| rs.preferred) {
+            routes.entry(rs.target).or_default().push(NexthopInfo {
+                next_hop: rs.next_hop,
+                max_size: rs.max_bundle_size,
+                weight: rs.weight,
+            });
+        }
+        for next_hops in routes.values_mut() {
+            next_hops.sort_unstable_by(|a, b| a.next_hop.cmp(&b.next_hop));
+        }
 
         if let Some(lrt) = &self.last_routing_table
             && lrt == &routes
@@ -154,25 +393,55 @@ impl Daemon {
         self.routes
             .iter()
             .flat_map(|(target, routes)| {
+                let available = |r: &RouteEntry| {
+                    r.route_type == RouteType::Connected || connected_routes.contains(&r.next_hop)
+                };
+                // connected routes win over static ones, then the lowest metric
+                let best = routes
+                    .iter()
+                    .filter(|r| available(r))
+                    .map(|r| (r.route_type, r.metric))
+                    .min();
+                let best_count = routes
+                    .iter()
+                    .filter(|r| available(r) && Some((r.route_type, r.metric)) == best)
+                    .count();
                 let mut routes: Vec<RouteStatus> = routes
                     .iter()
                     .map(|r| {
-                        let available = r.route_type == RouteType::Connected
-                            || connected_routes.contains(&r.next_hop);
+                        let available = available(r);
+                        let preferred = available && Some((r.route_type, r.metric)) == best;
+                        let reason = if !available {
+                            RouteReason::NextHopDown
+                        } else if best.is_some_and(|(t, _)| t == RouteType::Connected) {
+                            RouteReason::DirectlyConnected
+                        } else if !preferred {
+                            RouteReason::HigherMetric
+                        } else if best_count > 1 {
+                            RouteReason::EqualCost
+                        } else {
+                            RouteReason::LowestMetric
+                        };
                         RouteStatus {
                             target: target.clone(),
                             next_hop: r.next_hop.clone(),
                             available,
-                            preferred: false,
+                            preferred,
                             route_type: r.route_type,
                             max_bundle_size: r.max_bundle_size,
+                            metric: r.metric,
+                            weight: r.weight,
+                            reason,
                         }
                     })
                     .collect();
-                routes.sort_unstable_by_key(|e| e.route_type);
-                if !routes.is_empty() && routes[0].available {
-                    routes[0].preferred = true;
-                }
+                routes.sort_unstable_by(|a, b| {
+                    (a.route_type, a.metric, &a.next_hop).cmp(&(
+                        b.route_type,
+                        b.metric,
+                        &b.next_hop,
+                    ))
+                });
                 routes
             })
             .collect()
@@ -181,12 +450,13 @@ impl Daemon {
     fn get_connected_routes(&self) -> HashSet<Endpoint> {
         self.routes
             .iter()
-            .filter_map(|(target, routes)| {
-                if routes.iter().any(|r| r.route_type == RouteType::Connected) {
-                    Some(target.clone())
-                } else {
-                    None
+            .filter_map(|(target, routes)| match target {
+                RouteTarget::Node(node)
+                    if routes.iter().any(|r| r.route_type == RouteType::Connected) =>
+                {
+                    Some(node.clone())
                 }
+                _ => None,
             })
             .collect()
     }
@@ -0,0 +1,347 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Contact graph routing: finds the earliest arrival route for a bundle through the contacts of
+//! the contact plan.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use bp7::{endpoint::Endpoint, time::DtnTime};
+
+use super::messages::{Contact, ContactRoute, ContactStatus};
+
+#[derive(Debug)]
+struct PlannedContact {
+    contact: Contact,
+    /// Bytes booked on the contact, by the id of the bundle they belong to.
+    bookings: HashMap<String, u64>,
+}
+
+impl PlannedContact {
+    fn booked(&self) -> u64 {
+        self.bookings.values().sum()
+    }
+
+    /// When a bundle of `size` bytes that is ready to be sent at `ready` arrives at the end of
+    /// the contact, after everything already booked on it got sent. The booked bundles are still
+    /// waiting, so they are not sent before `ready` either. `None` if it does not fit into the
+    /// contact anymore.
+    fn arrival(&self, ready: u64, size: u64) -> Option<u64> {
+        let contact = &self.contact;
+        let backlog_done =
+            ready.max(contact.start.timestamp) + transmission_time(self.booked(), contact.rate);
+        let sent = backlog_done + transmission_time(size, contact.rate);
+        if sent > contact.end.timestamp {
+            return None;
+        }
+        Some(sent + contact.owlt)
+    }
+}
+
+/// Milliseconds needed to send `size` bytes at `rate` bytes per second.
+fn transmission_time(size: u64, rate: u64) -> u64 {
+    size.saturating_mul(1000).div_ceil(rate)
+}
+
+#[derive(Debug, Default)]
+pub struct ContactGraph {
+    contacts: Vec<PlannedContact>,
+}
+
+impl ContactGraph {
+    /// Adds the contact, replacing a contact between the same nodes starting at the same time.
+    pub fn add(&mut self, contact: Contact) {
+        self.remove(&contact.from, &contact.to, contact.start);
+        self.contacts.push(PlannedContact {
+            contact,
+            bookings: HashMap::new(),
+        });
+    }
+
+    pub fn remove(&mut self, from: &Endpoint, to: &Endpoint, start: DtnTime) -> bool {
+        let before = self.contacts.len();
+        self.contacts.retain(|c| {
+            !(&c.contact.from == from && &c.contact.to == to && c.contact.start == start)
+        });
+        self.contacts.len() != before
+    }
+
+    /// Forgets all contacts that ended before `now`.
+    pub fn prune(&mut self, now: DtnTime) {
+        self.contacts.retain(|c| c.contact.end > now);
+    }
+
+    pub fn contacts(&self) -> impl Iterator<Item = &Contact> {
+        self.contacts.iter().map(|c| &c.contact)
+    }
+
+    pub fn list(&self) -> Vec<ContactStatus> {
+        let mut contacts: Vec<ContactStatus> = self
+            .contacts
+            .iter()
+            .map(|c| ContactStatus {
+                contact: c.contact.clone(),
+                booked: c.booked(),
+            })
+            .collect();
+        contacts.sort_by_key(|c| c.contact.start);
+        contacts
+    }
+
+    /// Frees the capacity booked for the bundle on all contacts.
+    pub fn release(&mut self, bundle: &str) {
+        for contact in &mut self.contacts {
+            contact.bookings.remove(bundle);
+        }
+    }
+
+    /// Searches the route from `local` to `destination` arriving first, using Dijkstra over the
+    /// contacts. The bundle must arrive before it expires. The bundle size is booked on the first
+    /// contact of the route found, replacing what was booked for the bundle before. Later
+    /// contacts are planned by the nodes they start at.
+    pub fn find_route(
+        &mut self,
+        bundle: &str,
+        local: &Endpoint,
+        destination: &Endpoint,
+        size: u64,
+        now: DtnTime,
+        expires_at: DtnTime,
+    ) -> Option<ContactRoute> {
+        self.release(bundle);
+        let mut arrivals: HashMap<usize, u64> = HashMap::new();
+        let mut previous: HashMap<usize, usize> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        for (index, contact) in self.contacts.iter().enumerate() {
+            if &contact.contact.from == local
+                && let Some(arrival) = contact.arrival(now.timestamp, size)
+            {
+                arrivals.insert(index, arrival);
+                queue.push(Reverse((arrival, index)));
+            }
+        }
+
+        while let Some(Reverse((arrival, index))) = queue.pop() {
+            if arrival > arrivals[&index] {
+                continue;
+            }
+            if arrival > expires_at.timestamp {
+                return None;
+            }
+            let reached = &self.contacts[index].contact.to;
+            if reached == destination {
+                return Some(self.book(bundle, index, &previous, size, arrival));
+            }
+            for (next, contact) in self.contacts.iter().enumerate() {
+                if &contact.contact.from != reached || &contact.contact.to == local {
+                    continue;
+                }
+                let Some(next_arrival) = contact.arrival(arrival, size) else {
+                    continue;
+                };
+                if arrivals.get(&next).is_none_or(|a| next_arrival < *a) {
+                    arrivals.insert(next, next_arrival);
+                    previous.insert(next, index);
+                    queue.push(Reverse((next_arrival, next)));
+                }
+            }
+        }
+        None
+    }
+
+    fn book(
+        &mut self,
+        bundle: &str,
+        last: usize,
+        previous: &HashMap<usize, usize>,
+        size: u64,
+        arrival: u64,
+    ) -> ContactRoute {
+        let mut first = last;
+        while let Some(p) = previous.get(&first) {
+            first = *p;
+        }
+        self.contacts[first]
+            .bookings
+            .insert(bundle.to_string(), size);
+        ContactRoute {
+            next_hop: self.contacts[first].contact.to.clone(),
+            contact_end: self.contacts[first].contact.end,
+            arrival: DtnTime { timestamp: arrival },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bp7::{endpoint::Endpoint, time::DtnTime};
+
+    use super::ContactGraph;
+    use crate::routingagent::messages::{Contact, ContactRoute};
+
+    fn node(name: &str) -> Endpoint {
+        Endpoint::new(&format!("dtn://{name}/")).unwrap()
+    }
+
+    fn time(timestamp: u64) -> DtnTime {
+        DtnTime { timestamp }
+    }
+
+    /// A contact sending 1000 bytes per second with 10 ms one way light time.
+    fn contact(from: &str, to: &str, start: u64, end: u64) -> Contact {
+        Contact {
+            from: node(from),
+            to: node(to),
+            start: time(start),
+            end: time(end),
+            rate: 1000,
+            owlt: 10,
+            url: None,
+        }
+    }
+
+    fn booked(graph: &ContactGraph, from: &str, to: &str) -> u64 {
+        graph
+            .list()
+            .into_iter()
+            .find(|c| c.contact.from == node(from) && c.contact.to == node(to))
+            .unwrap()
+            .booked
+    }
+
+    #[test]
+    fn find_route_direct_contact() {
+        let mut graph = ContactGraph::default();
+        graph.add(contact("a", "b", 0, 10_000));
+        let route = graph.find_route("x", &node("a"), &node("b"), 100, time(0), time(60_000));
+        assert_eq!(
+            route,
+            Some(ContactRoute {
+                next_hop: node("b"),
+                contact_end: time(10_000),
+                arrival: time(110),
+            })
+        );
+        assert_eq!(booked(&graph, "a", "b"), 100);
+    }
+
+    #[test]
+    fn find_route_picks_earliest_arrival() {
+        let mut graph = ContactGraph::default();
+        graph.add(contact("a", "b", 0, 10_000));
+        graph.add(contact("b", "d", 5_000, 10_000));
+        graph.add(contact("a", "c", 0, 10_000));
+        graph.add(contact("c", "d", 1_000, 10_000));
+        let route = graph
+            .find_route("x", &node("a"), &node("d"), 100, time(0), time(60_000))
+            .unwrap();
+        assert_eq!(route.next_hop, node("c"));
+        assert_eq!(route.arrival, time(1_110));
+        assert_eq!(booked(&graph, "a", "c"), 100);
+        assert_eq!(booked(&graph, "c", "d"), 0);
+        assert_eq!(booked(&graph, "a", "b"), 0);
+        assert_eq!(booked(&graph, "b", "d"), 0);
+    }
+
+    #[test]
+    fn find_route_none_after_expiry() {
+        let mut graph = ContactGraph::default();
+        graph.add(contact("a", "b", 1_000, 10_000));
+        assert_eq!(
+            graph.find_route("x", &node("a"), &node("b"), 100, time(0), time(1_000)),
+            None
+        );
+        assert_eq!(
+            graph.find_route("x", &node("a"), &node("b"), 100, time(10_000), time(60_000)),
+            None
+        );
+        assert_eq!(booked(&graph, "a", "b"), 0);
+    }
+
+    #[test]
+    fn find_route_bookings_fill_contact() {
+        let mut graph = ContactGraph::default();
+        graph.add(contact("a", "b", 0, 1_000));
+        let first = graph
+            .find_route("x", &node("a"), &node("b"), 300, time(0), time(60_000))
+            .unwrap();
+        assert_eq!(first.arrival, time(310));
+        let second = graph
+            .find_route("y", &node("a"), &node("b"), 300, time(0), time(60_000))
+            .unwrap();
+        assert_eq!(second.arrival, time(610));
+        assert_eq!(
+            graph.find_route("z", &node("a"), &node("b"), 500, time(0), time(60_000)),
+            None
+        );
+        assert_eq!(booked(&graph, "a", "b"), 600);
+    }
+
+    #[test]
+    fn find_route_backlog_starts_when_ready() {
+        let mut graph = ContactGraph::default();
+        graph.add(contact("a", "b", 0, 10_000));
+        let first = graph
+            .find_route("x", &node("a"), &node("b"), 300, time(0), time(60_000))
+            .unwrap();
+        assert_eq!(first.arrival, time(310));
+        // x is still booked, so it is sent before y
+        let second = graph
+            .find_route("y", &node("a"), &node("b"), 300, time(5_000), time(60_000))
+            .unwrap();
+        assert_eq!(second.arrival, time(5_610));
+    }
+
+    #[test]
+    fn release_frees_booked_capacity() {
+        let mut graph = ContactGraph::default();
+        graph.add(contact("a", "b", 0, 1_000));
+        assert!(
+            graph
+                .find_route("x", &node("a"), &node("b"), 600, time(0), time(60_000))
+                .is_some()
+        );
+        assert_eq!(
+            graph.find_route("y", &node("a"), &node("b"), 600, time(0), time(60_000)),
+            None
+        );
+        graph.release("x");
+        assert_eq!(booked(&graph, "a", "b"), 0);
+        assert!(
+            graph
+                .find_route("y", &node("a"), &node("b"), 600, time(0), time(60_000))
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn find_route_again_replaces_booking() {
+        let mut graph = ContactGraph::default();
+        graph.add(contact("a", "b", 0, 1_000));
+        for _ in 0..3 {
+            let route = graph
+                .find_route("x", &node("a"), &node("b"), 600, time(0), time(60_000))
+                .unwrap();
+            assert_eq!(route.arrival, time(610));
+        }
+        assert_eq!(booked(&graph, "a", "b"), 600);
+    }
+}
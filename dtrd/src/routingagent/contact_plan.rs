@@ -0,0 +1,118 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Parsing of contact plan files.
+//!
+//! Every line describes one contact as `<from> <to> <start> <end> <rate> <owlt> [url]`. Times are
+//! either unix seconds or `+<seconds>` relative to when the plan is loaded, the rate is in bytes
+//! per second and the one way light time in milliseconds. Empty lines and lines starting with
+//! `#` are ignored.
+
+use bp7::{endpoint::Endpoint, time::DtnTime};
+use url::Url;
+
+use super::messages::Contact;
+
+pub fn parse(plan: &str, now: DtnTime) -> Result<Vec<Contact>, String> {
+    plan.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| parse_contact(line, now).map_err(|e| format!("line {number}: {e}")))
+        .collect()
+}
+
+fn parse_contact(line: &str, now: DtnTime) -> Result<Contact, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [from, to, start, end, rate, owlt, rest @ ..] = fields.as_slice() else {
+        return Err("expected <from> <to> <start> <end> <rate> <owlt> [url]".into());
+    };
+    let url = match rest {
+        [] => None,
+        [url] => Some(Url::parse(url).map_err(|e| format!("invalid url {url}: {e}"))?),
+        _ => return Err("too many fields".into()),
+    };
+    let contact = Contact {
+        from: parse_node(from)?,
+        to: parse_node(to)?,
+        start: parse_time(start, now)?,
+        end: parse_time(end, now)?,
+        rate: rate
+            .parse()
+            .map_err(|e| format!("invalid rate {rate}: {e}"))?,
+        owlt: owlt
+            .parse()
+            .map_err(|e| format!("invalid owlt {owlt}: {e}"))?,
+        url,
+    };
+    validate(&contact)?;
+    Ok(contact)
+}
+
+pub fn validate(contact: &Contact) -> Result<(), String> {
+    if contact.rate == 0 {
+        return Err("rate must be greater than 0".into());
+    }
+    if contact.end <= contact.start {
+        return Err("contact must end after it starts".into());
+    }
+    Ok(())
+}
+
+fn parse_node(endpoint: &str) -> Result<Endpoint, String> {
+    Endpoint::new(endpoint)
+        .map(|e| e.get_node_endpoint())
+        .ok_or_else(|| format!("invalid endpoint {endpoint}"))
+}
+
+/// Parses unix seconds or `+<seconds>` relative to `now`.
+pub fn parse_time(time: &str, now: DtnTime) -> Result<DtnTime, String> {
+    if let Some(offset) = time.strip_prefix('+') {
+        let offset: u64 = offset
+            .parse()
+            .map_err(|e| format!("invalid time {time}: {e}"))?;
+        return offset
+            .checked_mul(1000)
+            .and_then(|offset| now.timestamp.checked_add(offset))
+            .map(|timestamp| DtnTime { timestamp })
+            .ok_or_else(|| format!("time {time} is too far in the future"));
+    }
+    let seconds = time
+        .parse()
+        .map_err(|e| format!("invalid time {time}: {e}"))?;
+    DtnTime::from_unix_seconds(seconds).ok_or_else(|| format!("time {time} is before 2000"))
+}
+
+#[cfg(test)]
+mod tests {
+    use bp7::time::DtnTime;
+
+    use super::parse_time;
+
+    #[test]
+    fn parse_time_relative() {
+        let now = DtnTime { timestamp: 5000 };
+        assert_eq!(parse_time("+10", now), Ok(DtnTime { timestamp: 15000 }));
+    }
+
+    #[test]
+    fn parse_time_relative_overflow() {
+        let now = DtnTime { timestamp: 5000 };
+        assert!(parse_time(&format!("+{}", u64::MAX / 1000 + 1), now).is_err());
+        assert!(parse_time(&format!("+{}", u64::MAX / 1000), now).is_err());
+    }
+}
@@ -15,10 +15,13 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use actix::prelude::*;
-use bp7::endpoint::Endpoint;
+use bp7::{endpoint::Endpoint, time::DtnTime};
+use url::Url;
+
+use super::route_target::RouteTarget;
 
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
 pub enum RouteType {
@@ -26,41 +29,79 @@ pub enum RouteType {
     Static = 1,
 }
 
+/// Why a route is preferred or not.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RouteReason {
+    /// The only available route with the lowest metric.
+    LowestMetric,
+    /// One of several available routes with the lowest metric, bundles are spread over them.
+    EqualCost,
+    /// Another available route has a lower metric.
+    HigherMetric,
+    /// The target is directly connected, which beats any static route.
+    DirectlyConnected,
+    /// There is no session to the next hop.
+    NextHopDown,
+}
+
+impl std::fmt::Display for RouteReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RouteReason::LowestMetric => "lowest metric",
+            RouteReason::EqualCost => "equal cost",
+            RouteReason::HigherMetric => "higher metric",
+            RouteReason::DirectlyConnected => "directly connected",
+            RouteReason::NextHopDown => "next hop down",
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct RouteStatus {
-    pub target: Endpoint,
+    pub target: RouteTarget,
     pub next_hop: Endpoint,
     pub route_type: RouteType,
     pub preferred: bool,
     pub available: bool,
     pub max_bundle_size: Option<u64>,
+    pub metric: u64,
+    pub weight: u64,
+    pub reason: RouteReason,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct NexthopInfo {
     pub next_hop: Endpoint,
     pub max_size: Option<u64>,
+    /// The share of bundles this next hop gets among equal cost routes.
+    pub weight: u64,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct EventRoutingTableUpdate {
-    pub routes: HashMap<Endpoint, NexthopInfo>,
+    /// The next hops of the preferred routes of each target, sorted by next hop.
+    pub routes: HashMap<RouteTarget, Vec<NexthopInfo>>,
 }
 
+/// Adds a route, or updates metric and weight of an existing one of the same type and next
+/// hop.
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct AddRoute {
-    pub target: Endpoint,
+    pub target: RouteTarget,
     pub route_type: RouteType,
     pub next_hop: Endpoint,
     pub max_bundle_size: Option<u64>,
+    /// Lower metrics are preferred.
+    pub metric: u64,
+    pub weight: u64,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct RemoveRoute {
-    pub target: Endpoint,
+    pub target: RouteTarget,
     pub route_type: RouteType,
     pub next_hop: Endpoint,
 }
@@ -68,3 +109,91 @@ pub struct RemoveRoute {
 #[derive(Message)]
 #[rtype(result = "Vec<RouteStatus>")]
 pub struct ListRoutes {}
+
+/// A scheduled period in which `from` can send to `to`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Contact {
+    pub from: Endpoint,
+    pub to: Endpoint,
+    pub start: DtnTime,
+    pub end: DtnTime,
+    /// Transmission rate in bytes per second.
+    pub rate: u64,
+    /// One way light time in milliseconds.
+    pub owlt: u64,
+    /// Where to connect to `to` if the contact starts at this node.
+    pub url: Option<Url>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContactStatus {
+    pub contact: Contact,
+    /// Bytes already planned to be sent over the contact.
+    pub booked: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "Result<(), String>")]
+pub struct AddContact {
+    pub contact: Contact,
+}
+
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct RemoveContact {
+    pub from: Endpoint,
+    pub to: Endpoint,
+    pub start: DtnTime,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<ContactStatus>")]
+pub struct ListContacts {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContactRoute {
+    pub next_hop: Endpoint,
+    /// End of the contact to the next hop. The bundle needs a new route if it is not sent by
+    /// then.
+    pub contact_end: DtnTime,
+    pub arrival: DtnTime,
+}
+
+/// Finds the earliest arriving route through the contact plan and books the bundle on all its
+/// contacts. An earlier booking of the bundle is released.
+#[derive(Message)]
+#[rtype(result = "Option<ContactRoute>")]
+pub struct FindContactRoute {
+    pub bundle: String,
+    pub destination: Endpoint,
+    pub size: u64,
+    pub expires_at: DtnTime,
+}
+
+/// Frees the capacity booked for the bundle, e.g. because it expired or could not be forwarded.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReleaseContactBooking {
+    pub bundle: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EventContactPlanChanged {}
+
+/// Records that we met the peer for Prophet routing. Returns our delivery predictabilities to
+/// send it.
+#[derive(Message)]
+#[rtype(result = "HashMap<Endpoint, f64>")]
+pub struct RecordEncounter {
+    pub peer: Endpoint,
+}
+
+/// Learns the delivery predictabilities the peer sent us. Returns the nodes the peer is more
+/// likely to deliver bundles to than we are.
+#[derive(Message)]
+#[rtype(result = "HashSet<Endpoint>")]
+pub struct LearnPredictabilities {
+    pub peer: Endpoint,
+    pub predictabilities: HashMap<Endpoint, f64>,
+}
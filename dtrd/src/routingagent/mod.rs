@@ -16,4 +16,8 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 pub mod agent;
+mod cgr;
+mod contact_plan;
 pub mod messages;
+mod prophet;
+pub mod route_target;
@@ -0,0 +1,109 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use bp7::endpoint::Endpoint;
+
+/// How much an encounter raises the predictability of the peer (`P_encounter_max`).
+const P_ENCOUNTER: f64 = 0.75;
+/// How much a predictability learned from a peer counts compared to a direct one.
+const BETA: f64 = 0.25;
+/// How much of a predictability remains after one `AGING_INTERVAL` without encounters.
+const GAMMA: f64 = 0.98;
+const AGING_INTERVAL: Duration = Duration::from_secs(30);
+/// Predictabilities below this are dropped instead of being aged forever.
+const P_FIRST_THRESHOLD: f64 = 0.0001;
+
+/// The Prophet (RFC 6693) delivery predictabilities of this node: how likely it is to meet
+/// another node, directly or through the nodes it meets.
+#[derive(Debug)]
+pub struct DeliveryPredictabilities {
+    predictabilities: HashMap<Endpoint, f64>,
+    last_aged: Instant,
+}
+
+impl Default for DeliveryPredictabilities {
+    fn default() -> Self {
+        DeliveryPredictabilities {
+            predictabilities: HashMap::new(),
+            last_aged: Instant::now(),
+        }
+    }
+}
+
+impl DeliveryPredictabilities {
+    /// Decays all predictabilities by `GAMMA` for each full `AGING_INTERVAL` since the last
+    /// time.
+    fn age(&mut self) {
+        let intervals =
+            (self.last_aged.elapsed().as_secs_f64() / AGING_INTERVAL.as_secs_f64()).floor();
+        if intervals < 1.0 {
+            return;
+        }
+        // whole intervals only, so frequent calls do not lose the remainder
+        self.last_aged += AGING_INTERVAL.mul_f64(intervals);
+        let factor = GAMMA.powf(intervals);
+        self.predictabilities.retain(|_, p| {
+            *p *= factor;
+            *p >= P_FIRST_THRESHOLD
+        });
+    }
+
+    /// Raises the predictability of a peer we just met.
+    pub fn encounter(&mut self, peer: &Endpoint) {
+        self.age();
+        let p = self.predictabilities.entry(peer.clone()).or_default();
+        *p += (1.0 - *p) * P_ENCOUNTER;
+    }
+
+    /// Learns the predictabilities of `peer` for the nodes it meets. Returns the nodes the
+    /// peer is more likely to deliver to than we are.
+    pub fn update_transitive(
+        &mut self,
+        local: &Endpoint,
+        peer: &Endpoint,
+        peer_predictabilities: &HashMap<Endpoint, f64>,
+    ) -> HashSet<Endpoint> {
+        self.age();
+        let p_peer = self.predictability(peer);
+        let mut better = HashSet::new();
+        for (node, p_peer_node) in peer_predictabilities {
+            if node == local || node == peer {
+                continue;
+            }
+            let p = self.predictabilities.entry(node.clone()).or_default();
+            if *p_peer_node > *p {
+                better.insert(node.clone());
+            }
+            *p = p.max(p_peer * p_peer_node * BETA);
+        }
+        better
+    }
+
+    pub fn predictability(&self, node: &Endpoint) -> f64 {
+        self.predictabilities.get(node).copied().unwrap_or_default()
+    }
+
+    pub fn all(&mut self) -> HashMap<Endpoint, f64> {
+        self.age();
+        self.predictabilities.clone()
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashMap, fmt::Display};
+
+use bp7::endpoint::Endpoint;
+
+/// What a route applies to. Lookups use the most specific target matching the destination.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum RouteTarget {
+    /// A single node.
+    Node(Endpoint),
+    /// All nodes whose endpoint matches the pattern, where `*` matches any characters, e.g.
+    /// `dtn://mars-*`.
+    Pattern(String),
+    /// All nodes without any other route.
+    Default,
+}
+
+impl RouteTarget {
+    /// Parses `default`, a pattern containing `*` or a node endpoint.
+    pub fn parse(target: &str) -> Option<Self> {
+        if target == "default" {
+            return Some(RouteTarget::Default);
+        }
+        if target.contains('*') {
+            // routes are per node, so dtn://mars-*/ is the same as dtn://mars-*
+            let pattern = target.strip_suffix('/').unwrap_or(target);
+            let (schema, _) = pattern.split_once(':')?;
+            return matches!(schema, "dtn" | "ipn").then(|| RouteTarget::Pattern(pattern.into()));
+        }
+        Some(RouteTarget::Node(
+            Endpoint::new(target)?.get_node_endpoint(),
+        ))
+    }
+
+    pub fn matches(&self, node: &Endpoint) -> bool {
+        match self {
+            RouteTarget::Node(target) => target.matches_node(node),
+            RouteTarget::Pattern(pattern) => {
+                wildcard_match(pattern, &node.get_node_endpoint().to_string())
+            }
+            RouteTarget::Default => true,
+        }
+    }
+
+    /// Nodes are more specific than patterns, which are more specific the more characters they
+    /// match literally.
+    fn specificity(&self) -> (u8, usize) {
+        match self {
+            RouteTarget::Node(_) => (2, 0),
+            RouteTarget::Pattern(pattern) => (1, pattern.chars().filter(|c| *c != '*').count()),
+            RouteTarget::Default => (0, 0),
+        }
+    }
+}
+
+impl Display for RouteTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteTarget::Node(endpoint) => endpoint.fmt(f),
+            RouteTarget::Pattern(pattern) => f.write_str(pattern),
+            RouteTarget::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// Finds the route of the most specific target matching `destination`.
+pub fn lookup<'a, T>(routes: &'a HashMap<RouteTarget, T>, destination: &Endpoint) -> Option<&'a T> {
+    let node = destination.get_node_endpoint();
+    if let Some(route) = routes.get(&RouteTarget::Node(node.clone())) {
+        return Some(route);
+    }
+    routes
+        .iter()
+        .filter(|(target, _)| target.matches(&node))
+        // ties go to the lexicographically smaller pattern, so lookups do not depend on the
+        // order of the map
+        .max_by(|(a, _), (b, _)| {
+            a.specificity()
+                .cmp(&b.specificity())
+                .then_with(|| b.to_string().cmp(&a.to_string()))
+        })
+        .map(|(_, route)| route)
+}
+
+/// Matches `text` against `pattern`, where `*` matches any characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // no wildcard at all
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
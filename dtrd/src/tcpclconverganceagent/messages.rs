@@ -29,7 +29,3 @@ pub struct ConnectRemote {
 pub struct DisconnectRemote {
     pub url: Url,
 }
-
-#[derive(Message)]
-#[rtype(result = "()")]
-pub(crate) struct ForceShutdown {}
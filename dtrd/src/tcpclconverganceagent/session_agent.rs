@@ -15,32 +15,45 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    io::{self, SeekFrom},
+    net::SocketAddr,
+    sync::Arc,
+};
 
-use bp7::endpoint::Endpoint;
-use log::{debug, error, warn};
+use bp7::{bundle::Bundle, endpoint::Endpoint};
+use log::{debug, error, info, warn};
 use tcpcl::{
-    connection_info::ConnectionInfo, errors::TransferSendErrors, session::TCPCLSession,
-    transfer::Transfer,
+    connection_info::ConnectionInfo,
+    errors::TransferSendErrors,
+    extensions::EidSchemes,
+    session::TCPCLSession,
+    stats::{SessionEvent, SessionStats},
+    transfer::{Transfer, TransferData, TransferFile},
 };
 use tokio::{
-    net::TcpStream,
-    sync::{mpsc, oneshot},
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+    net::{TcpStream, UnixStream},
+    sync::{broadcast::error::RecvError, mpsc, oneshot, watch},
 };
 use tokio_stream::wrappers::ReceiverStream;
 
 use crate::{
-    bundlestorageagent::messages::StoreReceivedBundle,
+    bundlestorageagent::messages::{ReceivedBundle, StoreReceivedBundle},
     common::messages::Shutdown,
     converganceagent::messages::{
         AgentForwardBundle, CLRegisterNode, CLUnregisterNode, EventBundleForwarded,
-        EventBundleForwardingFailed,
+        EventBundleForwardingFailed, EventBundleForwardingInterrupted,
     },
 };
 
 use actix::prelude::*;
 
-use super::messages::ForceShutdown;
+/// How much of a received bundle is read at once while looking for its payload.
+const BLOCKS_READ_SIZE: u64 = 4096;
+/// How large the blocks before or after the payload of a received bundle may be.
+const MAX_BLOCKS_SIZE: usize = 1 << 20;
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -49,6 +62,12 @@ pub struct NewClientConnectedOnSocket {
     pub address: SocketAddr,
 }
 
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NewClientConnectedOnUnixSocket {
+    pub stream: UnixStream,
+}
+
 type TCPCLSendChannel = mpsc::Sender<(
     Arc<Vec<u8>>,
     oneshot::Sender<Result<(), TransferSendErrors>>,
@@ -57,6 +76,9 @@ type TCPCLSendChannel = mpsc::Sender<(
 pub struct TCPCLSessionAgent {
     close_channel: Option<oneshot::Sender<()>>,
     send_channel: TCPCLSendChannel,
+    link_stats: watch::Receiver<SessionStats>,
+    /// Received transfers that are not yet passed to the storage.
+    storing_transfers: usize,
 }
 
 impl Actor for TCPCLSessionAgent {
@@ -68,8 +90,11 @@ impl Actor for TCPCLSessionAgent {
         // about the connection using StreamHandler<ConnectionInfo>. However
         // the stream is closed afterwards. Actix treats this as a reason to stop
         // the whole actor. With this pice of code we only stop when we actually
-        // want to or if the connection got finished by the remote (send_channel)
-        if self.close_channel.is_some() && !self.send_channel.is_closed() {
+        // want to or if the connection got finished by the remote (send_channel).
+        // Transfers received before the end are still stored in any case.
+        if self.storing_transfers > 0
+            || self.close_channel.is_some() && !self.send_channel.is_closed()
+        {
             Running::Continue
         } else {
             Running::Stop
@@ -79,19 +104,75 @@ impl Actor for TCPCLSessionAgent {
 
 impl StreamHandler<Transfer> for TCPCLSessionAgent {
     fn handle(&mut self, item: Transfer, ctx: &mut Self::Context) {
-        let bundle_data =
-            Arc::try_unwrap(item.data).expect("We get exclusive ownership from tcpcl");
         let transferid = item.id;
-        crate::bundlestorageagent::agent::Daemon::from_registry()
-                    .send(StoreReceivedBundle { bundle_data })
-                    .into_actor(self)
-                    .then(move |res, _act, _ctx| {
-                        match res {
-                            Ok(()) => {debug!("Successfully received transfer {transferid}")},
-                            Err(_) => {error!("Error storing transfered bundle with id {transferid}. It would have been better if we did not ack it")},
+        let interrupted = item.interrupted;
+        let reservation = item.reservation;
+        let fut = async move {
+            let bundle = match item.data {
+                TransferData::Writer => {
+                    error!(
+                        "Transfer {transferid} was passed to a writer, but we never configure one as transfer sink"
+                    );
+                    return;
+                }
+                TransferData::File(file) if !interrupted => {
+                    match read_without_payload(&file).await {
+                        Ok((head, tail, payload_size)) => ReceivedBundle::File {
+                            file,
+                            head,
+                            tail,
+                            size: item.length,
+                            payload_size,
+                        },
+                        Err(e) => {
+                            error!(
+                                "Error reading transfered bundle with id {transferid}: {e}. It would have been better if we did not ack it"
+                            );
+                            return;
                         }
-                        fut::ready(())})
-                    .spawn(ctx);
+                    }
+                }
+                // the fragment we keep is a new bundle, which is built in memory
+                TransferData::File(file) => match fs::read(file.path()).await {
+                    Ok(data) => match received_fragment(transferid, &data) {
+                        Some(fragment) => ReceivedBundle::Memory(fragment),
+                        None => return,
+                    },
+                    Err(e) => {
+                        debug!("Dropping interrupted transfer {transferid}: {e}");
+                        return;
+                    }
+                },
+                TransferData::Memory(data) if interrupted => {
+                    match received_fragment(transferid, &data) {
+                        Some(fragment) => ReceivedBundle::Memory(fragment),
+                        None => return,
+                    }
+                }
+                TransferData::Memory(data) => ReceivedBundle::Memory(data),
+            };
+            match crate::bundlestorageagent::agent::Daemon::from_registry()
+                .send(StoreReceivedBundle {
+                    bundle,
+                    reservation,
+                })
+                .await
+            {
+                Ok(()) => debug!("Successfully received transfer {transferid}"),
+                Err(_) => error!(
+                    "Error storing transfered bundle with id {transferid}. It would have been better if we did not ack it"
+                ),
+            }
+        };
+        self.storing_transfers += 1;
+        fut.into_actor(self)
+            .map(|(), act, ctx| {
+                act.storing_transfers -= 1;
+                if act.storing_transfers == 0 && act.send_channel.is_closed() {
+                    ctx.stop();
+                }
+            })
+            .spawn(ctx);
     }
 }
 
@@ -108,7 +189,8 @@ impl Handler<AgentForwardBundle> for TCPCLSessionAgent {
         let bundle_endpoint = bundle.get_primary_block().destination_endpoint.clone();
 
         let channel = self.send_channel.clone();
-        let fut = async move { channel.send((bundle_data, result_sender)).await };
+        let sent_data = bundle_data.clone();
+        let fut = async move { channel.send((sent_data, result_sender)).await };
         fut.into_actor(self)
             .then(|res, _act, ctx| {
                 if res.is_err() {
@@ -127,8 +209,32 @@ impl Handler<AgentForwardBundle> for TCPCLSessionAgent {
                                         .await
                                         .unwrap();
                                 }
+                                Err(TransferSendErrors::Interrupted { acknowledged })
+                                    if let Some(received_payload) =
+                                        received_payload(&bundle_data, acknowledged) =>
+                                {
+                                    crate::bundleprotocolagent::agent::Daemon::from_registry()
+                                        .send(EventBundleForwardingInterrupted {
+                                            endpoint: bundle_endpoint,
+                                            bundle,
+                                            received_payload,
+                                        })
+                                        .await
+                                        .unwrap();
+                                }
                                 Err(e) => {
-                                    error!("Error during sending of bundle: {e:?}");
+                                    match e {
+                                        TransferSendErrors::SessionClosed
+                                        | TransferSendErrors::Interrupted { .. } => {
+                                            debug!("Session closed before the bundle was sent");
+                                        }
+                                        TransferSendErrors::Refused => {
+                                            info!("Peer refused the bundle");
+                                        }
+                                        e @ TransferSendErrors::BundleTooLarge { .. } => {
+                                            error!("Error during sending of bundle: {e:?}");
+                                        }
+                                    }
                                     crate::bundleprotocolagent::agent::Daemon::from_registry()
                                         .send(EventBundleForwardingFailed {
                                             endpoint: bundle_endpoint,
@@ -166,14 +272,6 @@ impl Handler<Shutdown> for TCPCLSessionAgent {
     }
 }
 
-impl Handler<ForceShutdown> for TCPCLSessionAgent {
-    type Result = ();
-
-    fn handle(&mut self, _msg: ForceShutdown, ctx: &mut Self::Context) -> Self::Result {
-        ctx.stop();
-    }
-}
-
 impl StreamHandler<ConnectionInfo> for TCPCLSessionAgent {
     fn handle(&mut self, item: ConnectionInfo, ctx: &mut Self::Context) {
         if let Some(node) = Endpoint::new(item.peer_endpoint.as_ref().unwrap()) {
@@ -184,6 +282,7 @@ impl StreamHandler<ConnectionInfo> for TCPCLSessionAgent {
                     .max_bundle_size
                     .expect("We must have a bundle size if we are connected"),
                 sender: ctx.address().recipient(),
+                link_stats: Some(self.link_stats.clone()),
             });
         } else {
             warn!(
@@ -195,23 +294,122 @@ impl StreamHandler<ConnectionInfo> for TCPCLSessionAgent {
     }
 }
 
+impl StreamHandler<SessionEvent> for TCPCLSessionAgent {
+    fn handle(&mut self, item: SessionEvent, _ctx: &mut Self::Context) {
+        match item {
+            SessionEvent::TlsUpgraded => debug!("Session upgraded to TLS"),
+            SessionEvent::Established(ci) => {
+                info!(
+                    "Session with {} established via {}",
+                    ci.peer_endpoint.as_deref().unwrap_or_default(),
+                    ci.peer_url
+                );
+                if let Some(EidSchemes(schemes)) = ci.session_extensions.get() {
+                    debug!("Peer supports the endpoint id schemes {schemes:?}");
+                }
+            }
+            SessionEvent::Terminating {
+                reason,
+                initiated_by_peer,
+            } => info!(
+                "Session is terminating with reason {reason:?} (initiated by peer: {initiated_by_peer})"
+            ),
+            SessionEvent::Closed => {
+                let stats = self.link_stats.borrow();
+                debug!("Session closed. Final statistics: {stats:?}");
+            }
+        }
+    }
+}
+
+/// Keeps what we received of an interrupted transfer as a fragment, the peer only sends the rest
+/// again.
+fn received_fragment(transferid: u64, data: &[u8]) -> Option<Vec<u8>> {
+    match Bundle::from_received_prefix(data).and_then(|fragment| Ok(Vec::<u8>::try_from(fragment)?))
+    {
+        Ok(fragment) => {
+            debug!("Keeping the received part of interrupted transfer {transferid}");
+            Some(fragment)
+        }
+        Err(e) => {
+            debug!("Dropping interrupted transfer {transferid}: {e:?}");
+            None
+        }
+    }
+}
+
+/// Reads the parts of the bundle received into `file` before and after its payload data, see
+/// [`Bundle::without_payload`]. Returns them with the size of the payload.
+async fn read_without_payload(file: &TransferFile) -> io::Result<(Vec<u8>, Vec<u8>, u64)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "no payload block found");
+    let mut reader = fs::File::open(file.path()).await?;
+    let mut head = Vec::new();
+    let payload = loop {
+        if let Some(payload) = Bundle::payload_range(&head) {
+            break payload;
+        }
+        if head.len() >= MAX_BLOCKS_SIZE {
+            return Err(invalid());
+        }
+        let read = (&mut reader)
+            .take(BLOCKS_READ_SIZE)
+            .read_to_end(&mut head)
+            .await?;
+        if read == 0 {
+            return Err(invalid());
+        }
+    };
+    head.truncate(payload.start);
+    reader.seek(SeekFrom::Start(payload.end as u64)).await?;
+    let mut tail = Vec::new();
+    (&mut reader)
+        .take(MAX_BLOCKS_SIZE as u64)
+        .read_to_end(&mut tail)
+        .await?;
+    Ok((head, tail, payload.len() as u64))
+}
+
+/// Returns how many bytes of the payload the peer received, if it can keep them as a fragment.
+fn received_payload(bundle_data: &[u8], acknowledged: u64) -> Option<u64> {
+    let prefix = bundle_data.get(..usize::try_from(acknowledged).ok()?)?;
+    let fragment = Bundle::from_received_prefix(prefix).ok()?;
+    Some(fragment.payload_block().data.len() as u64)
+}
+
 impl TCPCLSessionAgent {
     pub fn new(mut session: TCPCLSession) -> Addr<Self> {
         TCPCLSessionAgent::create(|ctx| {
             ctx.add_stream(ReceiverStream::new(session.get_receive_channel()));
 
+            let mut event_channel = session.get_event_channel();
+            ctx.add_stream(async_stream::stream! {
+                loop {
+                    match event_channel.recv().await {
+                        Ok(event) => yield event,
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("Missed {count} session events");
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            let link_stats = session.get_stats_channel();
+
             let established_channel = session.get_established_channel();
             ctx.add_stream(async_stream::stream! {yield established_channel.await.unwrap();});
 
             let close_channel = session.get_close_channel();
             let send_channel = session.get_send_channel();
 
-            let session_agent_address = ctx.address();
-
             let fut = async move {
+                // we stop once the session is dropped and all of its channels are closed, after
+                // handling the transfers it passed up
                 if let Err(e) = session.manage_connection().await {
                     warn!("Connection closed with error: {e:?}");
-                    session_agent_address.do_send(ForceShutdown {});
+                }
+                for (_, result_sender) in session.take_unsent_transfers() {
+                    // the forwarding listener requeues the bundle
+                    let _ = result_sender.send(Err(TransferSendErrors::SessionClosed));
                 }
                 let ci = session.get_connection_info();
                 let node = match ci.peer_endpoint {
@@ -228,6 +426,8 @@ impl TCPCLSessionAgent {
             TCPCLSessionAgent {
                 close_channel: Some(close_channel),
                 send_channel,
+                link_stats,
+                storing_transfers: 0,
             }
         })
     }
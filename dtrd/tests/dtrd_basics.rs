@@ -21,15 +21,20 @@ use std::{process::Stdio, time::Duration};
 
 use bp7::administrative_record::AdministrativeRecord;
 use bp7::administrative_record::bundle_status_report::BundleStatusReason;
+use bp7::time::DtnTime;
+use dtrd_client::{BundleStatus, Contact, Priority};
 use futures_util::StreamExt;
 use tokio::fs;
 use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
     process::{Child, Command},
     time::sleep,
 };
 use tokio_util::time::FutureExt;
 
 const DUMMY_DATA: &str = "dummydata";
+const TCPCL_SOCKET_NAME: &str = "tcpcl.sock";
 const DTRD_BIN_PATH: &str = env!("CARGO_BIN_EXE_dtrd");
 
 static PORT_COUNTER: AtomicU16 = AtomicU16::new(50000);
@@ -38,11 +43,16 @@ type Res<T> = Result<T, Box<dyn std::error::Error>>;
 
 struct DtrdRunner {
     cmd: Option<Child>,
+    /// Additional environment variables to start dtrd with.
+    env: Vec<(String, String)>,
 }
 
 impl DtrdRunner {
     async fn new(node_id: &str, grpc_port: u16, tcpcl_port: u16, bundle_dir: &Path) -> Res<Self> {
-        let mut runner = DtrdRunner { cmd: None };
+        let mut runner = DtrdRunner {
+            cmd: None,
+            env: Vec::new(),
+        };
         runner
             .start(node_id, grpc_port, tcpcl_port, bundle_dir)
             .await?;
@@ -61,11 +71,16 @@ impl DtrdRunner {
             .env("NODE_ID", node_id)
             .env("GRPC_CLIENTAPI_ADDRESS", format!("127.0.0.1:{grpc_port}"))
             .env("TCPCL_LISTEN_ADDRESS", format!("127.0.0.1:{tcpcl_port}"))
+            .env(
+                "TCPCL_UNIX_SOCKET_PATH",
+                bundle_dir.with_file_name(TCPCL_SOCKET_NAME),
+            )
             .env(
                 "BUNDLE_STORAGE_PATH",
                 bundle_dir.to_string_lossy().to_string(),
             )
             .env("RUST_LOG", "info,dtrd=debug")
+            .envs(self.env.iter().cloned())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()?;
@@ -190,18 +205,45 @@ impl Dtrd {
             .await
     }
 
+    /// Sets an environment variable for the next time dtrd is started.
+    fn set_env(&mut self, key: &str, value: &str) {
+        self.runner.env.push((key.to_string(), value.to_string()));
+    }
+
     fn allow_message(&mut self, msg: &str) {
         self.allowed_messages.push(msg.to_string());
     }
 
+    /// Names of all files in the bundle storage, except for the seen bundles.
+    async fn stored_files(&self) -> Res<Vec<String>> {
+        let mut files = Vec::new();
+        let mut readdir = fs::read_dir(&self.bundle_dir).await?;
+        while let Some(entry) = readdir.next_entry().await? {
+            if entry.file_name() != "seen_bundles" {
+                files.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(files)
+    }
+
     fn with_node_id(&self, suffix: &str) -> String {
         format!("{}/{}", self.node_id, suffix)
     }
 
+    /// Connects using the unix socket of the other dtrd.
     async fn connect_to(&mut self, other: &Dtrd) -> Res<()> {
-        self.client
-            .add_node(format!("tcpcl://127.0.0.1:{}", other.tcpcl_port))
-            .await?;
+        let socket = other.tmpdir.join(TCPCL_SOCKET_NAME);
+        self.connect_to_url(other, format!("tcpcl+unix://{}", socket.display()))
+            .await
+    }
+
+    async fn connect_to_tcp(&mut self, other: &Dtrd) -> Res<()> {
+        self.connect_to_url(other, format!("tcpcl://127.0.0.1:{}", other.tcpcl_port))
+            .await
+    }
+
+    async fn connect_to_url(&mut self, other: &Dtrd, url: String) -> Res<()> {
+        self.client.add_node(url).await?;
         sleep(Duration::from_secs(1)).await;
         assert!(
             self.client
@@ -277,7 +319,7 @@ async fn delivers_bundles_connected() -> Result<(), Box<dyn std::error::Error>>
     with_dtrds(2, async |mut dtrds| {
         let dtrd1 = dtrds.remove(0);
         let dtrd2 = dtrds.remove(0);
-        dtrd1.connect_to(dtrd2).await?;
+        dtrd1.connect_to_tcp(dtrd2).await?;
         dtrd1
             .client
             .submit_bundle(
@@ -328,12 +370,343 @@ async fn delivers_bundles_routed() -> Result<(), Box<dyn std::error::Error>> {
     .await
 }
 
+#[tokio::test]
+async fn delivers_bundles_by_pattern_and_default_routes() -> Result<(), Box<dyn std::error::Error>>
+{
+    const REMOTE_NODE: &str = "dtn://faraway";
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        dtrd1.connect_to(dtrd2).await?;
+        dtrd2.connect_to(dtrd3).await?;
+        let pattern = format!("{}*", &dtrd3.node_id[..dtrd3.node_id.len() - 1]);
+        for target in [pattern.clone(), "default".to_string()] {
+            dtrd1
+                .client
+                .add_route(target, dtrd2.node_id.clone())
+                .await?;
+        }
+        let routes = dtrd1.client.list_routes().await?;
+        for target in [&pattern, "default"] {
+            assert!(routes.iter().any(|r| {
+                let route = r.route.as_ref().unwrap();
+                route.target == target && r.preferred
+            }));
+        }
+
+        for destination in [dtrd3.with_node_id("testendpoint"), REMOTE_NODE.to_string()] {
+            dtrd1
+                .client
+                .submit_bundle(&destination, 60, DUMMY_DATA.as_bytes(), false)
+                .await?;
+        }
+        let data = dtrd3
+            .client
+            .receive_bundle(&dtrd3.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+
+        // the default route took the other bundle to dtrd2, which has no route for it
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let statistics = dtrd1.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 0);
+        let statistics = dtrd2.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 1);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn spreads_bundles_over_equal_cost_routes() -> Result<(), Box<dyn std::error::Error>> {
+    const REMOTE_NODE: &str = "dtn://faraway";
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        dtrd1.connect_to(dtrd2).await?;
+        dtrd1.connect_to(dtrd3).await?;
+        for next_hop in [&dtrd2.node_id, &dtrd3.node_id] {
+            dtrd1
+                .client
+                .add_route_with_metric(REMOTE_NODE.to_string(), next_hop.clone(), 10, 1)
+                .await?;
+        }
+        let submit = async |dtrd1: &mut Dtrd| {
+            dtrd1
+                .client
+                .submit_bundle(REMOTE_NODE, 60, DUMMY_DATA.as_bytes(), false)
+                .await
+        };
+        submit(dtrd1).await?;
+        submit(dtrd1).await?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for dtrd in [&mut *dtrd2, &mut *dtrd3] {
+            let statistics = dtrd.client.get_statistics().await?;
+            assert_eq!(statistics.queued_bundles["normal"], 1);
+        }
+
+        // a lower metric wins, until its next hop goes away
+        dtrd1
+            .client
+            .add_route_with_metric(REMOTE_NODE.to_string(), dtrd2.node_id.clone(), 5, 1)
+            .await?;
+        let reasons = async |dtrd1: &mut Dtrd| -> Res<Vec<(String, bool, String)>> {
+            let mut routes: Vec<(String, bool, String)> = dtrd1
+                .client
+                .list_routes()
+                .await?
+                .into_iter()
+                .filter(|r| r.route.as_ref().unwrap().target == REMOTE_NODE)
+                .map(|r| (r.route.unwrap().next_hop, r.preferred, r.reason))
+                .collect();
+            routes.sort();
+            Ok(routes)
+        };
+        let mut expected = vec![
+            (dtrd2.node_id.clone(), true, "lowest metric".to_string()),
+            (dtrd3.node_id.clone(), false, "higher metric".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(reasons(dtrd1).await?, expected);
+
+        let socket = dtrd2.tmpdir.join(TCPCL_SOCKET_NAME);
+        dtrd1
+            .client
+            .remove_node(format!("tcpcl+unix://{}", socket.display()))
+            .await?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let mut expected = vec![
+            (dtrd2.node_id.clone(), false, "next hop down".to_string()),
+            (dtrd3.node_id.clone(), true, "lowest metric".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(reasons(dtrd1).await?, expected);
+        submit(dtrd1).await?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let statistics = dtrd3.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 2);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn routes_bundles_by_contact_plan() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        let socket = dtrd2.tmpdir.join(TCPCL_SOCKET_NAME);
+        let plan = format!(
+            "# dtrd1 reaches dtrd2 shortly after starting\n\
+             {} {} +3 +60 100000 10 tcpcl+unix://{}\n\
+             {} {} +0 +60 100000 10\n",
+            dtrd1.node_id,
+            dtrd2.node_id,
+            socket.display(),
+            dtrd2.node_id,
+            dtrd3.node_id,
+        );
+        let plan_path = dtrd1.tmpdir.join("contact_plan");
+        fs::write(&plan_path, plan).await?;
+        dtrd1.set_env("CONTACT_PLAN_PATH", &plan_path.to_string_lossy());
+        dtrd1.stop().await?;
+        dtrd1.restart().await?;
+        dtrd2.connect_to(dtrd3).await?;
+
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd3.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        // the contact has not started yet, so the bundle is booked on it but still waiting
+        sleep(Duration::from_millis(200)).await;
+        assert!(dtrd1.client.list_nodes().await?.is_empty());
+        let contacts = dtrd1.client.list_contacts().await?;
+        assert_eq!(contacts.len(), 2);
+        for status in &contacts {
+            // only the contact starting at dtrd1 is booked, dtrd2 plans its own contacts
+            let from = &status.contact.as_ref().unwrap().from;
+            assert_eq!(status.booked_bytes > 0, from == &dtrd1.node_id);
+        }
+
+        let data = dtrd3
+            .client
+            .receive_bundle(&dtrd3.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn contacts_only_disconnect_the_nodes_they_connected()
+-> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        dtrd1.connect_to(dtrd2).await?;
+
+        let now = DtnTime::now().timestamp;
+        for other in [&dtrd2, &dtrd3] {
+            let socket = other.tmpdir.join(TCPCL_SOCKET_NAME);
+            dtrd1
+                .client
+                .add_contact(Contact {
+                    from: dtrd1.node_id.clone(),
+                    to: other.node_id.clone(),
+                    start_time: now,
+                    end_time: now + 2000,
+                    rate_bytes_per_second: 100_000,
+                    owlt_millis: 10,
+                    url: format!("tcpcl+unix://{}", socket.display()),
+                })
+                .await?;
+        }
+        sleep(Duration::from_secs(1)).await;
+        let nodes = dtrd1.client.list_nodes().await?;
+        assert!(nodes.iter().any(|n| n.endpoint == dtrd2.node_id));
+        assert!(nodes.iter().any(|n| n.endpoint == dtrd3.node_id));
+
+        // dtrd2 was connected before its contact started, so it stays connected
+        sleep(Duration::from_secs(2)).await;
+        let nodes = dtrd1.client.list_nodes().await?;
+        assert!(nodes.iter().any(|n| n.endpoint == dtrd2.node_id));
+        assert!(!nodes.iter().any(|n| n.endpoint == dtrd3.node_id));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn epidemic_routing_copies_bundles() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        for dtrd in [&mut *dtrd1, &mut *dtrd2] {
+            dtrd.set_env("OPPORTUNISTIC_ROUTING", "epidemic");
+            dtrd.stop().await?;
+            dtrd.restart().await?;
+        }
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd3.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        // there is no route to dtrd3, so dtrd2 gets a copy once we meet it
+        dtrd1.connect_to(dtrd2).await?;
+        dtrd2.connect_to(dtrd3).await?;
+        let data = dtrd3
+            .client
+            .receive_bundle(&dtrd3.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+
+        // dtrd1 still keeps its own copy
+        let statistics = dtrd1.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 1);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn spray_and_wait_limits_copies() -> Result<(), Box<dyn std::error::Error>> {
+    const REMOTE_NODE: &str = "dtn://faraway";
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        for dtrd in [&mut *dtrd1, &mut *dtrd2, &mut *dtrd3] {
+            dtrd.set_env("OPPORTUNISTIC_ROUTING", "spray-and-wait");
+            dtrd.set_env("SPRAY_AND_WAIT_COPIES", "2");
+            dtrd.stop().await?;
+            dtrd.restart().await?;
+        }
+        dtrd1
+            .client
+            .submit_bundle(
+                &format!("{REMOTE_NODE}/testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        // dtrd2 gets one of the two copies, with a single copy it only waits for the destination
+        dtrd1.connect_to(dtrd2).await?;
+        dtrd2.connect_to(dtrd3).await?;
+
+        let statistics = dtrd2.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 1);
+        let statistics = dtrd3.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 0);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn prophet_copies_to_more_likely_peers() -> Result<(), Box<dyn std::error::Error>> {
+    const REMOTE_NODE: &str = "dtn://faraway";
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        for dtrd in [&mut *dtrd1, &mut *dtrd2] {
+            dtrd.set_env("OPPORTUNISTIC_ROUTING", "prophet");
+            dtrd.stop().await?;
+            dtrd.restart().await?;
+        }
+        // dtrd2 met dtrd3, so it is more likely to deliver to it than dtrd1
+        dtrd2.connect_to(dtrd3).await?;
+        for destination in [dtrd3.with_node_id("testendpoint"), REMOTE_NODE.to_string()] {
+            dtrd1
+                .client
+                .submit_bundle(&destination, 60, DUMMY_DATA.as_bytes(), false)
+                .await?;
+        }
+        dtrd1.connect_to(dtrd2).await?;
+        let data = dtrd3
+            .client
+            .receive_bundle(&dtrd3.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+
+        // nobody met the other destination, so dtrd2 did not get a copy of it
+        let statistics = dtrd2.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 0);
+        let statistics = dtrd1.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["normal"], 2);
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test]
 async fn hop_count_causes_expiry() -> Result<(), Box<dyn std::error::Error>> {
     const LOOP_NODE: &str = "dtn://thisnodedoesnotexist";
     with_dtrds(2, async |mut dtrds| {
         let dtrd1 = dtrds.remove(0);
         let dtrd2 = dtrds.remove(0);
+        // Otherwise the bundle is dropped the first time it comes back
+        for dtrd in [&mut *dtrd1, &mut *dtrd2] {
+            dtrd.stop().await?;
+            dtrd.set_env("BUNDLE_DUPLICATE_CACHE_SIZE", "0");
+            dtrd.restart().await?;
+        }
         dtrd1.connect_to(dtrd2).await?;
         dtrd1
             .client
@@ -367,6 +740,41 @@ async fn hop_count_causes_expiry() -> Result<(), Box<dyn std::error::Error>> {
     .await
 }
 
+#[tokio::test]
+async fn lifetime_causes_expiry() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd2.with_node_id("testendpoint"),
+                1,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_secs(2)).await;
+
+        // The bundle must not be forwarded once we can reach dtrd2. The status report has the
+        // same short lifetime, so we need to listen for it already.
+        let mut recvstream = dtrd1.client.listen_bundles(&dtrd1.node_id).await?;
+        dtrd1.connect_to(dtrd2).await?;
+        let data = recvstream.next().await.unwrap()?;
+        if let Ok(AdministrativeRecord::BundleStatusReport(bsr)) =
+            AdministrativeRecord::try_from(data)
+        {
+            assert_eq!(bsr.reason, BundleStatusReason::LifetimeExpired);
+            assert!(bsr.status_information.deleted_bundle.is_asserted);
+        } else {
+            unreachable!();
+        }
+
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test]
 async fn bundle_stored_across_restarts() -> Result<(), Box<dyn std::error::Error>> {
     with_dtrds(1, async |mut dtrds| {
@@ -395,32 +803,732 @@ async fn bundle_stored_across_restarts() -> Result<(), Box<dyn std::error::Error
 }
 
 #[tokio::test]
-async fn delivers_bundles_fragmented() -> Result<(), Box<dyn std::error::Error>> {
+async fn bundle_state_stored_across_restarts() -> Result<(), Box<dyn std::error::Error>> {
     with_dtrds(2, async |mut dtrds| {
         let dtrd1 = dtrds.remove(0);
         let dtrd2 = dtrds.remove(0);
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd2.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+
+        // The bundle is queued for forwarding, which is stored next to it
+        let files = dtrd1.stored_files().await?;
+        assert_eq!(files.len(), 2);
+        assert!(
+            files
+                .iter()
+                .any(|f| Path::new(f).extension().is_some_and(|e| e == "meta"))
+        );
+
+        dtrd1.stop().await?;
+        dtrd1.restart().await?;
         dtrd1.connect_to(dtrd2).await?;
 
-        // We now need to generate a bundle larger than the tcpcl max transfer
-        // size. What is in there is something we can ignore.
-        let target_size = tcpcl::v4::messages::sess_init::MAX_TRANSFER_MRU;
-        let mut data = Vec::with_capacity(target_size);
-        while data.len() < target_size {
-            data.extend_from_slice(DUMMY_DATA.as_bytes());
-        }
+        let data = dtrd2
+            .client
+            .receive_bundle(&dtrd2.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+
+        // Once forwarded neither the bundle nor its metadata are left
+        sleep(Duration::from_millis(500)).await;
+        assert!(dtrd1.stored_files().await?.is_empty());
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn memory_storage_backend_keeps_no_files() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1.stop().await?;
+        dtrd1.set_env("BUNDLE_STORAGE_BACKEND", "memory");
+        dtrd1.restart().await?;
 
         dtrd1
             .client
-            .submit_bundle(&dtrd2.with_node_id("testendpoint"), 60, &data, true)
+            .submit_bundle(
+                &dtrd2.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
             .await?;
-        let received_data = dtrd2
+        sleep(Duration::from_millis(500)).await;
+        assert!(dtrd1.stored_files().await?.is_empty());
+
+        dtrd1.connect_to(dtrd2).await?;
+        let data = dtrd2
             .client
             .receive_bundle(&dtrd2.with_node_id("testendpoint"))
             .await?;
-        assert_eq!(data, received_data);
-
-        // Ensure that we actually got more than one status report about a bundle being received
-        // from the remote side. That way we can be sure that fragmentation happened.
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn receives_bundles_into_files() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let incoming = dtrd2.tmpdir.join("incoming");
+        fs::create_dir_all(&incoming).await?;
+        dtrd2.stop().await?;
+        dtrd2.set_env("TCPCL_INCOMING_PATH", &incoming.to_string_lossy());
+        dtrd2.restart().await?;
+
+        // the blocks added while forwarding follow the payload
+        let payload = vec![0x42; 100_000];
+        dtrd1
+            .client
+            .submit_bundle(&dtrd2.with_node_id("testendpoint"), 60, &payload, false)
+            .await?;
+        dtrd1.connect_to_tcp(dtrd2).await?;
+        let data = dtrd2
+            .client
+            .receive_bundle(&dtrd2.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(data, payload);
+
+        let mut readdir = fs::read_dir(&incoming).await?;
+        assert!(readdir.next_entry().await?.is_none());
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(feature = "rocksdb")]
+#[tokio::test]
+async fn rocksdb_storage_backend_keeps_bundles_across_restarts()
+-> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(1, async |mut dtrds| {
+        let dtrd = dtrds.remove(0);
+        dtrd.stop().await?;
+        dtrd.set_env("BUNDLE_STORAGE_BACKEND", "rocksdb");
+        dtrd.restart().await?;
+
+        dtrd.client
+            .submit_bundle(
+                &dtrd.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+
+        dtrd.stop().await?;
+        dtrd.restart().await?;
+
+        let data = dtrd
+            .client
+            .receive_bundle(&dtrd.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn damaged_bundles_are_quarantined() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd2.with_node_id("damaged"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+        let damaged = dtrd1
+            .stored_files()
+            .await?
+            .into_iter()
+            .find(|f| Path::new(f).extension().is_none())
+            .unwrap();
+        dtrd1
+            .client
+            .submit_bundle(&dtrd2.with_node_id("intact"), 60, b"intact", false)
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+        dtrd1.stop().await?;
+
+        // Flip a bit in the payload, the bundle can still be decoded
+        let path = dtrd1.bundle_dir.join(&damaged);
+        let mut data = fs::read(&path).await?;
+        let pos = data
+            .windows(DUMMY_DATA.len())
+            .position(|w| w == DUMMY_DATA.as_bytes())
+            .unwrap();
+        data[pos] ^= 0x01;
+        fs::write(&path, data).await?;
+        fs::write(dtrd1.bundle_dir.join("garbage"), b"no bundle").await?;
+
+        dtrd1.allow_message("to quarantine");
+        dtrd1.restart().await?;
+        dtrd1.connect_to(dtrd2).await?;
+        let data = dtrd2
+            .client
+            .receive_bundle(&dtrd2.with_node_id("intact"))
+            .await?;
+        assert_eq!(data, b"intact");
+
+        let mut quarantined = Vec::new();
+        let mut readdir = fs::read_dir(dtrd1.bundle_dir.join("quarantine")).await?;
+        while let Some(entry) = readdir.next_entry().await? {
+            quarantined.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        quarantined.sort();
+        assert_eq!(
+            quarantined,
+            [damaged.clone(), format!("{damaged}.meta"), "garbage".into()]
+        );
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn check_storage_repairs_without_starting() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(1, async |mut dtrds| {
+        let dtrd = dtrds.remove(0);
+        dtrd.client
+            .submit_bundle(
+                &dtrd.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+        dtrd.stop().await?;
+        fs::write(dtrd.bundle_dir.join("garbage"), b"no bundle").await?;
+        fs::write(dtrd.bundle_dir.join("interrupted.tmp"), b"").await?;
+
+        let output = Command::new(DTRD_BIN_PATH)
+            .arg("--check-storage")
+            .env("BUNDLE_STORAGE_PATH", &dtrd.bundle_dir)
+            .output()
+            .await?;
+        // the damaged file is reported by the exit code
+        assert_eq!(output.status.code(), Some(2));
+        let stdout = String::from_utf8(output.stdout)?;
+        assert!(stdout.contains("quarantined garbage: not a bundle"));
+        assert!(stdout.contains("1 bundles loaded, 1 quarantined, 1 leftovers cleaned up"));
+
+        let files = dtrd.stored_files().await?;
+        assert_eq!(files.len(), 3);
+        assert!(files.iter().any(|f| f == "quarantine"));
+        assert!(
+            !files
+                .iter()
+                .any(|f| f == "garbage" || f == "interrupted.tmp")
+        );
+
+        // Nothing is left to complain about
+        dtrd.restart().await?;
+        let data = dtrd
+            .client
+            .receive_bundle(&dtrd.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn bundles_without_metadata_are_not_loaded() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(1, async |mut dtrds| {
+        let dtrd = dtrds.remove(0);
+        dtrd.client
+            .submit_bundle(
+                &dtrd.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+        dtrd.stop().await?;
+
+        // A bundle without metadata was never completely stored
+        let metadata = dtrd
+            .stored_files()
+            .await?
+            .into_iter()
+            .find(|f| Path::new(f).extension().is_some_and(|e| e == "meta"))
+            .unwrap();
+        fs::remove_file(dtrd.bundle_dir.join(metadata)).await?;
+
+        let output = Command::new(DTRD_BIN_PATH)
+            .arg("--check-storage")
+            .env("BUNDLE_STORAGE_PATH", &dtrd.bundle_dir)
+            .output()
+            .await?;
+        assert_eq!(output.status.code(), Some(0));
+        let stdout = String::from_utf8(output.stdout)?;
+        assert!(stdout.contains("0 bundles loaded, 0 quarantined, 1 leftovers cleaned up"));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn duplicate_bundles_are_dropped() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd2.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+
+        // Keep a copy of the bundle, so we can send it again as if the ack got lost
+        let backup = dtrd1.tmpdir.join("backup");
+        fs::create_dir_all(&backup).await?;
+        for file in dtrd1.stored_files().await? {
+            fs::copy(dtrd1.bundle_dir.join(&file), backup.join(&file)).await?;
+        }
+        dtrd1.connect_to(dtrd2).await?;
+        let data = dtrd2
+            .client
+            .receive_bundle(&dtrd2.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+
+        dtrd1.stop().await?;
+        let mut readdir = fs::read_dir(&backup).await?;
+        while let Some(entry) = readdir.next_entry().await? {
+            fs::copy(entry.path(), dtrd1.bundle_dir.join(entry.file_name())).await?;
+        }
+
+        // The seen bundles survive a restart once written
+        sleep(Duration::from_secs(1)).await;
+        dtrd2.stop().await?;
+        dtrd2.restart().await?;
+        dtrd1.restart().await?;
+        dtrd1.connect_to(dtrd2).await?;
+        sleep(Duration::from_millis(500)).await;
+
+        let statistics = dtrd2.client.get_statistics().await?;
+        assert_eq!(statistics.duplicate_bundles, 1);
+        assert!(dtrd2.stored_files().await?.is_empty());
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn urgent_bundles_overtake_bulk_bundles() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1.stop().await?;
+        dtrd1.set_env(
+            "BUNDLE_PRIORITY_POLICY",
+            &format!("destination:{}=expedited", dtrd2.with_node_id("commands")),
+        );
+        dtrd1.restart().await?;
+
+        let destination = dtrd2.with_node_id("testendpoint");
+        for i in 0..3 {
+            dtrd1
+                .client
+                .submit_bundle_with_priority(
+                    &destination,
+                    60,
+                    format!("bulk{i}").as_bytes(),
+                    false,
+                    Priority::Bulk,
+                )
+                .await?;
+        }
+        dtrd1
+            .client
+            .submit_bundle_with_priority(&destination, 60, b"urgent", false, Priority::Expedited)
+            .await?;
+        dtrd1
+            .client
+            .submit_bundle(&dtrd2.with_node_id("commands"), 60, b"command", false)
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+
+        let statistics = dtrd1.client.get_statistics().await?;
+        assert_eq!(statistics.queued_bundles["bulk"], 3);
+        assert_eq!(statistics.queued_bundles["normal"], 0);
+        assert_eq!(statistics.queued_bundles["expedited"], 2);
+
+        let stream = dtrd2.client.listen_bundles(&destination).await?;
+        dtrd1.connect_to(dtrd2).await?;
+        let received: Vec<Vec<u8>> = stream.take(4).map(Result::unwrap).collect().await;
+        assert_eq!(received[0], b"urgent");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn custody_moves_hop_by_hop() -> Result<(), Box<dyn std::error::Error>> {
+    const REMOTE_NODE: &str = "dtn://elsewhere";
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        for dtrd in [&mut *dtrd1, &mut *dtrd2] {
+            dtrd.stop().await?;
+            dtrd.set_env("CUSTODY_TRANSFER", "true");
+            dtrd.restart().await?;
+        }
+        dtrd1.connect_to(dtrd2).await?;
+        dtrd1
+            .client
+            .add_route(REMOTE_NODE.to_string(), dtrd2.node_id.clone())
+            .await?;
+        dtrd1
+            .client
+            .submit_bundle(
+                &format!("{REMOTE_NODE}/testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+
+        // dtrd2 can not forward the bundle yet, but took custody of it
+        let statistics = dtrd1.client.get_statistics().await?;
+        assert_eq!(statistics.custody_accepted, 1);
+        assert_eq!(statistics.bundles_in_custody, 0);
+        assert!(dtrd1.stored_files().await?.is_empty());
+        assert!(!dtrd2.stored_files().await?.is_empty());
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn custody_is_retransmitted_until_accepted() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(3, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        let dtrd3 = dtrds.remove(0);
+        dtrd1.stop().await?;
+        dtrd1.set_env("CUSTODY_TRANSFER", "true");
+        dtrd1.set_env("CUSTODY_RETRANSMISSION_TIMEOUT", "1");
+        dtrd1.restart().await?;
+        dtrd1.connect_to(dtrd2).await?;
+        dtrd1
+            .client
+            .add_route(dtrd3.node_id.clone(), dtrd2.node_id.clone())
+            .await?;
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd3.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+            )
+            .await?;
+        sleep(Duration::from_millis(1500)).await;
+
+        // dtrd2 does not take custody, so dtrd1 keeps sending the bundle
+        let statistics = dtrd1.client.get_statistics().await?;
+        assert!(statistics.custody_retransmissions >= 1);
+        assert!(!dtrd1.stored_files().await?.is_empty());
+        assert!(dtrd2.client.get_statistics().await?.duplicate_bundles >= 1);
+
+        // The destination takes custody once the bundle gets there
+        dtrd3
+            .client
+            .add_route(dtrd1.node_id.clone(), dtrd2.node_id.clone())
+            .await?;
+        dtrd2.connect_to(dtrd3).await?;
+        let data = dtrd3
+            .client
+            .receive_bundle(&dtrd3.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(&String::from_utf8(data)?, DUMMY_DATA);
+        sleep(Duration::from_millis(500)).await;
+
+        let statistics = dtrd1.client.get_statistics().await?;
+        assert_eq!(statistics.custody_accepted, 1);
+        assert_eq!(statistics.bundles_in_custody, 0);
+        assert!(dtrd1.stored_files().await?.is_empty());
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn applications_acknowledge_bundles() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1.connect_to(dtrd2).await?;
+        let endpoint = dtrd2.with_node_id("testendpoint");
+        let bundle_id = dtrd1
+            .client
+            .submit_bundle_with_acknowledgement(
+                &endpoint,
+                60,
+                DUMMY_DATA.as_bytes(),
+                false,
+                Priority::Normal,
+            )
+            .await?;
+        dtrd1
+            .client
+            .submit_bundle(&endpoint, 60, DUMMY_DATA.as_bytes(), false)
+            .await?;
+
+        let mut stream = dtrd2.client.listen_received_bundles(&endpoint).await?;
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let bundle = stream.next().await.ok_or("stream closed")??;
+            assert_eq!(&String::from_utf8(bundle.payload)?, DUMMY_DATA);
+            received.push((bundle.acknowledgement_requested, bundle.bundle_id.unwrap()));
+        }
+        let (requested, unrequested): (Vec<_>, Vec<_>) =
+            received.into_iter().partition(|(requested, _)| *requested);
+        assert_eq!(requested[0].1, bundle_id);
+
+        // Only bundles asking for it can be acknowledged, and only once
+        assert!(
+            dtrd2
+                .client
+                .acknowledge_bundle(&endpoint, unrequested[0].1.clone())
+                .await
+                .is_err()
+        );
+        dtrd2
+            .client
+            .acknowledge_bundle(&endpoint, bundle_id.clone())
+            .await?;
+        assert!(
+            dtrd2
+                .client
+                .acknowledge_bundle(&endpoint, bundle_id.clone())
+                .await
+                .is_err()
+        );
+
+        let acknowledgement = dtrd1.client.await_acknowledgement(bundle_id).await?;
+        assert_eq!(acknowledgement.acknowledged_by, endpoint);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn status_reports_build_bundle_history() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1.connect_to(dtrd2).await?;
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd2.with_node_id("testendpoint"),
+                60,
+                DUMMY_DATA.as_bytes(),
+                true,
+            )
+            .await?;
+        dtrd2
+            .client
+            .receive_bundle(&dtrd2.with_node_id("testendpoint"))
+            .await?;
+        sleep(Duration::from_millis(500)).await;
+
+        let history = dtrd1
+            .client
+            .list_bundle_history(dtrd1.node_id.clone())
+            .await?;
+        assert_eq!(history.len(), 1);
+        let events: Vec<(BundleStatus, &str)> = history[0]
+            .events
+            .iter()
+            .map(|event| (event.status(), event.reporting_node.as_str()))
+            .collect();
+        for expected in [
+            (BundleStatus::Forwarded, dtrd1.node_id.as_str()),
+            (BundleStatus::Received, dtrd2.node_id.as_str()),
+            (BundleStatus::Delivered, dtrd2.node_id.as_str()),
+        ] {
+            assert!(
+                events.contains(&expected),
+                "{expected:?} missing in {events:?}"
+            );
+        }
+        assert!(
+            dtrd1
+                .client
+                .list_bundle_history(dtrd2.node_id.clone())
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn full_storage_refuses_new_bundles() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(1, async |mut dtrds| {
+        let dtrd = dtrds.remove(0);
+        dtrd.stop().await?;
+        dtrd.set_env("BUNDLE_STORAGE_MAX_BUNDLES", "1");
+        dtrd.restart().await?;
+
+        // The destination is not reachable, so the first bundle stays stored
+        let destination = "dtn://unreachable/testendpoint";
+        dtrd.client
+            .submit_bundle(destination, 60, DUMMY_DATA.as_bytes(), false)
+            .await?;
+        let res = dtrd
+            .client
+            .submit_bundle(destination, 60, DUMMY_DATA.as_bytes(), false)
+            .await;
+        assert!(matches!(
+            res,
+            Err(dtrd_client::error::Error::GrpcError(status))
+                if status.code() == tonic::Code::ResourceExhausted
+        ));
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn full_storage_evicts_oldest_bundles() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1.stop().await?;
+        // Room for two of the bundles below and the status report about the evicted one
+        dtrd1.set_env("BUNDLE_STORAGE_MAX_BYTES", "2500");
+        dtrd1.set_env("BUNDLE_STORAGE_FULL_POLICY", "evict-oldest");
+        dtrd1.restart().await?;
+
+        let mut recvstream = dtrd1.client.listen_bundles(&dtrd1.node_id).await?;
+        for payload in ["a", "b", "c"] {
+            dtrd1
+                .client
+                .submit_bundle(
+                    &dtrd2.with_node_id("testendpoint"),
+                    60,
+                    payload.repeat(1000).as_bytes(),
+                    false,
+                )
+                .await?;
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        let data = recvstream.next().await.unwrap()?;
+        if let Ok(AdministrativeRecord::BundleStatusReport(bsr)) =
+            AdministrativeRecord::try_from(data)
+        {
+            assert_eq!(bsr.reason, BundleStatusReason::DepletedStorage);
+            assert!(bsr.status_information.deleted_bundle.is_asserted);
+        } else {
+            unreachable!();
+        }
+
+        dtrd1.connect_to(dtrd2).await?;
+        let mut received: Vec<String> = dtrd2
+            .client
+            .listen_bundles(&dtrd2.with_node_id("testendpoint"))
+            .await?
+            .take(2)
+            .map(|data| String::from_utf8(data.unwrap()).unwrap())
+            .collect()
+            .await;
+        received.sort();
+        assert_eq!(received, ["b".repeat(1000), "c".repeat(1000)]);
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn full_storage_refuses_transfers() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd2.stop().await?;
+        dtrd2.set_env("BUNDLE_STORAGE_MAX_BYTES", "500");
+        dtrd2.restart().await?;
+
+        dtrd1
+            .client
+            .submit_bundle(
+                &dtrd2.with_node_id("testendpoint"),
+                60,
+                "a".repeat(1000).as_bytes(),
+                false,
+            )
+            .await?;
+        dtrd1.connect_to(dtrd2).await?;
+        sleep(Duration::from_millis(500)).await;
+
+        // The bundle stays with us until the peer has space for it
+        assert_eq!(dtrd1.stored_files().await?.len(), 2);
+        assert!(dtrd2.stored_files().await?.is_empty());
+        dtrd1.allow_message("Forwarding bundle to endpoint");
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn delivers_bundles_fragmented() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+        dtrd1.connect_to(dtrd2).await?;
+
+        // We now need to generate a bundle larger than the tcpcl max transfer
+        // size. What is in there is something we can ignore.
+        let target_size = tcpcl::v4::messages::sess_init::MAX_TRANSFER_MRU;
+        let mut data = Vec::with_capacity(target_size);
+        while data.len() < target_size {
+            data.extend_from_slice(DUMMY_DATA.as_bytes());
+        }
+
+        dtrd1
+            .client
+            .submit_bundle(&dtrd2.with_node_id("testendpoint"), 60, &data, true)
+            .await?;
+        let received_data = dtrd2
+            .client
+            .receive_bundle(&dtrd2.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(data, received_data);
+
+        // Ensure that we actually got more than one status report about a bundle being received
+        // from the remote side. That way we can be sure that fragmentation happened.
         let mut receive_count = 0;
         let mut total_len = 0_u64;
         let mut recvstream = dtrd1.client.listen_bundles(&dtrd1.node_id).await?;
@@ -452,3 +1560,92 @@ async fn delivers_bundles_fragmented() -> Result<(), Box<dyn std::error::Error>>
     })
     .await
 }
+
+/// Forwards a single tcp connection to `target_port` and cuts it after `cut_after` bytes
+/// have been sent to the target.
+async fn interrupting_proxy(target_port: u16, cut_after: usize) -> Res<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        let (client, _) = listener.accept().await.unwrap();
+        let server = TcpStream::connect(("127.0.0.1", target_port))
+            .await
+            .unwrap();
+        let (mut client_read, mut client_write) = client.into_split();
+        let (mut server_read, mut server_write) = server.into_split();
+        let to_client =
+            tokio::spawn(async move { tokio::io::copy(&mut server_read, &mut client_write).await });
+        let mut forwarded = 0;
+        let mut buf = vec![0; 4096];
+        while forwarded < cut_after {
+            let len = client_read.read(&mut buf).await.unwrap();
+            let len = len.min(cut_after - forwarded);
+            server_write.write_all(&buf[..len]).await.unwrap();
+            forwarded += len;
+        }
+        // let the acks of what has been forwarded reach the client before the cut
+        sleep(Duration::from_millis(500)).await;
+        to_client.abort();
+    });
+    Ok(port)
+}
+
+#[tokio::test]
+async fn interrupted_transfer_is_fragmented_reactively() -> Result<(), Box<dyn std::error::Error>> {
+    with_dtrds(2, async |mut dtrds| {
+        let dtrd1 = dtrds.remove(0);
+        let dtrd2 = dtrds.remove(0);
+
+        // Segments are at most 100 KiB, so the peer acknowledges the first one before the cut
+        let mut data = Vec::with_capacity(300 * 1024);
+        while data.len() < 300 * 1024 {
+            data.extend_from_slice(DUMMY_DATA.as_bytes());
+        }
+        let proxy_port = interrupting_proxy(dtrd2.tcpcl_port, 150 * 1024).await?;
+        dtrd1
+            .connect_to_url(dtrd2, format!("tcpcl://127.0.0.1:{proxy_port}"))
+            .await?;
+
+        dtrd1
+            .client
+            .submit_bundle(&dtrd2.with_node_id("testendpoint"), 60, &data, true)
+            .await?;
+        sleep(Duration::from_secs(1)).await;
+        dtrd1.connect_to_tcp(dtrd2).await?;
+
+        let received_data = dtrd2
+            .client
+            .receive_bundle(&dtrd2.with_node_id("testendpoint"))
+            .await?;
+        assert_eq!(data, received_data);
+
+        // Both the received start and the resent rest were received as fragments. We also wait
+        // for the delivery report, so no report is still in flight when stopping.
+        let mut offsets = Vec::new();
+        let mut delivered = false;
+        let mut recvstream = dtrd1.client.listen_bundles(&dtrd1.node_id).await?;
+        while let Some(data) = recvstream.next().await {
+            if let Ok(AdministrativeRecord::BundleStatusReport(bsr)) =
+                AdministrativeRecord::try_from(data.unwrap())
+            {
+                if bsr.status_information.received_bundle.is_asserted {
+                    offsets.push(bsr.fragment_offset.unwrap());
+                }
+                delivered |= bsr.status_information.delivered_bundle.is_asserted;
+            }
+            if offsets.len() > 1 && delivered {
+                break;
+            }
+        }
+        offsets.sort_unstable();
+        assert_eq!(offsets[0], 0);
+        assert!(offsets[1] > 0);
+
+        // The status reports of the other fragments may still be delivered to the recvstream
+        dtrd1.allow_message("disconnected while sending bundles");
+        // dtrd2 sees the cut in the middle of a segment
+        dtrd2.allow_message("bytes remaining on stream");
+        Ok(())
+    })
+    .await
+}
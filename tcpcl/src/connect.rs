@@ -0,0 +1,153 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::VecDeque, io, net::SocketAddr, time::Duration};
+
+use futures_util::{StreamExt, stream::FuturesUnordered};
+use log::debug;
+use tokio::{
+    net::{TcpStream, lookup_host},
+    time::{Instant, sleep_until, timeout},
+};
+use url::Url;
+
+use crate::errors::ErrorType;
+
+const DEFAULT_PORT: u16 = 4556;
+// "Connection Attempt Delay" as recommended in RFC 8305 section 5
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+pub(crate) async fn resolve(url: &Url) -> Result<Vec<SocketAddr>, ErrorType> {
+    let host = url.host_str().ok_or_else(|| {
+        ErrorType::DnsError(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("url {url} does not contain a host"),
+        ))
+    })?;
+    let port = url.port().unwrap_or(DEFAULT_PORT);
+    let addrs: Vec<SocketAddr> = lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(ErrorType::DnsError)?
+        .collect();
+    if addrs.is_empty() {
+        return Err(ErrorType::DnsError(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{host} did not resolve to any address"),
+        )));
+    }
+    Ok(addrs)
+}
+
+/// Orders the addresses so that address families alternate, starting with the family of
+/// the first address (RFC 8305 section 4).
+fn interleave_address_families(addrs: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return VecDeque::new();
+    };
+    let first_is_ipv6 = first.is_ipv6();
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addrs
+        .into_iter()
+        .partition(|a| a.is_ipv6() == first_is_ipv6);
+    let mut out = VecDeque::with_capacity(preferred.len() + other.len());
+    loop {
+        match (preferred.pop_front(), other.pop_front()) {
+            (None, None) => break,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+    out
+}
+
+async fn connect_addr(addr: SocketAddr, connect_timeout: Duration) -> io::Result<TcpStream> {
+    match timeout(connect_timeout, TcpStream::connect(addr)).await {
+        Ok(res) => res,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("connection attempt timed out after {connect_timeout:?}"),
+        )),
+    }
+}
+
+/// Connects to the first reachable address. A new connection attempt is started every
+/// `CONNECTION_ATTEMPT_DELAY` or as soon as the previous attempt failed, while older attempts
+/// keep running in parallel.
+pub(crate) async fn connect_happy_eyeballs(
+    addrs: Vec<SocketAddr>,
+    connect_timeout: Duration,
+) -> Result<TcpStream, ErrorType> {
+    let mut addrs = interleave_address_families(addrs);
+    let mut attempts = FuturesUnordered::new();
+    let mut errors = Vec::new();
+    let mut next_attempt = Instant::now();
+
+    loop {
+        tokio::select! {
+            () = sleep_until(next_attempt), if !addrs.is_empty() => {
+                let addr = addrs.pop_front().unwrap();
+                debug!("Trying to connect to {addr}");
+                attempts.push(async move { (addr, connect_addr(addr, connect_timeout).await) });
+                next_attempt = Instant::now() + CONNECTION_ATTEMPT_DELAY;
+            }
+            Some((addr, res)) = attempts.next(), if !attempts.is_empty() => {
+                match res {
+                    Ok(stream) => return Ok(stream),
+                    Err(e) => {
+                        debug!("Connecting to {addr} failed: {e}");
+                        errors.push((addr, e));
+                        next_attempt = Instant::now();
+                    }
+                }
+            }
+            else => break,
+        }
+    }
+    Err(ErrorType::ConnectError(errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_falls_through_to_next_address() {
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let open = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let open_addr = open.local_addr().unwrap();
+
+        let stream = connect_happy_eyeballs(vec![closed_addr, open_addr], Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open_addr);
+    }
+
+    #[test]
+    fn interleaves_address_families() {
+        let v4: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let v4b: SocketAddr = "127.0.0.2:1".parse().unwrap();
+        let v6: SocketAddr = "[::1]:1".parse().unwrap();
+        assert_eq!(
+            interleave_address_families(vec![v4, v4b, v6]),
+            [v4, v6, v4b]
+        );
+    }
+}
@@ -17,9 +17,13 @@
 
 use url::Url;
 
+use crate::extensions::SessionExtensions;
+
 #[derive(Debug, Clone)]
 pub struct ConnectionInfo {
     pub peer_endpoint: Option<String>,
     pub peer_url: Url,
     pub max_bundle_size: Option<u64>,
+    /// Only set for version 4 sessions.
+    pub session_extensions: SessionExtensions,
 }
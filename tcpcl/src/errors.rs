@@ -15,6 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::net::SocketAddr;
+
 use openssl::error::ErrorStack;
 
 use crate::v4::messages::{self, MessageType};
@@ -33,7 +35,9 @@ pub enum ErrorType {
     IOError(std::io::Error),
     SSLError(openssl::ssl::Error),
     TCPCLError(Errors),
-    DnsError,
+    DnsError(std::io::Error),
+    /// Connecting failed for all resolved addresses. Contains the error of each attempt.
+    ConnectError(Vec<(SocketAddr, std::io::Error)>),
 }
 
 impl From<std::io::Error> for ErrorType {
@@ -74,5 +78,16 @@ impl From<messages::Errors> for Errors {
 
 #[derive(Debug)]
 pub enum TransferSendErrors {
-    BundleTooLarge { max_size: u64 },
+    BundleTooLarge {
+        max_size: u64,
+    },
+    /// The peer refused to accept the transfer.
+    Refused,
+    /// The session closed before the transfer was sent completely.
+    SessionClosed,
+    /// The session closed after the peer acknowledged the first `acknowledged` bytes.
+    /// The peer might keep them, so only the rest needs to be sent again.
+    Interrupted {
+        acknowledged: u64,
+    },
 }
@@ -0,0 +1,104 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+/// A typed session extension item (RFC 9174 section 4.8) that is exchanged in `SESS_INIT`.
+pub trait Extension: Sized {
+    const EXTENSION_TYPE: u16;
+
+    fn encode(&self) -> Vec<u8>;
+
+    /// Returns None if the value is not valid for this extension.
+    fn decode(value: &[u8]) -> Option<Self>;
+}
+
+/// The session extensions both we and the peer sent, with the values of the peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionExtensions {
+    values: BTreeMap<u16, Vec<u8>>,
+}
+
+impl SessionExtensions {
+    pub(crate) fn insert(&mut self, extension_type: u16, value: Vec<u8>) {
+        self.values.insert(extension_type, value);
+    }
+
+    /// Returns the value of the peer. None if the extension was not negotiated or the peer
+    /// sent an invalid value.
+    pub fn get<T: Extension>(&self) -> Option<T> {
+        self.values
+            .get(&T::EXTENSION_TYPE)
+            .and_then(|value| T::decode(value))
+    }
+
+    pub fn contains<T: Extension>(&self) -> bool {
+        self.values.contains_key(&T::EXTENSION_TYPE)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// The endpoint id schemes (by their bundle protocol scheme code) a node can handle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EidSchemes(pub Vec<u64>);
+
+impl Extension for EidSchemes {
+    // from the range for private and experimental use
+    const EXTENSION_TYPE: u16 = 0x8001;
+
+    fn encode(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .flat_map(|scheme| scheme.to_be_bytes())
+            .collect()
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        if !value.len().is_multiple_of(8) {
+            return None;
+        }
+        Some(EidSchemes(
+            value
+                .chunks_exact(8)
+                .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+}
+
+/// A hint of how much data the link to the node can carry. Can be used for routing decisions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkCapacity {
+    pub bits_per_second: u64,
+}
+
+impl Extension for LinkCapacity {
+    const EXTENSION_TYPE: u16 = 0x8002;
+
+    fn encode(&self) -> Vec<u8> {
+        self.bits_per_second.to_be_bytes().to_vec()
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        Some(LinkCapacity {
+            bits_per_second: u64::from_be_bytes(value.try_into().ok()?),
+        })
+    }
+}
@@ -20,10 +20,16 @@ use openssl::{
     x509::X509,
 };
 
+mod connect;
 pub mod connection_info;
 pub mod errors;
+pub mod extensions;
+pub mod rate_limit;
+pub mod receive_limit;
 pub mod session;
+pub mod stats;
 pub mod transfer;
+pub mod v3;
 pub mod v4;
 
 #[derive(Clone)]
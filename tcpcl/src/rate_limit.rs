@@ -0,0 +1,158 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+/// A single segment should not block the link for longer than this, so keepalives and acks
+/// can be sent in between.
+const MAX_SEGMENT_TIME: Duration = Duration::from_millis(100);
+
+/// Limits the outbound transfer segments of a session using a token bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub bytes_per_second: u64,
+    /// How many bytes may be sent at once after the session was idle.
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// The largest segment we send with this limit.
+    pub fn segment_size(&self) -> usize {
+        let per_segment_time =
+            u128::from(self.bytes_per_second) * MAX_SEGMENT_TIME.as_nanos() / NANOS_PER_SECOND;
+        let size = per_segment_time.min(u128::from(self.burst)).max(1);
+        usize::try_from(size).unwrap_or(usize::MAX)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: u64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if self.limit.bytes_per_second == 0 {
+            self.last_refill = now;
+            return;
+        }
+        let elapsed = now.duration_since(self.last_refill).as_nanos();
+        let added = elapsed * u128::from(self.limit.bytes_per_second) / NANOS_PER_SECOND;
+        let tokens = u128::from(self.tokens) + added;
+        if tokens >= u128::from(self.limit.burst) {
+            self.tokens = self.limit.burst;
+            self.last_refill = now;
+        } else {
+            self.tokens = u64::try_from(tokens).unwrap();
+            // only advance by the time that was converted to tokens to not lose fractions
+            let used = added * NANOS_PER_SECOND / u128::from(self.limit.bytes_per_second);
+            self.last_refill += Duration::from_nanos(u64::try_from(used).unwrap_or(u64::MAX));
+        }
+    }
+
+    /// Returns how long to wait until `bytes` can be sent.
+    fn delay(&mut self, bytes: u64) -> Option<Duration> {
+        self.refill();
+        let bytes = bytes.min(self.limit.burst);
+        if self.tokens >= bytes {
+            return None;
+        }
+        if self.limit.bytes_per_second == 0 {
+            return Some(Duration::MAX);
+        }
+        let missing = u128::from(bytes - self.tokens);
+        let nanos = missing * NANOS_PER_SECOND / u128::from(self.limit.bytes_per_second) + 1;
+        Some(Duration::from_nanos(
+            u64::try_from(nanos).unwrap_or(u64::MAX),
+        ))
+    }
+
+    fn consume(&mut self, bytes: u64) {
+        self.tokens = self.tokens.saturating_sub(bytes);
+    }
+}
+
+/// Applies the current rate limit of a session to its outbound segments.
+#[derive(Debug)]
+pub(crate) struct Shaper {
+    receiver: watch::Receiver<Option<RateLimit>>,
+    bucket: Option<TokenBucket>,
+    bytes_sent: u64,
+}
+
+impl Shaper {
+    pub(crate) fn new(mut receiver: watch::Receiver<Option<RateLimit>>) -> Self {
+        let bucket = receiver.borrow_and_update().map(TokenBucket::new);
+        Shaper {
+            receiver,
+            bucket,
+            bytes_sent: 0,
+        }
+    }
+
+    /// The largest segment we may send, if limited.
+    pub(crate) fn segment_size(&self) -> Option<usize> {
+        self.bucket.as_ref().map(|b| b.limit.segment_size())
+    }
+
+    /// Returns how long to wait until a segment of `bytes` can be sent.
+    pub(crate) fn delay(&mut self, bytes: usize) -> Option<Duration> {
+        self.bucket.as_mut().and_then(|b| b.delay(bytes as u64))
+    }
+
+    /// Takes the bytes sent since the last call from the bucket. `bytes_sent` is the total
+    /// of the session, so segments are accounted even if sending them was interrupted.
+    pub(crate) fn record_sent(&mut self, bytes_sent: u64) {
+        if let Some(bucket) = &mut self.bucket {
+            bucket.consume(bytes_sent.saturating_sub(self.bytes_sent));
+        }
+        self.bytes_sent = bytes_sent;
+    }
+
+    /// Waits until the rate limit is changed and applies the new one.
+    /// Never returns if the limit can not change anymore.
+    pub(crate) async fn changed(&mut self) {
+        if self.receiver.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+        let limit = *self.receiver.borrow_and_update();
+        self.bucket = limit.map(|limit| match self.bucket.take() {
+            // keep the tokens we have to not allow an additional burst
+            Some(mut bucket) => {
+                bucket.refill();
+                bucket.limit = limit;
+                bucket.tokens = bucket.tokens.min(limit.burst);
+                bucket
+            }
+            None => TokenBucket::new(limit),
+        });
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use tokio::sync::watch;
+
+/// How many bytes may be received, shared between all sessions that receive into the same
+/// storage. Received data is reserved until the transfer is stored, so transfers received at
+/// the same time can not take more than is available together.
+#[derive(Debug, Clone)]
+pub struct ReceiveLimit {
+    available: watch::Receiver<u64>,
+    reserved: Arc<AtomicU64>,
+}
+
+impl ReceiveLimit {
+    /// `available` is how many bytes can be stored, not counting the reserved ones.
+    pub fn new(available: watch::Receiver<u64>) -> Self {
+        ReceiveLimit {
+            available,
+            reserved: Arc::default(),
+        }
+    }
+
+    /// The bytes reserved by transfers that are received or not yet stored.
+    pub fn reserved(&self) -> u64 {
+        self.reserved.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn available(&self) -> u64 {
+        *self.available.borrow()
+    }
+
+    /// Adds `bytes` to the reservation, unless there is not enough space left.
+    pub(crate) fn reserve(&self, reservation: &mut Reservation, bytes: u64) -> bool {
+        let available = self.available();
+        let reserved = self
+            .reserved
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| {
+                r.checked_add(bytes).filter(|total| *total <= available)
+            });
+        if reserved.is_err() {
+            return false;
+        }
+        if reservation.reserved.is_none() {
+            reservation.reserved = Some(self.reserved.clone());
+        }
+        reservation.bytes += bytes;
+        true
+    }
+}
+
+impl From<watch::Receiver<u64>> for ReceiveLimit {
+    fn from(available: watch::Receiver<u64>) -> Self {
+        ReceiveLimit::new(available)
+    }
+}
+
+/// Space reserved in a [`ReceiveLimit`] for a transfer. It is released when this is dropped, so
+/// it should be kept until the transfer is stored or discarded.
+#[derive(Debug, Default)]
+pub struct Reservation {
+    reserved: Option<Arc<AtomicU64>>,
+    bytes: u64,
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl PartialEq for Reservation {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for Reservation {}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(reserved) = &self.reserved {
+            reserved.fetch_sub(self.bytes, Ordering::SeqCst);
+        }
+    }
+}
@@ -16,12 +16,14 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
+    collections::HashMap,
+    mem,
     pin::Pin,
     sync::Arc,
     time::{Duration, Instant},
 };
 
-use futures_util::StreamExt;
+use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
 use openssl::{
     error::ErrorStack,
@@ -29,10 +31,10 @@ use openssl::{
     x509::{X509, store::X509StoreBuilder},
 };
 use tokio::{
-    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
-    net::TcpStream,
-    sync::{mpsc, oneshot},
-    time::Interval,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+    sync::{broadcast, mpsc, oneshot, watch},
+    time::{Interval, Sleep},
 };
 use tokio_openssl::SslStream;
 use tokio_util::codec::{FramedRead, FramedWrite};
@@ -43,12 +45,23 @@ use x509_parser::{
 };
 
 use crate::{
-    TLSSettings,
+    TLSSettings, connect,
     connection_info::ConnectionInfo,
     errors::{ErrorType, Errors, TransferSendErrors},
-    transfer::Transfer,
+    extensions::{Extension, SessionExtensions},
+    rate_limit::{RateLimit, Shaper},
+    receive_limit::ReceiveLimit,
+    stats::{SessionEvent, SessionState, SessionStats},
+    transfer::{ReceivingTransfer, Transfer, TransferSink},
+    v3,
     v4::{
-        messages::{self, Codec, Messages, sess_term::ReasonCode, xfer_segment},
+        messages::{
+            self, Codec, Messages,
+            sess_init::SessionExtension,
+            sess_term::ReasonCode,
+            xfer_refuse::{self, XferRefuse},
+            xfer_segment,
+        },
         statemachine::StateMachine,
     },
 };
@@ -56,8 +69,24 @@ use crate::{
 pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Send {}
 impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Send {}
 
+mod v3_session;
+
 type CustomFramedReader = FramedRead<tokio::io::ReadHalf<Pin<Box<dyn AsyncReadWrite>>>, Codec>;
 type CustomFramedWriter = FramedWrite<tokio::io::WriteHalf<Pin<Box<dyn AsyncReadWrite>>>, Codec>;
+type V3FramedReader =
+    FramedRead<tokio::io::ReadHalf<Pin<Box<dyn AsyncReadWrite>>>, v3::messages::Codec>;
+type V3FramedWriter =
+    FramedWrite<tokio::io::WriteHalf<Pin<Box<dyn AsyncReadWrite>>>, v3::messages::Codec>;
+
+/// The TCPCL version used for a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtocolVersion {
+    /// RFC 7242
+    V3,
+    /// RFC 9174
+    #[default]
+    V4,
+}
 
 struct Stream {
     read: CustomFramedReader,
@@ -66,8 +95,8 @@ struct Stream {
 }
 
 impl Stream {
-    fn from_tcp_stream(ts: TcpStream) -> Self {
-        let boxed_stream: Pin<Box<dyn AsyncReadWrite>> = Box::pin(ts);
+    fn new<S: AsyncReadWrite + 'static>(stream: S) -> Self {
+        let boxed_stream: Pin<Box<dyn AsyncReadWrite>> = Box::pin(stream);
         let (read, write) = tokio::io::split(boxed_stream);
         Stream {
             read: FramedRead::new(read, Codec::default()),
@@ -95,6 +124,29 @@ impl Stream {
         })
     }
 
+    /// Returns the version byte of the peer's contact header without consuming it.
+    /// Returns `None` if the peer closed the connection before sending it.
+    async fn peek_version(&mut self) -> Result<Option<u8>, std::io::Error> {
+        const VERSION_POSITION: usize = 4;
+        while self.read.read_buffer().len() <= VERSION_POSITION {
+            let mut buf = [0; VERSION_POSITION + 1];
+            let missing = VERSION_POSITION + 1 - self.read.read_buffer().len();
+            let len = self.read.get_mut().read(&mut buf[..missing]).await?;
+            if len == 0 {
+                return Ok(None);
+            }
+            self.read.read_buffer_mut().extend_from_slice(&buf[..len]);
+        }
+        Ok(Some(self.read.read_buffer()[VERSION_POSITION]))
+    }
+
+    fn into_v3(self) -> (V3FramedReader, V3FramedWriter) {
+        (
+            self.read.map_decoder(|_| v3::messages::Codec::default()),
+            self.write.map_encoder(|_| v3::messages::Codec::default()),
+        )
+    }
+
     fn get_peer_certificate(&mut self) -> Option<&X509> {
         self.peer_cert.as_ref()
     }
@@ -114,13 +166,20 @@ type TransferRequest = (
 );
 
 const STARTUP_IDLE_INTERVAL: u16 = 60;
+const EVENT_CHANNEL_SIZE: usize = 16;
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The url scheme of sessions over unix sockets.
+pub const UNIX_SCHEME: &str = "tcpcl+unix";
 
 pub struct TCPCLSession {
     is_server: bool,
+    version: ProtocolVersion,
     stream: Option<Stream>,
     ssl_context: Option<SslContext>,
     statemachine: StateMachine,
-    receiving_transfer: Option<Transfer>,
+    transfer_sink: TransferSink,
+    receiving_transfer: Option<ReceivingTransfer>,
+    receive_limit: Option<ReceiveLimit>,
     connection_info: ConnectionInfo,
     established_channel: (
         Option<oneshot::Sender<ConnectionInfo>>,
@@ -132,12 +191,19 @@ pub struct TCPCLSession {
         mpsc::Sender<TransferRequest>,
         Option<mpsc::Receiver<TransferRequest>>,
     ),
+    stats_channel: watch::Sender<SessionStats>,
+    rate_limit_channel: watch::Sender<Option<RateLimit>>,
+    event_channel: broadcast::Sender<SessionEvent>,
     last_received_keepalive: Instant,
+    established_at: Option<Instant>,
 
     initialized_keepalive: bool,
     initialized_tls: bool,
 
-    transfer_result_sender: Option<oneshot::Sender<Result<(), TransferSendErrors>>>,
+    // one for each outbound transfer in flight, by transfer id
+    transfer_result_senders: HashMap<u64, oneshot::Sender<Result<(), TransferSendErrors>>>,
+    shutdown_deadline: Option<Duration>,
+    unsent_transfers: Vec<TransferRequest>,
 }
 
 impl TCPCLSession {
@@ -155,44 +221,48 @@ impl TCPCLSession {
         Ok(ssl_context_builder.build().into_context())
     }
 
+    /// Creates a passive session for a peer that connected to us.
     pub fn new(
         stream: TcpStream,
         node_id: String,
         tls_settings: Option<TLSSettings>,
     ) -> Result<Self, std::io::Error> {
-        let can_tls = tls_settings.is_some();
-        let established_channel = oneshot::channel();
-        let close_channel = oneshot::channel();
-        let receive_channel = mpsc::channel(10);
-        let send_channel = mpsc::channel(10);
-
-        let ssl_context = match tls_settings {
-            Some(s) => Some(TCPCLSession::make_ssl_context(s)?),
-            None => None,
-        };
         let peer_url = Url::parse(&format!("tcpcl://{}", stream.peer_addr().unwrap()))
             .expect("This is our url");
+        TCPCLSession::new_with_stream(stream, peer_url, node_id, tls_settings)
+    }
 
-        Ok(TCPCLSession {
-            is_server: true,
-            stream: Some(Stream::from_tcp_stream(stream)),
-            ssl_context,
-            statemachine: StateMachine::new_passive(node_id, can_tls),
-            receiving_transfer: None,
-            connection_info: ConnectionInfo {
-                peer_endpoint: None,
-                peer_url,
-                max_bundle_size: None,
-            },
-            established_channel: (Some(established_channel.0), Some(established_channel.1)),
-            close_channel: (Some(close_channel.0), Some(close_channel.1)),
-            receive_channel: (receive_channel.0, Some(receive_channel.1)),
-            send_channel: (send_channel.0, Some(send_channel.1)),
-            last_received_keepalive: Instant::now(),
-            initialized_keepalive: false,
-            initialized_tls: false,
-            transfer_result_sender: None,
-        })
+    /// Creates a passive session on any kind of stream, e.g. a unix socket or
+    /// `tokio::io::duplex`. `peer_url` is only used to identify the peer.
+    pub fn new_with_stream<S: AsyncReadWrite + 'static>(
+        stream: S,
+        peer_url: Url,
+        node_id: String,
+        tls_settings: Option<TLSSettings>,
+    ) -> Result<Self, std::io::Error> {
+        Ok(TCPCLSession::with_stream(
+            Stream::new(stream),
+            true,
+            peer_url,
+            node_id,
+            tls_settings,
+        )?)
+    }
+
+    /// Creates an active session on any kind of stream that is already connected to the peer.
+    pub fn connect_with_stream<S: AsyncReadWrite + 'static>(
+        stream: S,
+        peer_url: Url,
+        node_id: String,
+        tls_settings: Option<TLSSettings>,
+    ) -> Result<Self, std::io::Error> {
+        Ok(TCPCLSession::with_stream(
+            Stream::new(stream),
+            false,
+            peer_url,
+            node_id,
+            tls_settings,
+        )?)
     }
 
     pub async fn connect(
@@ -200,14 +270,49 @@ impl TCPCLSession {
         node_id: String,
         tls_settings: Option<TLSSettings>,
     ) -> Result<Self, ErrorType> {
-        let addr = url
-            .socket_addrs(|| Some(4556))
-            .map_err(|_| ErrorType::DnsError)
-            .and_then(|mut r| r.pop().ok_or(ErrorType::DnsError))?;
-        let stream = TcpStream::connect(addr)
+        TCPCLSession::connect_with_timeout(url, node_id, tls_settings, DEFAULT_CONNECT_TIMEOUT)
             .await
-            .map_err::<ErrorType, _>(std::convert::Into::into)?;
-        debug!("Connected to peer at {url}");
+    }
+
+    /// Connects to the peer at `url`. All addresses the host resolves to are tried as described
+    /// in RFC 8305. `connect_timeout` limits each individual connection attempt.
+    /// For `tcpcl+unix` urls the path of the url is the unix socket to connect to.
+    pub async fn connect_with_timeout(
+        url: Url,
+        node_id: String,
+        tls_settings: Option<TLSSettings>,
+        connect_timeout: Duration,
+    ) -> Result<Self, ErrorType> {
+        if url.scheme() == UNIX_SCHEME {
+            let stream = tokio::time::timeout(connect_timeout, UnixStream::connect(url.path()))
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+            debug!("Connected to peer at {url}");
+            return Ok(TCPCLSession::connect_with_stream(
+                stream,
+                url,
+                node_id,
+                tls_settings,
+            )?);
+        }
+        let addrs = connect::resolve(&url).await?;
+        let stream = connect::connect_happy_eyeballs(addrs, connect_timeout).await?;
+        debug!("Connected to peer at {url} using {}", stream.peer_addr()?);
+        Ok(TCPCLSession::connect_with_stream(
+            stream,
+            url,
+            node_id,
+            tls_settings,
+        )?)
+    }
+
+    fn with_stream(
+        stream: Stream,
+        is_server: bool,
+        peer_url: Url,
+        node_id: String,
+        tls_settings: Option<TLSSettings>,
+    ) -> Result<Self, ErrorStack> {
         let can_tls = tls_settings.is_some();
         let established_channel = oneshot::channel();
         let close_channel = oneshot::channel();
@@ -218,29 +323,118 @@ impl TCPCLSession {
             Some(s) => Some(TCPCLSession::make_ssl_context(s)?),
             None => None,
         };
+        let statemachine = if is_server {
+            StateMachine::new_passive(node_id, can_tls)
+        } else {
+            StateMachine::new_active(node_id, can_tls)
+        };
 
         Ok(TCPCLSession {
-            is_server: false,
-            stream: Some(Stream::from_tcp_stream(stream)),
+            is_server,
+            version: ProtocolVersion::default(),
+            stream: Some(stream),
             ssl_context,
-            statemachine: StateMachine::new_active(node_id, can_tls),
+            statemachine,
+            transfer_sink: TransferSink::default(),
             receiving_transfer: None,
+            receive_limit: None,
             connection_info: ConnectionInfo {
                 peer_endpoint: None,
-                peer_url: url,
+                peer_url,
                 max_bundle_size: None,
+                session_extensions: SessionExtensions::default(),
             },
             established_channel: (Some(established_channel.0), Some(established_channel.1)),
             close_channel: (Some(close_channel.0), Some(close_channel.1)),
             receive_channel: (receive_channel.0, Some(receive_channel.1)),
             send_channel: (send_channel.0, Some(send_channel.1)),
+            stats_channel: watch::Sender::new(SessionStats::default()),
+            rate_limit_channel: watch::Sender::new(None),
+            event_channel: broadcast::Sender::new(EVENT_CHANNEL_SIZE),
             last_received_keepalive: Instant::now(),
+            established_at: None,
             initialized_keepalive: false,
             initialized_tls: false,
-            transfer_result_sender: None,
+            transfer_result_senders: HashMap::new(),
+            shutdown_deadline: None,
+            unsent_transfers: Vec::new(),
         })
     }
 
+    /// Sets the TCPCL version to use when connecting to a peer. Passive sessions detect the
+    /// version from the contact header of the peer instead.
+    /// Must be called before `manage_connection`.
+    pub fn set_protocol_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Sends the extension in our `SESS_INIT`. The value the peer sent for the same extension
+    /// is available in the `ConnectionInfo` once the session is established. The peer must
+    /// close the session if it does not know a critical extension. Likewise we only accept
+    /// critical extensions of the peer that we have added ourselves.
+    pub fn add_session_extension<T: Extension>(&mut self, extension: &T, critical: bool) {
+        self.statemachine
+            .add_session_extension(SessionExtension::new(
+                T::EXTENSION_TYPE,
+                extension.encode(),
+                critical,
+            ));
+    }
+
+    /// Allows new transfers to be started while up to `window` bytes of the previous ones
+    /// are not yet acknowledged. This also limits the unacknowledged bytes of a single
+    /// transfer. Without a window every transfer is sent completely, but the next one only
+    /// starts once it is fully acknowledged. Only applies to version 4 sessions.
+    pub fn set_send_window(&mut self, window: u64) {
+        self.statemachine.set_send_window(window);
+    }
+
+    /// Limits how fast transfer segments are sent. Segments are made small enough to take
+    /// at most 100ms of the rate, so keepalives and acks are not stuck behind them.
+    /// `None` removes the limit. Can also be changed while the session is running.
+    pub fn set_rate_limit(&self, rate_limit: Option<RateLimit>) {
+        self.rate_limit_channel.send_replace(rate_limit);
+    }
+
+    /// Returns a channel to change the rate limit while `manage_connection` is running.
+    pub fn get_rate_limit_channel(&self) -> watch::Sender<Option<RateLimit>> {
+        self.rate_limit_channel.clone()
+    }
+
+    /// Makes the close channel terminate the session gracefully. The current transfers in
+    /// both directions are finished before the connection is closed, but at most until
+    /// `deadline` has passed. Without this the session is terminated as soon as no transfer
+    /// is in progress.
+    pub fn set_graceful_shutdown(&mut self, deadline: Duration) {
+        self.shutdown_deadline = Some(deadline);
+    }
+
+    /// Returns the transfers that have been queued on the send channel but have not been
+    /// (completely) sent when the session closed. Only useful after `manage_connection`.
+    /// Transfers the peer acknowledged partially are not included, they are completed with
+    /// `TransferSendErrors::Interrupted` instead.
+    pub fn take_unsent_transfers(&mut self) -> Vec<TransferRequest> {
+        mem::take(&mut self.unsent_transfers)
+    }
+
+    /// Sets where received transfers are written to. Defaults to `TransferSink::Memory`.
+    pub fn set_transfer_sink(&mut self, sink: TransferSink) {
+        self.transfer_sink = sink;
+    }
+
+    /// Sets the largest transfer we announce to accept from the peer.
+    /// Must be called before `manage_connection`.
+    pub fn set_transfer_mru(&mut self, transfer_mru: u64) {
+        self.statemachine.set_transfer_mru(transfer_mru);
+    }
+
+    /// Refuses incoming transfers once they do not fit into `limit` anymore, e.g. because
+    /// there is no space left to store them. Received data is reserved in the limit until the
+    /// transfer passed up is dropped.
+    pub fn set_receive_limit(&mut self, limit: impl Into<ReceiveLimit>) {
+        self.receive_limit = Some(limit.into());
+    }
+
     pub fn get_established_channel(&mut self) -> oneshot::Receiver<ConnectionInfo> {
         self.established_channel
             .1
@@ -270,6 +464,72 @@ impl TCPCLSession {
         self.connection_info.clone()
     }
 
+    /// Returns a receiver that always contains the latest statistics of the session.
+    pub fn get_stats_channel(&self) -> watch::Receiver<SessionStats> {
+        self.stats_channel.subscribe()
+    }
+
+    /// Returns a receiver for state changes of the session.
+    /// Only events happening after this has been called are received.
+    pub fn get_event_channel(&self) -> broadcast::Receiver<SessionEvent> {
+        self.event_channel.subscribe()
+    }
+
+    fn send_event(&self, event: SessionEvent) {
+        // this only fails if nobody is listening
+        let _ = self.event_channel.send(event);
+    }
+
+    fn report_established(&mut self, peer_endpoint: String, max_bundle_size: u64) {
+        self.connection_info.peer_endpoint = Some(peer_endpoint);
+        self.connection_info.max_bundle_size = Some(max_bundle_size);
+        self.established_at = Some(Instant::now());
+        self.send_event(SessionEvent::Established(self.connection_info.clone()));
+
+        if let Some(sender) = self.established_channel.0.take()
+            && let Err(e) = sender.send(self.connection_info.clone())
+        {
+            warn!("Error sending connection info: {e:?}");
+        }
+    }
+
+    fn publish_stats(
+        &self,
+        mut stats: SessionStats,
+        termination: Option<(Option<ReasonCode>, bool)>,
+    ) {
+        stats.established_at = self.established_at;
+        let (previous_state, closed_at) = {
+            let current = self.stats_channel.borrow();
+            (current.state, current.closed_at)
+        };
+        stats.closed_at = match stats.state {
+            SessionState::Closed => closed_at.or_else(|| Some(Instant::now())),
+            _ => None,
+        };
+        let ending = |state| matches!(state, SessionState::Terminating | SessionState::Closed);
+        // a session can also go straight from established to closed, e.g. if the peer vanishes
+        if ending(stats.state)
+            && !ending(previous_state)
+            && (stats.state == SessionState::Terminating
+                || previous_state == SessionState::Established)
+        {
+            let (reason, initiated_by_peer) = termination.unwrap_or((None, false));
+            self.send_event(SessionEvent::Terminating {
+                reason,
+                initiated_by_peer,
+            });
+        }
+        self.stats_channel.send_if_modified(|current| {
+            // we only want to wake up receivers if something actually changed
+            if *current == stats {
+                return false;
+            }
+            *current = stats;
+            true
+        });
+    }
+
     pub async fn manage_connection(&mut self) -> Result<(), ErrorType> {
         self.last_received_keepalive = Instant::now();
 
@@ -279,7 +539,60 @@ impl TCPCLSession {
             .take()
             .expect("can not manage the connection > 1 time");
 
-        let out = self.drive_statemachine(&mut send_channel_receiver).await;
+        if self.is_server {
+            let version = self.stream.as_mut().unwrap().peek_version().await?;
+            if version == Some(v3::messages::contact_header::VERSION) {
+                self.version = ProtocolVersion::V3;
+            }
+        }
+        debug!("Using TCPCL version {:?}", self.version);
+
+        let out = match self.version {
+            ProtocolVersion::V3 => self.drive_v3(&mut send_channel_receiver).await,
+            ProtocolVersion::V4 => self.drive_statemachine(&mut send_channel_receiver).await,
+        };
+        let unfinished = self.statemachine.take_unfinished_transfers();
+        for (id, data, acknowledged) in unfinished {
+            let Some(result_sender) = self.transfer_result_senders.remove(&id) else {
+                continue;
+            };
+            if acknowledged == 0 {
+                self.unsent_transfers.push((data, result_sender));
+            } else if let Err(e) = result_sender.send(Err(TransferSendErrors::Interrupted {
+                acknowledged: acknowledged as u64,
+            })) {
+                error!("Error sending error to bundle sender {e:?}");
+            }
+        }
+        if let Some(transfer) = self.receiving_transfer.take() {
+            debug!(
+                "Transfer {} was interrupted after {} bytes",
+                transfer.id, transfer.length
+            );
+            match transfer.interrupt().await {
+                Ok(transfer) => {
+                    if let Err(e) = self.receive_channel.0.send(transfer).await {
+                        warn!("Error sending transfer to receive channel: {e:?}");
+                    }
+                }
+                Err(e) => warn!("Error finishing interrupted transfer: {e:?}"),
+            }
+        }
+        send_channel_receiver.close();
+        while let Ok(transfer) = send_channel_receiver.try_recv() {
+            self.unsent_transfers.push(transfer);
+        }
+        if !self.unsent_transfers.is_empty() {
+            debug!(
+                "{} transfers have not been sent before the session closed",
+                self.unsent_transfers.len()
+            );
+        }
+
+        let mut stats = self.stats_channel.borrow().clone();
+        stats.state = SessionState::Closed;
+        self.publish_stats(stats, None);
+        self.send_event(SessionEvent::Closed);
         if let Err(error) = out {
             warn!("Connection completed with error {error:?}");
             if self.stream.is_some() {
@@ -307,6 +620,8 @@ impl TCPCLSession {
             .take()
             .expect("can not manage the connection > 1 time");
 
+        let mut shutdown_timer: Option<Pin<Box<Sleep>>> = None;
+        let mut shaper = Shaper::new(self.rate_limit_channel.subscribe());
         let mut keepalive_timer: Option<Interval> = Some(tokio::time::interval(
             Duration::from_secs(STARTUP_IDLE_INTERVAL.into()),
         ));
@@ -323,23 +638,18 @@ impl TCPCLSession {
                             .upgrade_tls(ssl, self.is_server)
                             .await?,
                     );
+                    self.send_event(SessionEvent::TlsUpgraded);
                 }
                 self.initialized_tls = true;
             }
 
-            if self.statemachine.is_established() && self.established_channel.0.is_some() {
-                self.connection_info.peer_endpoint = Some(self.statemachine.get_peer_node_id());
-                self.connection_info.max_bundle_size = Some(self.statemachine.get_peer_mru());
-
-                if let Err(e) = self
-                    .established_channel
-                    .0
-                    .take()
-                    .unwrap()
-                    .send(self.connection_info.clone())
-                {
-                    warn!("Error sending connection info: {e:?}");
-                }
+            if self.statemachine.is_established() && self.established_at.is_none() {
+                self.connection_info.session_extensions =
+                    self.statemachine.get_negotiated_extensions();
+                self.report_established(
+                    self.statemachine.get_peer_node_id(),
+                    self.statemachine.get_peer_mru(),
+                );
             }
 
             if !self.initialized_keepalive && self.statemachine.is_established() {
@@ -359,13 +669,19 @@ impl TCPCLSession {
                 self.initialized_keepalive = true;
             }
 
-            if self.statemachine.could_send_transfer()
-                && self.transfer_result_sender.is_some()
-                && let Err(e) = self.transfer_result_sender.take().unwrap().send(Ok(()))
-            {
-                error!("Error sending error to bundle sender {e:?}");
+            for (id, result) in self.statemachine.take_completed_transfers() {
+                if let Some(result_sender) = self.transfer_result_senders.remove(&id)
+                    && let Err(e) = result_sender.send(result)
+                {
+                    error!("Error sending error to bundle sender {e:?}");
+                }
             }
 
+            self.publish_stats(
+                self.statemachine.get_stats(),
+                self.statemachine.get_termination(),
+            );
+
             if self.statemachine.should_close() {
                 debug!("We are done. Closing connection");
                 self.stream.take().unwrap().shutdown().await?;
@@ -375,7 +691,24 @@ impl TCPCLSession {
             let stream = self.stream.as_mut().unwrap();
             let (read_stream, write_stream) = stream.as_split();
 
+            shaper.record_sent(self.statemachine.get_stats().bytes_sent);
+            self.statemachine
+                .set_max_segment_size(shaper.segment_size());
+            let shaping_delay = self
+                .statemachine
+                .next_segment_size()
+                .and_then(|size| shaper.delay(size));
+
             let stream_interest = self.statemachine.get_interests();
+            let can_send = stream_interest.is_writable() && shaping_delay.is_none();
+            // a canceled send_message might have left its segment in the buffer
+            let needs_flush = !write_stream.write_buffer().is_empty();
+            let can_close = !self.statemachine.connection_closing()
+                && if self.shutdown_deadline.is_some() {
+                    self.statemachine.session_state() == SessionState::Established
+                } else {
+                    self.statemachine.is_established() && !self.statemachine.has_unacked_transfers()
+                };
 
             tokio::select! {
                 read_out = async { read_stream.next().await }, if stream_interest.is_readable() => {
@@ -392,18 +725,31 @@ impl TCPCLSession {
                         }
                     }
                 }
-                res = async { self.statemachine.send_message(write_stream).await }, if stream_interest.is_writable() => {
+                res = async {
+                    if can_send {
+                        self.statemachine.send_message(write_stream).await
+                    } else {
+                        write_stream.flush().await
+                    }
+                }, if can_send || needs_flush => {
                     res?;
                 }
+                () = async { tokio::time::sleep(shaping_delay.unwrap()).await }, if shaping_delay.is_some() => {}
+                () = shaper.changed() => {
+                    debug!("Rate limit changed to {:?}", self.rate_limit_channel.borrow());
+                }
                 transfer = async { send_channel_receiver.as_mut().unwrap().recv().await }, if send_channel_receiver.is_some() && self.statemachine.could_send_transfer() => {
                     match transfer {
                         Some((bundle_data, result_sender)) => {
-                            if let Err(transfer_err) = self.statemachine.send_transfer(bundle_data) {
-                                if let Err(e) = result_sender.send(Err(transfer_err)) {
-                                    error!("Error sending error to bundle sender {e:?}");
+                            match self.statemachine.send_transfer(bundle_data) {
+                                Ok(id) => {
+                                    self.transfer_result_senders.insert(id, result_sender);
+                                }
+                                Err(transfer_err) => {
+                                    if let Err(e) = result_sender.send(Err(transfer_err)) {
+                                        error!("Error sending error to bundle sender {e:?}");
+                                    }
                                 }
-                            } else {
-                                self.transfer_result_sender = Some(result_sender);
                             }
                         },
                         None => {send_channel_receiver = None;}
@@ -417,8 +763,20 @@ impl TCPCLSession {
                         self.statemachine.send_keepalive();
                     }
                 }
-                _ = (&mut close_channel), if !self.statemachine.connection_closing() && self.statemachine.is_established() => {
-                    self.statemachine.close_connection(Some(ReasonCode::ResourceExhaustion));
+                _ = (&mut close_channel), if can_close => {
+                    match self.shutdown_deadline {
+                        Some(deadline) => {
+                            debug!("Closing the session gracefully within {deadline:?}");
+                            self.statemachine.close_connection_gracefully(ReasonCode::ResourceExhaustion);
+                            shutdown_timer = Some(Box::pin(tokio::time::sleep(deadline)));
+                        }
+                        None => self.statemachine.close_connection(Some(ReasonCode::ResourceExhaustion)),
+                    }
+                }
+                () = async { shutdown_timer.as_mut().unwrap().await }, if shutdown_timer.is_some() => {
+                    info!("Session did not terminate gracefully in time. Closing the connection");
+                    self.stream.take().unwrap().shutdown().await?;
+                    return Ok(());
                 }
             }
         }
@@ -458,24 +816,23 @@ impl TCPCLSession {
             }
             Ok(Messages::XferSegment(x)) => {
                 debug!("Got xfer segment {x:?}");
-                if self.receiving_transfer.is_some()
+                if self.statemachine.is_refused(x.transfer_id) {
+                    debug!("Ignoring segment of refused transfer {}", x.transfer_id);
+                    return Ok(());
+                }
+                if let Some(t) = &self.receiving_transfer
                     && x.flags.contains(xfer_segment::MessageFlags::START)
                 {
                     warn!(
                         "Remote startet transfer with id {} while {} is still being received",
-                        x.transfer_id,
-                        self.receiving_transfer.as_ref().unwrap().id
+                        x.transfer_id, t.id
                     );
                     //TODO close connection
                 }
 
-                let ack = if let Some(t) = &mut self.receiving_transfer {
-                    if t.id == x.transfer_id {
-                        Arc::get_mut(&mut t.data)
-                            .expect("we are the only ones currently receiving")
-                            .extend_from_slice(&x.data);
-                        x.to_xfer_ack(t.data.len() as u64)
-                    } else {
+                let t = match &mut self.receiving_transfer {
+                    Some(t) if t.id == x.transfer_id => t,
+                    Some(t) => {
                         warn!(
                             "Remote sent transfer with id {} while {} is still being received",
                             x.transfer_id, t.id
@@ -483,40 +840,54 @@ impl TCPCLSession {
                         //TODO close connection
                         return Ok(());
                     }
-                } else {
-                    if !x.flags.contains(xfer_segment::MessageFlags::START) {
-                        warn!(
-                            "Remote did not sent a start flag for a new transfer. Accepting it anyway"
-                        );
+                    None => {
+                        if !x.flags.contains(xfer_segment::MessageFlags::START) {
+                            warn!(
+                                "Remote did not sent a start flag for a new transfer. Accepting it anyway"
+                            );
+                        }
+                        self.receiving_transfer.insert(
+                            ReceivingTransfer::new(x.transfer_id, &mut self.transfer_sink)
+                                .await
+                                .inspect_err(|e| {
+                                    warn!("Error preparing to receive transfer: {e:?}");
+                                })?,
+                        )
                     }
-                    let a = x.to_xfer_ack(x.data.len() as u64);
-                    self.receiving_transfer = Some(Transfer {
-                        id: x.transfer_id,
-                        data: Arc::new(x.data),
-                    });
-                    a
                 };
+                if let Some(limit) = &self.receive_limit
+                    && !limit.reserve(&mut t.reservation, x.data.len() as u64)
+                {
+                    info!(
+                        "Refusing transfer {} as it exceeds our receive limit of {} bytes",
+                        x.transfer_id,
+                        limit.available()
+                    );
+                    self.receiving_transfer = None;
+                    self.statemachine.send_refuse(XferRefuse::new(
+                        xfer_refuse::ReasonCode::NoResources,
+                        x.transfer_id,
+                    ));
+                    return Ok(());
+                }
+                t.write(&x.data).await.inspect_err(|e| {
+                    warn!("Error writing received transfer data: {e:?}");
+                })?;
+                let ack = x.to_xfer_ack(t.length);
 
                 if x.flags.contains(xfer_segment::MessageFlags::END) {
                     debug!("Fully received transfer {}, passing it up", x.transfer_id);
-                    if let Err(e) = self
-                        .receive_channel
-                        .0
-                        .send(self.receiving_transfer.take().unwrap())
-                        .await
-                    {
+                    let transfer = self.receiving_transfer.take().unwrap().finish().await?;
+                    if let Err(e) = self.receive_channel.0.send(transfer).await {
                         warn!("Error sending transfer to receive channel: {e:?}");
                         //TODO close connection
                     }
                 }
                 self.statemachine.send_ack(ack);
             }
-            Ok(Messages::XferAck(_)) => {
+            Ok(Messages::XferAck(_) | Messages::XferRefuse(_)) => {
                 //statemachine cares about it
             }
-            Ok(Messages::XferRefuse(x)) => {
-                warn!("Got xfer refuse, no idea what to do now: {x:?}");
-            }
             Ok(Messages::MsgReject(m)) => {
                 info!("Got msg reject: {m:?}. Will close the connection now");
                 return Err(Errors::RemoteRejected.into());
@@ -530,14 +901,14 @@ impl TCPCLSession {
                 return Err(e.unwrap_err().into());
             }
             Err(Errors::MessageError(messages::Errors::UnkownCriticalSessionExtension(ext))) => {
-                warn!("Remote send critical session extension {ext} that we dont know",);
+                warn!("Remote send critical session extension {ext} that we dont know");
                 return Err(Errors::MessageError(
                     messages::Errors::UnkownCriticalSessionExtension(ext),
                 )
                 .into());
             }
             Err(Errors::MessageError(messages::Errors::UnkownCriticalTransferExtension(ext))) => {
-                warn!("Remote send critical transfer extension {ext} that we dont know",);
+                warn!("Remote send critical transfer extension {ext} that we dont know");
                 return Err(Errors::MessageError(
                     messages::Errors::UnkownCriticalTransferExtension(ext),
                 )
@@ -547,7 +918,7 @@ impl TCPCLSession {
                 warn!("Received a unkown message type");
             }
             Err(Errors::MessageTypeInappropriate(mt)) => {
-                warn!("Remote send message type currently not applicable: {mt:?}",);
+                warn!("Remote send message type currently not applicable: {mt:?}");
             }
             Err(Errors::RemoteRejected) => {
                 warn!("In the remote rejected state");
@@ -555,7 +926,9 @@ impl TCPCLSession {
             Err(Errors::TLSNameMissmatch(_)) => {
                 warn!("In the tls name missmatch state");
             }
-            e @ Err(Errors::MessageError(messages::Errors::InvalidACKValue)) => {
+            e @ Err(Errors::MessageError(
+                messages::Errors::InvalidACKValue | messages::Errors::InvalidSdnv,
+            )) => {
                 return Err(e.unwrap_err().into());
             }
             e @ Err(Errors::DoesNotSpeakTCPCL) => {
@@ -0,0 +1,454 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    cmp::min,
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{self, Interval, Sleep},
+};
+
+use super::{STARTUP_IDLE_INTERVAL, TCPCLSession, TransferRequest, V3FramedReader, V3FramedWriter};
+use crate::{
+    errors::{ErrorType, Errors, TransferSendErrors},
+    rate_limit::Shaper,
+    stats::{SessionState, SessionStats},
+    transfer::ReceivingTransfer,
+    v3::messages::{
+        Messages,
+        ack_segment::AckSegment,
+        contact_header::{ContactHeader, ContactHeaderFlags},
+        data_segment::{self, DataSegment},
+        keepalive::Keepalive,
+        length::Length,
+        refuse_bundle::{self, RefuseBundle},
+        shutdown::{self, Shutdown},
+    },
+    v4::messages::{self, sess_init::MAX_SEGMENT_MRU, sess_term::ReasonCode},
+};
+
+struct OutgoingBundle {
+    data: Arc<Vec<u8>>,
+    pos: usize,
+    acked: usize,
+    length_sent: bool,
+    // end position and send time of each segment not yet acked. Used to measure the rtt
+    segments_in_flight: VecDeque<(usize, Instant)>,
+    result_sender: oneshot::Sender<Result<(), TransferSendErrors>>,
+}
+
+impl OutgoingBundle {
+    fn complete(self, result: Result<(), TransferSendErrors>) {
+        if let Err(e) = self.result_sender.send(result) {
+            warn!("Error sending transfer result to bundle sender {e:?}");
+        }
+    }
+}
+
+/// State of an established version 3 session.
+struct V3State {
+    stats: SessionStats,
+    termination: Option<(Option<ReasonCode>, bool)>,
+    acks: bool,
+    refusals: bool,
+    send_length: bool,
+    keepalive_interval: Option<Duration>,
+    sending: Option<OutgoingBundle>,
+    next_receive_id: u64,
+    // the bundle the peer is sending but we refused, its remaining segments are ignored
+    refused_bundle: Option<u64>,
+}
+
+impl TCPCLSession {
+    pub(super) async fn drive_v3(
+        &mut self,
+        scr: &mut mpsc::Receiver<TransferRequest>,
+    ) -> Result<(), ErrorType> {
+        let (mut read, mut write) = self.stream.take().unwrap().into_v3();
+
+        let my_contact_header = ContactHeader::new(
+            self.statemachine.get_my_node_id().to_string(),
+            STARTUP_IDLE_INTERVAL,
+        );
+        write
+            .send(Messages::ContactHeader(my_contact_header.clone()))
+            .await?;
+        let peer_contact_header = match read.next().await {
+            None => {
+                debug!("Connection closed by peer");
+                return Ok(());
+            }
+            Some(Ok(Messages::ContactHeader(ch))) => ch,
+            Some(Ok(m)) => unreachable!("The codec always decodes a contact header first: {m:?}"),
+            Some(Err(messages::Errors::InvalidHeader)) => {
+                return Err(Errors::DoesNotSpeakTCPCL.into());
+            }
+            Some(Err(e)) => return Err(e.into()),
+        };
+        debug!("Got contact header: {peer_contact_header:?}");
+
+        let both_set = |flag| {
+            my_contact_header.flags.contains(flag) && peer_contact_header.flags.contains(flag)
+        };
+        let keepalive_interval = min(
+            my_contact_header.keepalive_interval,
+            peer_contact_header.keepalive_interval,
+        );
+        let mut state = V3State {
+            stats: SessionStats {
+                state: SessionState::Established,
+                ..Default::default()
+            },
+            termination: None,
+            acks: both_set(ContactHeaderFlags::REQUEST_ACK),
+            // refusals are only allowed if acks are in use as well
+            refusals: both_set(ContactHeaderFlags::REQUEST_ACK | ContactHeaderFlags::ALLOW_REFUSAL),
+            send_length: peer_contact_header
+                .flags
+                .contains(ContactHeaderFlags::REQUEST_LENGTH),
+            keepalive_interval: match keepalive_interval {
+                0 => None,
+                x => Some(Duration::from_secs(x.into())),
+            },
+            sending: None,
+            next_receive_id: 0,
+            refused_bundle: None,
+        };
+        // TCPCLv3 does not negotiate a maximum bundle size
+        self.report_established(peer_contact_header.eid, u64::MAX);
+
+        let result = self.run_v3_session(&mut state, read, write, scr).await;
+        if let Some(bundle) = state.sending.take() {
+            if bundle.acked == 0 {
+                self.unsent_transfers
+                    .push((bundle.data, bundle.result_sender));
+            } else {
+                let acknowledged = bundle.acked as u64;
+                bundle.complete(Err(TransferSendErrors::Interrupted { acknowledged }));
+            }
+        }
+        if !state.acks {
+            // the peer can not know what we received
+            self.receiving_transfer = None;
+        }
+        result
+    }
+
+    async fn run_v3_session(
+        &mut self,
+        state: &mut V3State,
+        mut read: V3FramedReader,
+        mut write: V3FramedWriter,
+        scr: &mut mpsc::Receiver<TransferRequest>,
+    ) -> Result<(), ErrorType> {
+        let mut send_channel_receiver = Some(scr);
+        let mut close_channel = self
+            .close_channel
+            .1
+            .take()
+            .expect("can not manage the connection > 1 time");
+        let mut keepalive_timer: Option<Interval> = state.keepalive_interval.map(|interval| {
+            let mut timer = time::interval_at(time::Instant::now() + interval, interval);
+            timer.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+            timer
+        });
+        let mut shutdown_timer: Option<Pin<Box<Sleep>>> = None;
+        let mut shaper = Shaper::new(self.rate_limit_channel.subscribe());
+
+        loop {
+            if !state.acks
+                && state
+                    .sending
+                    .as_ref()
+                    .is_some_and(|b| b.pos == b.data.len())
+            {
+                // without acks a bundle is done once it is sent
+                state.stats.transfers_sent += 1;
+                state.sending.take().unwrap().complete(Ok(()));
+            }
+            self.publish_stats(state.stats.clone(), state.termination);
+
+            if shutdown_timer.is_some()
+                && state.sending.is_none()
+                && self.receiving_transfer.is_none()
+            {
+                debug!("All transfers finished. Closing the session");
+                self.send_v3_shutdown(state, &mut write, None).await?;
+                return Ok(());
+            }
+
+            shaper.record_sent(state.stats.bytes_sent);
+            let segment_size = min(MAX_SEGMENT_MRU, shaper.segment_size().unwrap_or(usize::MAX));
+            let shaping_delay = state
+                .sending
+                .as_ref()
+                .filter(|b| b.pos < b.data.len())
+                .and_then(|b| shaper.delay(min(segment_size, b.data.len() - b.pos)));
+            let can_send = state.termination.is_none()
+                && state.sending.as_ref().is_some_and(|b| b.pos < b.data.len())
+                && shaping_delay.is_none();
+            // a canceled send might have left its segment in the buffer
+            let needs_flush = !write.write_buffer().is_empty();
+
+            tokio::select! {
+                read_out = read.next() => {
+                    match read_out {
+                        None => {
+                            debug!("Connection closed by peer");
+                            return Ok(());
+                        }
+                        Some(message) => {
+                            if !self.read_v3_message(message?, state, &mut write).await? {
+                                write.close().await?;
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+                res = async {
+                    if !can_send {
+                        return write.flush().await;
+                    }
+                    let bundle = state.sending.as_mut().unwrap();
+                    if state.send_length && !bundle.length_sent {
+                        write.feed(Messages::Length(Length::new(bundle.data.len() as u64))).await?;
+                        bundle.length_sent = true;
+                    }
+                    let end_pos = min(bundle.pos + segment_size, bundle.data.len());
+                    let mut flags = data_segment::MessageFlags::empty();
+                    if bundle.pos == 0 {
+                        flags |= data_segment::MessageFlags::START;
+                    }
+                    if end_pos == bundle.data.len() {
+                        flags |= data_segment::MessageFlags::END;
+                    }
+                    let data = bundle.data[bundle.pos..end_pos].to_vec();
+                    // see the v4 statemachine on why we only update the position after feeding
+                    write.feed(Messages::DataSegment(DataSegment::new(flags, data))).await?;
+                    state.stats.segments_sent += 1;
+                    state.stats.bytes_sent += (end_pos - bundle.pos) as u64;
+                    bundle.segments_in_flight.push_back((end_pos, Instant::now()));
+                    bundle.pos = end_pos;
+                    write.flush().await
+                }, if can_send || needs_flush => {
+                    res?;
+                }
+                () = async { time::sleep(shaping_delay.unwrap()).await }, if shaping_delay.is_some() => {}
+                () = shaper.changed() => {
+                    debug!("Rate limit changed to {:?}", self.rate_limit_channel.borrow());
+                }
+                transfer = async { send_channel_receiver.as_mut().unwrap().recv().await }, if send_channel_receiver.is_some() && state.sending.is_none() && state.termination.is_none() && shutdown_timer.is_none() => {
+                    match transfer {
+                        Some((data, result_sender)) => {
+                            state.sending = Some(OutgoingBundle {
+                                data,
+                                pos: 0,
+                                acked: 0,
+                                length_sent: false,
+                                segments_in_flight: VecDeque::new(),
+                                result_sender,
+                            });
+                        }
+                        None => {send_channel_receiver = None;}
+                    }
+                }
+                _ = async { keepalive_timer.as_mut().unwrap().tick().await }, if keepalive_timer.is_some() => {
+                    let interval = state.keepalive_interval.unwrap();
+                    if self.last_received_keepalive.elapsed() > interval * 2 {
+                        info!("Peer did not send anything for {:?}. Shutting down", interval * 2);
+                        self.send_v3_shutdown(state, &mut write, Some(shutdown::ReasonCode::IdleTimeout)).await?;
+                        return Ok(());
+                    }
+                    write.send(Messages::Keepalive(Keepalive::new())).await?;
+                    state.stats.keepalives_sent += 1;
+                }
+                _ = (&mut close_channel), if state.termination.is_none() && shutdown_timer.is_none() => {
+                    let Some(deadline) = self.shutdown_deadline else {
+                        self.send_v3_shutdown(state, &mut write, None).await?;
+                        return Ok(());
+                    };
+                    // TCPCLv3 has no way to announce the shutdown in advance, so we just stop
+                    // taking new bundles and send the shutdown once the current ones are done
+                    debug!("Closing the session gracefully within {deadline:?}");
+                    shutdown_timer = Some(Box::pin(time::sleep(deadline)));
+                }
+                () = async { shutdown_timer.as_mut().unwrap().await }, if shutdown_timer.is_some() => {
+                    info!("Session did not terminate gracefully in time. Closing the connection");
+                    self.send_v3_shutdown(state, &mut write, None).await?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn send_v3_shutdown(
+        &mut self,
+        state: &mut V3State,
+        write: &mut V3FramedWriter,
+        reason: Option<shutdown::ReasonCode>,
+    ) -> Result<(), ErrorType> {
+        state.termination = Some((reason.map(Into::into), false));
+        state.stats.state = SessionState::Terminating;
+        self.publish_stats(state.stats.clone(), state.termination);
+        write
+            .send(Messages::Shutdown(Shutdown::new(reason)))
+            .await?;
+        write.close().await?;
+        Ok(())
+    }
+
+    /// Handles a single message of the peer. Returns false if the session should be closed.
+    async fn read_v3_message(
+        &mut self,
+        message: Messages,
+        state: &mut V3State,
+        write: &mut V3FramedWriter,
+    ) -> Result<bool, ErrorType> {
+        // in TCPCLv3 every message counts as a keepalive
+        self.last_received_keepalive = Instant::now();
+        match message {
+            Messages::ContactHeader(_) => {
+                unreachable!("The codec only decodes a single contact header")
+            }
+            Messages::DataSegment(segment) => {
+                debug!("Got data segment {segment:?}");
+                state.stats.segments_received += 1;
+                state.stats.bytes_received += segment.data.len() as u64;
+                if let Some(id) = state.refused_bundle {
+                    if !segment.flags.contains(data_segment::MessageFlags::START) {
+                        debug!("Ignoring segment of refused bundle {id}");
+                        return Ok(true);
+                    }
+                    state.refused_bundle = None;
+                }
+                if segment.flags.contains(data_segment::MessageFlags::START)
+                    && self.receiving_transfer.take().is_some()
+                {
+                    warn!(
+                        "Remote started a new bundle while the previous one was not finished. Dropping the previous one"
+                    );
+                }
+                if self.receiving_transfer.is_none() {
+                    if !segment.flags.contains(data_segment::MessageFlags::START) {
+                        warn!(
+                            "Remote did not sent a start flag for a new bundle. Accepting it anyway"
+                        );
+                    }
+                    state.next_receive_id += 1;
+                    self.receiving_transfer = Some(
+                        ReceivingTransfer::new(state.next_receive_id, &mut self.transfer_sink)
+                            .await
+                            .inspect_err(|e| {
+                                warn!("Error preparing to receive transfer: {e:?}");
+                            })?,
+                    );
+                }
+                let t = self.receiving_transfer.as_mut().unwrap();
+                if state.refusals
+                    && let Some(limit) = &self.receive_limit
+                    && !limit.reserve(&mut t.reservation, segment.data.len() as u64)
+                {
+                    info!(
+                        "Refusing bundle {} as it exceeds our receive limit of {} bytes",
+                        t.id,
+                        limit.available()
+                    );
+                    if !segment.flags.contains(data_segment::MessageFlags::END) {
+                        state.refused_bundle = Some(t.id);
+                    }
+                    self.receiving_transfer = None;
+                    write
+                        .send(Messages::RefuseBundle(RefuseBundle::new(
+                            refuse_bundle::ReasonCode::NoResources,
+                        )))
+                        .await?;
+                    return Ok(true);
+                }
+                t.write(&segment.data).await.inspect_err(|e| {
+                    warn!("Error writing received transfer data: {e:?}");
+                })?;
+                let ack = AckSegment::new(t.length);
+
+                if segment.flags.contains(data_segment::MessageFlags::END) {
+                    debug!("Fully received bundle {}, passing it up", t.id);
+                    let transfer = self.receiving_transfer.take().unwrap().finish().await?;
+                    state.stats.transfers_received += 1;
+                    if let Err(e) = self.receive_channel.0.send(transfer).await {
+                        warn!("Error sending transfer to receive channel: {e:?}");
+                    }
+                }
+                if state.acks {
+                    write.send(Messages::AckSegment(ack)).await?;
+                }
+            }
+            Messages::AckSegment(ack) => {
+                let Some(bundle) = state.sending.as_mut() else {
+                    warn!("Got ack {ack:?} while we are not sending anything");
+                    return Ok(true);
+                };
+                let acked = usize::try_from(ack.acknowleged_length).unwrap_or(usize::MAX);
+                if acked > bundle.pos {
+                    return Err(messages::Errors::InvalidACKValue.into());
+                }
+                while let Some((end_pos, sent_at)) = bundle.segments_in_flight.front()
+                    && *end_pos <= acked
+                {
+                    if *end_pos == acked {
+                        state.stats.add_rtt_sample(sent_at.elapsed());
+                    }
+                    bundle.segments_in_flight.pop_front();
+                }
+                bundle.acked = acked;
+                if acked == bundle.data.len() {
+                    info!("Bundle of {acked} bytes finished (sent and acked)");
+                    state.stats.transfers_sent += 1;
+                    state.sending.take().unwrap().complete(Ok(()));
+                }
+            }
+            Messages::RefuseBundle(refuse) => {
+                info!("Peer refused the current bundle: {:?}", refuse.reason);
+                state.stats.transfers_refused += 1;
+                if let Some(bundle) = state.sending.take() {
+                    bundle.complete(Err(TransferSendErrors::Refused));
+                }
+            }
+            Messages::Keepalive(_) => {
+                debug!("Got keepalive");
+                state.stats.keepalives_received += 1;
+            }
+            Messages::Length(length) => {
+                debug!("Peer announced a bundle of {} bytes", length.bundle_length);
+            }
+            Messages::Shutdown(shutdown) => {
+                debug!("Got shutdown: {shutdown:?}");
+                state.termination = Some((shutdown.reason.map(Into::into), true));
+                state.stats.state = SessionState::Terminating;
+                self.publish_stats(state.stats.clone(), state.termination);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
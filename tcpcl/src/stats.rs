@@ -0,0 +1,81 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+use crate::{connection_info::ConnectionInfo, v4::messages::sess_term::ReasonCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionState {
+    /// Exchanging contact headers and possibly upgrading to TLS.
+    #[default]
+    ContactNegotiation,
+    /// Exchanging `SESS_INIT` messages.
+    SessionNegotiation,
+    Established,
+    Terminating,
+    Closed,
+}
+
+/// Counters of a single session. Byte counters only include the payload of transfer segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub state: SessionState,
+    pub established_at: Option<Instant>,
+    pub closed_at: Option<Instant>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub segments_sent: u64,
+    pub segments_received: u64,
+    pub transfers_sent: u64,
+    pub transfers_received: u64,
+    pub transfers_refused: u64,
+    pub keepalives_sent: u64,
+    pub keepalives_received: u64,
+    /// Smoothed round trip time measured between sending a segment and receiving its `XFER_ACK`.
+    pub rtt: Option<Duration>,
+}
+
+impl SessionStats {
+    /// How long the session has been established, or was until it closed.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.established_at.map(|t| {
+            self.closed_at
+                .unwrap_or_else(Instant::now)
+                .saturating_duration_since(t)
+        })
+    }
+
+    /// Adds a new rtt sample using the smoothing of RFC 6298.
+    pub(crate) fn add_rtt_sample(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            None => sample,
+            Some(rtt) => rtt * 7 / 8 + sample / 8,
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    TlsUpgraded,
+    Established(ConnectionInfo),
+    Terminating {
+        reason: Option<ReasonCode>,
+        initiated_by_peer: bool,
+    },
+    Closed,
+}
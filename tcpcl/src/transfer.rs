@@ -15,19 +15,209 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use log::{debug, warn};
+use tokio::{
+    fs,
+    io::{AsyncWrite, AsyncWriteExt},
+};
+
+use crate::receive_limit::Reservation;
+
+pub type TransferWriter = Pin<Box<dyn AsyncWrite + Send>>;
+pub type TransferWriterFactory = Box<dyn FnMut(u64) -> io::Result<TransferWriter> + Send>;
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Defines where the data of received transfers is written to while they are received.
+#[derive(Default)]
+pub enum TransferSink {
+    /// Accumulate each transfer in memory. Only suitable for small transfer MRUs.
+    #[default]
+    Memory,
+    /// Write each transfer to a new temporary file in the given directory.
+    /// The file is passed upwards as `TransferData::File`.
+    TempFile(PathBuf),
+    /// Write each transfer to a writer created by the factory. The factory is called with the
+    /// transfer id once the first segment of a transfer arrives.
+    Writer(TransferWriterFactory),
+}
+
+/// A fully received transfer stored in a temporary file.
+/// The file is deleted when this is dropped unless it has been persisted before.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TransferFile {
+    path: Option<PathBuf>,
+}
+
+impl TransferFile {
+    pub fn path(&self) -> &Path {
+        self.path.as_ref().expect("only unset while persisting")
+    }
+
+    /// Moves the file to `target`. If both are on the same filesystem this does not copy
+    /// any data.
+    pub async fn persist(mut self, target: &Path) -> io::Result<()> {
+        let path = self.path.take().expect("only unset while persisting");
+        if let Err(e) = fs::rename(&path, target).await {
+            // renaming fails across filesystems, so we fall back to copying the data
+            debug!(
+                "Could not rename {} to {}: {e}. Copying instead",
+                path.to_string_lossy(),
+                target.to_string_lossy()
+            );
+            if let Err(e) = fs::copy(&path, target).await {
+                self.path = Some(path);
+                return Err(e);
+            }
+            fs::remove_file(&path).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TransferFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take()
+            && let Err(e) = std::fs::remove_file(&path)
+        {
+            warn!(
+                "Error removing transfer file {}: {e}",
+                path.to_string_lossy()
+            );
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransferData {
+    Memory(Vec<u8>),
+    File(TransferFile),
+    /// The data has been written to a writer of `TransferSink::Writer`.
+    Writer,
+}
+
+impl TransferData {
+    /// Returns the data of the transfer, reading it from disk if needed.
+    pub async fn into_vec(self) -> io::Result<Vec<u8>> {
+        match self {
+            TransferData::Memory(data) => Ok(data),
+            TransferData::File(file) => fs::read(file.path()).await,
+            TransferData::Writer => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "transfer data has been passed to a custom writer",
+            )),
+        }
+    }
+}
 
 #[derive(PartialEq, Eq)]
 pub struct Transfer {
     pub id: u64,
-    pub data: Arc<Vec<u8>>,
+    pub length: u64,
+    pub data: TransferData,
+    /// The session closed before the transfer was complete. `data` only contains the
+    /// `length` bytes received and acknowledged until then.
+    pub interrupted: bool,
+    /// The space reserved for the transfer in the receive limit of the session. Keep it until
+    /// the transfer is stored.
+    pub reservation: Reservation,
 }
 
 impl Debug for Transfer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Transfer")
             .field("id", &self.id)
-            .field("data (length)", &self.data.len())
-            .finish()
+            .field("length", &self.length)
+            .field("interrupted", &self.interrupted)
+            .finish_non_exhaustive()
+    }
+}
+
+enum ReceiveTarget {
+    Memory(Vec<u8>),
+    File(fs::File, TransferFile),
+    Writer(TransferWriter),
+}
+
+pub(crate) struct ReceivingTransfer {
+    pub id: u64,
+    pub length: u64,
+    pub reservation: Reservation,
+    target: ReceiveTarget,
+}
+
+impl ReceivingTransfer {
+    pub async fn new(id: u64, sink: &mut TransferSink) -> io::Result<Self> {
+        let target = match sink {
+            TransferSink::Memory => ReceiveTarget::Memory(Vec::new()),
+            TransferSink::TempFile(dir) => {
+                let mut path = dir.clone();
+                path.push(format!(
+                    "tcpcl-{}-{}.part",
+                    std::process::id(),
+                    TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .await?;
+                ReceiveTarget::File(file, TransferFile { path: Some(path) })
+            }
+            TransferSink::Writer(factory) => ReceiveTarget::Writer(factory(id)?),
+        };
+        Ok(ReceivingTransfer {
+            id,
+            length: 0,
+            reservation: Reservation::default(),
+            target,
+        })
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.target {
+            ReceiveTarget::Memory(v) => v.extend_from_slice(data),
+            ReceiveTarget::File(f, _) => f.write_all(data).await?,
+            ReceiveTarget::Writer(w) => w.write_all(data).await?,
+        }
+        self.length += data.len() as u64;
+        Ok(())
+    }
+
+    pub async fn finish(self) -> io::Result<Transfer> {
+        let data = match self.target {
+            ReceiveTarget::Memory(v) => TransferData::Memory(v),
+            ReceiveTarget::File(mut f, tf) => {
+                f.flush().await?;
+                f.sync_all().await?;
+                TransferData::File(tf)
+            }
+            ReceiveTarget::Writer(mut w) => {
+                w.shutdown().await?;
+                TransferData::Writer
+            }
+        };
+        Ok(Transfer {
+            id: self.id,
+            length: self.length,
+            data,
+            interrupted: false,
+            reservation: self.reservation,
+        })
+    }
+
+    /// Finishes the transfer with the data received so far, after the session closed.
+    pub async fn interrupt(self) -> io::Result<Transfer> {
+        let mut transfer = self.finish().await?;
+        transfer.interrupted = true;
+        Ok(transfer)
     }
 }
@@ -0,0 +1,45 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use bytes::{Buf, BytesMut};
+
+use super::sdnv;
+use crate::v4::messages::Errors;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AckSegment {
+    /// The amount of bytes of the current bundle received so far.
+    pub acknowleged_length: u64,
+}
+
+impl AckSegment {
+    pub fn new(acknowleged_length: u64) -> Self {
+        AckSegment { acknowleged_length }
+    }
+
+    pub fn decode(src: &mut BytesMut) -> Result<Option<Self>, Errors> {
+        let Some((acknowleged_length, sdnv_length)) = sdnv::decode(src)? else {
+            return Ok(None);
+        };
+        src.advance(sdnv_length);
+        Ok(Some(AckSegment { acknowleged_length }))
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) {
+        sdnv::encode(self.acknowleged_length, dst);
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use bitflags::bitflags;
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::sdnv;
+use crate::v4::messages::Errors;
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct ContactHeaderFlags: u8 {
+        const REQUEST_ACK = 0x01;
+        const REACTIVE_FRAGMENTATION = 0x02;
+        const ALLOW_REFUSAL = 0x04;
+        const REQUEST_LENGTH = 0x08;
+    }
+}
+
+const DTN_MAGIC_BYTES: [u8; 4] = [0x64, 0x74, 0x6E, 0x21];
+pub const VERSION: u8 = 3;
+/// The longest EID we accept from a peer, the same limit as the 16 bit node ID length of v4.
+pub const MAX_EID_LENGTH: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone)]
+pub struct ContactHeader {
+    pub flags: ContactHeaderFlags,
+    pub keepalive_interval: u16,
+    pub eid: String,
+}
+
+impl ContactHeader {
+    pub fn new(eid: String, keepalive_interval: u16) -> Self {
+        ContactHeader {
+            flags: ContactHeaderFlags::REQUEST_ACK | ContactHeaderFlags::ALLOW_REFUSAL,
+            keepalive_interval,
+            eid,
+        }
+    }
+
+    pub fn decode(src: &mut BytesMut) -> Result<Option<Self>, Errors> {
+        if src.remaining() < 8 {
+            return Ok(None);
+        }
+        if src[0..4] != DTN_MAGIC_BYTES || src[4] != VERSION {
+            return Err(Errors::InvalidHeader);
+        }
+        let Some((eid_length, sdnv_length)) = sdnv::decode(&src[8..])? else {
+            return Ok(None);
+        };
+        let eid_length = usize::try_from(eid_length)
+            .ok()
+            .filter(|l| *l <= MAX_EID_LENGTH)
+            .ok_or(Errors::NodeIdInvalid)?;
+        let header_length = (8 + sdnv_length)
+            .checked_add(eid_length)
+            .ok_or(Errors::NodeIdInvalid)?;
+        if src.remaining() < header_length {
+            return Ok(None);
+        }
+
+        src.advance(5);
+        let flags = ContactHeaderFlags::from_bits_truncate(src.get_u8());
+        let keepalive_interval = src.get_u16();
+        src.advance(sdnv_length);
+        let eid = String::from_utf8(src.split_to(eid_length).to_vec())
+            .map_err(|_| Errors::NodeIdInvalid)?;
+
+        Ok(Some(ContactHeader {
+            flags,
+            keepalive_interval,
+            eid,
+        }))
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) {
+        dst.reserve(8 + 10 + self.eid.len());
+        dst.put_slice(&DTN_MAGIC_BYTES);
+        dst.put_u8(VERSION);
+        dst.put_u8(self.flags.bits());
+        dst.put_u16(self.keepalive_interval);
+        sdnv::encode(self.eid.len() as u64, dst);
+        dst.put_slice(self.eid.as_bytes());
+    }
+}
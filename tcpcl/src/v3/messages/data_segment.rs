@@ -0,0 +1,83 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt::Debug;
+
+use bitflags::bitflags;
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::sdnv;
+use crate::v4::messages::{Errors, sess_init::MAX_SEGMENT_MRU};
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct MessageFlags: u8 {
+        const END = 0x01;
+        const START = 0x02;
+    }
+}
+
+pub struct DataSegment {
+    pub flags: MessageFlags,
+    pub data: Vec<u8>,
+}
+
+impl Debug for DataSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataSegment")
+            .field("flags", &self.flags)
+            .field("data (length)", &self.data.len())
+            .finish()
+    }
+}
+
+impl DataSegment {
+    pub fn new(flags: MessageFlags, data: Vec<u8>) -> Self {
+        DataSegment { flags, data }
+    }
+
+    pub fn decode(flags: u8, src: &mut BytesMut) -> Result<Option<Self>, Errors> {
+        let Some((data_length, sdnv_length)) = sdnv::decode(src)? else {
+            return Ok(None);
+        };
+        let data_length = usize::try_from(data_length).map_err(|_| Errors::SegmentTooLong)?;
+        if data_length > MAX_SEGMENT_MRU {
+            return Err(Errors::SegmentTooLong);
+        }
+        if src.remaining() < sdnv_length + data_length {
+            return Ok(None);
+        }
+        src.advance(sdnv_length);
+        let mut data = vec![0; data_length];
+        src.copy_to_slice(&mut data);
+
+        Ok(Some(DataSegment {
+            flags: MessageFlags::from_bits_truncate(flags),
+            data,
+        }))
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.flags.bits()
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) {
+        dst.reserve(10 + self.data.len());
+        sdnv::encode(self.data.len() as u64, dst);
+        dst.put_slice(&self.data);
+    }
+}
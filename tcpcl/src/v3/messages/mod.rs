@@ -0,0 +1,156 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::v4::messages::Errors;
+
+use self::ack_segment::AckSegment;
+use self::contact_header::ContactHeader;
+use self::data_segment::DataSegment;
+use self::keepalive::Keepalive;
+use self::length::Length;
+use self::refuse_bundle::RefuseBundle;
+use self::shutdown::Shutdown;
+
+pub mod ack_segment;
+pub mod contact_header;
+pub mod data_segment;
+pub mod keepalive;
+pub mod length;
+pub mod refuse_bundle;
+pub mod sdnv;
+pub mod shutdown;
+
+#[derive(Debug)]
+pub enum Messages {
+    ContactHeader(ContactHeader),
+    DataSegment(DataSegment),
+    AckSegment(AckSegment),
+    RefuseBundle(RefuseBundle),
+    Keepalive(Keepalive),
+    Shutdown(Shutdown),
+    Length(Length),
+}
+
+/// The message type is stored in the upper 4 bits of the first byte of each message.
+/// The lower 4 bits contain message specific flags.
+#[derive(Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive, Clone, Copy)]
+#[repr(u8)]
+pub enum MessageType {
+    DataSegment = 0x01,
+    AckSegment = 0x02,
+    RefuseBundle = 0x03,
+    Keepalive = 0x04,
+    Shutdown = 0x05,
+    Length = 0x06,
+}
+
+fn header(message_type: MessageType, flags: u8) -> u8 {
+    (u8::from(message_type) << 4) | (flags & 0x0F)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Codec {
+    contact_header_done: bool,
+    curr_message: Option<(MessageType, u8)>,
+}
+
+impl Decoder for Codec {
+    type Item = Messages;
+
+    type Error = Errors;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if !self.contact_header_done {
+            let contact_header = ContactHeader::decode(src)?;
+            if contact_header.is_some() {
+                self.contact_header_done = true;
+            }
+            return Ok(contact_header.map(Messages::ContactHeader));
+        }
+
+        if src.is_empty() {
+            return Ok(None);
+        }
+        if self.curr_message.is_none() {
+            let header = src.get_u8();
+            match (header >> 4).try_into() {
+                Ok(mt) => {
+                    self.curr_message = Some((mt, header & 0x0F));
+                }
+                Err(_) => return Err(Errors::InvalidMessageType(header >> 4)),
+            }
+        }
+
+        let (message_type, flags) = self.curr_message.unwrap();
+        let decoded = match message_type {
+            MessageType::DataSegment => {
+                DataSegment::decode(flags, src).map(|o| o.map(Messages::DataSegment))
+            }
+            MessageType::AckSegment => AckSegment::decode(src).map(|o| o.map(Messages::AckSegment)),
+            MessageType::RefuseBundle => {
+                Ok(Some(Messages::RefuseBundle(RefuseBundle::decode(flags))))
+            }
+            MessageType::Keepalive => Ok(Some(Messages::Keepalive(Keepalive::new()))),
+            MessageType::Shutdown => {
+                Shutdown::decode(flags, src).map(|o| o.map(Messages::Shutdown))
+            }
+            MessageType::Length => Length::decode(src).map(|o| o.map(Messages::Length)),
+        };
+
+        if decoded.is_ok() && decoded.as_ref().unwrap().is_some() {
+            self.curr_message = None;
+        }
+        decoded
+    }
+}
+
+impl Encoder<Messages> for Codec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Messages, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match item {
+            Messages::ContactHeader(m) => m.encode(dst),
+            Messages::DataSegment(m) => {
+                dst.put_u8(header(MessageType::DataSegment, m.flags()));
+                m.encode(dst);
+            }
+            Messages::AckSegment(m) => {
+                dst.put_u8(header(MessageType::AckSegment, 0));
+                m.encode(dst);
+            }
+            Messages::RefuseBundle(m) => {
+                dst.put_u8(header(MessageType::RefuseBundle, m.flags()));
+            }
+            Messages::Keepalive(_) => {
+                dst.put_u8(header(MessageType::Keepalive, 0));
+            }
+            Messages::Shutdown(m) => {
+                dst.put_u8(header(MessageType::Shutdown, m.flags()));
+                m.encode(dst);
+            }
+            Messages::Length(m) => {
+                dst.put_u8(header(MessageType::Length, 0));
+                m.encode(dst);
+            }
+        }
+        Ok(())
+    }
+}
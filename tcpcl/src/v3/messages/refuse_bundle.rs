@@ -0,0 +1,50 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum ReasonCode {
+    Unkown = 0x00,
+    Completed = 0x01,
+    NoResources = 0x02,
+    Retransmit = 0x03,
+}
+
+/// Refuses the bundle currently being received. The reason is encoded in the flags of the
+/// message header, so this message has no body.
+#[derive(Debug)]
+pub struct RefuseBundle {
+    pub reason: ReasonCode,
+}
+
+impl RefuseBundle {
+    pub fn new(reason: ReasonCode) -> Self {
+        RefuseBundle { reason }
+    }
+
+    pub fn decode(flags: u8) -> Self {
+        RefuseBundle {
+            reason: flags.try_into().unwrap_or(ReasonCode::Unkown),
+        }
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.reason.into()
+    }
+}
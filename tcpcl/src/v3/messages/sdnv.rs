@@ -0,0 +1,53 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use bytes::{BufMut, BytesMut};
+
+use crate::v4::messages::Errors;
+
+// a u64 needs at most 10 bytes encoded as a sdnv
+const MAX_SDNV_LENGTH: usize = 10;
+
+/// Decodes a SDNV at the start of `src` without advancing it.
+/// Returns the value and the amount of bytes it used or `None` if `src` is too short.
+pub fn decode(src: &[u8]) -> Result<Option<(u64, usize)>, Errors> {
+    let mut value: u64 = 0;
+    for (i, byte) in src.iter().enumerate() {
+        if i >= MAX_SDNV_LENGTH || value.leading_zeros() < 7 {
+            return Err(Errors::InvalidSdnv);
+        }
+        value = (value << 7) | u64::from(byte & 0x7F);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+    Ok(None)
+}
+
+pub fn encode(value: u64, dst: &mut BytesMut) {
+    let mut bytes = [0u8; MAX_SDNV_LENGTH];
+    let mut pos = MAX_SDNV_LENGTH - 1;
+    let mut remaining = value;
+    bytes[pos] = (remaining & 0x7F) as u8;
+    remaining >>= 7;
+    while remaining > 0 {
+        pos -= 1;
+        bytes[pos] = 0x80 | (remaining & 0x7F) as u8;
+        remaining >>= 7;
+    }
+    dst.put_slice(&bytes[pos..]);
+}
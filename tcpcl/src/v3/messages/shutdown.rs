@@ -0,0 +1,122 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use bitflags::bitflags;
+use bytes::{Buf, BufMut, BytesMut};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+use super::sdnv;
+use crate::v4::messages::{Errors, sess_term};
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    struct MessageFlags: u8 {
+        const HAS_DELAY = 0x01;
+        const HAS_REASON = 0x02;
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum ReasonCode {
+    IdleTimeout = 0x00,
+    VersionMissmatch = 0x01,
+    Busy = 0x02,
+}
+
+impl From<ReasonCode> for sess_term::ReasonCode {
+    fn from(value: ReasonCode) -> Self {
+        match value {
+            ReasonCode::IdleTimeout => sess_term::ReasonCode::IdleTimeout,
+            ReasonCode::VersionMissmatch => sess_term::ReasonCode::VersionMissmatch,
+            ReasonCode::Busy => sess_term::ReasonCode::Busy,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Shutdown {
+    pub reason: Option<ReasonCode>,
+    /// Seconds the peer should wait before reconnecting.
+    pub reconnection_delay: Option<u64>,
+}
+
+impl Shutdown {
+    pub fn new(reason: Option<ReasonCode>) -> Self {
+        Shutdown {
+            reason,
+            reconnection_delay: None,
+        }
+    }
+
+    pub fn decode(flags: u8, src: &mut BytesMut) -> Result<Option<Self>, Errors> {
+        let flags = MessageFlags::from_bits_truncate(flags);
+        let mut length = 0;
+        if flags.contains(MessageFlags::HAS_REASON) {
+            length += 1;
+        }
+        let mut delay = None;
+        if flags.contains(MessageFlags::HAS_DELAY) {
+            if src.remaining() < length {
+                return Ok(None);
+            }
+            let Some((value, sdnv_length)) = sdnv::decode(&src[length..])? else {
+                return Ok(None);
+            };
+            delay = Some((value, sdnv_length));
+        }
+        if src.remaining() < length {
+            return Ok(None);
+        }
+
+        // unknown reason codes are treated like no reason at all
+        let reason = if flags.contains(MessageFlags::HAS_REASON) {
+            src.get_u8().try_into().ok()
+        } else {
+            None
+        };
+        let reconnection_delay = delay.map(|(value, sdnv_length)| {
+            src.advance(sdnv_length);
+            value
+        });
+
+        Ok(Some(Shutdown {
+            reason,
+            reconnection_delay,
+        }))
+    }
+
+    pub fn flags(&self) -> u8 {
+        let mut flags = MessageFlags::empty();
+        if self.reason.is_some() {
+            flags |= MessageFlags::HAS_REASON;
+        }
+        if self.reconnection_delay.is_some() {
+            flags |= MessageFlags::HAS_DELAY;
+        }
+        flags.bits()
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) {
+        if let Some(reason) = self.reason {
+            dst.put_u8(reason.into());
+        }
+        if let Some(delay) = self.reconnection_delay {
+            sdnv::encode(delay, dst);
+        }
+    }
+}
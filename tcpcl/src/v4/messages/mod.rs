@@ -90,6 +90,7 @@ pub enum Errors {
     SegmentTooLong,
     NodeIdInvalid,
     InvalidACKValue,
+    InvalidSdnv,
 }
 
 impl From<std::io::Error> for Errors {
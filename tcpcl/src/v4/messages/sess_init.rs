@@ -40,6 +40,29 @@ pub struct SessionExtension {
 }
 
 impl SessionExtension {
+    pub fn new(extension_type: u16, value: Vec<u8>, critical: bool) -> Self {
+        let mut flags = SessionExtensionFlags::empty();
+        flags.set(SessionExtensionFlags::CRITICAL, critical);
+        SessionExtension {
+            flags,
+            extension_type,
+            value,
+        }
+    }
+
+    pub fn extension_type(&self) -> u16 {
+        self.extension_type
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// The peer must terminate the session if it does not understand a critical extension.
+    pub fn is_critical(&self) -> bool {
+        self.flags.contains(SessionExtensionFlags::CRITICAL)
+    }
+
     pub fn decode(src: &mut BytesMut) -> Result<Self, crate::v4::messages::Errors> {
         let flags = src.get_u8();
         let extension_type = src.get_u16();
@@ -120,13 +143,7 @@ impl SessInit {
         let mut session_extensions: Vec<SessionExtension> = Vec::new();
         let target_remaining = src.remaining() - session_extensions_length as usize;
         while src.remaining() > target_remaining {
-            let se = SessionExtension::decode(src)?;
-            if se.flags.contains(SessionExtensionFlags::CRITICAL) {
-                return Err(crate::v4::messages::Errors::UnkownCriticalSessionExtension(
-                    se.extension_type,
-                ));
-            }
-            session_extensions.push(se);
+            session_extensions.push(SessionExtension::decode(src)?);
         }
 
         Ok(Some(SessInit {
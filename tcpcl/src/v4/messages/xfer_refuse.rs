@@ -33,16 +33,16 @@ pub enum ReasonCode {
     SessionTerminating = 0x06,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct XferRefuse {
-    reason: ReasonCode,
-    transfer_id: u64,
+    pub reason: ReasonCode,
+    pub transfer_id: u64,
 }
 
 impl XferRefuse {
-    pub fn new(transfer_id: u64) -> Self {
+    pub fn new(reason: ReasonCode, transfer_id: u64) -> Self {
         XferRefuse {
-            reason: ReasonCode::Unkown,
+            reason,
             transfer_id,
         }
     }
@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{cmp::min, mem, pin::Pin, sync::Arc};
+use std::{cmp::min, collections::VecDeque, mem, pin::Pin, sync::Arc, time::Instant};
 
 use log::{error, info, warn};
 use tokio::io::{Interest, WriteHalf};
@@ -23,8 +23,9 @@ use tokio_util::codec::FramedWrite;
 
 use crate::{
     errors::{Errors, TransferSendErrors},
+    extensions::SessionExtensions,
     session::AsyncReadWrite,
-    transfer::Transfer,
+    stats::{SessionState, SessionStats},
 };
 use futures_util::SinkExt;
 
@@ -33,17 +34,28 @@ use super::messages::{
     contact_header::ContactHeader,
     keepalive::Keepalive,
     msg_reject::{self, MsgReject},
-    sess_init::SessInit,
+    sess_init::{MAX_TRANSFER_MRU, SessInit, SessionExtension},
     sess_term::{ReasonCode, SessTerm},
     xfer_ack::XferAck,
+    xfer_refuse::XferRefuse,
     xfer_segment::{self, XferSegment},
 };
 
 #[derive(Debug, PartialEq, Eq)]
 struct TransferTracker {
-    transfer: Transfer,
+    id: u64,
+    data: Arc<Vec<u8>>,
     pos: usize,
     pos_acked: usize,
+    // end position and send time of each segment not yet acked. Used to measure the rtt
+    segments_in_flight: VecDeque<(usize, Instant)>,
+}
+
+/// How we answer a segment the peer sent.
+#[derive(Debug, PartialEq, Eq)]
+enum TransferReply {
+    Ack(XferAck),
+    Refuse(XferRefuse),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -63,15 +75,17 @@ enum States {
     // Session Established
     SessionEstablished,
     // Data Transfer (Receiving)
-    SendXferAck(XferAck),
+    SendXferAck(TransferReply),
     // Data Transfer (Sending)
     SendXferSegments(TransferTracker),
     // Data Transfer (both),
-    SendXferSegmentsAndAck(TransferTracker, XferAck),
+    SendXferSegmentsAndAck(TransferTracker, TransferReply),
     // Keepalive
     SendKeepalive(Box<States>),
     // Session Termination
     SendSessTerm(Option<ReasonCode>),
+    // Sends a SESS_TERM and afterwards continues with the wrapped state to finish transfers
+    SendSessTermGraceful(ReasonCode, Box<States>),
     WaitSessTerm,
     // Rejects (peer errors)
     SendMsgReject(msg_reject::ReasonCode, u8),
@@ -82,17 +96,67 @@ enum States {
     ShouldNeverExist,
 }
 
+impl States {
+    fn session_state(&self) -> SessionState {
+        match self {
+            States::ActiveSendContactHeader
+            | States::PassiveWaitContactHeader
+            | States::ActiveWaitContactHeader
+            | States::PassiveSendContactHeader => SessionState::ContactNegotiation,
+            States::ActiveSendSessInit
+            | States::PassiveWaitSessInit
+            | States::ActiveWaitSessInit
+            | States::PassiveSendSessInit => SessionState::SessionNegotiation,
+            States::SessionEstablished
+            | States::SendXferAck(_)
+            | States::SendXferSegments(_)
+            | States::SendXferSegmentsAndAck(_, _) => SessionState::Established,
+            States::SendKeepalive(s) => s.session_state(),
+            States::SendSessTerm(_)
+            | States::SendSessTermGraceful(_, _)
+            | States::WaitSessTerm
+            | States::SendMsgReject(_, _) => SessionState::Terminating,
+            States::ConnectionClose => SessionState::Closed,
+            States::ShouldNeverExist => {
+                panic!("Reached a state that should never exist")
+            }
+        }
+    }
+}
+
+/// Tracks a session termination that waits for the current transfers to finish.
+#[derive(Debug, Default)]
+struct ShutdownProgress {
+    sess_term_sent: bool,
+    peer_sess_term_received: bool,
+    // the peer is in the middle of a transfer
+    receiving: bool,
+}
+
 #[derive(Debug)]
 pub struct StateMachine {
     state: States,
     can_tls: bool,
     my_node_id: String,
+    transfer_mru: u64,
     last_used_transfer_id: u64,
     my_contact_header: Option<ContactHeader>,
     peer_contact_header: Option<ContactHeader>,
     my_sess_init: Option<SessInit>,
     peer_sess_init: Option<SessInit>,
     terminating: bool,
+    shutdown: ShutdownProgress,
+    // transfers that have been sent completely but are not yet fully acknowledged
+    unacked_transfers: VecDeque<TransferTracker>,
+    // the last transfer of the peer we refused, its remaining segments are ignored
+    refused_transfer: Option<u64>,
+    // ids and results of the outbound transfers that finished since they were last taken
+    completed_transfers: Vec<(u64, Result<(), TransferSendErrors>)>,
+    send_window: Option<u64>,
+    max_segment_size: Option<usize>,
+    session_extensions: Vec<SessionExtension>,
+    termination: Option<(Option<ReasonCode>, bool)>,
+    stats: SessionStats,
 }
 
 impl StateMachine {
@@ -101,12 +165,22 @@ impl StateMachine {
             state: States::ActiveSendContactHeader,
             can_tls,
             my_node_id: node_id,
+            transfer_mru: MAX_TRANSFER_MRU as u64,
             last_used_transfer_id: 0,
             my_contact_header: None,
             peer_contact_header: None,
             my_sess_init: None,
             peer_sess_init: None,
             terminating: false,
+            shutdown: ShutdownProgress::default(),
+            unacked_transfers: VecDeque::new(),
+            refused_transfer: None,
+            completed_transfers: Vec::new(),
+            send_window: None,
+            max_segment_size: None,
+            session_extensions: Vec::new(),
+            termination: None,
+            stats: SessionStats::default(),
         }
     }
     pub fn new_passive(node_id: String, can_tls: bool) -> Self {
@@ -114,19 +188,91 @@ impl StateMachine {
             state: States::PassiveWaitContactHeader,
             can_tls,
             my_node_id: node_id,
+            transfer_mru: MAX_TRANSFER_MRU as u64,
             last_used_transfer_id: 0,
             my_contact_header: None,
             peer_contact_header: None,
             my_sess_init: None,
             peer_sess_init: None,
             terminating: false,
+            shutdown: ShutdownProgress::default(),
+            unacked_transfers: VecDeque::new(),
+            refused_transfer: None,
+            completed_transfers: Vec::new(),
+            send_window: None,
+            max_segment_size: None,
+            session_extensions: Vec::new(),
+            termination: None,
+            stats: SessionStats::default(),
         }
     }
 
+    pub fn set_transfer_mru(&mut self, transfer_mru: u64) {
+        assert!(
+            self.my_sess_init.is_none(),
+            "The transfer mru can only be set before the session is initialized"
+        );
+        self.transfer_mru = transfer_mru;
+    }
+
+    /// Allows up to `window` bytes to be unacknowledged. New transfers are started while the
+    /// previous ones are still waiting for acks, as long as the window is not exhausted.
+    pub fn set_send_window(&mut self, window: u64) {
+        self.send_window = Some(window);
+    }
+
+    /// Limits our segments to be smaller than the segment mru of the peer.
+    pub fn set_max_segment_size(&mut self, max_segment_size: Option<usize>) {
+        self.max_segment_size = max_segment_size;
+    }
+
+    /// Returns the size of the segment the next call to `send_message` sends, if it sends one.
+    pub fn next_segment_size(&self) -> Option<usize> {
+        match &self.state {
+            States::SendXferSegments(tt) if tt.pos < tt.data.len() => {
+                Some(min(self.segment_size(), tt.data.len() - tt.pos))
+            }
+            _ => None,
+        }
+    }
+
+    fn segment_size(&self) -> usize {
+        let mru = self.peer_sess_init.as_ref().map_or(usize::MAX, |si| {
+            usize::try_from(si.segment_mru).unwrap_or(usize::MAX)
+        });
+        min(mru, self.max_segment_size.unwrap_or(usize::MAX))
+    }
+
+    pub fn add_session_extension(&mut self, extension: SessionExtension) {
+        assert!(
+            self.my_sess_init.is_none(),
+            "Session extensions can only be added before the session is initialized"
+        );
+        self.session_extensions.push(extension);
+    }
+
+    /// Returns the extensions both sides sent, with the values of the peer.
+    pub fn get_negotiated_extensions(&self) -> SessionExtensions {
+        let mut negotiated = SessionExtensions::default();
+        for extension in &self.peer_sess_init.as_ref().unwrap().session_extensions {
+            if self.knows_extension(extension.extension_type()) {
+                negotiated.insert(extension.extension_type(), extension.value().to_vec());
+            }
+        }
+        negotiated
+    }
+
+    fn knows_extension(&self, extension_type: u16) -> bool {
+        self.session_extensions
+            .iter()
+            .any(|extension| extension.extension_type() == extension_type)
+    }
+
     pub async fn send_message(
         &mut self,
         writer: &mut FramedWrite<WriteHalf<Pin<Box<dyn AsyncReadWrite>>>, Codec>,
     ) -> Result<(), std::io::Error> {
+        let segment_size = self.segment_size();
         match &mut self.state {
             States::ActiveSendContactHeader | States::PassiveSendContactHeader => {
                 let ch = ContactHeader::new(self.can_tls);
@@ -134,53 +280,67 @@ impl StateMachine {
                 writer.send(Messages::ContactHeader(ch)).await?;
             }
             States::ActiveSendSessInit | States::PassiveSendSessInit => {
-                let si = SessInit::new(self.my_node_id.clone());
+                let mut si = SessInit::new(self.my_node_id.clone());
+                si.transfer_mru = self.transfer_mru;
+                si.session_extensions.clone_from(&self.session_extensions);
                 self.my_sess_init = Some(si.clone());
                 writer.send(Messages::SessInit(si)).await?;
             }
-            States::SendXferAck(xfer_ack) | States::SendXferSegmentsAndAck(_, xfer_ack) => {
-                writer.send(Messages::XferAck(xfer_ack.clone())).await?;
+            States::SendXferAck(reply) | States::SendXferSegmentsAndAck(_, reply) => {
+                let message = match reply {
+                    TransferReply::Ack(xfer_ack) => Messages::XferAck(xfer_ack.clone()),
+                    TransferReply::Refuse(xfer_refuse) => Messages::XferRefuse(xfer_refuse.clone()),
+                };
+                writer.send(message).await?;
             }
             States::SendSessTerm(r) => {
                 let st = SessTerm::new(r.unwrap_or(ReasonCode::Unkown), self.terminating);
                 writer.send(Messages::SessTerm(st)).await?;
             }
+            States::SendSessTermGraceful(r, _) => {
+                writer
+                    .send(Messages::SessTerm(SessTerm::new(*r, self.terminating)))
+                    .await?;
+            }
             States::SendXferSegments(tt) => {
-                let mru = self.peer_sess_init.as_ref().unwrap().segment_mru;
-                let end_pos = min(tt.pos + mru as usize, tt.transfer.data.len());
-                if tt.pos == tt.transfer.data.len() {
+                let end_pos = min(tt.pos.saturating_add(segment_size), tt.data.len());
+                if tt.pos == tt.data.len() {
                     warn!(
                         "We should not try to send a transfer if we already sent all data. We just dont do anything"
                     );
                     return Ok(());
                 }
                 let mut data = Vec::with_capacity(end_pos - tt.pos);
-                data.extend_from_slice(&tt.transfer.data[tt.pos..end_pos]);
+                data.extend_from_slice(&tt.data[tt.pos..end_pos]);
 
                 let mut flags = xfer_segment::MessageFlags::empty();
                 if tt.pos == 0 {
                     flags |= xfer_segment::MessageFlags::START;
                 }
-                if end_pos == tt.transfer.data.len() {
+                if end_pos == tt.data.len() {
                     flags |= xfer_segment::MessageFlags::END;
                 }
 
-                let xfer_seg = XferSegment::new(flags, tt.transfer.id, data);
+                let xfer_seg = XferSegment::new(flags, tt.id, data);
 
                 // The following is some magic to ensure we are actually cancelation safe (so send_message can be used in select!)
                 // We first feed the data to the writer. According to https://users.rust-lang.org/t/is-tokio-codec-framed-cancel-safe/86408/14
                 // this is cancelation safe. So if this future is canceled the message has either been appended to the buffer
                 // (and we where at flush below) or it has not yet been appended to the buffer.
                 // Only after we have appended to the buffer (and done so successfully) are we allowed to increase our transfer tracking position.
-                // The call to flush at the end is just so the data is actually out. It should not hurt if it does not happen as the buffer should
-                // be flushed in the background anyway
+                // The call to flush at the end is just so the data is actually out. If it does not happen the session flushes the leftover
+                // buffer in its next loop iteration
                 writer.feed(Messages::XferSegment(xfer_seg)).await?;
+                self.stats.segments_sent += 1;
+                self.stats.bytes_sent += (end_pos - tt.pos) as u64;
+                tt.segments_in_flight.push_back((end_pos, Instant::now()));
                 tt.pos = end_pos;
                 writer.flush().await?;
             }
             States::SendKeepalive(_) => {
                 let ka = Keepalive::new();
                 writer.send(Messages::Keepalive(ka)).await?;
+                self.stats.keepalives_sent += 1;
             }
             States::SendMsgReject(r, t) => {
                 let mr = MsgReject::new(*r, *t);
@@ -201,6 +361,7 @@ impl StateMachine {
         &mut self,
         message: Result<Messages, messages::Errors>,
     ) -> Result<Messages, Errors> {
+        self.count_received_message(&message);
         match self.state {
             States::PassiveWaitContactHeader | States::ActiveWaitContactHeader => {
                 let ch = match &message {
@@ -229,6 +390,17 @@ impl StateMachine {
                     );
                     return message.map_err(std::convert::Into::into);
                 }
+                if let Ok(Messages::SessInit(si)) = &message
+                    && let Some(extension) = si.session_extensions.iter().find(|extension| {
+                        extension.is_critical() && !self.knows_extension(extension.extension_type())
+                    })
+                {
+                    return Err(Errors::MessageError(
+                        messages::Errors::UnkownCriticalSessionExtension(
+                            extension.extension_type(),
+                        ),
+                    ));
+                }
                 match &message {
                     Ok(Messages::SessInit(si)) if self.state == States::ActiveWaitSessInit => {
                         self.peer_sess_init = Some(si.clone());
@@ -238,65 +410,43 @@ impl StateMachine {
                         self.peer_sess_init = Some(si.clone());
                         self.state = States::PassiveSendSessInit;
                     }
-                    Ok(Messages::SessTerm(_)) if self.state == States::WaitSessTerm => {
-                        self.state = States::ConnectionClose;
+                    Ok(Messages::SessTerm(_)) if self.shutdown.sess_term_sent => {
+                        // The peer replied to our SESS_TERM. We might still need to finish transfers
+                        self.shutdown.peer_sess_term_received = true;
+                        if self.state == States::WaitSessTerm {
+                            self.state = self.idle_state();
+                        }
                     }
                     Ok(Messages::SessTerm(st))
                         if self.state == States::SessionEstablished
                             || matches!(self.state, States::SendXferSegments(_))
                             || matches!(self.state, States::SendXferSegmentsAndAck(_, _)) =>
                     {
-                        self.state = States::SendSessTerm(Some(st.reason));
+                        // We may finish the transfers in progress before closing (RFC 9174 6.1)
                         self.terminating = true;
+                        self.shutdown.peer_sess_term_received = true;
+                        self.termination = Some((Some(st.reason), true));
+                        let state = mem::replace(&mut self.state, States::ShouldNeverExist);
+                        self.state = if state == States::SessionEstablished
+                            && self.unacked_transfers.is_empty()
+                        {
+                            States::SendSessTerm(Some(st.reason))
+                        } else {
+                            States::SendSessTermGraceful(st.reason, Box::new(state))
+                        };
                     }
-                    Ok(Messages::XferSegment(_))
+                    Ok(Messages::XferSegment(x))
                         if self.state == States::SessionEstablished
+                            || self.state == States::WaitSessTerm
                             || matches!(self.state, States::SendXferSegments(_))
-                            || matches!(self.state, States::SendXferSegmentsAndAck(_, _)) => {}
-                    Ok(Messages::XferAck(xa)) => match &mut self.state {
-                        States::SendXferSegments(tt) | States::SendXferSegmentsAndAck(tt, _) => {
-                            assert!(
-                                tt.transfer.id == xa.transfer_id,
-                                "Remote send ack for transfer {}, but we are currently sending {}",
-                                xa.transfer_id,
-                                tt.transfer.id
-                            );
-                            tt.pos_acked = xa.acknowleged_length as usize;
-                            if tt.pos_acked > tt.pos {
-                                error!(
-                                    "Peer acked a transfer further than we have send it (current send potition {}, current acked position {}).",
-                                    tt.pos, tt.pos_acked
-                                );
-                                return Err(Errors::MessageError(
-                                    messages::Errors::InvalidACKValue,
-                                ));
-                            }
-                            if tt.pos_acked == tt.transfer.data.len() {
-                                info!("Transfer {} finished (sent and acked)", tt.transfer.id);
-                                let state = mem::replace(&mut self.state, States::ShouldNeverExist);
-                                match state {
-                                    States::SendXferSegments(_) => {
-                                        self.state = States::SessionEstablished;
-                                    }
-                                    States::SendXferSegmentsAndAck(_, ack) => {
-                                        self.state = States::SendXferAck(ack);
-                                    }
-                                    _ => panic!("Invalid state {state:?}"),
-                                }
-                            }
-                        }
-                        _ => {
-                            warn!(
-                                "Received inappropriate message type {:?} while in state {:?}",
-                                message, self.state
-                            );
-                            self.state = States::SendMsgReject(
-                                msg_reject::ReasonCode::MessageUnexpected,
-                                MessageType::XferAck.into(),
-                            );
-                            return Err(Errors::MessageTypeInappropriate(MessageType::XferAck));
-                        }
-                    },
+                            || matches!(self.state, States::SendXferSegmentsAndAck(_, _)) =>
+                    {
+                        self.shutdown.receiving =
+                            !x.flags.contains(xfer_segment::MessageFlags::END)
+                                && !self.is_refused(x.transfer_id);
+                    }
+                    Ok(Messages::XferAck(xa)) => self.receive_ack(xa)?,
+                    Ok(Messages::XferRefuse(xr)) => self.receive_refuse(xr),
                     Ok(Messages::Keepalive(_) | Messages::MsgReject(_)) | Err(_) => {}
                     Ok(m) => {
                         warn!(
@@ -321,6 +471,103 @@ impl StateMachine {
         message.map_err(std::convert::Into::into)
     }
 
+    fn receive_ack(&mut self, xa: &XferAck) -> Result<(), Errors> {
+        let tt = if let Some(tt) = self
+            .unacked_transfers
+            .iter_mut()
+            .find(|tt| tt.id == xa.transfer_id)
+        {
+            tt
+        } else if let States::SendXferSegments(tt) | States::SendXferSegmentsAndAck(tt, _) =
+            &mut self.state
+            && tt.id == xa.transfer_id
+        {
+            tt
+        } else {
+            warn!(
+                "Received ack for unknown transfer {} while in state {:?}",
+                xa.transfer_id, self.state
+            );
+            self.state = States::SendMsgReject(
+                msg_reject::ReasonCode::MessageUnexpected,
+                MessageType::XferAck.into(),
+            );
+            return Err(Errors::MessageTypeInappropriate(MessageType::XferAck));
+        };
+        tt.pos_acked = xa.acknowleged_length as usize;
+        if tt.pos_acked > tt.pos {
+            error!(
+                "Peer acked a transfer further than we have send it (current send potition {}, current acked position {}).",
+                tt.pos, tt.pos_acked
+            );
+            return Err(Errors::MessageError(messages::Errors::InvalidACKValue));
+        }
+        while let Some((end_pos, sent_at)) = tt.segments_in_flight.front()
+            && *end_pos <= tt.pos_acked
+        {
+            if *end_pos == tt.pos_acked {
+                self.stats.add_rtt_sample(sent_at.elapsed());
+            }
+            tt.segments_in_flight.pop_front();
+        }
+        if tt.pos_acked < tt.data.len() {
+            return Ok(());
+        }
+
+        info!("Transfer {} finished (sent and acked)", tt.id);
+        self.stats.transfers_sent += 1;
+        self.finish_transfer(xa.transfer_id, Ok(()));
+        Ok(())
+    }
+
+    fn receive_refuse(&mut self, xr: &XferRefuse) {
+        let current = match &self.state {
+            States::SendXferSegments(tt) | States::SendXferSegmentsAndAck(tt, _) => Some(tt),
+            _ => None,
+        };
+        if !self
+            .unacked_transfers
+            .iter()
+            .chain(current)
+            .any(|tt| tt.id == xr.transfer_id)
+        {
+            warn!(
+                "Peer refused transfer {} which we are not sending. Ignoring it",
+                xr.transfer_id
+            );
+            return;
+        }
+        info!("Peer refused transfer {}: {:?}", xr.transfer_id, xr.reason);
+        self.finish_transfer(xr.transfer_id, Err(TransferSendErrors::Refused));
+    }
+
+    /// Stops tracking the transfer, which is either an unacknowledged one or the one currently
+    /// being sent.
+    fn finish_transfer(&mut self, transfer_id: u64, result: Result<(), TransferSendErrors>) {
+        self.completed_transfers.push((transfer_id, result));
+        if let Some(i) = self
+            .unacked_transfers
+            .iter()
+            .position(|tt| tt.id == transfer_id)
+        {
+            self.unacked_transfers.remove(i);
+            if self.state == States::WaitSessTerm {
+                self.state = self.idle_state();
+            }
+            return;
+        }
+        let state = mem::replace(&mut self.state, States::ShouldNeverExist);
+        match state {
+            States::SendXferSegments(_) => {
+                self.state = self.idle_state();
+            }
+            States::SendXferSegmentsAndAck(_, reply) => {
+                self.state = States::SendXferAck(reply);
+            }
+            _ => panic!("Invalid state {state:?}"),
+        }
+    }
+
     pub fn get_interests(&self) -> Interest {
         match &self.state {
             States::ActiveSendContactHeader
@@ -329,6 +576,7 @@ impl StateMachine {
             | States::PassiveSendSessInit
             | States::SendXferAck(_)
             | States::SendSessTerm(_)
+            | States::SendSessTermGraceful(_, _)
             | States::SendXferSegmentsAndAck(_, _)
             | States::SendKeepalive(_)
             | States::SendMsgReject(_, _) => Interest::WRITABLE,
@@ -339,7 +587,7 @@ impl StateMachine {
             | States::SessionEstablished
             | States::WaitSessTerm => Interest::READABLE,
             States::SendXferSegments(tt) => {
-                if tt.pos < tt.transfer.data.len() {
+                if tt.pos < tt.data.len() && self.send_window_open() {
                     return Interest::READABLE | Interest::WRITABLE;
                 }
                 Interest::READABLE
@@ -359,30 +607,29 @@ impl StateMachine {
             States::ActiveSendContactHeader => self.state = States::ActiveWaitContactHeader,
             States::PassiveSendContactHeader => self.state = States::PassiveWaitSessInit,
             States::ActiveSendSessInit => self.state = States::ActiveWaitSessInit,
-            States::PassiveSendSessInit | States::SendXferAck(_) => {
-                self.state = States::SessionEstablished;
-            }
+            States::PassiveSendSessInit => self.state = States::SessionEstablished,
+            States::SendXferAck(_) => self.state = self.idle_state(),
             States::SendXferSegmentsAndAck(tt, _) => {
                 // We here rely on the fact that send_message will prefer
                 // acks over xfers
-                self.state = States::SendXferSegments(tt);
+                self.state = self.continue_transfer(tt);
             }
             States::SendXferSegments(tt) => {
-                // This will probably never happen, but just to be sure
-                if tt.pos == tt.transfer.data.len() && tt.pos == tt.pos_acked {
-                    self.state = States::SessionEstablished;
-                } else {
-                    self.state = States::SendXferSegments(tt);
-                }
+                self.state = self.continue_transfer(tt);
             }
             States::SendKeepalive(s) => {
-                self.state = *s;
+                self.state = self.settle(*s);
+            }
+            States::SendSessTermGraceful(_, s) => {
+                self.shutdown.sess_term_sent = true;
+                self.state = self.settle(*s);
             }
             States::SendSessTerm(_) if self.terminating => {
                 self.state = States::ConnectionClose;
             }
             States::SendSessTerm(_) if !self.terminating => {
                 self.terminating = true;
+                self.shutdown.sess_term_sent = true;
                 self.state = States::WaitSessTerm;
             }
             States::SendMsgReject(_, _) => {
@@ -395,19 +642,20 @@ impl StateMachine {
         }
     }
 
-    pub fn send_transfer(&mut self, data: Arc<Vec<u8>>) -> Result<(), TransferSendErrors> {
+    /// Starts sending the data and returns the id of the new transfer.
+    pub fn send_transfer(&mut self, data: Arc<Vec<u8>>) -> Result<u64, TransferSendErrors> {
         if self.peer_sess_init.as_ref().unwrap().transfer_mru < data.len() as u64 {
             return Err(TransferSendErrors::BundleTooLarge {
                 max_size: self.peer_sess_init.as_ref().unwrap().transfer_mru,
             });
         }
+        let id = self.last_used_transfer_id;
         let tracker = TransferTracker {
-            transfer: Transfer {
-                id: self.last_used_transfer_id,
-                data,
-            },
+            id,
+            data,
             pos: 0,
             pos_acked: 0,
+            segments_in_flight: VecDeque::new(),
         };
         self.last_used_transfer_id += 1;
         let state = mem::replace(&mut self.state, States::ShouldNeverExist);
@@ -418,14 +666,35 @@ impl StateMachine {
                 panic!("Attempted to send a transfer on a non-established connection");
             }
         }
-        Ok(())
+        Ok(id)
     }
 
     pub fn send_ack(&mut self, ack: XferAck) {
+        self.send_reply(TransferReply::Ack(ack));
+    }
+
+    /// Refuses the transfer the peer is currently sending.
+    pub fn send_refuse(&mut self, refuse: XferRefuse) {
+        self.refused_transfer = Some(refuse.transfer_id);
+        self.shutdown.receiving = false;
+        self.send_reply(TransferReply::Refuse(refuse));
+    }
+
+    /// Whether we refused the transfer. Segments the peer sent before it got our refusal are
+    /// still arriving.
+    pub fn is_refused(&self, transfer_id: u64) -> bool {
+        self.refused_transfer == Some(transfer_id)
+    }
+
+    fn send_reply(&mut self, reply: TransferReply) {
         let state = mem::replace(&mut self.state, States::ShouldNeverExist);
         match state {
-            States::SendXferSegments(tt) => self.state = States::SendXferSegmentsAndAck(tt, ack),
-            States::SessionEstablished => self.state = States::SendXferAck(ack),
+            States::SendXferSegments(tt) => {
+                self.state = States::SendXferSegmentsAndAck(tt, reply);
+            }
+            States::SessionEstablished | States::WaitSessTerm => {
+                self.state = States::SendXferAck(reply);
+            }
             _ => {
                 panic!("Attempted to send an ack on a non-established connection");
             }
@@ -443,13 +712,142 @@ impl StateMachine {
             "Attempted to close a non-established connection"
         );
         self.state = States::SendSessTerm(reason);
+        self.termination = Some((reason, false));
+    }
+
+    /// Sends a `SESS_TERM` but finishes the current transfers in both directions before closing
+    /// the connection.
+    pub fn close_connection_gracefully(&mut self, reason: ReasonCode) {
+        assert!(
+            self.state.session_state() == SessionState::Established,
+            "Attempted to close a non-established connection"
+        );
+        let state = mem::replace(&mut self.state, States::ShouldNeverExist);
+        self.state = States::SendSessTermGraceful(reason, Box::new(state));
+        self.termination = Some((Some(reason), false));
+    }
+
+    /// Once all segments of a transfer are sent we only wait for its acks. This allows the
+    /// next transfer to start in the meantime.
+    fn continue_transfer(&mut self, tt: TransferTracker) -> States {
+        if tt.pos < tt.data.len() {
+            return States::SendXferSegments(tt);
+        }
+        self.unacked_transfers.push_back(tt);
+        self.idle_state()
+    }
+
+    fn bytes_in_flight(&self) -> u64 {
+        let unacked = |tt: &TransferTracker| (tt.pos - tt.pos_acked) as u64;
+        let current = match &self.state {
+            States::SendXferSegments(tt) | States::SendXferSegmentsAndAck(tt, _) => unacked(tt),
+            _ => 0,
+        };
+        self.unacked_transfers.iter().map(unacked).sum::<u64>() + current
+    }
+
+    fn send_window_open(&self) -> bool {
+        self.send_window
+            .is_none_or(|window| self.bytes_in_flight() < window)
+    }
+
+    /// The state to continue in once no outbound transfer is in progress.
+    fn idle_state(&self) -> States {
+        if !self.shutdown.sess_term_sent {
+            States::SessionEstablished
+        } else if self.shutdown.peer_sess_term_received
+            && !self.shutdown.receiving
+            && self.unacked_transfers.is_empty()
+        {
+            States::ConnectionClose
+        } else {
+            States::WaitSessTerm
+        }
+    }
+
+    fn settle(&self, state: States) -> States {
+        if state == States::SessionEstablished {
+            self.idle_state()
+        } else {
+            state
+        }
+    }
+
+    /// Returns the ids and results of the outbound transfers that have been fully acknowledged
+    /// or refused since the last call.
+    pub fn take_completed_transfers(&mut self) -> Vec<(u64, Result<(), TransferSendErrors>)> {
+        mem::take(&mut self.completed_transfers)
+    }
+
+    pub fn has_unacked_transfers(&self) -> bool {
+        !self.unacked_transfers.is_empty()
+    }
+
+    /// Returns the id, the data and the number of acknowledged bytes of all transfers that
+    /// have not been fully acknowledged, oldest first.
+    pub fn take_unfinished_transfers(&mut self) -> Vec<(u64, Arc<Vec<u8>>, usize)> {
+        fn find(state: &States) -> Option<&TransferTracker> {
+            match state {
+                States::SendXferSegments(tt) | States::SendXferSegmentsAndAck(tt, _) => Some(tt),
+                States::SendKeepalive(s) | States::SendSessTermGraceful(_, s) => find(s),
+                _ => None,
+            }
+        }
+        let mut unfinished: Vec<_> = self
+            .unacked_transfers
+            .drain(..)
+            .map(|tt| (tt.id, tt.data, tt.pos_acked))
+            .collect();
+        unfinished.extend(find(&self.state).map(|tt| (tt.id, tt.data.clone(), tt.pos_acked)));
+        unfinished
+    }
+
+    pub fn session_state(&self) -> SessionState {
+        let state = self.state.session_state();
+        if self.shutdown.sess_term_sent && state == SessionState::Established {
+            return SessionState::Terminating;
+        }
+        state
+    }
+
+    /// Returns the reason of the session termination and if the peer initiated it.
+    pub fn get_termination(&self) -> Option<(Option<ReasonCode>, bool)> {
+        self.termination
+    }
+
+    pub fn get_stats(&self) -> SessionStats {
+        let mut stats = self.stats.clone();
+        stats.state = self.session_state();
+        stats
+    }
+
+    fn count_received_message(&mut self, message: &Result<Messages, messages::Errors>) {
+        match message {
+            Ok(Messages::XferSegment(x)) => {
+                self.stats.segments_received += 1;
+                self.stats.bytes_received += x.data.len() as u64;
+                if x.flags.contains(xfer_segment::MessageFlags::END) {
+                    self.stats.transfers_received += 1;
+                }
+            }
+            Ok(Messages::XferRefuse(_)) => self.stats.transfers_refused += 1,
+            Ok(Messages::Keepalive(_)) => self.stats.keepalives_received += 1,
+            _ => {}
+        }
     }
 
     pub fn connection_closing(&self) -> bool {
         matches!(
             self.state,
-            States::SendSessTerm(_) | States::WaitSessTerm | States::ConnectionClose
-        )
+            States::SendSessTerm(_)
+                | States::SendSessTermGraceful(_, _)
+                | States::WaitSessTerm
+                | States::ConnectionClose
+        ) || self.shutdown.sess_term_sent
+    }
+
+    pub fn get_my_node_id(&self) -> &str {
+        &self.my_node_id
     }
 
     pub fn get_peer_node_id(&self) -> String {
@@ -507,6 +905,10 @@ impl StateMachine {
 
     pub fn could_send_transfer(&self) -> bool {
         self.state == States::SessionEstablished
+            && match self.send_window {
+                None => self.unacked_transfers.is_empty(),
+                Some(window) => self.bytes_in_flight() < window,
+            }
     }
 
     pub fn should_close(&self) -> bool {
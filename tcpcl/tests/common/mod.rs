@@ -15,14 +15,16 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{future::Future, net::SocketAddrV4, str::FromStr};
+use std::future::Future;
 
-use tcpcl::{errors::ErrorType, session::TCPCLSession};
+use tcpcl::session::TCPCLSession;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
     task::JoinHandle,
 };
+use url::Url;
+
+const DUPLEX_BUFFER_SIZE: usize = 64 * 1024;
 
 pub mod tls;
 
@@ -124,17 +126,29 @@ pub const SESS_INIT_SERVER_NAME_2: [u8; 38] = [
     0x00, 0x00, 0x00, 0x00, // session extension length
 ];
 
-pub async fn setup_conn_custom_sessinit<Fut>(
-    do_test: impl FnOnce(TcpStream) -> Fut + Send + 'static,
+/// Creates a passive session connected to an in-memory stream for the peer side of the test.
+#[allow(dead_code)]
+pub fn setup_duplex() -> (DuplexStream, TCPCLSession) {
+    let (client, server) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+    let session = TCPCLSession::new_with_stream(
+        server,
+        Url::parse("tcpcl://client").unwrap(),
+        "dtn://server".into(),
+        None,
+    )
+    .unwrap();
+    (client, session)
+}
+
+pub fn setup_conn_custom_sessinit<Fut>(
+    do_test: impl FnOnce(DuplexStream) -> Fut + Send + 'static,
     sessinit: [u8; 37],
-) -> Result<(JoinHandle<()>, TCPCLSession), ErrorType>
+) -> (JoinHandle<()>, TCPCLSession)
 where
     Fut: Future<Output = ()> + Send,
 {
-    let listener = TcpListener::bind(SocketAddrV4::from_str("127.0.0.1:0").unwrap()).await?;
-    let addr = listener.local_addr()?;
+    let (mut client, session) = setup_duplex();
     let jh = tokio::spawn(async move {
-        let mut client = TcpStream::connect(&addr).await.unwrap();
         client.write_all(&CONTACT_HEADER_NO_TLS).await.unwrap();
 
         let mut buf: [u8; 6] = [0; 6];
@@ -148,18 +162,15 @@ where
         do_test(client).await;
     });
 
-    let (socket, _) = listener.accept().await?;
-    let session = TCPCLSession::new(socket, "dtn://server".into(), None)?;
-
-    Ok((jh, session))
+    (jh, session)
 }
 
 #[allow(dead_code)]
-pub async fn setup_conn<Fut>(
-    do_test: impl FnOnce(TcpStream) -> Fut + Send + 'static,
-) -> Result<(JoinHandle<()>, TCPCLSession), ErrorType>
+pub fn setup_conn<Fut>(
+    do_test: impl FnOnce(DuplexStream) -> Fut + Send + 'static,
+) -> (JoinHandle<()>, TCPCLSession)
 where
     Fut: Future<Output = ()> + Send,
 {
-    setup_conn_custom_sessinit(do_test, SESS_INIT_CLIENT_SMRU_2).await
+    setup_conn_custom_sessinit(do_test, SESS_INIT_CLIENT_SMRU_2)
 }
@@ -60,8 +60,7 @@ async fn test_closes_on_missed_keepalive() -> Result<(), ErrorType> {
             );
         },
         SESS_INIT_CLIENT_KEEPALIVE_1S,
-    )
-    .await?;
+    );
 
     session.manage_connection().await.unwrap();
     jh.await.unwrap();
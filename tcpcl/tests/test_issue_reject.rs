@@ -47,8 +47,7 @@ async fn test_unkown_message_type() -> Result<(), ErrorType> {
         let mut buf: [u8; 100] = [0; 100];
         let len = client.read(&mut buf).await.unwrap();
         assert_eq!(len, 0);
-    })
-    .await?;
+    });
 
     session.manage_connection().await.unwrap();
     jh.await.unwrap();
@@ -0,0 +1,247 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{net::SocketAddrV4, str::FromStr, sync::Arc, time::Duration};
+
+use tcpcl::{errors::ErrorType, session::TCPCLSession, stats::SessionStats};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::{
+        TcpListener, TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{mpsc, oneshot, watch},
+    time::{Instant, sleep_until},
+};
+use url::Url;
+
+use crate::common::*;
+
+mod common;
+
+const TRANSFERS: u64 = 10;
+// every transfer fits into a single segment of the peer segment mru
+const TRANSFER_SIZE: u64 = 2;
+const SEGMENT_LENGTH: usize = 24;
+
+const ONE_WAY_DELAY: Duration = Duration::from_millis(25);
+const DELAYED_TRANSFERS: usize = 20;
+const DELAYED_TRANSFER_SIZE: usize = 1000;
+
+async fn read_transfer_id(client: &mut DuplexStream) -> u64 {
+    let mut buf = [0; SEGMENT_LENGTH];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf[0], 0x01);
+    u64::from_be_bytes(buf[2..10].try_into().unwrap())
+}
+
+async fn ack(client: &mut DuplexStream, transfer_id: u64) {
+    let mut ack = vec![
+        0x02, // message type
+        0x03, // flags (start + end)
+    ];
+    ack.extend_from_slice(&transfer_id.to_be_bytes());
+    ack.extend_from_slice(&TRANSFER_SIZE.to_be_bytes());
+    client.write_all(&ack).await.unwrap();
+}
+
+/// Sends `TRANSFERS` transfers and checks that `in_flight` of them are sent before the peer
+/// acknowledges the first one.
+async fn check_transfers_in_flight(
+    send_window: Option<u64>,
+    in_flight: u64,
+) -> Result<(), ErrorType> {
+    let (stats_sender, stats_receiver) = oneshot::channel::<watch::Receiver<SessionStats>>();
+    let (jh, mut session) = setup_conn(move |mut client| async move {
+        let mut stats = stats_receiver.await.unwrap();
+        for id in 0..in_flight {
+            assert_eq!(read_transfer_id(&mut client).await, id);
+        }
+        let segments_sent = stats
+            .wait_for(|stats| stats.segments_sent >= in_flight)
+            .await
+            .unwrap()
+            .segments_sent;
+        assert_eq!(segments_sent, in_flight);
+
+        // every ack makes room for the next transfer
+        let mut next = in_flight;
+        for id in 0..TRANSFERS {
+            ack(&mut client, id).await;
+            if next < TRANSFERS {
+                assert_eq!(read_transfer_id(&mut client).await, next);
+                next += 1;
+            }
+        }
+    });
+    if let Some(window) = send_window {
+        session.set_send_window(window);
+    }
+    stats_sender.send(session.get_stats_channel()).unwrap();
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+    let (results_sender, results_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        let mut results = Vec::new();
+        for _ in 0..TRANSFERS {
+            let (result_sender, result_receiver) = oneshot::channel();
+            send_channel
+                .send((Arc::new(vec![0x55; TRANSFER_SIZE as usize]), result_sender))
+                .await
+                .unwrap();
+            results.push(result_receiver);
+        }
+        results_sender.send(results).unwrap();
+    });
+
+    session.manage_connection().await?;
+    jh.await.unwrap();
+
+    for result in results_receiver.await.unwrap() {
+        result.await.unwrap().unwrap();
+    }
+    assert_eq!(
+        session.get_stats_channel().borrow().transfers_sent,
+        TRANSFERS
+    );
+
+    Ok(())
+}
+
+/// Forwards everything from `from` to `to` after `ONE_WAY_DELAY` without limiting the throughput.
+async fn delay(mut from: OwnedReadHalf, mut to: OwnedWriteHalf) {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<(Instant, Vec<u8>)>();
+    tokio::spawn(async move {
+        while let Some((deliver_at, data)) = receiver.recv().await {
+            sleep_until(deliver_at).await;
+            if to.write_all(&data).await.is_err() {
+                return;
+            }
+        }
+        let _ = to.shutdown().await;
+    });
+    let mut buf = vec![0; 65536];
+    while let Ok(len) = from.read(&mut buf).await
+        && len > 0
+    {
+        if sender
+            .send((Instant::now() + ONE_WAY_DELAY, buf[..len].to_vec()))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Sends all transfers over a delayed loopback connection and returns how long it took.
+async fn send_transfers(send_window: Option<u64>) -> Result<Duration, ErrorType> {
+    let listener = TcpListener::bind(SocketAddrV4::from_str("127.0.0.1:0").unwrap()).await?;
+    let addr = listener.local_addr()?;
+    let proxy = TcpListener::bind(SocketAddrV4::from_str("127.0.0.1:0").unwrap()).await?;
+    let proxy_addr = proxy.local_addr()?;
+
+    tokio::spawn(async move {
+        let (client, _) = proxy.accept().await.unwrap();
+        let server = TcpStream::connect(addr).await.unwrap();
+        let (client_read, client_write) = client.into_split();
+        let (server_read, server_write) = server.into_split();
+        tokio::spawn(delay(client_read, server_write));
+        tokio::spawn(delay(server_read, client_write));
+    });
+
+    let server = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut session = TCPCLSession::new(socket, "dtn://server".into(), None).unwrap();
+        let mut receive_channel = session.get_receive_channel();
+        let receiver = tokio::spawn(async move {
+            let mut received = 0;
+            while receive_channel.recv().await.is_some() {
+                received += 1;
+            }
+            received
+        });
+        session.manage_connection().await.unwrap();
+        drop(session);
+        receiver.await.unwrap()
+    });
+
+    let url = Url::parse(&format!("tcpcl://{proxy_addr}")).unwrap();
+    let mut session = TCPCLSession::connect(url, "dtn://client".into(), None).await?;
+    if let Some(window) = send_window {
+        session.set_send_window(window);
+    }
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+    let close_channel = session.get_close_channel();
+    let client = tokio::spawn(async move { session.manage_connection().await });
+
+    established_channel.await.unwrap();
+    let start = Instant::now();
+    let mut results = Vec::new();
+    for _ in 0..DELAYED_TRANSFERS {
+        let (result_sender, result_receiver) = oneshot::channel();
+        send_channel
+            .send((Arc::new(vec![0x55; DELAYED_TRANSFER_SIZE]), result_sender))
+            .await
+            .unwrap();
+        results.push(result_receiver);
+    }
+    for result in results {
+        result.await.unwrap().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    close_channel.send(()).unwrap();
+    client.await.unwrap()?;
+    assert_eq!(server.await.unwrap(), DELAYED_TRANSFERS);
+
+    Ok(elapsed)
+}
+
+#[tokio::test]
+async fn test_sequential_transfers() -> Result<(), ErrorType> {
+    check_transfers_in_flight(None, 1).await
+}
+
+#[tokio::test]
+async fn test_pipelining_within_send_window() -> Result<(), ErrorType> {
+    check_transfers_in_flight(Some(5 * TRANSFER_SIZE), 5).await
+}
+
+#[tokio::test]
+async fn test_pipelining_all_transfers() -> Result<(), ErrorType> {
+    check_transfers_in_flight(Some(64 * 1024), TRANSFERS).await
+}
+
+#[tokio::test]
+#[ignore = "benchmark, depends on the timing of the host"]
+async fn test_pipelining_on_delayed_link() -> Result<(), ErrorType> {
+    let sequential = send_transfers(None).await?;
+    let pipelined = send_transfers(Some(64 * 1024)).await?;
+    println!(
+        "{DELAYED_TRANSFERS} transfers with {:?} rtt: sequential {sequential:?}, pipelined {pipelined:?}",
+        ONE_WAY_DELAY * 2
+    );
+
+    // without pipelining every transfer needs at least one round trip
+    assert!(sequential >= ONE_WAY_DELAY * 2 * u32::try_from(DELAYED_TRANSFERS).unwrap());
+    assert!(pipelined * 4 < sequential);
+
+    Ok(())
+}
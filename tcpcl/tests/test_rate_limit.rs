@@ -0,0 +1,138 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{sync::Arc, time::Duration};
+
+use tcpcl::{
+    errors::ErrorType,
+    rate_limit::RateLimit,
+    session::{ProtocolVersion, TCPCLSession},
+    stats::SessionStats,
+};
+use tokio::{sync::oneshot, time::Instant};
+use url::Url;
+
+use crate::common::*;
+
+mod common;
+
+const TRANSFER_SIZE: usize = 5000;
+
+/// Sends a single transfer with `rate_limit` and returns how long it took and the stats of
+/// the sender. If `change` is set the rate limit is replaced after the given time.
+async fn send_limited(
+    version: ProtocolVersion,
+    rate_limit: RateLimit,
+    change: Option<(Duration, Option<RateLimit>)>,
+) -> Result<(Duration, SessionStats), ErrorType> {
+    let (client_stream, mut session) = setup_duplex();
+    let server = tokio::spawn(async move {
+        let mut receive_channel = session.get_receive_channel();
+        let receiver = tokio::spawn(async move { receive_channel.recv().await.unwrap() });
+        session.manage_connection().await.unwrap();
+        receiver.await.unwrap()
+    });
+
+    let url = Url::parse("tcpcl://server").unwrap();
+    let mut session =
+        TCPCLSession::connect_with_stream(client_stream, url, "dtn://client".into(), None)?;
+    session.set_protocol_version(version);
+    session.set_rate_limit(Some(rate_limit));
+    let rate_limit_channel = session.get_rate_limit_channel();
+    let stats_channel = session.get_stats_channel();
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+    let close_channel = session.get_close_channel();
+    let client = tokio::spawn(async move { session.manage_connection().await });
+
+    established_channel.await.unwrap();
+    let start = Instant::now();
+    let (result_sender, result_receiver) = oneshot::channel();
+    send_channel
+        .send((Arc::new(vec![0x55; TRANSFER_SIZE]), result_sender))
+        .await
+        .unwrap();
+    if let Some((after, new_limit)) = change {
+        tokio::time::sleep(after).await;
+        rate_limit_channel.send_replace(new_limit);
+    }
+    result_receiver.await.unwrap().unwrap();
+    let elapsed = start.elapsed();
+    let stats = stats_channel.borrow().clone();
+
+    close_channel.send(()).unwrap();
+    client.await.unwrap()?;
+    assert_eq!(server.await.unwrap().length, TRANSFER_SIZE as u64);
+
+    Ok((elapsed, stats))
+}
+
+#[tokio::test]
+async fn test_rate_limit() -> Result<(), ErrorType> {
+    for version in [ProtocolVersion::V4, ProtocolVersion::V3] {
+        let rate_limit = RateLimit {
+            bytes_per_second: 10_000,
+            burst: 2000,
+        };
+        let (elapsed, stats) = send_limited(version, rate_limit, None).await?;
+
+        // the burst is sent immediately, the rest at the rate
+        assert!(
+            elapsed >= Duration::from_millis(300),
+            "{version:?}: {elapsed:?}"
+        );
+        // segments take at most 100ms of the rate
+        assert_eq!(rate_limit.segment_size(), 1000);
+        assert_eq!(stats.segments_sent, 5, "{version:?}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rate_limit_segment_size() {
+    let slow = RateLimit {
+        bytes_per_second: 5,
+        burst: 100,
+    };
+    assert_eq!(slow.segment_size(), 1);
+    let small_burst = RateLimit {
+        bytes_per_second: 1_000_000,
+        burst: 500,
+    };
+    assert_eq!(small_burst.segment_size(), 500);
+}
+
+#[tokio::test]
+async fn test_rate_limit_change_at_runtime() -> Result<(), ErrorType> {
+    let rate_limit = RateLimit {
+        bytes_per_second: 100,
+        burst: 100,
+    };
+    // this would take 50 seconds with the initial limit
+    let (elapsed, _) = send_limited(
+        ProtocolVersion::V4,
+        rate_limit,
+        Some((Duration::from_millis(200), None)),
+    )
+    .await?;
+
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+
+    Ok(())
+}
@@ -15,16 +15,20 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{net::SocketAddrV4, str::FromStr, sync::Arc};
+use std::{net::SocketAddrV4, str::FromStr, sync::Arc, time::Duration};
 
 use tcpcl::{
-    errors::{ErrorType, Errors},
+    errors::{ErrorType, Errors, TransferSendErrors},
+    receive_limit::ReceiveLimit,
     session::TCPCLSession,
+    stats::{SessionEvent, SessionState},
+    transfer::{Transfer, TransferData, TransferSink},
+    v4::messages::sess_term::ReasonCode,
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    sync::oneshot,
+    net::{TcpListener, TcpStream, UnixListener},
+    sync::{oneshot, watch},
 };
 use url::Url;
 
@@ -65,6 +69,82 @@ async fn test_connection_setup_client() -> Result<(), ErrorType> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_connect_by_hostname() -> Result<(), ErrorType> {
+    let listener = TcpListener::bind(SocketAddrV4::from_str("127.0.0.1:0").unwrap()).await?;
+    let port = listener.local_addr()?.port();
+    let jh = tokio::spawn(async move {
+        listener.accept().await.unwrap();
+    });
+
+    let url = Url::parse(&format!("tcpcl://localhost:{port}")).unwrap();
+    TCPCLSession::connect(url, "dtn://client".into(), None).await?;
+    jh.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_refused() -> Result<(), ErrorType> {
+    let listener = TcpListener::bind(SocketAddrV4::from_str("127.0.0.1:0").unwrap()).await?;
+    let addr = listener.local_addr()?;
+    drop(listener);
+
+    let url = Url::parse(&format!("tcpcl://{addr}")).unwrap();
+    let ret = TCPCLSession::connect_with_timeout(
+        url,
+        "dtn://client".into(),
+        None,
+        Duration::from_secs(1),
+    )
+    .await;
+    let Err(ErrorType::ConnectError(errors)) = ret else {
+        panic!("connecting should have failed");
+    };
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, addr);
+    assert_eq!(errors[0].1.kind(), std::io::ErrorKind::ConnectionRefused);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connect_without_host() {
+    let url = Url::parse("tcpcl:nohost").unwrap();
+    let ret = TCPCLSession::connect(url, "dtn://client".into(), None).await;
+    assert!(matches!(ret, Err(ErrorType::DnsError(_))));
+}
+
+#[tokio::test]
+async fn test_connect_unix() -> Result<(), ErrorType> {
+    let path = std::env::temp_dir().join(format!("tcpcl-test-{}.sock", std::process::id()));
+    let listener = UnixListener::bind(&path)?;
+    let jh = tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.unwrap();
+        let url = Url::parse("tcpcl+unix://client").unwrap();
+        let mut session =
+            TCPCLSession::new_with_stream(socket, url, "dtn://server".into(), None).unwrap();
+        session.manage_connection().await.unwrap();
+    });
+
+    let url = Url::parse(&format!("tcpcl+unix://{}", path.display())).unwrap();
+    let mut session = TCPCLSession::connect(url, "dtn://client".into(), None).await?;
+    let established = session.get_established_channel();
+    let close_channel = session.get_close_channel();
+    tokio::spawn(async move {
+        established.await.unwrap();
+        close_channel.send(()).unwrap();
+    });
+    session.manage_connection().await?;
+    jh.await.unwrap();
+
+    let conn_info = session.get_connection_info();
+    assert_eq!(conn_info.peer_endpoint.unwrap(), "dtn://server");
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_connection_setup_server() -> Result<(), ErrorType> {
     let listener = TcpListener::bind(SocketAddrV4::from_str("127.0.0.1:0").unwrap()).await?;
@@ -128,8 +208,7 @@ async fn test_session_termination_receive() -> Result<(), ErrorType> {
         let mut buf: [u8; 100] = [0; 100];
         let len = client.read(&mut buf).await.unwrap();
         assert_eq!(len, 0);
-    })
-    .await?;
+    });
 
     session.manage_connection().await.unwrap();
     jh.await.unwrap();
@@ -164,8 +243,7 @@ async fn test_session_termination_send() -> Result<(), ErrorType> {
         let mut buf: [u8; 100] = [0; 100];
         let len = client.read(&mut buf).await.unwrap();
         assert_eq!(len, 0);
-    })
-    .await?;
+    });
 
     let established_channel = session.get_established_channel();
     let close_channel = session.get_close_channel();
@@ -208,8 +286,7 @@ async fn test_xfer_single_segment_receive() -> Result<(), ErrorType> {
                 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // ack length
             ]
         );
-    })
-    .await?;
+    });
 
     let mut receive_channel = session.get_receive_channel();
 
@@ -218,7 +295,7 @@ async fn test_xfer_single_segment_receive() -> Result<(), ErrorType> {
 
     let received = receive_channel.recv().await.unwrap();
     assert_eq!(received.id, 1);
-    assert_eq!(Arc::try_unwrap(received.data).unwrap(), [0x55, 0xAA]);
+    assert_eq!(received.data.into_vec().await?, [0x55, 0xAA]);
 
     Ok(())
 }
@@ -274,8 +351,7 @@ async fn test_xfer_single_multi_receive() -> Result<(), ErrorType> {
                 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, // ack length
             ]
         );
-    })
-    .await?;
+    });
 
     let mut receive_channel = session.get_receive_channel();
 
@@ -284,11 +360,103 @@ async fn test_xfer_single_multi_receive() -> Result<(), ErrorType> {
 
     let received = receive_channel.recv().await.unwrap();
     assert_eq!(received.id, 1);
+    assert_eq!(received.data.into_vec().await?, [0x55, 0xAA, 0xAA, 0x55]);
+
+    Ok(())
+}
+
+async fn receive_multi_segment_transfer(sink: TransferSink) -> Result<Transfer, ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        client
+            .write_all(&[
+                0x01, // message type
+                0x02, // flags (start)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, // transfer extensions
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 18] = [0; 18];
+        client.read_exact(&mut buf).await.unwrap();
+
+        client
+            .write_all(&[
+                0x01, // message type
+                0x01, // flags (end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0xAA, 0x55, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 18] = [0; 18];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf[10..18],
+            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04] // ack length
+        );
+    });
+
+    session.set_transfer_sink(sink);
+    let mut receive_channel = session.get_receive_channel();
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    Ok(receive_channel.recv().await.unwrap())
+}
+
+#[tokio::test]
+async fn test_xfer_receive_to_temp_file() -> Result<(), ErrorType> {
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("tcpcl-test-{}", std::process::id()));
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let received = receive_multi_segment_transfer(TransferSink::TempFile(dir.clone())).await?;
+    assert_eq!(received.id, 1);
+    assert_eq!(received.length, 4);
+    let TransferData::File(file) = received.data else {
+        panic!("transfer should have been stored in a file");
+    };
+    assert!(file.path().starts_with(&dir));
     assert_eq!(
-        Arc::try_unwrap(received.data).unwrap(),
+        tokio::fs::read(file.path()).await?,
         [0x55, 0xAA, 0xAA, 0x55]
     );
 
+    let mut target = dir.clone();
+    target.push("persisted");
+    let path = file.path().to_path_buf();
+    file.persist(&target).await?;
+    assert!(!path.exists());
+    assert_eq!(tokio::fs::read(&target).await?, [0x55, 0xAA, 0xAA, 0x55]);
+
+    tokio::fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xfer_receive_to_writer() -> Result<(), ErrorType> {
+    let (writer, mut reader) = tokio::io::duplex(100);
+    let mut writer = Some(writer);
+    let sink = TransferSink::Writer(Box::new(move |id| {
+        assert_eq!(id, 1);
+        Ok(Box::pin(writer.take().unwrap()))
+    }));
+
+    let received = receive_multi_segment_transfer(sink).await?;
+    assert_eq!(received.id, 1);
+    assert_eq!(received.length, 4);
+    assert_eq!(received.data, TransferData::Writer);
+
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).await?;
+    assert_eq!(buf, [0x55, 0xAA, 0xAA, 0x55]);
+
     Ok(())
 }
 
@@ -319,8 +487,7 @@ async fn test_xfer_single_segment_send() -> Result<(), ErrorType> {
             ])
             .await
             .unwrap();
-    })
-    .await?;
+    });
 
     let established_channel = session.get_established_channel();
     let send_channel = session.get_send_channel();
@@ -393,8 +560,7 @@ async fn test_xfer_multi_segment_send() -> Result<(), ErrorType> {
             ])
             .await
             .unwrap();
-    })
-    .await?;
+    });
 
     let established_channel = session.get_established_channel();
     let send_channel = session.get_send_channel();
@@ -420,70 +586,751 @@ async fn test_xfer_multi_segment_send() -> Result<(), ErrorType> {
 }
 
 #[tokio::test]
-async fn test_sends_keepalive() -> Result<(), ErrorType> {
-    let (jh, mut session) = setup_conn_custom_sessinit(
-        |mut client| async move {
-            let mut buf: [u8; 100] = [0; 100];
-            let len = client.read(&mut buf).await.unwrap();
-            assert_eq!(len, 1);
-            assert_eq!(
-                buf[0..1],
-                [
-                    0x04, // message type
-                ]
-            );
+async fn test_xfer_refuse_receive() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        client
+            .write_all(&[
+                0x01, // message type
+                0x02, // flags (start)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, // transfer extensions
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
 
-            client
-                .write_all(&[
-                    0x04, // message type
-                ])
-                .await
-                .unwrap();
+        let mut buf: [u8; 18] = [0; 18];
+        client.read_exact(&mut buf).await.unwrap();
 
-            let mut buf: [u8; 100] = [0; 100];
-            let len = client.read(&mut buf).await.unwrap();
-            assert_eq!(len, 1);
-            assert_eq!(
-                buf[0..1],
-                [
-                    0x04, // message type
-                ]
-            );
-        },
-        SESS_INIT_CLIENT_KEEPALIVE_1S,
-    )
-    .await?;
+        client
+            .write_all(&[
+                0x01, // message type
+                0x00, // flags
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 10);
+        assert_eq!(
+            buf[0..10],
+            [
+                0x03, // message type
+                0x02, // reason (no resources)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+            ]
+        );
+
+        // sent before we got the refusal, it is ignored
+        client
+            .write_all(&[
+                0x01, // message type
+                0x01, // flags (end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        client
+            .write_all(&[
+                0x01, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // transfer id
+                0x00, 0x00, 0x00, 0x00, // transfer extensions
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0xAA, 0x55, // data
+            ])
+            .await
+            .unwrap();
+
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 18);
+        assert_eq!(
+            buf[0..18],
+            [
+                0x02, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // ack length
+            ]
+        );
+    });
+
+    let (_limit_sender, limit) = watch::channel(3);
+    session.set_receive_limit(limit);
+    let mut receive_channel = session.get_receive_channel();
 
     session.manage_connection().await.unwrap();
     jh.await.unwrap();
 
+    let received = receive_channel.recv().await.unwrap();
+    assert_eq!(received.id, 2);
+    assert_eq!(received.data.into_vec().await?, [0xAA, 0x55]);
+    assert!(receive_channel.try_recv().is_err());
+
     Ok(())
 }
 
 #[tokio::test]
-async fn test_closes_on_msg_reject() -> Result<(), ErrorType> {
+async fn test_xfer_receive_limit_reserves_received_data() -> Result<(), ErrorType> {
     let (jh, mut session) = setup_conn(|mut client| async move {
         client
             .write_all(&[
-                0x06, // message type
-                0x01, // reason code
-                0xFF, // wrong message type
+                0x01, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, // transfer extensions
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0x55, 0xAA, // data
             ])
             .await
             .unwrap();
 
-        let mut buf: [u8; 100] = [0; 100];
-        let len = client.read(&mut buf).await.unwrap();
-        assert_eq!(len, 0);
-    })
-    .await?;
+        let mut buf: [u8; 18] = [0; 18];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[0], 0x02);
 
-    let ret = session.manage_connection().await;
-    assert!(matches!(
-        ret,
-        Err(ErrorType::TCPCLError(Errors::RemoteRejected))
-    ));
+        // the first transfer is not stored yet, so there is no space left for this one
+        client
+            .write_all(&[
+                0x01, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // transfer id
+                0x00, 0x00, 0x00, 0x00, // transfer extensions
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 10] = [0; 10];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x03, // message type
+                0x02, // reason (no resources)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // transfer id
+            ]
+        );
+    });
+
+    let (_limit_sender, limit) = watch::channel(3);
+    let limit = ReceiveLimit::new(limit);
+    session.set_receive_limit(limit.clone());
+    let mut receive_channel = session.get_receive_channel();
+
+    session.manage_connection().await.unwrap();
     jh.await.unwrap();
 
+    let received = receive_channel.recv().await.unwrap();
+    assert_eq!(received.id, 1);
+    assert!(receive_channel.try_recv().is_err());
+    assert_eq!(limit.reserved(), 2);
+    drop(received);
+    assert_eq!(limit.reserved(), 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xfer_refused_send() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        let mut buf: [u8; 24] = [0; 24];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[2..10], [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        client
+            .write_all(&[
+                0x03, // message type
+                0x02, // reason (no resources)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // transfer id
+            ])
+            .await
+            .unwrap();
+
+        // the next transfer is sent normally
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[2..10], [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]);
+        client
+            .write_all(&[
+                0x02, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // ack length
+            ])
+            .await
+            .unwrap();
+    });
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+
+    let (refused_result_sender, refused_result_receiver) = oneshot::channel();
+    let (transfer_result_sender, transfer_result_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        send_channel
+            .send((Arc::new([0x55, 0xAA].into()), refused_result_sender))
+            .await
+            .unwrap();
+        send_channel
+            .send((Arc::new([0xAA, 0x55].into()), transfer_result_sender))
+            .await
+            .unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    assert!(matches!(
+        refused_result_receiver.await.unwrap(),
+        Err(TransferSendErrors::Refused)
+    ));
+    transfer_result_receiver.await.unwrap().unwrap();
+    assert_eq!(session.get_stats_channel().borrow().transfers_refused, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xfer_refused_out_of_order() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        // both transfers are sent without waiting for acks
+        let mut buf: [u8; 48] = [0; 48];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf[26..34],
+            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]
+        );
+
+        // the newer transfer is refused before the older one is acked
+        client
+            .write_all(&[
+                0x03, // message type
+                0x02, // reason (no resources)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+            ])
+            .await
+            .unwrap();
+        client
+            .write_all(&[
+                0x02, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // ack length
+            ])
+            .await
+            .unwrap();
+    });
+    session.set_send_window(1024);
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+
+    let (transfer_result_sender, transfer_result_receiver) = oneshot::channel();
+    let (refused_result_sender, refused_result_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        send_channel
+            .send((Arc::new([0x55, 0xAA].into()), transfer_result_sender))
+            .await
+            .unwrap();
+        send_channel
+            .send((Arc::new([0xAA, 0x55].into()), refused_result_sender))
+            .await
+            .unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    transfer_result_receiver.await.unwrap().unwrap();
+    assert!(matches!(
+        refused_result_receiver.await.unwrap(),
+        Err(TransferSendErrors::Refused)
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xfer_interrupted_receive() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        client
+            .write_all(&[
+                0x01, // message type
+                0x02, // flags (start)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, // transfer extensions
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // data bytes
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 18] = [0; 18];
+        client.read_exact(&mut buf).await.unwrap();
+        // the connection drops before the end of the transfer
+    });
+
+    let mut receive_channel = session.get_receive_channel();
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    let received = receive_channel.recv().await.unwrap();
+    assert_eq!(received.id, 1);
+    assert!(received.interrupted);
+    assert_eq!(received.length, 2);
+    assert_eq!(received.data.into_vec().await?, [0x55, 0xAA]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_xfer_interrupted_send() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        let mut buf: [u8; 24] = [0; 24];
+        client.read_exact(&mut buf).await.unwrap();
+
+        client
+            .write_all(&[
+                0x02, // message type
+                0x02, // flags (start)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // ack length
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 20] = [0; 20];
+        client.read_exact(&mut buf).await.unwrap();
+        // the connection drops before the last segment is acknowledged
+    });
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+
+    let (transfer_result_sender, transfer_result_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        send_channel
+            .send((
+                Arc::new([0x55, 0xAA, 0xAA, 0x55].into()),
+                transfer_result_sender,
+            ))
+            .await
+            .unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    assert!(matches!(
+        transfer_result_receiver.await.unwrap(),
+        Err(TransferSendErrors::Interrupted { acknowledged: 2 })
+    ));
+    assert!(session.take_unsent_transfers().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_graceful_termination_finishes_transfer() -> Result<(), ErrorType> {
+    let (close_sender, close_receiver) = oneshot::channel();
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        // both segments are sent without waiting for acks
+        let mut buf: [u8; 44] = [0; 44];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[0..2], [0x01, 0x02]);
+        assert_eq!(buf[24..26], [0x01, 0x01]);
+
+        close_sender.send(()).unwrap();
+        let mut buf: [u8; 3] = [0; 3];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x05, // message type
+                0x00, // flags
+                0x05, // reason (resource exhaustion)
+            ]
+        );
+
+        // the transfer is still finished after the SESS_TERM
+        client
+            .write_all(&[
+                0x02, // message type
+                0x01, // flags (end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, // ack length
+            ])
+            .await
+            .unwrap();
+        client
+            .write_all(&[
+                0x05, // message type
+                0x01, // flags (reply)
+                0x05, // reason (resource exhaustion)
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+    session.set_graceful_shutdown(Duration::from_secs(10));
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+    let close_channel = session.get_close_channel();
+
+    let (transfer_result_sender, transfer_result_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        send_channel
+            .send((
+                Arc::new([0x55, 0xAA, 0xAA, 0x55].into()),
+                transfer_result_sender,
+            ))
+            .await
+            .unwrap();
+        close_receiver.await.unwrap();
+        close_channel.send(()).unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    transfer_result_receiver.await.unwrap().unwrap();
+    assert!(session.take_unsent_transfers().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_termination_finishes_transfer() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        let mut buf: [u8; 44] = [0; 44];
+        client.read_exact(&mut buf).await.unwrap();
+
+        client
+            .write_all(&[
+                0x05, // message type
+                0x00, // flags
+                0x05, // reason (resource exhaustion)
+            ])
+            .await
+            .unwrap();
+        let mut buf: [u8; 3] = [0; 3];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x05, // message type
+                0x01, // flags (reply)
+                0x05, // reason (resource exhaustion)
+            ]
+        );
+
+        // the transfer in flight is still finished after the SESS_TERM
+        client
+            .write_all(&[
+                0x02, // message type
+                0x01, // flags (end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, // ack length
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+
+    let (transfer_result_sender, transfer_result_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        send_channel
+            .send((
+                Arc::new([0x55, 0xAA, 0xAA, 0x55].into()),
+                transfer_result_sender,
+            ))
+            .await
+            .unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    transfer_result_receiver.await.unwrap().unwrap();
+    assert!(session.take_unsent_transfers().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_graceful_termination_deadline() -> Result<(), ErrorType> {
+    let (close_sender, close_receiver) = oneshot::channel();
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        let mut buf: [u8; 44] = [0; 44];
+        client.read_exact(&mut buf).await.unwrap();
+
+        close_sender.send(()).unwrap();
+        let mut buf: [u8; 3] = [0; 3];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[0], 0x05);
+
+        // we never ack the transfer, so the session closes once the deadline passed
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+    session.set_graceful_shutdown(Duration::from_millis(100));
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+    let close_channel = session.get_close_channel();
+
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        for data in [[0x55, 0xAA, 0xAA, 0x55], [0x01, 0x02, 0x03, 0x04]] {
+            let (transfer_result_sender, _) = oneshot::channel();
+            send_channel
+                .send((Arc::new(data.into()), transfer_result_sender))
+                .await
+                .unwrap();
+        }
+        close_receiver.await.unwrap();
+        close_channel.send(()).unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    let unsent: Vec<Vec<u8>> = session
+        .take_unsent_transfers()
+        .into_iter()
+        .map(|(data, _)| data.to_vec())
+        .collect();
+    assert_eq!(
+        unsent,
+        vec![vec![0x55, 0xAA, 0xAA, 0x55], vec![0x01, 0x02, 0x03, 0x04]]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sends_keepalive() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn_custom_sessinit(
+        |mut client| async move {
+            let mut buf: [u8; 100] = [0; 100];
+            let len = client.read(&mut buf).await.unwrap();
+            assert_eq!(len, 1);
+            assert_eq!(
+                buf[0..1],
+                [
+                    0x04, // message type
+                ]
+            );
+
+            client
+                .write_all(&[
+                    0x04, // message type
+                ])
+                .await
+                .unwrap();
+
+            let mut buf: [u8; 100] = [0; 100];
+            let len = client.read(&mut buf).await.unwrap();
+            assert_eq!(len, 1);
+            assert_eq!(
+                buf[0..1],
+                [
+                    0x04, // message type
+                ]
+            );
+        },
+        SESS_INIT_CLIENT_KEEPALIVE_1S,
+    );
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_closes_on_msg_reject() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        client
+            .write_all(&[
+                0x06, // message type
+                0x01, // reason code
+                0xFF, // wrong message type
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+
+    let ret = session.manage_connection().await;
+    assert!(matches!(
+        ret,
+        Err(ErrorType::TCPCLError(Errors::RemoteRejected))
+    ));
+    jh.await.unwrap();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_stats_and_events() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_conn(|mut client| async move {
+        let mut buf: [u8; 24] = [0; 24];
+        client.read_exact(&mut buf).await.unwrap();
+
+        client
+            .write_all(&[
+                0x02, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // transfer id
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, // ack length
+            ])
+            .await
+            .unwrap();
+
+        client
+            .write_all(&[
+                0x01, // message type
+                0x03, // flags (start + end)
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // transfer id
+                0x00, 0x00, 0x00, 0x00, // transfer extensions
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // data bytes
+                0x55, 0xAA, 0x55, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 18] = [0; 18];
+        client.read_exact(&mut buf).await.unwrap();
+
+        client
+            .write_all(&[
+                0x05, // message type
+                0x00, // flags
+                0x03, // reason (busy)
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 3] = [0; 3];
+        client.read_exact(&mut buf).await.unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+    let stats_channel = session.get_stats_channel();
+    let mut event_channel = session.get_event_channel();
+
+    let (transfer_result_sender, transfer_result_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        send_channel
+            .send((Arc::new([0x55, 0xAA].into()), transfer_result_sender))
+            .await
+            .unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+    transfer_result_receiver.await.unwrap().unwrap();
+
+    let stats = stats_channel.borrow().clone();
+    assert_eq!(stats.state, SessionState::Closed);
+    assert!(stats.uptime().is_some());
+    assert_eq!(stats.segments_sent, 1);
+    assert_eq!(stats.bytes_sent, 2);
+    assert_eq!(stats.transfers_sent, 1);
+    assert_eq!(stats.segments_received, 1);
+    assert_eq!(stats.bytes_received, 3);
+    assert_eq!(stats.transfers_received, 1);
+    assert_eq!(stats.transfers_refused, 0);
+    assert!(stats.rtt.is_some());
+
+    let SessionEvent::Established(conn_info) = event_channel.recv().await.unwrap() else {
+        panic!("expected the session to be established first");
+    };
+    assert_eq!(conn_info.peer_endpoint.unwrap(), "dtn://client");
+    let SessionEvent::Terminating {
+        reason,
+        initiated_by_peer,
+    } = event_channel.recv().await.unwrap()
+    else {
+        panic!("expected the session to terminate");
+    };
+    assert_eq!(reason, Some(ReasonCode::Busy));
+    assert!(initiated_by_peer);
+    assert!(matches!(
+        event_channel.recv().await.unwrap(),
+        SessionEvent::Closed
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_stats_on_abrupt_close() -> Result<(), ErrorType> {
+    // the peer goes away without terminating the session
+    let (jh, mut session) = setup_conn(|client| async move {
+        drop(client);
+    });
+
+    let stats_channel = session.get_stats_channel();
+    let mut event_channel = session.get_event_channel();
+
+    let _ = session.manage_connection().await;
+    jh.await.unwrap();
+
+    let stats = stats_channel.borrow().clone();
+    assert_eq!(stats.state, SessionState::Closed);
+    let uptime = stats.uptime().unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(stats_channel.borrow().uptime().unwrap(), uptime);
+
+    assert!(matches!(
+        event_channel.recv().await.unwrap(),
+        SessionEvent::Established(_)
+    ));
+    assert!(matches!(
+        event_channel.recv().await.unwrap(),
+        SessionEvent::Terminating { reason: None, .. }
+    ));
+    assert!(matches!(
+        event_channel.recv().await.unwrap(),
+        SessionEvent::Closed
+    ));
+
     Ok(())
 }
@@ -0,0 +1,134 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use tcpcl::{
+    errors::{ErrorType, Errors},
+    extensions::{EidSchemes, Extension, LinkCapacity},
+    session::TCPCLSession,
+    v4::messages,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use url::Url;
+
+use crate::common::*;
+
+mod common;
+
+#[test]
+fn test_extension_encoding() {
+    let schemes = EidSchemes(vec![1, 2]);
+    assert_eq!(
+        schemes.encode(),
+        [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2]
+    );
+    assert_eq!(EidSchemes::decode(&schemes.encode()), Some(schemes));
+    assert_eq!(EidSchemes::decode(&[0, 1, 2]), None);
+
+    let capacity = LinkCapacity {
+        bits_per_second: 1_000_000,
+    };
+    assert_eq!(LinkCapacity::decode(&capacity.encode()), Some(capacity));
+    assert_eq!(LinkCapacity::decode(&[]), None);
+}
+
+#[tokio::test]
+async fn test_extension_negotiation() -> Result<(), ErrorType> {
+    let (client_stream, mut session) = setup_duplex();
+    let server = tokio::spawn(async move {
+        session.add_session_extension(
+            &LinkCapacity {
+                bits_per_second: 1_000_000,
+            },
+            true,
+        );
+        session.add_session_extension(&EidSchemes(vec![1]), false);
+        let established_channel = session.get_established_channel();
+        session.manage_connection().await.unwrap();
+        established_channel.await.unwrap()
+    });
+
+    let url = Url::parse("tcpcl://server").unwrap();
+    let mut session =
+        TCPCLSession::connect_with_stream(client_stream, url, "dtn://client".into(), None)?;
+    session.add_session_extension(
+        &LinkCapacity {
+            bits_per_second: 5000,
+        },
+        false,
+    );
+    let established_channel = session.get_established_channel();
+    let close_channel = session.get_close_channel();
+    let client = tokio::spawn(async move { session.manage_connection().await });
+
+    let client_info = established_channel.await.unwrap();
+    close_channel.send(()).unwrap();
+    client.await.unwrap()?;
+    let server_info = server.await.unwrap();
+
+    // only extensions sent by both sides are negotiated
+    assert_eq!(
+        client_info.session_extensions.get(),
+        Some(LinkCapacity {
+            bits_per_second: 1_000_000
+        })
+    );
+    assert!(!client_info.session_extensions.contains::<EidSchemes>());
+    assert_eq!(
+        server_info.session_extensions.get(),
+        Some(LinkCapacity {
+            bits_per_second: 5000
+        })
+    );
+    assert!(!server_info.session_extensions.contains::<EidSchemes>());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unknown_critical_extension() -> Result<(), ErrorType> {
+    let (mut client, mut session) = setup_duplex();
+    let jh = tokio::spawn(async move {
+        client.write_all(&CONTACT_HEADER_NO_TLS).await.unwrap();
+
+        let mut buf: [u8; 6] = [0; 6];
+        client.read_exact(&mut buf).await.unwrap();
+
+        let mut sess_init = SESS_INIT_CLIENT[..33].to_vec();
+        sess_init.extend_from_slice(&[
+            0x00, 0x00, 0x00, 0x06, // session extension length
+            0x01, // flags (critical)
+            0x80, 0x05, // extension type
+            0x00, 0x01, // value length
+            0x2A, // value
+        ]);
+        client.write_all(&sess_init).await.unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        while client.read(&mut buf).await.unwrap() > 0 {}
+    });
+
+    let ret = session.manage_connection().await;
+    assert!(matches!(
+        ret,
+        Err(ErrorType::TCPCLError(Errors::MessageError(
+            messages::Errors::UnkownCriticalSessionExtension(0x8005)
+        )))
+    ));
+    jh.await.unwrap();
+
+    Ok(())
+}
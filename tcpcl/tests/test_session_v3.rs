@@ -0,0 +1,375 @@
+// Copyright (C) 2023 Felix Huettner
+//
+// This file is part of DTRD.
+//
+// DTRD is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// DTRD is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{net::SocketAddrV4, str::FromStr, sync::Arc};
+
+use bytes::BytesMut;
+use tcpcl::{
+    errors::{ErrorType, TransferSendErrors},
+    session::{ProtocolVersion, TCPCLSession},
+    stats::SessionEvent,
+    v3::messages::{
+        contact_header::{ContactHeader, MAX_EID_LENGTH},
+        sdnv,
+    },
+    v4::messages::sess_term::ReasonCode,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::TcpListener,
+    sync::{oneshot, watch},
+};
+use url::Url;
+
+use crate::common::*;
+
+mod common;
+
+const CONTACT_HEADER_CLIENT: [u8; 21] = [
+    0x64, 0x74, 0x6E, 0x21, // magic "dtn!"
+    0x03, // version 3
+    0x05, // flags (request ack + allow refusal)
+    0x00, 0x00, // keepalive interval
+    0x0C, // eid length
+    0x64, 0x74, 0x6E, 0x3A, 0x2F, 0x2F, 0x63, 0x6C, 0x69, 0x65, 0x6E,
+    0x74, // eid "dtn://client"
+];
+
+const CONTACT_HEADER_SERVER: [u8; 21] = [
+    0x64, 0x74, 0x6E, 0x21, // magic "dtn!"
+    0x03, // version 3
+    0x05, // flags (request ack + allow refusal)
+    0x00, 0x3C, // keepalive interval
+    0x0C, // eid length
+    0x64, 0x74, 0x6E, 0x3A, 0x2F, 0x2F, 0x73, 0x65, 0x72, 0x76, 0x65,
+    0x72, // eid "dtn://server"
+];
+
+fn setup_passive_v3<Fut>(
+    do_test: impl FnOnce(DuplexStream) -> Fut + Send + 'static,
+) -> (tokio::task::JoinHandle<()>, TCPCLSession)
+where
+    Fut: Future<Output = ()> + Send,
+{
+    let (mut client, session) = setup_duplex();
+    let jh = tokio::spawn(async move {
+        client.write_all(&CONTACT_HEADER_CLIENT).await.unwrap();
+
+        let mut buf: [u8; 21] = [0; 21];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, CONTACT_HEADER_SERVER);
+
+        do_test(client).await;
+    });
+
+    (jh, session)
+}
+
+#[test]
+fn test_sdnv() {
+    for value in [0, 1, 127, 128, 16383, 16384, u64::from(u32::MAX), u64::MAX] {
+        let mut buf = BytesMut::new();
+        sdnv::encode(value, &mut buf);
+        assert_eq!(sdnv::decode(&buf).unwrap(), Some((value, buf.len())));
+    }
+
+    assert_eq!(sdnv::decode(&[0x81, 0x00]).unwrap(), Some((128, 2)));
+    assert_eq!(sdnv::decode(&[0x81]).unwrap(), None);
+    assert!(sdnv::decode(&[0xFF; 11]).is_err());
+}
+
+#[test]
+fn test_v3_contact_header_rejects_long_eid() {
+    for eid_length in [MAX_EID_LENGTH as u64 + 1, u64::MAX] {
+        let mut buf = BytesMut::from(&CONTACT_HEADER_CLIENT[..8]);
+        sdnv::encode(eid_length, &mut buf);
+        assert!(ContactHeader::decode(&mut buf).is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_v3_receive_bundle() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_passive_v3(|mut client| async move {
+        client
+            .write_all(&[
+                0x12, // message type (data segment) + flags (start)
+                0x02, // length
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 2] = [0; 2];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x20, // message type (ack segment)
+                0x02, // acked length
+            ]
+        );
+
+        client
+            .write_all(&[
+                0x11, // message type (data segment) + flags (end)
+                0x01, // length
+                0x55, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 2] = [0; 2];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x20, // message type (ack segment)
+                0x03, // acked length
+            ]
+        );
+
+        client
+            .write_all(&[
+                0x50, // message type (shutdown)
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+
+    let established = session.get_established_channel();
+    let mut receive_channel = session.get_receive_channel();
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    let conn_info = established.await.unwrap();
+    assert_eq!(conn_info.peer_endpoint.unwrap(), "dtn://client");
+
+    let received = receive_channel.recv().await.unwrap();
+    assert_eq!(received.data.into_vec().await?, [0x55, 0xAA, 0x55]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_v3_receive_refuse() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_passive_v3(|mut client| async move {
+        client
+            .write_all(&[
+                0x12, // message type (data segment) + flags (start)
+                0x04, // length
+                0x55, 0xAA, 0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 1] = [0; 1];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x32, // message type (refuse bundle) + reason (no resources)
+            ]
+        );
+
+        client
+            .write_all(&[
+                0x11, // message type (data segment) + flags (end)
+                0x01, // length
+                0x55, // data of the refused bundle, ignored
+                0x13, // message type (data segment) + flags (start + end)
+                0x02, // length
+                0x55, 0xAA, // data
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 2] = [0; 2];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x20, // message type (ack segment)
+                0x02, // acked length
+            ]
+        );
+
+        client
+            .write_all(&[
+                0x50, // message type (shutdown)
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+
+    let (_limit_sender, limit) = watch::channel(3);
+    session.set_receive_limit(limit);
+    let mut receive_channel = session.get_receive_channel();
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    let received = receive_channel.recv().await.unwrap();
+    assert_eq!(received.data.into_vec().await?, [0x55, 0xAA]);
+    assert!(receive_channel.try_recv().is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_v3_send_bundle_and_refusal() -> Result<(), ErrorType> {
+    let (jh, mut session) = setup_passive_v3(|mut client| async move {
+        let mut buf: [u8; 4] = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x13, // message type (data segment) + flags (start + end)
+                0x02, // length
+                0x55, 0xAA, // data
+            ]
+        );
+        client
+            .write_all(&[
+                0x20, // message type (ack segment)
+                0x02, // acked length
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 4] = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            buf,
+            [
+                0x13, // message type (data segment) + flags (start + end)
+                0x02, // length
+                0xAA, 0x55, // data
+            ]
+        );
+        client
+            .write_all(&[
+                0x32, // message type (refuse bundle) + reason (no resources)
+            ])
+            .await
+            .unwrap();
+
+        client
+            .write_all(&[
+                0x52, // message type (shutdown) + flags (reason present)
+                0x02, // reason (busy)
+            ])
+            .await
+            .unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+
+    let established_channel = session.get_established_channel();
+    let send_channel = session.get_send_channel();
+    let mut event_channel = session.get_event_channel();
+
+    let (first_sender, first_receiver) = oneshot::channel();
+    let (second_sender, second_receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        established_channel.await.unwrap();
+        send_channel
+            .send((Arc::new([0x55, 0xAA].into()), first_sender))
+            .await
+            .unwrap();
+        send_channel
+            .send((Arc::new([0xAA, 0x55].into()), second_sender))
+            .await
+            .unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    first_receiver.await.unwrap().unwrap();
+    assert!(matches!(
+        second_receiver.await.unwrap(),
+        Err(TransferSendErrors::Refused)
+    ));
+
+    assert!(matches!(
+        event_channel.recv().await.unwrap(),
+        SessionEvent::Established(_)
+    ));
+    let SessionEvent::Terminating {
+        reason,
+        initiated_by_peer,
+    } = event_channel.recv().await.unwrap()
+    else {
+        panic!("expected the session to terminate");
+    };
+    assert_eq!(reason, Some(ReasonCode::Busy));
+    assert!(initiated_by_peer);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_v3_active_session() -> Result<(), ErrorType> {
+    let listener = TcpListener::bind(SocketAddrV4::from_str("127.0.0.1:0").unwrap()).await?;
+    let addr = listener.local_addr()?;
+    let jh = tokio::spawn(async move {
+        let (mut server, _) = listener.accept().await.unwrap();
+        let mut buf: [u8; 21] = [0; 21];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf[0..5], CONTACT_HEADER_CLIENT[0..5]);
+        assert_eq!(buf[9..21], CONTACT_HEADER_CLIENT[9..21]);
+
+        server.write_all(&CONTACT_HEADER_SERVER).await.unwrap();
+
+        let mut buf: [u8; 100] = [0; 100];
+        let len = server.read(&mut buf).await.unwrap();
+        assert_eq!(len, 1);
+        assert_eq!(
+            buf[0],
+            0x50 // message type (shutdown)
+        );
+        let len = server.read(&mut buf).await.unwrap();
+        assert_eq!(len, 0);
+    });
+
+    let url = Url::parse(&format!("tcpcl://{addr}")).unwrap();
+    let mut session = TCPCLSession::connect(url, "dtn://client".into(), None).await?;
+    session.set_protocol_version(ProtocolVersion::V3);
+    let established_channel = session.get_established_channel();
+    let close_channel = session.get_close_channel();
+    tokio::spawn(async move {
+        let conn_info = established_channel.await.unwrap();
+        assert_eq!(conn_info.peer_endpoint.unwrap(), "dtn://server");
+        close_channel.send(()).unwrap();
+    });
+
+    session.manage_connection().await.unwrap();
+    jh.await.unwrap();
+
+    Ok(())
+}